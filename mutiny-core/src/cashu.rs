@@ -1,13 +1,118 @@
 use crate::error::MutinyError;
-use moksha_core::primitives::PostMeltBolt11Response;
+use crate::logging::MutinyLogger;
+use crate::storage::{
+    get_cashu_mint_quote, get_cashu_pending_send, get_cashu_proofs, list_cashu_mint_quotes,
+    list_cashu_pending_sends, list_cashu_proofs, persist_cashu_mint_quote,
+    persist_cashu_pending_send, persist_cashu_proofs, MutinyStorage,
+};
+use crate::utils;
+use async_lock::RwLock;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{rand, PublicKey, Secp256k1, SecretKey};
+use bitcoin::Network;
+use ciborium::Value as CborValue;
+use futures::Future;
+use futures_util::lock::Mutex;
+use hex_conservative::DisplayHex;
+use lightning::ln::PaymentSecret;
+use lightning::{log_debug, log_trace, log_warn};
+use lightning_invoice::{Bolt11Invoice, InvoiceBuilder};
+use moksha_core::blind::{BlindedMessage, BlindedSignature};
+use moksha_core::dhke::Dhke;
 use moksha_core::primitives::{
-    CashuErrorResponse, PostMeltBolt11Request, PostMeltQuoteBolt11Request,
-    PostMeltQuoteBolt11Response,
+    CashuErrorResponse, CurrencyUnit, KeyResponse, KeysResponse, PostMeltBolt11Request,
+    PostMeltBolt11Response, PostMeltQuoteBolt11Request, PostMeltQuoteBolt11Response,
+    PostMintBolt11Request, PostMintBolt11Response, PostMintQuoteBolt11Request,
+    PostMintQuoteBolt11Response, PostSwapRequest, PostSwapResponse,
 };
+use moksha_core::proof::{Proof, Proofs};
+use moksha_core::token::{Token, TokenV3};
 use reqwest::Client;
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use url::Url;
+use uuid::Uuid;
+
+const HASH_TO_CURVE_DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+const CASHU_URI_PREFIX: &str = "cashu:";
+const TOKEN_V3_PREFIX: &str = "cashuA";
+const TOKEN_V4_PREFIX: &str = "cashuB";
+const DEFAULT_UNIT: &str = "sat";
+
+/// The NUTs we check a mint supports before using it
+pub const NUT_MELT: u8 = 5;
+pub const NUT_CHECK_STATE: u8 = 7;
+pub const NUT_FEE_RETURN: u8 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostCheckStateRequest {
+    #[serde(rename = "Ys")]
+    pub ys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostCheckStateResponse {
+    pub states: Vec<ProofState>,
+}
+
+impl PostCheckStateResponse {
+    /// Whether the mint says every proof we asked about is spent
+    pub fn all_spent(&self) -> bool {
+        !self.states.is_empty()
+            && self
+                .states
+                .iter()
+                .all(|s| s.state == ProofSpendState::Spent)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofState {
+    #[serde(rename = "Y")]
+    pub y: String,
+    pub state: ProofSpendState,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ProofSpendState {
+    Unspent,
+    Pending,
+    Spent,
+}
+
+/// What a mint tells us about itself from `/v1/info` (NUT-06)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MintInfo {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub pubkey: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub nuts: HashMap<String, Value>,
+}
+
+impl MintInfo {
+    /// Whether the mint lists the given NUT without disabling it
+    pub fn supports_nut(&self, nut: u8) -> bool {
+        match self.nuts.get(&nut.to_string()) {
+            None => false,
+            Some(settings) => {
+                settings.get("disabled").and_then(Value::as_bool) != Some(true)
+                    && settings.get("supported").and_then(Value::as_bool) != Some(false)
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct CashuHttpClient {
@@ -21,6 +126,61 @@ impl CashuHttpClient {
         }
     }
 
+    pub async fn get_info(&self, url: &Url) -> Result<MintInfo, MutinyError> {
+        let url = format!("{url}/v1/info");
+        self.mint_get(url).await
+    }
+
+    pub async fn get_keys(&self, url: &Url) -> Result<KeysResponse, MutinyError> {
+        let url = format!("{url}/v1/keys");
+        self.mint_get(url).await
+    }
+
+    pub async fn post_mint_quote_bolt11(
+        &self,
+        url: &Url,
+        mint_quote_request: PostMintQuoteBolt11Request,
+    ) -> Result<PostMintQuoteBolt11Response, MutinyError> {
+        let url = format!("{url}/v1/mint/quote/bolt11");
+        self.mint_post(url, json!(mint_quote_request)).await
+    }
+
+    pub async fn get_mint_quote_bolt11(
+        &self,
+        url: &Url,
+        quote: &str,
+    ) -> Result<PostMintQuoteBolt11Response, MutinyError> {
+        let url = format!("{url}/v1/mint/quote/bolt11/{quote}");
+        self.mint_get(url).await
+    }
+
+    pub async fn post_mint_bolt11(
+        &self,
+        url: &Url,
+        mint_request: PostMintBolt11Request,
+    ) -> Result<PostMintBolt11Response, MutinyError> {
+        let url = format!("{url}/v1/mint/bolt11");
+        self.mint_post(url, json!(mint_request)).await
+    }
+
+    pub async fn post_swap(
+        &self,
+        url: &Url,
+        swap_request: PostSwapRequest,
+    ) -> Result<PostSwapResponse, MutinyError> {
+        let url = format!("{url}/v1/swap");
+        self.mint_post(url, json!(swap_request)).await
+    }
+
+    pub async fn post_check_state(
+        &self,
+        url: &Url,
+        check_state_request: PostCheckStateRequest,
+    ) -> Result<PostCheckStateResponse, MutinyError> {
+        let url = format!("{url}/v1/checkstate");
+        self.mint_post(url, json!(check_state_request)).await
+    }
+
     pub async fn post_melt_quote_bolt11(
         &self,
         url: &Url,
@@ -39,6 +199,19 @@ impl CashuHttpClient {
         self.mint_post(url, json!(melt_request)).await
     }
 
+    async fn mint_get<T: serde::de::DeserializeOwned>(
+        &self,
+        url: String,
+    ) -> Result<T, MutinyError> {
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|_| MutinyError::CashuMintError)?;
+        Self::parse_cashu_mint_response(res).await
+    }
+
     async fn mint_post<T: serde::de::DeserializeOwned>(
         &self,
         url: String,
@@ -80,3 +253,1283 @@ impl CashuHttpClient {
         }
     }
 }
+
+/// The proofs we hold for a single mint, saved to the DB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashuProofs {
+    pub mint_url: Url,
+    pub proofs: Vec<Proof>,
+    pub version: u32,
+}
+
+impl CashuProofs {
+    pub fn total_amount(&self) -> u64 {
+        self.proofs.iter().map(|p| p.amount).sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MintQuoteState {
+    /// Waiting for the invoice to be paid
+    Unpaid,
+    /// The invoice was paid but we have not minted the ecash yet
+    Paid,
+    /// The ecash was minted and is in our wallet
+    Issued,
+}
+
+/// A request to mint ecash from a lightning payment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CashuMintQuote {
+    pub mint_url: Url,
+    pub quote: String,
+    pub amount: u64,
+    pub bolt11: Bolt11Invoice,
+    pub state: MintQuoteState,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PendingSendState {
+    /// The recipient has not claimed the token yet
+    Pending,
+    /// The recipient claimed the token
+    Claimed,
+    /// We took the ecash back into our wallet
+    Reclaimed,
+}
+
+/// A token we created for someone else, kept until they claim it
+/// so we can take the ecash back if they never do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CashuPendingSend {
+    pub id: String,
+    pub mint_url: Url,
+    pub proofs: Vec<Proof>,
+    pub amount: u64,
+    pub state: PendingSendState,
+    pub created_at: u64,
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CashuMintBalance {
+    pub mint_url: Url,
+    pub balance: u64,
+}
+
+/// A cashu token decoded from either the V3 (`cashuA`) or V4 (`cashuB`) format
+#[derive(Debug, Clone)]
+pub struct CashuToken {
+    pub token: TokenV3,
+    pub unit: Option<String>,
+    pub memo: Option<String>,
+}
+
+/// What a cashu token holds, so it can be shown before claiming it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CashuTokenPreview {
+    pub mints: Vec<CashuTokenMint>,
+    pub amount: u64,
+    pub unit: String,
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CashuTokenMint {
+    pub mint_url: Option<Url>,
+    pub amount: u64,
+    pub proofs: usize,
+}
+
+impl CashuToken {
+    pub fn unit(&self) -> &str {
+        self.unit.as_deref().unwrap_or(DEFAULT_UNIT)
+    }
+
+    /// Whether the token is denominated in sats, the only unit we can spend
+    pub fn is_sat(&self) -> bool {
+        self.unit() == DEFAULT_UNIT
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.token
+            .tokens
+            .iter()
+            .map(|t| t.proofs.total_amount())
+            .sum()
+    }
+
+    pub fn preview(&self) -> CashuTokenPreview {
+        let mut mints: Vec<CashuTokenMint> = vec![];
+        for token in self.token.tokens.iter() {
+            let amount = token.proofs.total_amount();
+            let proofs = token.proofs.proofs().len();
+            match mints.iter_mut().find(|m| m.mint_url == token.mint) {
+                Some(mint) => {
+                    mint.amount += amount;
+                    mint.proofs += proofs;
+                }
+                None => mints.push(CashuTokenMint {
+                    mint_url: token.mint.clone(),
+                    amount,
+                    proofs,
+                }),
+            }
+        }
+
+        CashuTokenPreview {
+            mints,
+            amount: self.amount(),
+            unit: self.unit().to_string(),
+            memo: self.memo.clone(),
+        }
+    }
+}
+
+impl From<TokenV3> for CashuToken {
+    fn from(token: TokenV3) -> Self {
+        Self {
+            token,
+            unit: None,
+            memo: None,
+        }
+    }
+}
+
+impl FromStr for CashuToken {
+    type Err = MutinyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix(CASHU_URI_PREFIX).unwrap_or(s);
+
+        // both versions are turned into the V3 json so moksha can read the proofs
+        let mut json = if let Some(data) = s.strip_prefix(TOKEN_V3_PREFIX) {
+            serde_json::from_slice::<Value>(&decode_token_base64(data)?)
+                .map_err(|_| MutinyError::InvalidCashuToken)?
+        } else if let Some(data) = s.strip_prefix(TOKEN_V4_PREFIX) {
+            token_v4_to_json(&decode_token_base64(data)?)?
+        } else {
+            return Err(MutinyError::InvalidCashuToken);
+        };
+
+        let obj = json.as_object_mut().ok_or(MutinyError::InvalidCashuToken)?;
+        let unit = obj
+            .remove("unit")
+            .and_then(|u| u.as_str().map(str::to_string));
+        let memo = obj.get("memo").and_then(Value::as_str).map(str::to_string);
+        let token =
+            serde_json::from_value::<TokenV3>(json).map_err(|_| MutinyError::InvalidCashuToken)?;
+        if token.tokens.is_empty() {
+            return Err(MutinyError::InvalidCashuToken);
+        }
+
+        Ok(Self { token, unit, memo })
+    }
+}
+
+fn decode_token_base64(data: &str) -> Result<Vec<u8>, MutinyError> {
+    // tokens are meant to be url safe but padding and the alphabet vary by wallet
+    let data = data.trim_end_matches('=');
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(data, base64::STANDARD_NO_PAD))
+        .map_err(|_| MutinyError::InvalidCashuToken)
+}
+
+fn cbor_get<'a>(map: &'a [(CborValue, CborValue)], key: &str) -> Option<&'a CborValue> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+/// Converts a V4 CBOR token into the equivalent V3 json
+fn token_v4_to_json(bytes: &[u8]) -> Result<Value, MutinyError> {
+    let invalid = || MutinyError::InvalidCashuToken;
+
+    let cbor: CborValue = ciborium::de::from_reader(bytes).map_err(|_| invalid())?;
+    let token = cbor.as_map().ok_or_else(invalid)?;
+
+    let mint = cbor_get(token, "m")
+        .and_then(CborValue::as_text)
+        .ok_or_else(invalid)?;
+    let unit = cbor_get(token, "u").and_then(CborValue::as_text);
+    let memo = cbor_get(token, "d").and_then(CborValue::as_text);
+
+    let mut proofs = vec![];
+    let keysets = cbor_get(token, "t")
+        .and_then(CborValue::as_array)
+        .ok_or_else(invalid)?;
+    for keyset in keysets {
+        let keyset = keyset.as_map().ok_or_else(invalid)?;
+        let id = cbor_get(keyset, "i")
+            .and_then(CborValue::as_bytes)
+            .ok_or_else(invalid)?
+            .to_lower_hex_string();
+
+        let keyset_proofs = cbor_get(keyset, "p")
+            .and_then(CborValue::as_array)
+            .ok_or_else(invalid)?;
+        for proof in keyset_proofs {
+            let proof = proof.as_map().ok_or_else(invalid)?;
+            let amount = cbor_get(proof, "a")
+                .and_then(CborValue::as_integer)
+                .and_then(|a| u64::try_from(a).ok())
+                .ok_or_else(invalid)?;
+            let secret = cbor_get(proof, "s")
+                .and_then(CborValue::as_text)
+                .ok_or_else(invalid)?;
+            let c = cbor_get(proof, "c")
+                .and_then(CborValue::as_bytes)
+                .ok_or_else(invalid)?
+                .to_lower_hex_string();
+
+            proofs.push(json!({
+                "amount": amount,
+                "id": id,
+                "secret": secret,
+                "C": c,
+            }));
+        }
+    }
+
+    Ok(json!({
+        "token": [{ "mint": mint, "proofs": proofs }],
+        "unit": unit,
+        "memo": memo,
+    }))
+}
+
+/// A Cashu ecash wallet, holds proofs from any number of mints.
+pub struct CashuWallet<S: MutinyStorage> {
+    client: CashuHttpClient,
+    storage: S,
+    // held while we are changing the proofs we have stored
+    proofs_lock: Mutex<()>,
+    mint_info: RwLock<HashMap<Url, MintInfo>>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<S: MutinyStorage> CashuWallet<S> {
+    pub fn new(storage: S, logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            client: CashuHttpClient::new(),
+            storage,
+            proofs_lock: Mutex::new(()),
+            mint_info: RwLock::new(HashMap::new()),
+            logger,
+        }
+    }
+
+    /// Total balance across all of our mints
+    pub fn balance(&self) -> Result<u64, MutinyError> {
+        Ok(list_cashu_proofs(&self.storage)?
+            .iter()
+            .map(|p| p.total_amount())
+            .sum())
+    }
+
+    pub fn list_balances(&self) -> Result<Vec<CashuMintBalance>, MutinyError> {
+        Ok(list_cashu_proofs(&self.storage)?
+            .into_iter()
+            .map(|p| CashuMintBalance {
+                balance: p.total_amount(),
+                mint_url: p.mint_url,
+            })
+            .collect())
+    }
+
+    /// Gets what a mint supports, cached after the first time we ask
+    pub async fn get_mint_info(&self, mint_url: &Url) -> Result<MintInfo, MutinyError> {
+        if let Some(info) = self.mint_info.read().await.get(mint_url) {
+            return Ok(info.clone());
+        }
+
+        let info = self.client.get_info(mint_url).await?;
+        self.mint_info
+            .write()
+            .await
+            .insert(mint_url.clone(), info.clone());
+        Ok(info)
+    }
+
+    /// Forgets what we know about a mint so its info is fetched again
+    pub(crate) async fn invalidate_mint_info(&self, mint_url: &Url) {
+        self.mint_info.write().await.remove(mint_url);
+    }
+
+    pub fn list_mint_quotes(&self) -> Result<Vec<CashuMintQuote>, MutinyError> {
+        list_cashu_mint_quotes(&self.storage)
+    }
+
+    /// Asks the mint for an invoice that will mint us `amount` sats of ecash once paid
+    pub async fn create_mint_quote(
+        &self,
+        mint_url: Url,
+        amount: u64,
+    ) -> Result<CashuMintQuote, MutinyError> {
+        log_trace!(self.logger, "calling create_mint_quote");
+
+        let request = PostMintQuoteBolt11Request {
+            amount,
+            unit: CurrencyUnit::Sat,
+        };
+        let res = self
+            .client
+            .post_mint_quote_bolt11(&mint_url, request)
+            .await?;
+
+        let bolt11 = Bolt11Invoice::from_str(&res.payment_request)
+            .map_err(|_| MutinyError::CashuMintError)?;
+        if bolt11.amount_milli_satoshis() != Some(amount * 1_000) {
+            log_warn!(self.logger, "mint gave us an invoice for the wrong amount");
+            return Err(MutinyError::CashuMintError);
+        }
+
+        let quote = CashuMintQuote {
+            mint_url,
+            quote: res.quote,
+            amount,
+            bolt11,
+            state: MintQuoteState::Unpaid,
+            created_at: utils::now().as_secs(),
+        };
+        persist_cashu_mint_quote(&self.storage, &quote)?;
+
+        log_trace!(self.logger, "finished calling create_mint_quote");
+        Ok(quote)
+    }
+
+    /// Mints the ecash for a quote if its invoice has been paid.
+    /// Returns the quote with its updated state.
+    pub async fn mint(&self, quote_id: &str) -> Result<CashuMintQuote, MutinyError> {
+        log_trace!(self.logger, "calling mint");
+
+        let mut quote =
+            get_cashu_mint_quote(&self.storage, quote_id)?.ok_or(MutinyError::NotFound)?;
+        if quote.state == MintQuoteState::Issued {
+            return Ok(quote);
+        }
+
+        let res = self
+            .client
+            .get_mint_quote_bolt11(&quote.mint_url, &quote.quote)
+            .await?;
+        if !res.paid {
+            return Ok(quote);
+        }
+        quote.state = MintQuoteState::Paid;
+        persist_cashu_mint_quote(&self.storage, &quote)?;
+
+        let keyset = self.get_active_keyset(&quote.mint_url).await?;
+        let mint_url = &quote.mint_url;
+        let quote_id = quote.quote.clone();
+        let proofs = self
+            .sign_outputs(
+                &keyset,
+                split_amount(quote.amount),
+                false,
+                |outputs| async move {
+                    let request = PostMintBolt11Request {
+                        quote: quote_id,
+                        outputs,
+                    };
+                    let res = self.client.post_mint_bolt11(mint_url, request).await?;
+                    Ok(res.signatures)
+                },
+            )
+            .await?;
+        self.add_proofs(&quote.mint_url, proofs).await?;
+
+        quote.state = MintQuoteState::Issued;
+        persist_cashu_mint_quote(&self.storage, &quote)?;
+
+        log_debug!(
+            self.logger,
+            "minted {} sats from {}",
+            quote.amount,
+            quote.mint_url
+        );
+        log_trace!(self.logger, "finished calling mint");
+        Ok(quote)
+    }
+
+    /// Claims a token by swapping its proofs for new ones only we know.
+    /// Returns the amount received.
+    pub async fn receive(&self, token: CashuToken) -> Result<u64, MutinyError> {
+        log_trace!(self.logger, "calling receive");
+
+        if !token.is_sat() {
+            log_warn!(self.logger, "can't receive {} tokens", token.unit());
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        let token_v3 = token.token;
+
+        let mut received = 0;
+        for token in token_v3.tokens {
+            let mint_url = token.mint.ok_or(MutinyError::EmptyMintURLError)?;
+            let amount = token.proofs.total_amount();
+
+            let keyset = self.get_active_keyset(&mint_url).await?;
+            let url = &mint_url;
+            let proofs = self
+                .sign_outputs(&keyset, split_amount(amount), false, |outputs| async move {
+                    self.swap(url, token.proofs, outputs).await
+                })
+                .await?;
+            self.add_proofs(&mint_url, proofs).await?;
+
+            received += amount;
+        }
+
+        log_trace!(self.logger, "finished calling receive");
+        Ok(received)
+    }
+
+    /// Creates a token worth `amount` sats from our proofs at the given mint.
+    /// The proofs are kept as a pending send until the recipient claims
+    /// them, so they can be reclaimed with `reclaim_pending_send`.
+    pub async fn send(&self, mint_url: Url, amount: u64) -> Result<TokenV3, MutinyError> {
+        log_trace!(self.logger, "calling send");
+
+        let proofs = self.take_proofs(&mint_url, amount).await?;
+        let pending = CashuPendingSend {
+            id: Uuid::new_v4().to_string(),
+            mint_url: mint_url.clone(),
+            proofs: proofs.clone(),
+            amount,
+            state: PendingSendState::Pending,
+            created_at: utils::now().as_secs(),
+            version: 0,
+        };
+        if let Err(e) = persist_cashu_pending_send(&self.storage, &pending).await {
+            log_warn!(self.logger, "could not save pending send: {e}");
+            self.add_proofs(&mint_url, proofs).await?;
+            return Err(e);
+        }
+
+        let token = TokenV3::new(Token {
+            mint: Some(mint_url),
+            proofs: Proofs::new(proofs),
+        });
+
+        log_trace!(self.logger, "finished calling send");
+        Ok(token)
+    }
+
+    pub fn list_pending_sends(&self) -> Result<Vec<CashuPendingSend>, MutinyError> {
+        list_cashu_pending_sends(&self.storage)
+    }
+
+    /// Takes the ecash of a token we sent back into our wallet,
+    /// fails if the recipient already claimed it. Returns the amount reclaimed.
+    pub async fn reclaim_pending_send(&self, id: &str) -> Result<u64, MutinyError> {
+        log_trace!(self.logger, "calling reclaim_pending_send");
+
+        let mut pending =
+            get_cashu_pending_send(&self.storage, id)?.ok_or(MutinyError::NotFound)?;
+        if pending.state != PendingSendState::Pending {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let keyset = self.get_active_keyset(&pending.mint_url).await?;
+        let url = &pending.mint_url;
+        let inputs = Proofs::new(pending.proofs.clone());
+        let proofs = self
+            .sign_outputs(
+                &keyset,
+                split_amount(pending.amount),
+                false,
+                |outputs| async move { self.swap(url, inputs, outputs).await },
+            )
+            .await?;
+        self.add_proofs(&pending.mint_url, proofs).await?;
+
+        pending.state = PendingSendState::Reclaimed;
+        pending.version += 1;
+        persist_cashu_pending_send(&self.storage, &pending).await?;
+
+        log_trace!(self.logger, "finished calling reclaim_pending_send");
+        Ok(pending.amount)
+    }
+
+    /// Pays a lightning invoice with ecash from the given mint.
+    /// Returns the unused fee reserve that came back to us as change.
+    pub async fn melt(&self, mint_url: &Url, invoice: &Bolt11Invoice) -> Result<u64, MutinyError> {
+        log_trace!(self.logger, "calling melt");
+
+        let quote = self.melt_quote(mint_url, invoice).await?;
+        let inputs = self
+            .take_proofs(mint_url, quote.amount + quote.fee_reserve)
+            .await?;
+
+        // give the proofs back if the payment didn't go through,
+        // check_proofs will clean them up if the mint did spend them
+        match self.melt_proofs(mint_url, &quote, inputs.clone()).await {
+            Ok(change) => {
+                log_trace!(self.logger, "finished calling melt");
+                Ok(change)
+            }
+            Err(e) => {
+                self.add_proofs(mint_url, inputs).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Asks the mint how much it needs to pay the given invoice
+    pub(crate) async fn melt_quote(
+        &self,
+        mint_url: &Url,
+        invoice: &Bolt11Invoice,
+    ) -> Result<PostMeltQuoteBolt11Response, MutinyError> {
+        let quote_request = PostMeltQuoteBolt11Request {
+            request: invoice.to_string(),
+            unit: CurrencyUnit::Sat,
+        };
+        self.client
+            .post_melt_quote_bolt11(mint_url, quote_request)
+            .await
+    }
+
+    /// Asks the mint what fee reserve it needs to pay out `amount` sats.
+    ///
+    /// Mints only quote invoices, so this quotes one signed with a throwaway
+    /// key that is never paid, rather than leaving an invoice of ours unused.
+    pub(crate) async fn quote_fee_reserve(
+        &self,
+        mint_url: &Url,
+        amount: u64,
+        network: Network,
+    ) -> Result<u64, MutinyError> {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&rand::random::<[u8; 32]>())
+            .map_err(|_| MutinyError::InvoiceCreationFailed)?;
+        let invoice = InvoiceBuilder::new(network.into())
+            .description(String::new())
+            .payment_hash(sha256::Hash::hash(&rand::random::<[u8; 32]>()))
+            .payment_secret(PaymentSecret(rand::random()))
+            .duration_since_epoch(utils::now())
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(amount * 1_000)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &key))
+            .map_err(|_| MutinyError::InvoiceCreationFailed)?;
+
+        Ok(self.melt_quote(mint_url, &invoice).await?.fee_reserve)
+    }
+
+    /// Pays a melt quote with the given proofs, they do not need to be ours.
+    /// Blank outputs are passed along so any fee reserve the mint does not use
+    /// comes back to our wallet as change (NUT-08). Returns the change amount.
+    pub(crate) async fn melt_proofs(
+        &self,
+        mint_url: &Url,
+        quote: &PostMeltQuoteBolt11Response,
+        inputs: Vec<Proof>,
+    ) -> Result<u64, MutinyError> {
+        // the info endpoint being down shouldn't stop us from trying the melt
+        let info = match self.get_mint_info(mint_url).await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                log_warn!(self.logger, "could not get info for mint {mint_url}: {e}");
+                None
+            }
+        };
+        if info.as_ref().is_some_and(|i| !i.supports_nut(NUT_MELT)) {
+            log_warn!(self.logger, "mint {mint_url} does not support melting");
+            return Err(MutinyError::CashuMintUnsupported);
+        }
+
+        // without NUT-08 the mint keeps any unused fee reserve,
+        // when we don't know we offer the outputs anyway
+        let blank_outputs = if info.map_or(true, |i| i.supports_nut(NUT_FEE_RETURN)) {
+            vec![1; blank_outputs_count(quote.fee_reserve)]
+        } else {
+            vec![]
+        };
+        let keyset = self.get_active_keyset(mint_url).await?;
+        let quote_id = quote.quote.clone();
+        let paid = AtomicBool::new(false);
+        let paid_ref = &paid;
+        let change = self
+            .sign_outputs(&keyset, blank_outputs, true, |outputs| async move {
+                let melt_request = PostMeltBolt11Request {
+                    quote: quote_id,
+                    inputs: Proofs::new(inputs),
+                    outputs,
+                };
+                let res = self.client.post_melt_bolt11(mint_url, melt_request).await?;
+                if !res.paid {
+                    return Err(MutinyError::RoutingFailed);
+                }
+                paid_ref.store(true, Ordering::Relaxed);
+                Ok(res.change)
+            })
+            .await;
+
+        // once the mint paid the invoice only the change can be lost
+        let change = match change {
+            Ok(change) => change,
+            Err(e) if paid.load(Ordering::Relaxed) => {
+                log_warn!(
+                    self.logger,
+                    "could not unblind melt change from {mint_url}: {e}"
+                );
+                return Ok(0);
+            }
+            Err(e) => {
+                // what the mint supports may have changed since we cached it
+                self.invalidate_mint_info(mint_url).await;
+                return Err(e);
+            }
+        };
+
+        let change_amount = change.iter().map(|p| p.amount).sum();
+        if !change.is_empty() {
+            self.add_proofs(mint_url, change).await?;
+        }
+
+        Ok(change_amount)
+    }
+
+    /// Moves `amount` sats of ecash from one mint to another over lightning
+    pub async fn transfer(
+        &self,
+        from: &Url,
+        to: Url,
+        amount: u64,
+    ) -> Result<CashuMintQuote, MutinyError> {
+        log_trace!(self.logger, "calling transfer");
+
+        let quote = self.create_mint_quote(to, amount).await?;
+        self.melt(from, &quote.bolt11).await?;
+        let quote = self.mint(&quote.quote).await?;
+
+        log_trace!(self.logger, "finished calling transfer");
+        Ok(quote)
+    }
+
+    /// Asks each mint which of our proofs have been spent and removes them,
+    /// and which of the tokens we sent have been claimed.
+    /// Returns the amount that was removed.
+    pub async fn check_proofs(&self) -> Result<u64, MutinyError> {
+        log_trace!(self.logger, "calling check_proofs");
+
+        let _lock = self.proofs_lock.lock().await;
+        let mut removed = 0;
+        for mut stored in list_cashu_proofs(&self.storage)? {
+            if stored.proofs.is_empty() {
+                continue;
+            }
+            match self.get_mint_info(&stored.mint_url).await {
+                Ok(info) if info.supports_nut(NUT_CHECK_STATE) => (),
+                Ok(_) => {
+                    log_debug!(
+                        self.logger,
+                        "mint {} can't check proof states",
+                        stored.mint_url
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    log_warn!(self.logger, "could not reach {}: {e}", stored.mint_url);
+                    continue;
+                }
+            }
+
+            let ys = proof_ys(&stored.proofs)?;
+            let res = self
+                .client
+                .post_check_state(&stored.mint_url, PostCheckStateRequest { ys: ys.clone() })
+                .await?;
+            let spent: Vec<&String> = res
+                .states
+                .iter()
+                .filter(|s| s.state == ProofSpendState::Spent)
+                .map(|s| &s.y)
+                .collect();
+            if spent.is_empty() {
+                continue;
+            }
+
+            let before = stored.total_amount();
+            stored.proofs = stored
+                .proofs
+                .into_iter()
+                .zip(ys.iter())
+                .filter(|(_, y)| !spent.contains(y))
+                .map(|(p, _)| p)
+                .collect();
+            removed += before - stored.total_amount();
+            stored.version += 1;
+            persist_cashu_proofs(&self.storage, &stored).await?;
+        }
+
+        for mut pending in list_cashu_pending_sends(&self.storage)?
+            .into_iter()
+            .filter(|p| p.state == PendingSendState::Pending)
+        {
+            match self.get_mint_info(&pending.mint_url).await {
+                Ok(info) if info.supports_nut(NUT_CHECK_STATE) => (),
+                Ok(_) => continue,
+                Err(e) => {
+                    log_warn!(self.logger, "could not reach {}: {e}", pending.mint_url);
+                    continue;
+                }
+            }
+
+            let ys = proof_ys(&pending.proofs)?;
+            let res = match self
+                .client
+                .post_check_state(&pending.mint_url, PostCheckStateRequest { ys })
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    log_warn!(self.logger, "could not check pending send: {e}");
+                    continue;
+                }
+            };
+            if res.all_spent() {
+                pending.state = PendingSendState::Claimed;
+                pending.version += 1;
+                persist_cashu_pending_send(&self.storage, &pending).await?;
+            }
+        }
+
+        log_trace!(self.logger, "finished calling check_proofs");
+        Ok(removed)
+    }
+
+    async fn get_active_keyset(&self, mint_url: &Url) -> Result<KeyResponse, MutinyError> {
+        let keys = self.client.get_keys(mint_url).await?;
+        keys.keysets
+            .into_iter()
+            .find(|k| matches!(k.unit, CurrencyUnit::Sat))
+            .ok_or(MutinyError::CashuMintError)
+    }
+
+    async fn swap(
+        &self,
+        mint_url: &Url,
+        inputs: Proofs,
+        outputs: Vec<BlindedMessage>,
+    ) -> Result<Vec<BlindedSignature>, MutinyError> {
+        let res = self
+            .client
+            .post_swap(mint_url, PostSwapRequest { inputs, outputs })
+            .await?;
+        Ok(res.signatures)
+    }
+
+    /// Blinds new secrets for the given amounts, has the mint sign them
+    /// with `request` and unblinds the signatures into proofs.
+    /// `blank` outputs are ones the mint sets the amount of (NUT-08).
+    async fn sign_outputs<F, Fut>(
+        &self,
+        keyset: &KeyResponse,
+        amounts: Vec<u64>,
+        blank: bool,
+        request: F,
+    ) -> Result<Vec<Proof>, MutinyError>
+    where
+        F: FnOnce(Vec<BlindedMessage>) -> Fut,
+        Fut: Future<Output = Result<Vec<BlindedSignature>, MutinyError>>,
+    {
+        let dhke = Dhke::new();
+
+        let mut secrets = Vec::with_capacity(amounts.len());
+        let mut outputs = Vec::with_capacity(amounts.len());
+        for amount in amounts {
+            let secret = generate_secret();
+            let (b_, r) = dhke
+                .step1_alice(secret.clone(), None)
+                .map_err(|_| MutinyError::CashuMintError)?;
+            outputs.push(BlindedMessage {
+                amount,
+                b_,
+                id: keyset.id.clone(),
+            });
+            secrets.push(Some((secret, r)));
+        }
+
+        let signatures = request(outputs.clone()).await?;
+        let matched = match_signatures(&outputs, &signatures, blank)?;
+
+        signatures
+            .into_iter()
+            .zip(matched)
+            .map(|(sig, i)| {
+                let (secret, r) = secrets[i].take().ok_or(MutinyError::CashuMintError)?;
+                let a = keyset
+                    .keys
+                    .get(&sig.amount)
+                    .ok_or(MutinyError::CashuMintError)?;
+                let c = dhke
+                    .step3_alice(sig.c_, r, *a)
+                    .map_err(|_| MutinyError::CashuMintError)?;
+                Ok(Proof::new(sig.amount, secret, c, sig.id))
+            })
+            .collect()
+    }
+
+    async fn add_proofs(&self, mint_url: &Url, proofs: Vec<Proof>) -> Result<(), MutinyError> {
+        let _lock = self.proofs_lock.lock().await;
+        let mut stored = get_cashu_proofs(&self.storage, mint_url)?.unwrap_or(CashuProofs {
+            mint_url: mint_url.clone(),
+            proofs: vec![],
+            version: 0,
+        });
+        stored.proofs.extend(proofs);
+        stored.version += 1;
+        persist_cashu_proofs(&self.storage, &stored).await
+    }
+
+    /// Removes proofs worth exactly `amount` from our wallet, swapping
+    /// for change with the mint if we don't have the exact amount.
+    async fn take_proofs(&self, mint_url: &Url, amount: u64) -> Result<Vec<Proof>, MutinyError> {
+        let _lock = self.proofs_lock.lock().await;
+        let mut stored =
+            get_cashu_proofs(&self.storage, mint_url)?.ok_or(MutinyError::InsufficientBalance)?;
+
+        let amounts: Vec<u64> = stored.proofs.iter().map(|p| p.amount).collect();
+        let selected = select_amounts(&amounts, amount).ok_or(MutinyError::InsufficientBalance)?;
+
+        let mut inputs = Vec::with_capacity(selected.len());
+        let mut keep = Vec::with_capacity(stored.proofs.len() - selected.len());
+        for (i, proof) in stored.proofs.into_iter().enumerate() {
+            if selected.contains(&i) {
+                inputs.push(proof);
+            } else {
+                keep.push(proof);
+            }
+        }
+        let total: u64 = inputs.iter().map(|p| p.amount).sum();
+
+        let proofs = if total == amount {
+            inputs
+        } else {
+            let send_amounts = split_amount(amount);
+            let num_send = send_amounts.len();
+            let mut amounts = send_amounts;
+            amounts.extend(split_amount(total - amount));
+
+            let keyset = self.get_active_keyset(mint_url).await?;
+            let mut proofs = self
+                .sign_outputs(&keyset, amounts, false, |outputs| async move {
+                    self.swap(mint_url, Proofs::new(inputs), outputs).await
+                })
+                .await?;
+            keep.extend(proofs.split_off(num_send));
+            proofs
+        };
+
+        stored.proofs = keep;
+        stored.version += 1;
+        persist_cashu_proofs(&self.storage, &stored).await?;
+
+        Ok(proofs)
+    }
+}
+
+/// Finds which of our outputs each signature is for.
+/// Signatures are matched on keyset id and amount. Blank outputs (NUT-08)
+/// are given their amount by the mint, so those are matched in order.
+/// The mint may sign fewer outputs than we give it, like with blank
+/// outputs for change, in which case the unsigned ones are dropped.
+pub(crate) fn match_signatures(
+    outputs: &[BlindedMessage],
+    signatures: &[BlindedSignature],
+    blank: bool,
+) -> Result<Vec<usize>, MutinyError> {
+    if signatures.len() > outputs.len() {
+        return Err(MutinyError::CashuMintError);
+    }
+
+    let mut used = vec![false; outputs.len()];
+    signatures
+        .iter()
+        .map(|sig| {
+            let i = outputs
+                .iter()
+                .enumerate()
+                .position(|(i, o)| !used[i] && o.id == sig.id && (blank || o.amount == sig.amount))
+                .ok_or(MutinyError::CashuMintError)?;
+            used[i] = true;
+            Ok(i)
+        })
+        .collect()
+}
+
+// The Y values the mint identifies proofs by when checking their state (NUT-07)
+fn proof_ys(proofs: &[Proof]) -> Result<Vec<String>, MutinyError> {
+    proofs
+        .iter()
+        .map(|p| hash_to_curve(p.secret.as_bytes()).map(|y| y.to_string()))
+        .collect()
+}
+
+/// Splits an amount into the power of two denominations mints sign
+pub(crate) fn split_amount(amount: u64) -> Vec<u64> {
+    (0..64)
+        .map(|bit| 1 << bit)
+        .filter(|denomination| amount & denomination != 0)
+        .collect()
+}
+
+/// Picks which of the given amounts to spend to cover `target`,
+/// preferring an exact match so we don't need to swap for change,
+/// then whatever leaves the least change.
+/// Returns `None` if the amounts are not enough.
+pub(crate) fn select_amounts(amounts: &[u64], target: u64) -> Option<Vec<usize>> {
+    let mut sorted: Vec<usize> = (0..amounts.len()).collect();
+    sorted.sort_by(|a, b| amounts[*b].cmp(&amounts[*a]));
+
+    // take the largest amounts that fit
+    let mut selected = vec![];
+    let mut total = 0;
+    for i in sorted.iter() {
+        if total + amounts[*i] <= target {
+            selected.push(*i);
+            total += amounts[*i];
+        }
+        if total == target {
+            return Some(selected);
+        }
+    }
+
+    // the smallest single amount that covers everything
+    let single = sorted
+        .iter()
+        .rev()
+        .find(|i| amounts[**i] >= target)
+        .copied();
+
+    // or cover the rest with the smallest amount that is big enough
+    let remaining = target - total;
+    let combined = match sorted
+        .iter()
+        .rev()
+        .find(|i| !selected.contains(i) && amounts[**i] >= remaining)
+    {
+        Some(i) => {
+            selected.push(*i);
+            Some(selected)
+        }
+        // or as many small amounts as it takes
+        None => {
+            let unselected: Vec<usize> = sorted
+                .iter()
+                .rev()
+                .filter(|i| !selected.contains(i))
+                .copied()
+                .collect();
+            unselected.into_iter().find_map(|i| {
+                selected.push(i);
+                total += amounts[i];
+                (total >= target).then(|| selected.clone())
+            })
+        }
+    };
+
+    let sum = |selected: &[usize]| selected.iter().map(|i| amounts[*i]).sum::<u64>();
+    match (single, combined) {
+        (Some(i), Some(combined)) if sum(&combined) < amounts[i] => Some(combined),
+        (Some(i), _) => Some(vec![i]),
+        (None, combined) => combined,
+    }
+}
+
+/// How many blank outputs to give the mint so it can return any
+/// amount of unused fee reserve as change, per NUT-08
+pub(crate) fn blank_outputs_count(fee_reserve: u64) -> usize {
+    if fee_reserve == 0 {
+        return 0;
+    }
+    // ceil(log2(fee_reserve)), at least 1
+    (64 - (fee_reserve - 1).leading_zeros()).max(1) as usize
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate secret");
+    bytes.to_lower_hex_string()
+}
+
+/// Maps a proof's secret to the point `Y` mints use to track whether it is spent (NUT-00)
+pub(crate) fn hash_to_curve(message: &[u8]) -> Result<PublicKey, MutinyError> {
+    let msg_hash =
+        sha256::Hash::hash(&[HASH_TO_CURVE_DOMAIN_SEPARATOR, message].concat()).to_byte_array();
+
+    for counter in 0..(1u32 << 16) {
+        let hash =
+            sha256::Hash::hash(&[&msg_hash[..], &counter.to_le_bytes()].concat()).to_byte_array();
+        if let Ok(point) = PublicKey::from_slice(&[&[0x02], &hash[..]].concat()) {
+            return Ok(point);
+        }
+    }
+
+    Err(MutinyError::CashuMintError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_conservative::FromHex;
+
+    #[test]
+    fn test_hash_to_curve() {
+        let cases = [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "022e7158e11c9506f1aa4248bf531298daa7febd6194f003edcd9b93ade6253acf",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "026cdbe15362df59cd1dd3c9c11de8aedac2106eca69236ecd9fbe117af897be4f",
+            ),
+        ];
+
+        for (message, expected) in cases {
+            let message = Vec::<u8>::from_hex(message).unwrap();
+            let y = hash_to_curve(&message).unwrap();
+            assert_eq!(y.to_string(), expected);
+        }
+    }
+
+    const TOKEN_V3: &str = "cashuAeyJ0b2tlbiI6W3sibWludCI6Imh0dHBzOi8vODMzMy5zcGFjZTozMzM4IiwicHJvb2ZzIjpbeyJhbW91bnQiOjIsImlkIjoiMDA5YTFmMjkzMjUzZTQxZSIsInNlY3JldCI6IjQwNzkxNWJjMjEyYmU2MWE3N2UzZTZkMmFlYjRjNzI3OTgwYmRhNTFjZDA2YTZhZmMyOWUyODYxNzY4YTc4MzciLCJDIjoiMDJiYzkwOTc5OTdkODFhZmIyY2M3MzQ2YjVlNDM0NWE5MzQ2YmQyYTUwNmViNzk1ODU5OGE3MmYwY2Y4NTE2M2VhIn0seyJhbW91bnQiOjgsImlkIjoiMDA5YTFmMjkzMjUzZTQxZSIsInNlY3JldCI6ImZlMTUxMDkzMTRlNjFkNzc1NmIwZjhlZTBmMjNhNjI0YWNhYTNmNGUwNDJmNjE0MzNjNzI4YzcwNTdiOTMxYmUiLCJDIjoiMDI5ZThlNTA1MGI4OTBhN2Q2YzA5NjhkYjE2YmMxZDVkNWZhMDQwZWExZGUyODRmNmVjNjlkNjEyOTlmNjcxMDU5In1dfV0sInVuaXQiOiJzYXQiLCJtZW1vIjoiVGhhbmsgeW91LiJ9";
+    const TOKEN_V4: &str = "cashuBpGF0gaJhaUgArSaMTR9YJmFwgaNhYQFhc3hAOWE2ZGJiODQ3YmQyMzJiYTc2ZGIwZGYxOTcyMTZiMjlkM2I4Y2MxNDU1M2NkMjc4MjdmYzFjYzk0MmZlZGI0ZWFjWCEDhhhUP_trhpXfStS6vN6So0qWvc2X3O4NfM-Y1HISZ5JhZGlUaGFuayB5b3VhbXVodHRwOi8vbG9jYWxob3N0OjMzMzhhdWNzYXQ=";
+
+    #[test]
+    fn test_parse_token_v3() {
+        let token = CashuToken::from_str(TOKEN_V3).unwrap();
+        assert!(token.is_sat());
+        assert_eq!(token.memo.as_deref(), Some("Thank you."));
+
+        let preview = token.preview();
+        assert_eq!(preview.amount, 10);
+        assert_eq!(preview.mints.len(), 1);
+        assert_eq!(preview.mints[0].proofs, 2);
+        assert_eq!(
+            preview.mints[0].mint_url,
+            Some(Url::parse("https://8333.space:3338").unwrap())
+        );
+
+        // uri prefix is allowed
+        let token = CashuToken::from_str(&format!("cashu:{TOKEN_V3}")).unwrap();
+        assert_eq!(token.amount(), 10);
+    }
+
+    #[test]
+    fn test_parse_token_v4() {
+        let token = CashuToken::from_str(TOKEN_V4).unwrap();
+        assert_eq!(token.unit(), "sat");
+        assert_eq!(token.memo.as_deref(), Some("Thank you"));
+
+        let preview = token.preview();
+        assert_eq!(preview.amount, 1);
+        assert_eq!(
+            preview.mints[0].mint_url,
+            Some(Url::parse("http://localhost:3338").unwrap())
+        );
+
+        let proof = serde_json::to_value(&token.token.tokens[0].proofs.proofs()[0]).unwrap();
+        assert_eq!(proof["id"], "00ad268c4d1f5826");
+        assert_eq!(
+            proof["secret"],
+            "9a6dbb847bd232ba76db0df197216b29d3b8cc14553cd27827fc1cc942fedb4e"
+        );
+        assert_eq!(
+            proof["C"],
+            "038618543ffb6b8695df4ad4babcde92a34a96bdcd97dcee0d7ccf98d472126792"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_token() {
+        assert!(CashuToken::from_str("cashuA").is_err());
+        assert!(CashuToken::from_str("cashuBnotcbor").is_err());
+        assert!(CashuToken::from_str("lnbc1").is_err());
+    }
+
+    #[test]
+    fn test_mint_info_supports_nut() {
+        let info: MintInfo = serde_json::from_value(json!({
+            "name": "test mint",
+            "nuts": {
+                "4": { "methods": [], "disabled": false },
+                "5": { "methods": [], "disabled": true },
+                "7": { "supported": true },
+                "8": { "supported": false },
+            }
+        }))
+        .unwrap();
+
+        assert!(info.supports_nut(4));
+        assert!(!info.supports_nut(NUT_MELT));
+        assert!(info.supports_nut(NUT_CHECK_STATE));
+        assert!(!info.supports_nut(NUT_FEE_RETURN));
+        assert!(!info.supports_nut(12));
+    }
+
+    #[test]
+    fn test_split_amount() {
+        assert_eq!(split_amount(0), Vec::<u64>::new());
+        assert_eq!(split_amount(1), vec![1]);
+        assert_eq!(split_amount(13), vec![1, 4, 8]);
+        assert_eq!(split_amount(64), vec![64]);
+        assert_eq!(split_amount(100).iter().sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn test_blank_outputs_count() {
+        assert_eq!(blank_outputs_count(0), 0);
+        assert_eq!(blank_outputs_count(1), 1);
+        assert_eq!(blank_outputs_count(2), 1);
+        assert_eq!(blank_outputs_count(3), 2);
+        assert_eq!(blank_outputs_count(1000), 10);
+        assert_eq!(blank_outputs_count(1024), 10);
+    }
+
+    #[test]
+    fn test_select_amounts() {
+        // exact match
+        let selected = select_amounts(&[1, 2, 4, 8], 6).unwrap();
+        assert_eq!(selected.len(), 2);
+        assert!(selected.contains(&1) && selected.contains(&2));
+
+        // smallest amount that covers it
+        let selected = select_amounts(&[64, 16, 32], 20).unwrap();
+        assert_eq!(selected, vec![2]);
+
+        // single larger amount
+        assert_eq!(select_amounts(&[64, 1], 3), Some(vec![0]));
+
+        // the largest amounts that fit, then the smallest amount that covers the rest
+        assert_eq!(select_amounts(&[64, 16, 8], 20), Some(vec![1, 2]));
+
+        // not enough
+        assert_eq!(select_amounts(&[1, 2], 4), None);
+        assert_eq!(select_amounts(&[], 1), None);
+    }
+
+    #[test]
+    fn test_match_signatures() {
+        let (b_, _) = Dhke::new().step1_alice(generate_secret(), None).unwrap();
+        let output = |amount: u64, id: &str| BlindedMessage {
+            amount,
+            b_,
+            id: id.to_string(),
+        };
+        let sig = |amount: u64, id: &str| BlindedSignature {
+            amount,
+            c_: b_,
+            id: id.to_string(),
+        };
+
+        // signatures are matched on amount even when out of order
+        let outputs = vec![output(1, "a"), output(2, "a"), output(4, "a")];
+        assert_eq!(
+            match_signatures(&outputs, &[sig(4, "a"), sig(1, "a"), sig(2, "a")], false),
+            Ok(vec![2, 0, 1])
+        );
+
+        // and on keyset id
+        let outputs = vec![output(2, "a"), output(2, "b")];
+        assert_eq!(
+            match_signatures(&outputs, &[sig(2, "b"), sig(2, "a")], false),
+            Ok(vec![1, 0])
+        );
+
+        // a signature for an amount we didn't ask for is rejected
+        assert_eq!(
+            match_signatures(&outputs, &[sig(8, "a")], false),
+            Err(MutinyError::CashuMintError)
+        );
+        // as are more signatures than outputs
+        assert_eq!(
+            match_signatures(&outputs, &[sig(2, "a"), sig(2, "b"), sig(2, "a")], false),
+            Err(MutinyError::CashuMintError)
+        );
+
+        // blank outputs get their amounts from the mint, in order
+        let outputs = vec![output(1, "a"), output(1, "a"), output(1, "a")];
+        assert_eq!(
+            match_signatures(&outputs, &[sig(8, "a"), sig(2, "a")], true),
+            Ok(vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn test_check_state_all_spent() {
+        let state = |state| ProofState {
+            y: String::new(),
+            state,
+        };
+        let res = |states| PostCheckStateResponse { states };
+
+        assert!(res(vec![state(ProofSpendState::Spent)]).all_spent());
+        assert!(!res(vec![
+            state(ProofSpendState::Spent),
+            state(ProofSpendState::Unspent)
+        ])
+        .all_spent());
+        assert!(!res(vec![state(ProofSpendState::Pending)]).all_spent());
+        assert!(!res(vec![]).all_spent());
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_mint_info() {
+        use crate::storage::MemoryStorage;
+
+        let wallet = CashuWallet::new(MemoryStorage::default(), Arc::new(MutinyLogger::default()));
+        let mint_url = Url::parse("https://8333.space:3338").unwrap();
+        wallet
+            .mint_info
+            .write()
+            .await
+            .insert(mint_url.clone(), MintInfo::default());
+        assert!(wallet.get_mint_info(&mint_url).await.is_ok());
+
+        wallet.invalidate_mint_info(&mint_url).await;
+        assert!(!wallet.mint_info.read().await.contains_key(&mint_url));
+    }
+
+    #[tokio::test]
+    async fn test_send_keeps_pending_send() {
+        use crate::storage::MemoryStorage;
+
+        let wallet = CashuWallet::new(MemoryStorage::default(), Arc::new(MutinyLogger::default()));
+        let token = CashuToken::from_str(TOKEN_V3).unwrap().token.tokens[0].clone();
+        let mint_url = token.mint.clone().unwrap();
+        wallet
+            .add_proofs(&mint_url, token.proofs.proofs())
+            .await
+            .unwrap();
+        assert_eq!(wallet.balance().unwrap(), 10);
+
+        // the exact amount doesn't need a swap with the mint
+        let sent = wallet.send(mint_url.clone(), 10).await.unwrap();
+        assert_eq!(wallet.balance().unwrap(), 0);
+
+        let pending = wallet.list_pending_sends().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].mint_url, mint_url);
+        assert_eq!(pending[0].amount, 10);
+        assert_eq!(pending[0].state, PendingSendState::Pending);
+        assert_eq!(
+            pending[0].proofs.len(),
+            sent.tokens[0].proofs.proofs().len()
+        );
+
+        // unknown sends can't be reclaimed
+        assert_eq!(
+            wallet.reclaim_pending_send("unknown").await,
+            Err(MutinyError::NotFound)
+        );
+    }
+}
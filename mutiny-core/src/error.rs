@@ -176,6 +176,12 @@ pub enum MutinyError {
     /// Token already spent.
     #[error("Token has been already spent.")]
     TokenAlreadySpent,
+    /// Cashu token could not be parsed.
+    #[error("Invalid cashu token.")]
+    InvalidCashuToken,
+    /// Cashu mint does not support what we need.
+    #[error("Cashu mint does not support this operation.")]
+    CashuMintUnsupported,
     /// Federation required.
     #[error("A federation is required")]
     FederationRequired,
@@ -271,6 +277,8 @@ impl PartialEq for MutinyError {
             (Self::CashuMintError, Self::CashuMintError) => true,
             (Self::EmptyMintURLError, Self::EmptyMintURLError) => true,
             (Self::TokenAlreadySpent, Self::TokenAlreadySpent) => true,
+            (Self::InvalidCashuToken, Self::InvalidCashuToken) => true,
+            (Self::CashuMintUnsupported, Self::CashuMintUnsupported) => true,
             (Self::FederationRequired, Self::FederationRequired) => true,
             (Self::FederationConnectionFailed, Self::FederationConnectionFailed) => true,
             (Self::FederationTxTooLarge, Self::FederationTxTooLarge) => true,
@@ -543,6 +551,12 @@ impl From<nostr::nips::nip04::Error> for MutinyError {
     }
 }
 
+impl From<nostr::nips::nip44::Error> for MutinyError {
+    fn from(_e: nostr::nips::nip44::Error) -> Self {
+        Self::NostrError
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 impl From<nostr::nips::nip07::Error> for MutinyError {
     fn from(_e: nostr::nips::nip07::Error) -> Self {
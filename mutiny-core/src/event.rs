@@ -1,8 +1,9 @@
+use crate::federation::FederationHealthWarning;
 use crate::ldkstorage::{MutinyNodePersister, PhantomChannelManager};
 use crate::logging::MutinyLogger;
 use crate::lsp::{AnyLsp, Lsp};
 use crate::node::BumpTxEventHandler;
-use crate::nodemanager::ChannelClosure;
+use crate::nodemanager::{ChannelAcceptancePolicy, ChannelClosure};
 use crate::onchain::OnChainWallet;
 use crate::storage::MutinyStorage;
 use crate::utils::sleep;
@@ -10,18 +11,25 @@ use crate::{fees::MutinyFeeEstimator, storage::read_payment_info, PrivacyLevel};
 use crate::{keymanager::PhantomKeysManager, storage::persist_payment_info};
 use anyhow::anyhow;
 use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::secp256k1::Secp256k1;
+use bitcoin::Txid;
 use core::fmt;
+use fedimint_core::config::FederationId;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use hex_conservative::DisplayHex;
 use lightning::events::{Event, PaymentPurpose};
 use lightning::sign::SpendableOutputDescriptor;
 use lightning::{
     log_debug, log_error, log_info, log_warn, util::errors::APIError, util::logger::Logger,
 };
 use lightning_invoice::Bolt11Invoice;
+use nostr::EventId;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct PaymentInfo {
@@ -89,6 +97,112 @@ impl FromStr for HTLCStatus {
     }
 }
 
+/// An update about the wallet, sent to subscribers of [`EventBroadcaster`]
+/// so they do not need to poll for changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum MutinyEvent {
+    /// We received a lightning payment
+    PaymentReceived {
+        payment_hash: sha256::Hash,
+        amount_msat: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        federation_id: Option<FederationId>,
+    },
+    /// A lightning payment we sent succeeded
+    PaymentSent {
+        payment_hash: sha256::Hash,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fee_paid_msat: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        federation_id: Option<FederationId>,
+    },
+    /// A lightning payment we sent failed
+    PaymentFailed {
+        payment_hash: sha256::Hash,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        federation_id: Option<FederationId>,
+    },
+    /// A channel is ready to be used
+    ChannelReady {
+        user_channel_id: String,
+        counterparty_node_id: PublicKey,
+    },
+    /// An inbound channel open request was rejected
+    ChannelRejected {
+        counterparty_node_id: PublicKey,
+        funding_satoshis: u64,
+        reason: String,
+    },
+    /// A channel was closed
+    ChannelClosed {
+        user_channel_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        counterparty_node_id: Option<PublicKey>,
+        reason: String,
+    },
+    /// An on-chain transaction was confirmed
+    TransactionConfirmed {
+        txid: Txid,
+        height: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        federation_id: Option<FederationId>,
+    },
+    /// A nostr wallet connect request was received
+    NwcRequest {
+        event_id: EventId,
+        client_pubkey: nostr::PublicKey,
+    },
+    /// A background health check found a problem with a federation
+    FederationHealthWarning {
+        federation_id: FederationId,
+        warning: FederationHealthWarning,
+    },
+}
+
+/// Sends [`MutinyEvent`]s to every subscriber.
+///
+/// Subscribers that have dropped their receiver are removed on the next event.
+#[derive(Clone, Default)]
+pub struct EventBroadcaster {
+    subscribers: Arc<Mutex<Vec<(u32, UnboundedSender<MutinyEvent>)>>>,
+    next_id: Arc<AtomicU32>,
+}
+
+impl EventBroadcaster {
+    /// Subscribes to all future events.
+    /// Returns the subscription id, used to unsubscribe, and the receiver of the events.
+    pub fn subscribe(&self) -> (u32, UnboundedReceiver<MutinyEvent>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = unbounded();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push((id, sender));
+        }
+        (id, receiver)
+    }
+
+    /// Ends a subscription, its receiver's stream ends once it has
+    /// received the events that were already sent.
+    pub fn unsubscribe(&self, id: u32) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|(sub_id, _)| *sub_id != id);
+        }
+    }
+
+    /// Ends every subscription
+    pub(crate) fn close(&self) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.clear();
+        }
+    }
+
+    pub(crate) fn broadcast(&self, event: MutinyEvent) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|(_, s)| s.unbounded_send(event.clone()).is_ok());
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct EventHandler<S: MutinyStorage> {
     channel_manager: Arc<PhantomChannelManager<S>>,
@@ -340,6 +454,14 @@ impl<S: MutinyStorage> EventHandler<S> {
                         }
                     }
                 }
+
+                self.wallet
+                    .event_broadcaster
+                    .broadcast(MutinyEvent::PaymentReceived {
+                        payment_hash: sha256::Hash::from_byte_array(payment_hash.0),
+                        amount_msat,
+                        federation_id: None,
+                    });
             }
             Event::PaymentSent {
                 payment_preimage,
@@ -381,10 +503,20 @@ impl<S: MutinyStorage> EventHandler<S> {
                         );
                     }
                 }
+
+                self.wallet
+                    .event_broadcaster
+                    .broadcast(MutinyEvent::PaymentSent {
+                        payment_hash: sha256::Hash::from_byte_array(payment_hash.0),
+                        fee_paid_msat,
+                        federation_id: None,
+                    });
             }
             Event::OpenChannelRequest {
                 temporary_channel_id,
                 counterparty_node_id,
+                funding_satoshis,
+                channel_type,
                 ..
             } => {
                 log_debug!(
@@ -411,24 +543,82 @@ impl<S: MutinyStorage> EventHandler<S> {
                     None => None,
                 };
 
-                if lsp_pubkey.as_ref() != Some(&counterparty_node_id) {
-                    // did not match the lsp pubkey, normal open
-                    let result = self.channel_manager.accept_inbound_channel(
-                        &temporary_channel_id,
-                        &counterparty_node_id,
-                        internal_channel_id,
-                    );
-                    log_result(result);
+                let policy = if lsp_pubkey.as_ref() == Some(&counterparty_node_id) {
+                    // matched lsp pubkey, always accept 0 conf
+                    None
                 } else {
-                    // matched lsp pubkey, accept 0 conf
-                    let result = self
-                        .channel_manager
+                    match self.persister.storage.get_channel_acceptance_policy() {
+                        Ok(policy) => Some(policy),
+                        Err(e) => {
+                            log_error!(
+                                self.logger,
+                                "EVENT: OpenChannelRequest could not read acceptance policy: {e}"
+                            );
+                            Some(ChannelAcceptancePolicy::default())
+                        }
+                    }
+                };
+
+                let zero_conf = match policy.as_ref().map(|p| {
+                    p.evaluate(
+                        &counterparty_node_id,
+                        funding_satoshis,
+                        channel_type.supports_anchors_zero_fee_htlc_tx(),
+                    )
+                }) {
+                    None => true,
+                    Some(Ok(zero_conf)) => zero_conf,
+                    Some(Err(reason)) => {
+                        log_warn!(
+                            self.logger,
+                            "EVENT: OpenChannelRequest from {counterparty_node_id} rejected: {reason}"
+                        );
+                        let result = self.channel_manager.force_close_without_broadcasting_txn(
+                            &temporary_channel_id,
+                            &counterparty_node_id,
+                        );
+                        log_result(result);
+                        self.wallet
+                            .event_broadcaster
+                            .broadcast(MutinyEvent::ChannelRejected {
+                                counterparty_node_id,
+                                funding_satoshis,
+                                reason: reason.to_string(),
+                            });
+                        return;
+                    }
+                };
+
+                let result = if zero_conf {
+                    self.channel_manager
                         .accept_inbound_channel_from_trusted_peer_0conf(
                             &temporary_channel_id,
                             &counterparty_node_id,
                             internal_channel_id,
+                        )
+                } else {
+                    self.channel_manager.accept_inbound_channel(
+                        &temporary_channel_id,
+                        &counterparty_node_id,
+                        internal_channel_id,
+                    )
+                };
+                match result {
+                    Ok(_) => log_debug!(self.logger, "EVENT: OpenChannelRequest accepted"),
+                    Err(e) => {
+                        // LDK refuses announced channels here if the policy is private only
+                        log_warn!(
+                            self.logger,
+                            "EVENT: OpenChannelRequest from {counterparty_node_id} could not be accepted: {e:?}"
                         );
-                    log_result(result);
+                        self.wallet
+                            .event_broadcaster
+                            .broadcast(MutinyEvent::ChannelRejected {
+                                counterparty_node_id,
+                                funding_satoshis,
+                                reason: api_error_reason(e),
+                            });
+                    }
                 }
             }
             Event::PaymentPathSuccessful { .. } => {
@@ -484,6 +674,13 @@ impl<S: MutinyStorage> EventHandler<S> {
                         );
                     }
                 }
+
+                self.wallet
+                    .event_broadcaster
+                    .broadcast(MutinyEvent::PaymentFailed {
+                        payment_hash: sha256::Hash::from_byte_array(payment_hash.0),
+                        federation_id: None,
+                    });
             }
             Event::PaymentForwarded { .. } => {
                 log_info!(self.logger, "EVENT: PaymentForwarded somehow...");
@@ -550,6 +747,12 @@ impl<S: MutinyStorage> EventHandler<S> {
                     reason
                 );
 
+                let event = MutinyEvent::ChannelClosed {
+                    user_channel_id: user_channel_id.to_be_bytes().to_lower_hex_string(),
+                    counterparty_node_id: node_id,
+                    reason: reason.to_string(),
+                };
+
                 let closure = ChannelClosure::new(user_channel_id, channel_id, node_id, reason);
                 if let Err(e) = self
                     .persister
@@ -557,6 +760,8 @@ impl<S: MutinyStorage> EventHandler<S> {
                 {
                     log_error!(self.logger, "Failed to persist channel closure: {e}");
                 }
+
+                self.wallet.event_broadcaster.broadcast(event);
             }
             Event::DiscardFunding { .. } => {
                 // A "real" node should probably "lock" the UTXOs spent in funding transactions until
@@ -576,6 +781,13 @@ impl<S: MutinyStorage> EventHandler<S> {
                     user_channel_id,
                     counterparty_node_id,
                     channel_type);
+
+                self.wallet
+                    .event_broadcaster
+                    .broadcast(MutinyEvent::ChannelReady {
+                        user_channel_id: user_channel_id.to_be_bytes().to_lower_hex_string(),
+                        counterparty_node_id,
+                    });
             }
             Event::ChannelPending {
                 channel_id,
@@ -684,10 +896,25 @@ impl<S: MutinyStorage> EventHandler<S> {
     }
 }
 
+/// The message from an LDK API error, without the variant name its `Debug` output adds
+fn api_error_reason(e: APIError) -> String {
+    match e {
+        APIError::APIMisuseError { err }
+        | APIError::FeeRateTooHigh { err, .. }
+        | APIError::InvalidRoute { err }
+        | APIError::ChannelUnavailable { err } => err,
+        APIError::MonitorUpdateInProgress => "Channel monitor update in progress".to_string(),
+        APIError::IncompatibleShutdownScript { script } => {
+            format!("Incompatible shutdown script: {script}")
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::event::{HTLCStatus, MillisatAmount, PaymentInfo};
+    use crate::event::{EventBroadcaster, HTLCStatus, MillisatAmount, MutinyEvent, PaymentInfo};
     use crate::{utils, PrivacyLevel};
+    use bitcoin::hashes::{sha256, Hash};
     use bitcoin::secp256k1::PublicKey;
     use std::str::FromStr;
 
@@ -722,4 +949,37 @@ mod test {
         let deserialized: PaymentInfo = serde_json::from_value(serialized).unwrap();
         assert_eq!(payment_info, deserialized);
     }
+
+    #[test]
+    fn test_event_broadcaster() {
+        let broadcaster = EventBroadcaster::default();
+        let (_, mut first) = broadcaster.subscribe();
+        let (_, second) = broadcaster.subscribe();
+        drop(second);
+
+        let event = MutinyEvent::PaymentFailed {
+            payment_hash: sha256::Hash::hash(&[1; 32]),
+            federation_id: None,
+        };
+        broadcaster.broadcast(event.clone());
+
+        assert_eq!(first.try_next().unwrap(), Some(event.clone()));
+        // dropped subscribers are cleaned up
+        assert_eq!(broadcaster.subscribers.lock().unwrap().len(), 1);
+
+        // unsubscribing ends the stream
+        let (id, mut third) = broadcaster.subscribe();
+        broadcaster.unsubscribe(id);
+        broadcaster.broadcast(event.clone());
+        assert_eq!(third.try_next().unwrap(), None);
+        assert_eq!(first.try_next().unwrap(), Some(event.clone()));
+
+        broadcaster.close();
+        assert_eq!(first.try_next().unwrap(), None);
+
+        let serialized = serde_json::to_value(&event).unwrap();
+        assert_eq!(serialized["type"], "PaymentFailed");
+        let deserialized: MutinyEvent = serde_json::from_value(serialized).unwrap();
+        assert_eq!(event, deserialized);
+    }
 }
@@ -1,3 +1,4 @@
+use crate::nodemanager::PaymentOptions;
 use crate::storage::get_invoice_by_hash;
 use crate::utils::{
     convert_from_fedimint_invoice, convert_to_fedimint_invoice, fetch_with_timeout, now, spawn,
@@ -5,13 +6,16 @@ use crate::utils::{
 use crate::TransactionDetails;
 use crate::{
     error::{MutinyError, MutinyStorageError},
-    event::PaymentInfo,
+    event::{EventBroadcaster, MutinyEvent, PaymentInfo},
     key::{create_root_child_key, ChildKey},
     logging::MutinyLogger,
     onchain::coin_type_from_network,
     storage::{
-        delete_transaction_details, get_transaction_details, list_payment_info,
-        persist_payment_info, persist_transaction_details, MutinyStorage, VersionedValue,
+        delete_transaction_details, get_ecash_transfer, get_federation_deposit,
+        get_transaction_details, list_federation_deposits, list_payment_info,
+        persist_ecash_transfer, persist_federation_deposit, persist_payment_info,
+        persist_transaction_details, read_payment_info, MutinyStorage, VersionedValue,
+        ECASH_TRANSFER_PREFIX_KEY,
     },
     utils::sleep,
     HTLCStatus, MutinyInvoice, DEFAULT_PAYMENT_TIMEOUT,
@@ -31,6 +35,7 @@ use core::fmt;
 use esplora_client::AsyncClient;
 use fedimint_bip39::Bip39RootSecretStrategy;
 use fedimint_client::{
+    backup::Metadata,
     derivable_secret::DerivableSecret,
     oplog::{OperationLogEntry, UpdateStreamOrOutcome},
     secret::{get_default_client_secret, RootSecretStrategy},
@@ -40,10 +45,10 @@ use fedimint_core::bitcoin_migration::bitcoin30_to_bitcoin29_address;
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_MINT;
 use fedimint_core::{
-    api::InviteCode,
+    api::{FederationApiExt, InviteCode},
     config::FederationId,
     core::OperationId,
-    module::CommonModuleInit,
+    module::{ApiRequestErased, CommonModuleInit},
     task::{MaybeSend, MaybeSync},
     Amount,
 };
@@ -61,9 +66,13 @@ use fedimint_ln_client::{
 };
 use fedimint_ln_common::lightning_invoice::{Bolt11InvoiceDescription, Description, RoutingFees};
 use fedimint_ln_common::{LightningCommonInit, LightningGateway, LightningGatewayAnnouncement};
-use fedimint_mint_client::MintClientInit;
+use fedimint_mint_client::{
+    MintClientInit, MintClientModule, MintCommonInit, MintOperationMeta, MintOperationMetaVariant,
+    OOBNotes, ReissueExternalNotesState, SpendOOBState,
+};
 use fedimint_wallet_client::{
-    WalletClientInit, WalletClientModule, WalletCommonInit, WalletOperationMeta, WithdrawState,
+    config::WalletClientConfig, WalletClientInit, WalletClientModule, WalletCommonInit,
+    WalletOperationMeta, WithdrawState,
 };
 use futures::{select, FutureExt};
 use futures_util::{pin_mut, StreamExt};
@@ -95,8 +104,39 @@ const FEDIMINT_OPERATIONS_LIST_MAX: usize = 100;
 // On chain peg in timeout
 const PEG_IN_TIMEOUT_YEAR: Duration = Duration::from_secs(86400 * 365);
 
+// Confirmations we assume a deposit needs if we can't read the federation's finality delay
+const DEFAULT_DEPOSIT_CONFIRMATIONS: u32 = 10;
+
+// How long spent ecash can go unclaimed before we take it back
+const ECASH_SPEND_CANCEL_AFTER: Duration = Duration::from_secs(86400 * 7);
+
+// How long we wait for a guardian to answer a health check
+const GUARDIAN_HEALTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How far ahead of a federation's expiry we start warning about it
+const FEDERATION_EXPIRY_WARNING: Duration = Duration::from_secs(86400 * 30);
+
+// How many blocks a guardian can be behind the others before we warn
+const GUARDIAN_MAX_BLOCKS_BEHIND: u64 = 6;
+
+// How long we look for a transaction to a new deposit address before
+// we stop tracking it. The federation still claims anything sent later.
+const DEPOSIT_ADDRESS_EXPIRY: Duration = Duration::from_secs(86400);
+
+// How often we save that a gateway is still being seen
+const GATEWAY_SEEN_INTERVAL_SECS: u64 = 60 * 60;
+
 pub const FEDIMINTS_PREFIX_KEY: &str = "fedimints/";
 
+// The last list of federations we backed up to our federations
+pub const FEDERATION_BACKUP_KEY: &str = "federation_backup";
+
+// Where we keep how far a federation is in resyncing
+const RESYNC_STATE_PREFIX_KEY: &str = "resync_state/";
+
+// Where we keep the backup a federation returned while we were recovering it
+pub(crate) const RECOVERED_FEDERATION_BACKUP_PREFIX_KEY: &str = "recovered_federation_backup/";
+
 // Default signet/mainnet federation gateway info
 const SIGNET_GATEWAY: &str = "0256f5ef1d986e9abf559651b7167de28bfd954683cd0f14703be12d1421aedc55";
 const MAINNET_GATEWAY: &str = "025b9f090d3daab012346701f27d1c220d6d290f6b498255cddc492c255532a09d";
@@ -144,6 +184,47 @@ impl From<LnPayState> for HTLCStatus {
     }
 }
 
+impl From<SpendOOBState> for HTLCStatus {
+    fn from(state: SpendOOBState) -> Self {
+        match state {
+            SpendOOBState::Created => HTLCStatus::InFlight,
+            SpendOOBState::UserCanceledProcessing => HTLCStatus::InFlight,
+            SpendOOBState::UserCanceledSuccess => HTLCStatus::Failed,
+            SpendOOBState::UserCanceledFailure => HTLCStatus::Succeeded,
+            SpendOOBState::Success => HTLCStatus::Succeeded,
+            SpendOOBState::Refunded => HTLCStatus::Failed,
+        }
+    }
+}
+
+impl From<ReissueExternalNotesState> for HTLCStatus {
+    fn from(state: ReissueExternalNotesState) -> Self {
+        match state {
+            ReissueExternalNotesState::Created => HTLCStatus::Pending,
+            ReissueExternalNotesState::Issuing => HTLCStatus::InFlight,
+            ReissueExternalNotesState::Done => HTLCStatus::Succeeded,
+            ReissueExternalNotesState::Failed(_) => HTLCStatus::Failed,
+        }
+    }
+}
+
+/// Ecash notes that were sent or received out of band, handed over
+/// directly instead of through lightning or on-chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EcashTransfer {
+    /// The fedimint operation id, in hex
+    pub operation_id: String,
+    pub federation_id: FederationId,
+    /// The serialized notes
+    pub notes: String,
+    /// Amount in sats
+    pub amount_sats: u64,
+    pub inbound: bool,
+    pub status: HTLCStatus,
+    pub labels: Vec<String>,
+    pub last_updated: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ResyncProgress {
     pub total: u32,
@@ -151,6 +232,46 @@ pub struct ResyncProgress {
     pub done: bool,
 }
 
+/// The federations a user is in, backed up so they can be found again from just the seed
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FederationBackup {
+    pub federations: Vec<InviteCode>,
+    pub timestamp: u64,
+}
+
+impl FederationBackup {
+    /// Whether both backups list the same federations
+    pub(crate) fn same_federations(&self, other: &FederationBackup) -> bool {
+        let ids = |b: &FederationBackup| {
+            b.federations
+                .iter()
+                .map(|c| c.federation_id())
+                .collect::<HashSet<_>>()
+        };
+        ids(self) == ids(other)
+    }
+}
+
+pub(crate) fn resync_state_key(federation_id: &FederationId) -> String {
+    format!("{RESYNC_STATE_PREFIX_KEY}{federation_id}")
+}
+
+/// How far along a federation is in restoring from backup, `None` once it is done
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FederationResyncStatus {
+    pub federation_id: FederationId,
+    pub progress: Option<ResyncProgress>,
+}
+
+/// How far along restoring all of our federations from backup is
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FederationRestoreProgress {
+    pub federations: Vec<FederationResyncStatus>,
+    pub total: u32,
+    pub complete: u32,
+    pub done: bool,
+}
+
 // This is the FederationStorage object saved to the DB
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct FederationStorage {
@@ -227,6 +348,152 @@ impl From<RoutingFees> for GatewayFees {
     }
 }
 
+/// A lightning gateway registered with a federation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationGateway {
+    pub gateway_id: String,
+    pub node_pubkey: String,
+    pub lightning_alias: String,
+    pub api: String,
+    pub fees: GatewayFees,
+    pub vetted: bool,
+    pub supports_private_payments: bool,
+    /// When we last saw the gateway registered with the federation, in seconds since epoch
+    pub last_seen: Option<u64>,
+    /// If this is the gateway we use for receiving
+    pub active: bool,
+    pub pinned: bool,
+    pub excluded: bool,
+}
+
+/// The user's gateway choices for a federation, saved to the DB
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct GatewayPreferences {
+    /// Gateway to always use when the federation lists it
+    pub pinned: Option<String>,
+    /// Gateways to never use
+    pub excluded: HashSet<String>,
+    /// When we last saw each gateway, keyed by gateway id
+    pub last_seen: HashMap<String, u64>,
+    /// Whether payments may fall back to unvetted gateways that
+    /// charge suspiciously low fees
+    #[serde(default)]
+    pub allow_unvetted_fallback: bool,
+}
+
+/// Where an on-chain deposit into a federation is at
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DepositStatus {
+    /// No transaction to the address has been seen yet
+    WaitingForTransaction,
+    /// The transaction needs more confirmations before the federation accepts it
+    WaitingForConfirmation,
+    /// The federation accepted the transaction and is issuing ecash for it
+    Confirmed,
+    /// The ecash has been issued and is part of our balance
+    Claimed,
+    Failed,
+    /// The address expired before any transaction was seen
+    Expired,
+}
+
+/// An on-chain deposit (peg-in) into a federation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationDeposit {
+    pub operation_id: String,
+    pub federation_id: FederationId,
+    pub address: String,
+    /// The amount we asked to be sent, if any
+    pub expected_amount: Option<u64>,
+    pub txid: Option<Txid>,
+    /// The amount actually sent to the address
+    pub amount: Option<u64>,
+    pub confirmations: u32,
+    /// The confirmations the federation needs before it accepts the deposit
+    pub confirmations_required: u32,
+    pub status: DepositStatus,
+    pub labels: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub last_updated: u64,
+}
+
+impl FederationDeposit {
+    pub fn is_pending(&self) -> bool {
+        matches!(
+            self.status,
+            DepositStatus::WaitingForTransaction
+                | DepositStatus::WaitingForConfirmation
+                | DepositStatus::Confirmed
+        )
+    }
+
+    /// Whether a transaction to the deposit address has been seen
+    pub fn has_transaction(&self) -> bool {
+        !matches!(
+            self.status,
+            DepositStatus::WaitingForTransaction | DepositStatus::Expired
+        )
+    }
+}
+
+/// What a single guardian reported during a health check
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianHealth {
+    pub peer_id: u16,
+    pub name: String,
+    pub url: String,
+    pub reachable: bool,
+    /// The consensus session the guardian is on
+    pub session_count: Option<u64>,
+    /// The block height the guardian's bitcoin backend sees
+    pub block_height: Option<u64>,
+}
+
+/// A module the federation runs and its consensus version
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationModuleVersion {
+    pub instance_id: u16,
+    pub kind: String,
+    pub version: String,
+}
+
+/// Something about a federation the user should know about
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum FederationHealthWarning {
+    /// Some guardians could not be reached, but enough are online for consensus
+    GuardiansOffline { offline: usize, total: usize },
+    /// Too few guardians could be reached for the federation to reach consensus
+    NoConsensus { online: usize, threshold: usize },
+    /// A guardian is behind the rest on consensus sessions or block height
+    GuardianLagging { peer_id: u16 },
+    /// The federation is going to shut down soon
+    ExpiringSoon { expiry_timestamp: u64 },
+    /// The federation's announced shut down time has passed
+    Expired { expiry_timestamp: u64 },
+}
+
+/// The result of checking on a federation's guardians
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FederationHealth {
+    pub federation_id: FederationId,
+    pub guardians: Vec<GuardianHealth>,
+    /// How many guardians are needed to reach consensus
+    pub threshold: usize,
+    pub modules: Vec<FederationModuleVersion>,
+    pub expiry_timestamp: Option<u64>,
+    pub warnings: Vec<FederationHealthWarning>,
+    /// When the check was done, in seconds since epoch
+    pub checked_at: u64,
+}
+
+impl FederationHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
 // This is the FederationIndex reference that is saved to the DB
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct FederationIndex {
@@ -256,6 +523,7 @@ pub(crate) struct FederationClient<S: MutinyStorage> {
     gateway: Arc<RwLock<Option<LightningGateway>>>,
     esplora: Arc<AsyncClient>,
     stop: Arc<AtomicBool>,
+    event_broadcaster: EventBroadcaster,
     pub(crate) logger: Arc<MutinyLogger>,
 }
 
@@ -269,6 +537,7 @@ impl<S: MutinyStorage> FederationClient<S> {
         esplora: Arc<AsyncClient>,
         network: Network,
         stop: Arc<AtomicBool>,
+        event_broadcaster: EventBroadcaster,
         logger: Arc<MutinyLogger>,
         safe_mode: bool,
     ) -> Result<Self, MutinyError> {
@@ -355,6 +624,7 @@ impl<S: MutinyStorage> FederationClient<S> {
         // Set active gateway preference in background
         let client_clone = fedimint_client.clone();
         let gateway_clone = gateway.clone();
+        let storage_clone = storage.clone();
         let logger_clone = logger.clone();
         spawn(async move {
             let start = Instant::now();
@@ -374,15 +644,10 @@ impl<S: MutinyStorage> FederationClient<S> {
                 }
             }
 
-            let gateways = lightning_module.list_gateways().await;
-            if let Some(a) = get_gateway_preference(gateways, federation_id) {
-                log_info!(
-                    logger_clone,
-                    "Setting active gateway for federation {federation_id}: {a}"
-                );
-
-                let gateway = lightning_module.select_gateway(&a).await;
-                *gateway_lock = gateway;
+            if let Some(gateway) =
+                select_active_gateway(&client_clone, &storage_clone, &logger_clone).await
+            {
+                *gateway_lock = Some(gateway);
             }
 
             log_trace!(
@@ -403,6 +668,7 @@ impl<S: MutinyStorage> FederationClient<S> {
             invite_code: federation_code,
             esplora,
             stop,
+            event_broadcaster,
             gateway,
         };
 
@@ -439,6 +705,15 @@ impl<S: MutinyStorage> FederationClient<S> {
             .map(|(_h, i)| i.internal_id)
             .collect::<HashSet<Txid>>();
 
+        // pending out of band ecash
+        let pending_ecash = self
+            .storage
+            .scan::<EcashTransfer>(ECASH_TRANSFER_PREFIX_KEY, None)?
+            .into_values()
+            .filter(|t| matches!(t.status, HTLCStatus::InFlight | HTLCStatus::Pending))
+            .map(|t| t.operation_id)
+            .collect::<HashSet<String>>();
+
         // go through last 100 operations
         let operations = self
             .fedimint_client
@@ -475,6 +750,10 @@ impl<S: MutinyStorage> FederationClient<S> {
                 if !confirmed_wallet_txids.contains(&internal_id) {
                     self.subscribe_operation(entry, key.operation_id);
                 }
+            } else if module_type == MintCommonInit::KIND.as_str() {
+                if pending_ecash.contains(&key.operation_id.0.to_lower_hex_string()) {
+                    self.subscribe_operation(entry, key.operation_id);
+                }
             } else {
                 log_warn!(self.logger, "Unknown module type: {module_type}")
             }
@@ -483,6 +762,21 @@ impl<S: MutinyStorage> FederationClient<S> {
         Ok(())
     }
 
+    /// Gets an operation we started from the operation log
+    async fn get_operation(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<OperationLogEntry, MutinyError> {
+        self.fedimint_client
+            .operation_log()
+            .get_operation(operation_id)
+            .await
+            .ok_or_else(|| {
+                log_error!(self.logger, "operation {operation_id:?} not found");
+                MutinyError::NotFound
+            })
+    }
+
     fn subscribe_operation(&self, entry: OperationLogEntry, operation_id: OperationId) {
         subscribe_operation_ext(
             entry,
@@ -492,6 +786,7 @@ impl<S: MutinyStorage> FederationClient<S> {
             self.logger.clone(),
             self.stop.clone(),
             self.storage.clone(),
+            self.event_broadcaster.clone(),
         );
     }
 
@@ -505,7 +800,7 @@ impl<S: MutinyStorage> FederationClient<S> {
     ) -> Result<(), MutinyError> {
         let federation_id = federation_code.federation_id();
 
-        let storage_key = format!("resync_state/{federation_id}");
+        let storage_key = resync_state_key(&federation_id);
         storage.set_data(storage_key.clone(), ResyncProgress::default(), None)?;
 
         log_trace!(logger, "Building fedimint client db");
@@ -581,6 +876,24 @@ impl<S: MutinyStorage> FederationClient<S> {
                 log_error!(logger, "Error waiting for recoveries: {e}");
             }
 
+            // keep the federations listed in its backup so a restore can find them too
+            match fedimint_client.download_backup_from_federation().await {
+                Ok(Some(backup)) => {
+                    match backup.metadata.to_json_deserialized::<FederationBackup>() {
+                        Ok(b) => {
+                            let key =
+                                format!("{RECOVERED_FEDERATION_BACKUP_PREFIX_KEY}{federation_id}");
+                            if let Err(e) = storage.set_data(key, b, None) {
+                                log_error!(logger, "Error saving federation backup: {e}");
+                            }
+                        }
+                        Err(e) => log_warn!(logger, "Could not read federation backup: {e}"),
+                    }
+                }
+                Ok(None) => log_debug!(logger, "No backup found in federation"),
+                Err(e) => log_warn!(logger, "Could not download federation backup: {e}"),
+            }
+
             // can now delete the progress state
             if let Err(e) = storage.delete(&[storage_key]) {
                 log_error!(logger, "Error deleting resync progress state: {e}");
@@ -590,11 +903,250 @@ impl<S: MutinyStorage> FederationClient<S> {
         Ok(())
     }
 
+    /// Uploads an encrypted backup of our ecash to the federation,
+    /// along with the list of federations we are in.
+    pub(crate) async fn backup_to_federation(
+        &self,
+        backup: &FederationBackup,
+    ) -> Result<(), MutinyError> {
+        self.fedimint_client
+            .backup_to_federation(Metadata::from_json_serialized(backup))
+            .await?;
+        Ok(())
+    }
+
     pub(crate) async fn gateway_fee(&self) -> Result<GatewayFees, MutinyError> {
         let gateway = self.gateway.read().await;
         Ok(gateway.as_ref().map(|x| x.fees.into()).unwrap_or_default())
     }
 
+    /// Lists the gateways registered with the federation along with
+    /// their fees and our preferences for them.
+    pub(crate) async fn list_gateways(&self) -> Result<Vec<FederationGateway>, MutinyError> {
+        let lightning_module = self
+            .fedimint_client
+            .get_first_module::<LightningClientModule>();
+
+        let refreshed = match lightning_module.update_gateway_cache(true).await {
+            Ok(_) => true,
+            Err(e) => {
+                log_warn!(self.logger, "Could not update lightning gateway cache: {e}");
+                false
+            }
+        };
+
+        let gateways = lightning_module.list_gateways().await;
+        let federation_id = self.fedimint_client.federation_id();
+        let preferences = if refreshed {
+            record_gateways_seen(&self.storage, federation_id, &gateways)?
+        } else {
+            self.storage.get_gateway_preferences(&federation_id)?
+        };
+        // the stored times are only rewritten every so often, a fresh registry was just seen
+        let seen_now = refreshed.then(|| now().as_secs());
+        let active = self
+            .gateway
+            .read()
+            .await
+            .as_ref()
+            .map(|g| g.gateway_id.to_string());
+
+        let gateways = gateways
+            .into_iter()
+            .map(|g| {
+                let gateway_id = g.info.gateway_id.to_string();
+                FederationGateway {
+                    node_pubkey: g.info.node_pub_key.to_string(),
+                    lightning_alias: g.info.lightning_alias.clone(),
+                    api: g.info.api.to_string(),
+                    fees: g.info.fees.into(),
+                    vetted: g.vetted,
+                    supports_private_payments: g.info.supports_private_payments,
+                    last_seen: seen_now.or(preferences.last_seen.get(&gateway_id).copied()),
+                    active: active.as_ref() == Some(&gateway_id),
+                    pinned: preferences.pinned.as_ref() == Some(&gateway_id),
+                    excluded: preferences.excluded.contains(&gateway_id),
+                    gateway_id,
+                }
+            })
+            .collect();
+
+        Ok(gateways)
+    }
+
+    /// Pins a gateway so it is always used when available,
+    /// or removes the pin when `None`.
+    pub(crate) async fn set_pinned_gateway(
+        &self,
+        gateway_id: Option<String>,
+    ) -> Result<(), MutinyError> {
+        let gateway_id = gateway_id.map(|g| parse_gateway_id(&g)).transpose()?;
+
+        let federation_id = self.fedimint_client.federation_id();
+        let mut preferences = self.storage.get_gateway_preferences(&federation_id)?;
+        if let Some(ref g) = gateway_id {
+            preferences.excluded.remove(g);
+        }
+        preferences.pinned = gateway_id;
+        self.storage
+            .set_gateway_preferences(&federation_id, &preferences)?;
+
+        self.refresh_active_gateway().await;
+        Ok(())
+    }
+
+    /// Excludes a gateway so it is never used, or allows it again.
+    pub(crate) async fn set_gateway_excluded(
+        &self,
+        gateway_id: String,
+        excluded: bool,
+    ) -> Result<(), MutinyError> {
+        let gateway_id = parse_gateway_id(&gateway_id)?;
+
+        let federation_id = self.fedimint_client.federation_id();
+        let mut preferences = self.storage.get_gateway_preferences(&federation_id)?;
+        if excluded {
+            if preferences.pinned.as_ref() == Some(&gateway_id) {
+                preferences.pinned = None;
+            }
+            preferences.excluded.insert(gateway_id);
+        } else {
+            preferences.excluded.remove(&gateway_id);
+        }
+        self.storage
+            .set_gateway_preferences(&federation_id, &preferences)?;
+
+        self.refresh_active_gateway().await;
+        Ok(())
+    }
+
+    /// Sets whether payments may fall back to unvetted gateways
+    /// that charge suspiciously low fees.
+    pub(crate) fn set_allow_unvetted_gateways(&self, allowed: bool) -> Result<(), MutinyError> {
+        let federation_id = self.fedimint_client.federation_id();
+        let mut preferences = self.storage.get_gateway_preferences(&federation_id)?;
+        if preferences.allow_unvetted_fallback != allowed {
+            preferences.allow_unvetted_fallback = allowed;
+            self.storage
+                .set_gateway_preferences(&federation_id, &preferences)?;
+        }
+        Ok(())
+    }
+
+    async fn refresh_active_gateway(&self) {
+        let mut gateway_lock = self.gateway.write().await;
+        if let Some(gateway) =
+            select_active_gateway(&self.fedimint_client, &self.storage, &self.logger).await
+        {
+            *gateway_lock = Some(gateway);
+        }
+    }
+
+    /// Checks which guardians are reachable, what consensus session and
+    /// block height each one sees, and when the federation expires.
+    pub(crate) async fn get_health(&self) -> FederationHealth {
+        let federation_id = self.fedimint_client.federation_id();
+        let config = self.fedimint_client.get_config();
+        let api = self.fedimint_client.api();
+        let wallet_module = self
+            .fedimint_client
+            .get_first_module::<WalletClientModule>();
+
+        let guardians = futures::future::join_all(config.global.api_endpoints.iter().map(
+            |(peer_id, peer_url)| async {
+                let session_count = api
+                    .request_single_peer(
+                        Some(GUARDIAN_HEALTH_TIMEOUT),
+                        "session_count".to_string(),
+                        ApiRequestErased::default(),
+                        *peer_id,
+                    )
+                    .await
+                    .ok()
+                    .and_then(|v| serde_json::from_value::<u64>(v).ok());
+                let block_height = wallet_module
+                    .api
+                    .request_single_peer(
+                        Some(GUARDIAN_HEALTH_TIMEOUT),
+                        "block_count".to_string(),
+                        ApiRequestErased::default(),
+                        *peer_id,
+                    )
+                    .await
+                    .ok()
+                    .and_then(|v| serde_json::from_value::<u64>(v).ok());
+
+                GuardianHealth {
+                    peer_id: peer_id.to_usize() as u16,
+                    name: peer_url.name.clone(),
+                    url: peer_url.url.to_string(),
+                    reachable: session_count.is_some() || block_height.is_some(),
+                    session_count,
+                    block_height,
+                }
+            },
+        ))
+        .await;
+
+        let modules = config
+            .modules
+            .iter()
+            .map(|(id, m)| FederationModuleVersion {
+                instance_id: *id,
+                kind: m.kind.to_string(),
+                version: format!("{}.{}", m.version.major, m.version.minor),
+            })
+            .collect();
+
+        let expiry_timestamp = self
+            .get_mutiny_federation_identity()
+            .await
+            .federation_expiry_timestamp
+            .and_then(|t| t.parse::<u64>().ok());
+
+        let threshold = consensus_threshold(guardians.len());
+        let checked_at = now().as_secs();
+        let warnings =
+            federation_health_warnings(&guardians, threshold, expiry_timestamp, checked_at);
+        for warning in warnings.iter() {
+            log_warn!(
+                self.logger,
+                "federation {federation_id} health warning: {warning:?}"
+            );
+        }
+
+        FederationHealth {
+            federation_id,
+            guardians,
+            threshold,
+            modules,
+            expiry_timestamp,
+            warnings,
+            checked_at,
+        }
+    }
+
+    /// The most we want to hold in this federation, in sats.
+    ///
+    /// A limit set by the user takes precedence over the federation's
+    /// advertised `max_balance_msats` meta field.
+    pub(crate) fn max_balance_sats(&self) -> Result<Option<u64>, MutinyError> {
+        let federation_id = self.fedimint_client.federation_id().to_string();
+        if let Some(max) = self
+            .storage
+            .get_federation_max_balances()?
+            .get(&federation_id)
+        {
+            return Ok(Some(*max));
+        }
+
+        Ok(self
+            .fedimint_client
+            .get_meta("max_balance_msats")
+            .and_then(|m| m.parse::<u64>().ok())
+            .map(|msats| msats / 1_000))
+    }
+
     pub(crate) async fn get_invoice(
         &self,
         amount: u64,
@@ -646,12 +1198,16 @@ impl<S: MutinyStorage> FederationClient<S> {
         let storage_clone = self.storage.clone();
         let esplora_clone = self.esplora.clone();
         let stop = self.stop.clone();
+        let event_broadcaster = self.event_broadcaster.clone();
         spawn(async move {
-            let operation = fedimint_client_clone
+            let Some(operation) = fedimint_client_clone
                 .operation_log()
                 .get_operation(id)
                 .await
-                .expect("just created it");
+            else {
+                log_error!(logger_clone, "operation {id:?} not found, not subscribing");
+                return;
+            };
 
             subscribe_operation_ext(
                 operation,
@@ -661,6 +1217,7 @@ impl<S: MutinyStorage> FederationClient<S> {
                 logger_clone,
                 stop,
                 storage_clone,
+                event_broadcaster,
             );
         });
 
@@ -670,6 +1227,7 @@ impl<S: MutinyStorage> FederationClient<S> {
 
     pub(crate) async fn get_new_address(
         &self,
+        expected_amount: Option<u64>,
         labels: Vec<String>,
     ) -> Result<Address, MutinyError> {
         log_trace!(self.logger, "calling federation.get_new_address");
@@ -683,6 +1241,7 @@ impl<S: MutinyStorage> FederationClient<S> {
             self.fedimint_client.federation_id()
         );
 
+        let created_at = now().as_secs();
         let (op_id, address) = wallet_module
             .get_deposit_address(fedimint_core::time::now() + PEG_IN_TIMEOUT_YEAR, ())
             .await?;
@@ -697,13 +1256,26 @@ impl<S: MutinyStorage> FederationClient<S> {
         self.storage
             .set_address_labels(address.clone(), labels.clone())?;
 
+        // track the deposit so we can show it before the federation credits it
+        let deposit = FederationDeposit {
+            operation_id: op_id.0.to_lower_hex_string(),
+            federation_id: self.fedimint_client.federation_id(),
+            address: address.to_string(),
+            expected_amount,
+            txid: None,
+            amount: None,
+            confirmations: 0,
+            confirmations_required: self.deposit_confirmations_required(),
+            status: DepositStatus::WaitingForTransaction,
+            labels,
+            created_at,
+            expires_at: created_at + DEPOSIT_ADDRESS_EXPIRY.as_secs(),
+            last_updated: created_at,
+        };
+        persist_federation_deposit(&self.storage, &deposit)?;
+
         // subscribe
-        let operation = self
-            .fedimint_client
-            .operation_log()
-            .get_operation(op_id)
-            .await
-            .expect("just created it");
+        let operation = self.get_operation(op_id).await?;
         self.subscribe_operation(operation, op_id);
 
         log_trace!(self.logger, "finished calling get_new_address");
@@ -711,26 +1283,297 @@ impl<S: MutinyStorage> FederationClient<S> {
         Ok(address)
     }
 
+    // The federation only accepts deposits that are buried deeper than its finality delay
+    fn deposit_confirmations_required(&self) -> u32 {
+        self.fedimint_client
+            .get_config()
+            .get_first_module_by_kind::<WalletClientConfig>(WalletCommonInit::KIND)
+            .map(|(_, cfg)| cfg.finality_delay + 1)
+            .unwrap_or(DEFAULT_DEPOSIT_CONFIRMATIONS)
+    }
+
+    /// Lists the on-chain deposits into this federation that a
+    /// transaction has been seen for, newest first.
+    ///
+    /// Pending deposits are checked against esplora so we can report
+    /// the transaction and its confirmations before the federation sees it.
+    /// Addresses that haven't received anything in a day stop being checked.
+    pub(crate) async fn list_deposits(&self) -> Result<Vec<FederationDeposit>, MutinyError> {
+        let federation_id = self.fedimint_client.federation_id();
+        let mut deposits: Vec<FederationDeposit> = list_federation_deposits(&self.storage)?
+            .into_iter()
+            .filter(|d| d.federation_id == federation_id)
+            .collect();
+
+        let now = now().as_secs();
+        for deposit in deposits.iter_mut() {
+            if expire_unused_deposit(deposit, now) {
+                persist_federation_deposit(&self.storage, deposit)?;
+            }
+        }
+
+        if deposits.iter().any(|d| d.is_pending()) {
+            let tip = self.esplora.get_height().await?;
+            for deposit in deposits.iter_mut().filter(|d| d.is_pending()) {
+                if let Err(e) = self.refresh_deposit(deposit, tip).await {
+                    log_warn!(
+                        self.logger,
+                        "could not check deposit to {}: {e}",
+                        deposit.address
+                    );
+                }
+            }
+        }
+
+        deposits.retain(|d| d.has_transaction());
+        deposits.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(deposits)
+    }
+
+    async fn refresh_deposit(
+        &self,
+        deposit: &mut FederationDeposit,
+        tip: u32,
+    ) -> Result<(), MutinyError> {
+        let script = Address::from_str(&deposit.address)?
+            .assume_checked()
+            .script_pubkey();
+        let txs = self.esplora.scripthash_txs(&script, None).await?;
+
+        let found = txs.iter().find_map(|tx| {
+            tx.vout
+                .iter()
+                .find(|v| v.scriptpubkey == script)
+                .map(|v| (tx, v.value))
+        });
+
+        let Some((tx, value)) = found else {
+            // nothing to save until a transaction shows up
+            return Ok(());
+        };
+
+        deposit.txid = Some(tx.txid);
+        deposit.amount = Some(value);
+        deposit.confirmations = tx
+            .status
+            .block_height
+            .map(|h| tip.saturating_sub(h) + 1)
+            .unwrap_or(0);
+        if deposit.status == DepositStatus::WaitingForTransaction {
+            deposit.status = DepositStatus::WaitingForConfirmation;
+        }
+
+        deposit.last_updated = now().as_secs();
+        persist_federation_deposit(&self.storage, deposit)
+    }
+
     /// Get the balance of this federation client in sats
     pub(crate) async fn get_balance(&self) -> Result<u64, MutinyError> {
         Ok(self.fedimint_client.get_balance().await.msats / 1_000)
     }
 
+    /// Takes ecash notes out of the wallet so they can be handed to someone out of band.
+    /// If the notes are not claimed within a week they are reclaimed.
+    pub(crate) async fn spend_ecash(
+        &self,
+        amount: u64,
+        labels: Vec<String>,
+    ) -> Result<EcashTransfer, MutinyError> {
+        log_trace!(self.logger, "calling federation.spend_ecash");
+
+        if self.get_balance().await? < amount {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        let mint_module = self.fedimint_client.get_first_module::<MintClientModule>();
+        let (op_id, notes) = mint_module
+            .spend_notes(
+                Amount::from_sats(amount),
+                ECASH_SPEND_CANCEL_AFTER,
+                false,
+                (),
+            )
+            .await?;
+
+        // we may have spent a little more than requested if we didn't have exact change
+        let transfer = EcashTransfer {
+            operation_id: op_id.0.to_lower_hex_string(),
+            federation_id: self.fedimint_client.federation_id(),
+            notes: notes.to_string(),
+            amount_sats: sats_round_up(&notes.total_amount()),
+            inbound: false,
+            status: HTLCStatus::InFlight,
+            labels,
+            last_updated: now().as_secs(),
+        };
+        persist_ecash_transfer(&self.storage, &transfer)?;
+
+        // subscribe so we know when the notes are claimed or reclaimed
+        let operation = self.get_operation(op_id).await?;
+        self.subscribe_operation(operation, op_id);
+
+        log_trace!(self.logger, "finished calling federation.spend_ecash");
+
+        Ok(transfer)
+    }
+
+    /// Reissues ecash notes that were received out of band into our wallet.
+    pub(crate) async fn receive_ecash(
+        &self,
+        notes: OOBNotes,
+        labels: Vec<String>,
+    ) -> Result<EcashTransfer, MutinyError> {
+        log_trace!(self.logger, "calling federation.receive_ecash");
+
+        let mint_module = self.fedimint_client.get_first_module::<MintClientModule>();
+        let amount_sats = sats_round_up(&notes.total_amount());
+        let serialized = notes.to_string();
+        let op_id = mint_module.reissue_external_notes(notes, ()).await?;
+
+        let internal_id = op_id.0.to_lower_hex_string();
+        let transfer = EcashTransfer {
+            operation_id: internal_id.clone(),
+            federation_id: self.fedimint_client.federation_id(),
+            notes: serialized,
+            amount_sats,
+            inbound: true,
+            status: HTLCStatus::Pending,
+            labels,
+            last_updated: now().as_secs(),
+        };
+        persist_ecash_transfer(&self.storage, &transfer)?;
+
+        let operation = self.get_operation(op_id).await?;
+
+        // Subscribe for a little bit, reissuing should be quick
+        process_operation_until_timeout(
+            self.logger.clone(),
+            operation.clone(),
+            op_id,
+            self.fedimint_client.clone(),
+            self.storage.clone(),
+            self.esplora.clone(),
+            Some(DEFAULT_PAYMENT_TIMEOUT * 1_000),
+            self.stop.clone(),
+            self.event_broadcaster.clone(),
+        )
+        .await;
+
+        // now check the status of the reissue from storage
+        let res = match get_ecash_transfer(&self.storage, &internal_id, &self.logger) {
+            Some(t) => match t.status {
+                HTLCStatus::Succeeded => Ok(t),
+                HTLCStatus::Failed => Err(MutinyError::TokenAlreadySpent),
+                HTLCStatus::Pending | HTLCStatus::InFlight => {
+                    // keep watching in the background
+                    self.subscribe_operation(operation, op_id);
+                    Ok(t)
+                }
+            },
+            None => Ok(transfer),
+        };
+        log_trace!(self.logger, "finished calling federation.receive_ecash");
+
+        res
+    }
+
     fn maybe_update_after_checking_fedimint(
         &self,
         updated_invoice: MutinyInvoice,
     ) -> Result<MutinyInvoice, MutinyError> {
         maybe_update_after_checking_fedimint(
             updated_invoice,
+            self.fedimint_client.federation_id(),
+            &self.event_broadcaster,
             self.logger.clone(),
             self.storage.clone(),
         )
     }
 
+    /// Pays an invoice through the cheapest viable gateway, falling back to
+    /// the next one if the payment fails to route.
+    ///
+    /// Gateways charging more than the max fee are skipped, trying another
+    /// gateway counts as a retry and all attempts share the timeout.
     pub(crate) async fn pay_invoice(
         &self,
         invoice: Bolt11Invoice,
+        options: &PaymentOptions,
         labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        // wait for the active gateway to be set before choosing one
+        let active_gateway = self.gateway.read().await.clone();
+
+        let lightning_module = self
+            .fedimint_client
+            .get_first_module::<LightningClientModule>();
+        let federation_id = self.fedimint_client.federation_id();
+        let preferences = self.storage.get_gateway_preferences(&federation_id)?;
+
+        let amount_msat = invoice.amount_milli_satoshis().unwrap_or_default();
+        let mut gateways: Vec<Option<LightningGateway>> = rank_gateways_for_payment(
+            lightning_module.list_gateways().await,
+            &preferences,
+            amount_msat,
+        )
+        .into_iter()
+        .map(Some)
+        .collect();
+        if gateways.is_empty() {
+            gateways.push(active_gateway);
+        }
+
+        // we don't know what fedimint's own gateway choice would charge
+        if let Some(max_fee_msat) = options.max_total_routing_fee_msat(amount_msat) {
+            gateways.retain(|g| {
+                g.as_ref()
+                    .is_some_and(|g| gateway_fee_msat(&g.fees, amount_msat) <= max_fee_msat)
+            });
+        }
+        let max_attempts = options
+            .retry_attempts
+            .map_or(usize::MAX, |retries| retries as usize + 1);
+        gateways.truncate(max_attempts);
+
+        let deadline = now().as_secs() + options.timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let mut attempted_operations = HashSet::new();
+        for gateway in gateways {
+            let remaining_secs = deadline.saturating_sub(now().as_secs());
+            if remaining_secs == 0 {
+                return Err(MutinyError::PaymentTimeout);
+            }
+
+            let gateway_id = gateway.as_ref().map(|g| g.gateway_id);
+            match self
+                .pay_invoice_with_gateway(
+                    invoice.clone(),
+                    labels.clone(),
+                    gateway,
+                    remaining_secs * 1_000,
+                    &mut attempted_operations,
+                )
+                .await
+            {
+                Err(MutinyError::RoutingFailed) => {
+                    log_warn!(
+                        self.logger,
+                        "payment failed to route through gateway {gateway_id:?}, trying next"
+                    );
+                }
+                res => return res,
+            }
+        }
+
+        Err(MutinyError::RoutingFailed)
+    }
+
+    async fn pay_invoice_with_gateway(
+        &self,
+        invoice: Bolt11Invoice,
+        labels: Vec<String>,
+        gateway: Option<LightningGateway>,
+        timeout_ms: u64,
+        attempted_operations: &mut HashSet<OperationId>,
     ) -> Result<MutinyInvoice, MutinyError> {
         let inbound = false;
 
@@ -739,11 +1582,24 @@ impl<S: MutinyStorage> FederationClient<S> {
             .get_first_module::<LightningClientModule>();
 
         let fedimint_invoice = convert_to_fedimint_invoice(&invoice);
-        let gateway = self.gateway.read().await;
         let outgoing_payment = lightning_module
-            .pay_bolt11_invoice(gateway.clone(), fedimint_invoice, ())
+            .pay_bolt11_invoice(gateway, fedimint_invoice, ())
             .await?;
 
+        // a retry must be a new operation, otherwise we would just be
+        // watching the outcome of the attempt that already failed
+        let operation_id = match outgoing_payment.payment_type {
+            fedimint_ln_client::PayType::Internal(id) => id,
+            fedimint_ln_client::PayType::Lightning(id) => id,
+        };
+        if !attempted_operations.insert(operation_id) {
+            log_error!(
+                self.logger,
+                "fedimint reused operation {operation_id:?} when retrying payment"
+            );
+            return Err(MutinyError::NonUniquePaymentHash);
+        }
+
         // Save after payment was initiated successfully
         let mut stored_payment: MutinyInvoice = invoice.clone().into();
         stored_payment.inbound = inbound;
@@ -762,7 +1618,7 @@ impl<S: MutinyStorage> FederationClient<S> {
                             o,
                             process_pay_state_internal,
                             stored_payment,
-                            Some(DEFAULT_PAYMENT_TIMEOUT * 1_000),
+                            Some(timeout_ms),
                             self.stop.clone(),
                             Arc::clone(&self.logger),
                         )
@@ -779,7 +1635,7 @@ impl<S: MutinyStorage> FederationClient<S> {
                             o,
                             process_pay_state_ln,
                             stored_payment,
-                            Some(DEFAULT_PAYMENT_TIMEOUT * 1_000),
+                            Some(timeout_ms),
                             self.stop.clone(),
                             Arc::clone(&self.logger),
                         )
@@ -804,12 +1660,16 @@ impl<S: MutinyStorage> FederationClient<S> {
                 let storage_clone = self.storage.clone();
                 let esplora_clone = self.esplora.clone();
                 let stop = self.stop.clone();
+                let event_broadcaster = self.event_broadcaster.clone();
                 spawn(async move {
-                    let operation = fedimint_client_clone
+                    let Some(operation) = fedimint_client_clone
                         .operation_log()
                         .get_operation(id)
                         .await
-                        .expect("just created it");
+                    else {
+                        log_error!(logger_clone, "operation {id:?} not found, not subscribing");
+                        return;
+                    };
 
                     subscribe_operation_ext(
                         operation,
@@ -819,6 +1679,7 @@ impl<S: MutinyStorage> FederationClient<S> {
                         logger_clone,
                         stop,
                         storage_clone,
+                        event_broadcaster,
                     );
                 });
 
@@ -871,12 +1732,7 @@ impl<S: MutinyStorage> FederationClient<S> {
         self.storage.set_address_labels(send_to, labels)?;
 
         // subscribe
-        let operation = self
-            .fedimint_client
-            .operation_log()
-            .get_operation(op_id)
-            .await
-            .expect("just created it");
+        let operation = self.get_operation(op_id).await?;
 
         // Subscribe for a little bit, just to hopefully get transaction id
         process_operation_until_timeout(
@@ -888,6 +1744,7 @@ impl<S: MutinyStorage> FederationClient<S> {
             self.esplora.clone(),
             Some(DEFAULT_PAYMENT_TIMEOUT * 1_000),
             self.stop.clone(),
+            self.event_broadcaster.clone(),
         )
         .await;
 
@@ -899,12 +1756,7 @@ impl<S: MutinyStorage> FederationClient<S> {
         }
 
         // keep subscribing if txid wasn't retrieved, but then return timeout
-        let operation = self
-            .fedimint_client
-            .operation_log()
-            .get_operation(op_id)
-            .await
-            .expect("just created it");
+        let operation = self.get_operation(op_id).await?;
         self.subscribe_operation(operation, op_id);
 
         Err(MutinyError::PaymentTimeout)
@@ -1009,23 +1861,8 @@ pub(crate) async fn get_federation_identity(
             logger,
             "Getting config for {federation_id} from meta_external_url: {url}"
         );
-        let http_client = reqwest::Client::new();
-        let request = http_client.request(Method::GET, url);
-
-        match fetch_with_timeout(&http_client, request.build().expect("should build req")).await {
-            Ok(r) => match r.json::<FederationMetaConfig>().await {
-                Ok(c) =>
-                {
-                    #[allow(clippy::map_clone)]
-                    c.federations
-                        .get(&federation_id.to_string())
-                        .map(|f| f.clone())
-                }
-                Err(e) => {
-                    log_error!(logger, "Error parsing meta config: {e}");
-                    None
-                }
-            },
+        match fetch_external_meta(url, &federation_id).await {
+            Ok(c) => c,
             Err(e) => {
                 log_error!(logger, "Error fetching meta config: {e}");
                 None
@@ -1083,7 +1920,25 @@ pub(crate) async fn get_federation_identity(
     }
 }
 
-fn merge_values<T>(a: Option<T>, b: Option<T>) -> Option<T> {
+/// Fetches a federation's meta from its `meta_external_url`
+pub(crate) async fn fetch_external_meta(
+    url: &str,
+    federation_id: &FederationId,
+) -> Result<Option<FederationMeta>, MutinyError> {
+    let http_client = reqwest::Client::new();
+    let request = http_client.request(Method::GET, url);
+
+    let response =
+        fetch_with_timeout(&http_client, request.build().expect("should build req")).await?;
+    let mut config = response
+        .json::<FederationMetaConfig>()
+        .await
+        .map_err(|e| MutinyError::Other(anyhow::anyhow!("Error parsing meta config: {e}")))?;
+
+    Ok(config.federations.remove(&federation_id.to_string()))
+}
+
+pub(crate) fn merge_values<T>(a: Option<T>, b: Option<T>) -> Option<T> {
     match (a, b) {
         // If a has value return that; otherwise, use the one from b if available.
         (Some(val), _) => Some(val),
@@ -1100,6 +1955,7 @@ fn subscribe_operation_ext<S: MutinyStorage>(
     logger: Arc<MutinyLogger>,
     stop: Arc<AtomicBool>,
     storage: S,
+    event_broadcaster: EventBroadcaster,
 ) {
     spawn(async move {
         process_operation_until_timeout(
@@ -1111,6 +1967,7 @@ fn subscribe_operation_ext<S: MutinyStorage>(
             esplora,
             None,
             stop,
+            event_broadcaster,
         )
         .await;
     });
@@ -1118,6 +1975,8 @@ fn subscribe_operation_ext<S: MutinyStorage>(
 
 fn maybe_update_after_checking_fedimint<S: MutinyStorage>(
     mut updated_invoice: MutinyInvoice,
+    federation_id: FederationId,
+    event_broadcaster: &EventBroadcaster,
     logger: Arc<MutinyLogger>,
     storage: S,
 ) -> Result<MutinyInvoice, MutinyError> {
@@ -1125,6 +1984,8 @@ fn maybe_update_after_checking_fedimint<S: MutinyStorage>(
         HTLCStatus::Succeeded | HTLCStatus::Failed => {
             let hash = updated_invoice.payment_hash.into_32();
             let inbound = updated_invoice.inbound;
+            let previous_status =
+                read_payment_info(&storage, &hash, inbound, &logger).map(|i| i.status);
             updated_invoice.last_updated = now().as_secs();
             let payment_info = PaymentInfo::from(updated_invoice.clone());
             log_debug!(
@@ -1134,32 +1995,234 @@ fn maybe_update_after_checking_fedimint<S: MutinyStorage>(
                 payment_info.last_update
             );
             persist_payment_info(&storage, &hash, &payment_info, inbound)?;
+
+            // only notify subscribers the first time we see the final status
+            if previous_status.as_ref() != Some(&updated_invoice.status) {
+                let payment_hash = updated_invoice.payment_hash;
+                let federation_id = Some(federation_id);
+                let event = match (&updated_invoice.status, inbound) {
+                    (HTLCStatus::Succeeded, true) => MutinyEvent::PaymentReceived {
+                        payment_hash,
+                        amount_msat: updated_invoice.amount_sats.unwrap_or(0) * 1_000,
+                        federation_id,
+                    },
+                    (HTLCStatus::Succeeded, false) => MutinyEvent::PaymentSent {
+                        payment_hash,
+                        fee_paid_msat: updated_invoice.fees_paid.map(|f| f * 1_000),
+                        federation_id,
+                    },
+                    _ => MutinyEvent::PaymentFailed {
+                        payment_hash,
+                        federation_id,
+                    },
+                };
+                event_broadcaster.broadcast(event);
+            }
         }
         HTLCStatus::Pending | HTLCStatus::InFlight => (),
     }
 
-    Ok(updated_invoice)
+    Ok(updated_invoice)
+}
+
+impl<S: MutinyStorage> FedimintClient for FederationClient<S> {
+    async fn claim_external_receive(
+        &self,
+        secret_key: &SecretKey,
+        tweaks: Vec<u64>,
+    ) -> Result<(), MutinyError> {
+        self.claim_external_receive(secret_key, tweaks).await
+    }
+}
+
+fn sats_round_up(amount: &Amount) -> u64 {
+    Amount::from_msats(amount.msats + 999).sats_round_down()
+}
+
+// The number of guardians needed for consensus, allowing for up to a third to be faulty
+fn consensus_threshold(guardians: usize) -> usize {
+    guardians - guardians.saturating_sub(1) / 3
+}
+
+fn federation_health_warnings(
+    guardians: &[GuardianHealth],
+    threshold: usize,
+    expiry_timestamp: Option<u64>,
+    now: u64,
+) -> Vec<FederationHealthWarning> {
+    let mut warnings = vec![];
+
+    let online = guardians.iter().filter(|g| g.reachable).count();
+    if online < threshold {
+        warnings.push(FederationHealthWarning::NoConsensus { online, threshold });
+    } else if online < guardians.len() {
+        warnings.push(FederationHealthWarning::GuardiansOffline {
+            offline: guardians.len() - online,
+            total: guardians.len(),
+        });
+    }
+
+    let max_session = guardians.iter().filter_map(|g| g.session_count).max();
+    let max_height = guardians.iter().filter_map(|g| g.block_height).max();
+    for g in guardians.iter().filter(|g| g.reachable) {
+        let session_behind = match (g.session_count, max_session) {
+            (Some(s), Some(max)) => max.saturating_sub(s) > 1,
+            _ => false,
+        };
+        let height_behind = match (g.block_height, max_height) {
+            (Some(h), Some(max)) => max.saturating_sub(h) > GUARDIAN_MAX_BLOCKS_BEHIND,
+            _ => false,
+        };
+        if session_behind || height_behind {
+            warnings.push(FederationHealthWarning::GuardianLagging { peer_id: g.peer_id });
+        }
+    }
+
+    if let Some(expiry_timestamp) = expiry_timestamp {
+        if expiry_timestamp <= now {
+            warnings.push(FederationHealthWarning::Expired { expiry_timestamp });
+        } else if expiry_timestamp - now <= FEDERATION_EXPIRY_WARNING.as_secs() {
+            warnings.push(FederationHealthWarning::ExpiringSoon { expiry_timestamp });
+        }
+    }
+
+    warnings
+}
+
+fn parse_gateway_id(gateway_id: &str) -> Result<String, MutinyError> {
+    fedimint_ln_common::bitcoin::secp256k1::PublicKey::from_str(gateway_id)
+        .map(|p| p.to_string())
+        .map_err(|_| MutinyError::InvalidArgumentsError)
+}
+
+// Marks the given gateways as seen now and returns the updated preferences.
+// Only saves when a gateway is new or was last recorded a while ago.
+fn record_gateways_seen<S: MutinyStorage>(
+    storage: &S,
+    federation_id: FederationId,
+    gateways: &[LightningGatewayAnnouncement],
+) -> Result<GatewayPreferences, MutinyError> {
+    let mut preferences = storage.get_gateway_preferences(&federation_id)?;
+    if mark_gateways_seen(&mut preferences, gateways, now().as_secs()) {
+        storage.set_gateway_preferences(&federation_id, &preferences)?;
+    }
+
+    Ok(preferences)
 }
 
-impl<S: MutinyStorage> FedimintClient for FederationClient<S> {
-    async fn claim_external_receive(
-        &self,
-        secret_key: &SecretKey,
-        tweaks: Vec<u64>,
-    ) -> Result<(), MutinyError> {
-        self.claim_external_receive(secret_key, tweaks).await
+// Updates last seen times that are missing or stale, returns if any changed
+fn mark_gateways_seen(
+    preferences: &mut GatewayPreferences,
+    gateways: &[LightningGatewayAnnouncement],
+    now: u64,
+) -> bool {
+    let mut changed = false;
+    for g in gateways {
+        let last_seen = preferences
+            .last_seen
+            .entry(g.info.gateway_id.to_string())
+            .or_default();
+        if now.saturating_sub(*last_seen) >= GATEWAY_SEEN_INTERVAL_SECS {
+            *last_seen = now;
+            changed = true;
+        }
     }
+    changed
 }
 
-fn sats_round_up(amount: &Amount) -> u64 {
-    Amount::from_msats(amount.msats + 999).sats_round_down()
+// Select the gateway to use for receiving, and for paying when we
+// can't pick one per payment
+async fn select_active_gateway<S: MutinyStorage>(
+    fedimint_client: &ClientHandleArc,
+    storage: &S,
+    logger: &MutinyLogger,
+) -> Option<LightningGateway> {
+    let federation_id = fedimint_client.federation_id();
+    let lightning_module = fedimint_client.get_first_module::<LightningClientModule>();
+
+    let gateways = lightning_module.list_gateways().await;
+    let preferences = match record_gateways_seen(storage, federation_id, &gateways) {
+        Ok(p) => p,
+        Err(e) => {
+            log_error!(logger, "Could not read gateway preferences: {e}");
+            GatewayPreferences::default()
+        }
+    };
+
+    let a = get_gateway_preference(gateways, federation_id, &preferences)?;
+    log_info!(
+        logger,
+        "Setting active gateway for federation {federation_id}: {a}"
+    );
+
+    lightning_module.select_gateway(&a).await
+}
+
+// Unvetted gateways need to charge enough that they are likely to be real
+fn is_viable_gateway(gateway: &LightningGatewayAnnouncement) -> bool {
+    let fees = gateway.info.fees;
+    gateway.vetted || (fees.base_msat >= 1_000 && fees.proportional_millionths >= 100)
+}
+
+// The fee a gateway charges for paying `amount_msat`
+fn gateway_fee_msat(fees: &RoutingFees, amount_msat: u64) -> u64 {
+    let proportional = amount_msat as u128 * fees.proportional_millionths as u128 / 1_000_000;
+    fees.base_msat as u64 + proportional as u64
+}
+
+// Order the gateways we can pay through, best first.
+// A pinned gateway always comes first and excluded gateways are dropped,
+// the rest are ordered by the fee they would charge for the amount.
+// Gateways that aren't viable are only kept, after the viable ones,
+// when the user allows falling back to them.
+fn rank_gateways_for_payment(
+    gateways: Vec<LightningGatewayAnnouncement>,
+    preferences: &GatewayPreferences,
+    amount_msat: u64,
+) -> Vec<LightningGateway> {
+    let mut gateways: Vec<LightningGatewayAnnouncement> = gateways
+        .into_iter()
+        .filter(|g| {
+            let gateway_id = g.info.gateway_id.to_string();
+            !preferences.excluded.contains(&gateway_id)
+                && (preferences.allow_unvetted_fallback
+                    || preferences.pinned.as_ref() == Some(&gateway_id)
+                    || is_viable_gateway(g))
+        })
+        .collect();
+
+    gateways.sort_by_key(|g| {
+        let pinned = preferences.pinned.as_ref() == Some(&g.info.gateway_id.to_string());
+        let fee = gateway_fee_msat(&g.info.fees, amount_msat);
+        (!pinned, !is_viable_gateway(g), fee)
+    });
+
+    gateways.into_iter().map(|g| g.info).collect()
 }
 
 // Get a preferred gateway from a federation
 fn get_gateway_preference(
     gateways: Vec<LightningGatewayAnnouncement>,
     federation_id: FederationId,
+    preferences: &GatewayPreferences,
 ) -> Option<fedimint_ln_common::bitcoin::secp256k1::PublicKey> {
+    let gateways: Vec<LightningGatewayAnnouncement> = gateways
+        .into_iter()
+        .filter(|g| {
+            !preferences
+                .excluded
+                .contains(&g.info.gateway_id.to_string())
+        })
+        .collect();
+
+    // a gateway the user pinned takes the highest priority
+    if let Some(g) = gateways
+        .iter()
+        .find(|g| preferences.pinned.as_ref() == Some(&g.info.gateway_id.to_string()))
+    {
+        return Some(g.info.gateway_id);
+    }
+
     let mut active_choice: Option<LightningGatewayAnnouncement> = None;
 
     let signet_gateway_id =
@@ -1191,8 +2254,7 @@ fn get_gateway_preference(
         }
 
         // if not vetted, make sure fee is high enough
-        let fees = g.info.fees;
-        if fees.base_msat >= 1_000 && fees.proportional_millionths >= 100 {
+        if is_viable_gateway(g) {
             // only select gateways that support private payments, unless we don't have a gateway
             if g.info.supports_private_payments || active_choice.is_none() {
                 active_choice = Some(g.clone());
@@ -1248,7 +2310,9 @@ async fn process_operation_until_timeout<S: MutinyStorage>(
     esplora: Arc<AsyncClient>,
     timeout: Option<u64>,
     stop: Arc<AtomicBool>,
+    event_broadcaster: EventBroadcaster,
 ) {
+    let federation_id = fedimint_client.federation_id();
     let module_type = entry.operation_module_kind();
     if module_type == LightningCommonInit::KIND.as_str() {
         let lightning_meta: LightningOperationMeta = entry.meta();
@@ -1335,6 +2399,8 @@ async fn process_operation_until_timeout<S: MutinyStorage>(
         if let Some(updated_invoice) = updated_invoice {
             match maybe_update_after_checking_fedimint(
                 updated_invoice.clone(),
+                federation_id,
+                &event_broadcaster,
                 logger.clone(),
                 storage,
             ) {
@@ -1373,6 +2439,8 @@ async fn process_operation_until_timeout<S: MutinyStorage>(
                             esplora,
                             timeout,
                             stop,
+                            federation_id,
+                            event_broadcaster,
                             logger,
                         )
                         .await
@@ -1408,6 +2476,8 @@ async fn process_operation_until_timeout<S: MutinyStorage>(
                             esplora,
                             timeout,
                             stop,
+                            federation_id,
+                            event_broadcaster,
                             logger,
                         )
                         .await
@@ -1422,6 +2492,51 @@ async fn process_operation_until_timeout<S: MutinyStorage>(
                 unimplemented!("User RBF withdrawals not supported yet")
             }
         }
+    } else if module_type == MintCommonInit::KIND.as_str() {
+        let mint_meta: MintOperationMeta = entry.meta();
+        let mint_module = fedimint_client.get_first_module::<MintClientModule>();
+
+        let internal_id = operation_id.0.to_lower_hex_string();
+        let Some(transfer) = get_ecash_transfer(&storage, &internal_id, &logger) else {
+            log_debug!(
+                logger,
+                "No ecash transfer stored for operation {internal_id}"
+            );
+            return;
+        };
+
+        let updated_transfer = match mint_meta.variant {
+            MintOperationMetaVariant::SpendOOB { .. } => {
+                match mint_module.subscribe_spend_notes(operation_id).await {
+                    Ok(o) => {
+                        process_ecash_outcome(o, transfer, timeout, stop, logger.clone()).await
+                    }
+                    Err(e) => {
+                        log_error!(logger, "Error trying to process stream outcome: {e}");
+                        return;
+                    }
+                }
+            }
+            MintOperationMetaVariant::Reissuance { .. } => {
+                match mint_module
+                    .subscribe_reissue_external_notes(operation_id)
+                    .await
+                {
+                    Ok(o) => {
+                        process_ecash_outcome(o, transfer, timeout, stop, logger.clone()).await
+                    }
+                    Err(e) => {
+                        log_error!(logger, "Error trying to process stream outcome: {e}");
+                        return;
+                    }
+                }
+            }
+        };
+
+        match persist_ecash_transfer(&storage, &updated_transfer) {
+            Ok(_) => log_debug!(logger, "subscribed and updated ecash transfer"),
+            Err(e) => log_error!(logger, "could not update ecash transfer: {e}"),
+        }
     } else {
         log_warn!(logger, "Unknown module type: {module_type}")
     }
@@ -1528,6 +2643,87 @@ where
     invoice
 }
 
+async fn process_ecash_outcome<U>(
+    stream_or_outcome: UpdateStreamOrOutcome<U>,
+    mut transfer: EcashTransfer,
+    timeout: Option<u64>,
+    stop: Arc<AtomicBool>,
+    logger: Arc<MutinyLogger>,
+) -> EcashTransfer
+where
+    U: Into<HTLCStatus>
+        + Clone
+        + Serialize
+        + DeserializeOwned
+        + Debug
+        + MaybeSend
+        + MaybeSync
+        + 'static,
+{
+    let previous_status = transfer.status.clone();
+    match stream_or_outcome {
+        UpdateStreamOrOutcome::Outcome(outcome) => {
+            transfer.status = outcome.into();
+            log_trace!(logger, "Outcome received: {}", transfer.status);
+        }
+        UpdateStreamOrOutcome::UpdateStream(mut s) => {
+            // break out after sleep time or check stop signal
+            log_trace!(logger, "start timeout stream futures");
+            loop {
+                let timeout_future = if let Some(t) = timeout {
+                    sleep(t as i32)
+                } else {
+                    sleep(1_000_i32)
+                };
+
+                let mut stream_fut = Box::pin(s.next()).fuse();
+                let delay_fut = Box::pin(timeout_future).fuse();
+                pin_mut!(delay_fut);
+
+                select! {
+                    outcome_option = stream_fut => {
+                        let Some(outcome) = outcome_option else {
+                            log_trace!(logger, "Outcome stream ended");
+                            break;
+                        };
+                        log_trace!(logger, "Streamed Outcome received: {:?}", outcome);
+                        transfer.status = outcome.into();
+
+                        if matches!(transfer.status, HTLCStatus::Succeeded | HTLCStatus::Failed) {
+                            log_trace!(logger, "Streamed Outcome final, returning");
+                            break;
+                        }
+                    }
+                    _ = delay_fut => {
+                        if timeout.is_none() {
+                            if stop.load(Ordering::Relaxed)  {
+                                break;
+                            }
+                        } else {
+                            log_debug!(
+                                logger,
+                                "Timeout reached, exiting loop for ecash {}",
+                                transfer.operation_id
+                            );
+                            break;
+                        }
+                    }
+                }
+            }
+            log_trace!(
+                logger,
+                "Done with stream outcome, status: {}",
+                transfer.status
+            );
+        }
+    }
+
+    if transfer.status != previous_status {
+        transfer.last_updated = now().as_secs();
+    }
+    transfer
+}
+
 // FIXME: refactor
 #[allow(clippy::too_many_arguments)]
 async fn process_onchain_withdraw_outcome<S: MutinyStorage>(
@@ -1540,6 +2736,8 @@ async fn process_onchain_withdraw_outcome<S: MutinyStorage>(
     esplora: Arc<AsyncClient>,
     timeout: Option<u64>,
     stop: Arc<AtomicBool>,
+    federation_id: FederationId,
+    event_broadcaster: EventBroadcaster,
     logger: Arc<MutinyLogger>,
 ) {
     let internal_id = Txid::from_slice(&operation_id.0).expect("should convert");
@@ -1592,7 +2790,7 @@ async fn process_onchain_withdraw_outcome<S: MutinyStorage>(
                             }
 
                             // we need to get confirmations for this txid and update
-                            subscribe_onchain_confirmation_check(storage.clone(), esplora.clone(), txid, updated_transaction_details, stop, logger.clone()).await;
+                            subscribe_onchain_confirmation_check(storage.clone(), esplora.clone(), txid, updated_transaction_details, stop, federation_id, event_broadcaster, logger.clone()).await;
 
                             break
                         },
@@ -1632,12 +2830,15 @@ async fn process_onchain_withdraw_outcome<S: MutinyStorage>(
     log_trace!(logger, "Done with stream outcome",);
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn subscribe_onchain_confirmation_check<S: MutinyStorage>(
     storage: S,
     esplora: Arc<AsyncClient>,
     txid: Txid,
     mut transaction_details: TransactionDetails,
     stop: Arc<AtomicBool>,
+    federation_id: FederationId,
+    event_broadcaster: EventBroadcaster,
     logger: Arc<MutinyLogger>,
 ) {
     spawn(async move {
@@ -1650,13 +2851,19 @@ async fn subscribe_onchain_confirmation_check<S: MutinyStorage>(
                 Ok(s) => {
                     if s.confirmed {
                         log_info!(logger, "Transaction confirmed");
+                        let height = s.block_height.expect("confirmed");
                         transaction_details.confirmation_time = ConfirmationTime::Confirmed {
-                            height: s.block_height.expect("confirmed"),
+                            height,
                             time: s.block_time.unwrap_or(now().as_secs()),
                         };
                         match persist_transaction_details(&storage, &transaction_details) {
                             Ok(_) => {
                                 log_info!(logger, "Transaction updated");
+                                event_broadcaster.broadcast(MutinyEvent::TransactionConfirmed {
+                                    txid,
+                                    height,
+                                    federation_id: Some(federation_id),
+                                });
                                 break;
                             }
                             Err(e) => {
@@ -1682,6 +2889,43 @@ async fn subscribe_onchain_confirmation_check<S: MutinyStorage>(
     });
 }
 
+// Stops tracking an address nothing was sent to before it expired,
+// returns if the deposit changed
+fn expire_unused_deposit(deposit: &mut FederationDeposit, now: u64) -> bool {
+    if deposit.status == DepositStatus::WaitingForTransaction && now > deposit.expires_at {
+        deposit.status = DepositStatus::Expired;
+        deposit.last_updated = now;
+        true
+    } else {
+        false
+    }
+}
+
+// Updates the deposit we are tracking for the operation,
+// returns false if we are not tracking one
+fn update_federation_deposit<S: MutinyStorage>(
+    storage: &S,
+    operation_id: OperationId,
+    logger: &MutinyLogger,
+    update: impl FnOnce(&mut FederationDeposit),
+) -> bool {
+    match get_federation_deposit(storage, &operation_id.0.to_lower_hex_string()) {
+        Ok(Some(mut deposit)) => {
+            update(&mut deposit);
+            deposit.last_updated = now().as_secs();
+            if let Err(e) = persist_federation_deposit(storage, &deposit) {
+                log_error!(logger, "Error updating deposit: {e}");
+            }
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            log_error!(logger, "Error reading deposit: {e}");
+            false
+        }
+    }
+}
+
 // FIXME refactor
 #[allow(clippy::too_many_arguments)]
 async fn process_onchain_deposit_outcome<S: MutinyStorage>(
@@ -1692,6 +2936,8 @@ async fn process_onchain_deposit_outcome<S: MutinyStorage>(
     esplora: Arc<AsyncClient>,
     timeout: Option<u64>,
     stop: Arc<AtomicBool>,
+    federation_id: FederationId,
+    event_broadcaster: EventBroadcaster,
     logger: Arc<MutinyLogger>,
 ) {
     let mut s = stream_or_outcome.into_stream();
@@ -1724,6 +2970,16 @@ async fn process_onchain_deposit_outcome<S: MutinyStorage>(
                             let internal_id = Txid::from_slice(&operation_id.0).expect("should convert");
                             let output = tx.btc_transaction.output[tx.out_idx as usize].clone();
 
+                            // a tracked deposit shows the pending transaction itself
+                            let tracked = update_federation_deposit(&storage, operation_id, &logger, |d| {
+                                d.txid = Some(txid);
+                                d.amount = Some(output.value);
+                                d.status = DepositStatus::WaitingForConfirmation;
+                            });
+                            if tracked {
+                                continue;
+                            }
+
                             let updated_transaction_details = TransactionDetails {
                                 transaction: None,
                                 txid: Some(txid),
@@ -1751,6 +3007,13 @@ async fn process_onchain_deposit_outcome<S: MutinyStorage>(
                             let internal_id = Txid::from_slice(&operation_id.0).expect("should convert");
                             let output = tx.btc_transaction.output[tx.out_idx as usize].clone();
 
+                            update_federation_deposit(&storage, operation_id, &logger, |d| {
+                                d.txid = Some(txid);
+                                d.amount = Some(output.value);
+                                d.confirmations = d.confirmations.max(d.confirmations_required);
+                                d.status = DepositStatus::Confirmed;
+                            });
+
                             // store as confirmed 0 block height until we can check esplora after
                             let transaction_details_update = TransactionDetails {
                                 transaction: None,
@@ -1764,15 +3027,21 @@ async fn process_onchain_deposit_outcome<S: MutinyStorage>(
                             };
 
                             // we need to get confirmations for this txid and update
-                            subscribe_onchain_confirmation_check(storage.clone(), esplora.clone(), txid, transaction_details_update, stop.clone(), logger.clone()).await;
+                            subscribe_onchain_confirmation_check(storage.clone(), esplora.clone(), txid, transaction_details_update, stop.clone(), federation_id, event_broadcaster.clone(), logger.clone()).await;
                         }
                         fedimint_wallet_client::DepositState::Claimed(_) => {
                             // Nothing really to change from confirmed to claimed
                             log_debug!(logger, "Transaction claimed");
+                            update_federation_deposit(&storage, operation_id, &logger, |d| {
+                                d.status = DepositStatus::Claimed;
+                            });
                             break;
                         }
                         fedimint_wallet_client::DepositState::Failed(e) => {
                             log_error!(logger, "Transaction failed: {e}");
+                            update_federation_deposit(&storage, operation_id, &logger, |d| {
+                                d.status = DepositStatus::Failed;
+                            });
 
                             break;
                         }
@@ -2067,6 +3336,7 @@ fn gateway_preference() {
         PublicKey::from_str(UNVETTED_GATEWAY_KEY_LOW_FEE).unwrap();
 
     let random_federation_id = FederationId::dummy();
+    let no_preferences = GatewayPreferences::default();
 
     // Create some sample LightningGatewayAnnouncement structs to test with
     let signet_gateway = LightningGatewayAnnouncement {
@@ -2195,19 +3465,19 @@ fn gateway_preference() {
     // Test that the method returns a Gateway ID when given a matching federation ID and gateway ID
     let signet_federation_id = FederationId::from_str(SIGNET_FEDERATION).unwrap();
     assert_eq!(
-        get_gateway_preference(gateways.clone(), signet_federation_id),
+        get_gateway_preference(gateways.clone(), signet_federation_id, &no_preferences),
         Some(PublicKey::from_str(SIGNET_GATEWAY).unwrap())
     );
 
     let mainnet_federation_id = FederationId::from_str(MAINNET_FEDERATION).unwrap();
     assert_eq!(
-        get_gateway_preference(gateways.clone(), mainnet_federation_id),
+        get_gateway_preference(gateways.clone(), mainnet_federation_id, &no_preferences),
         Some(PublicKey::from_str(MAINNET_GATEWAY).unwrap())
     );
 
     // Test that the method returns the first vetted gateway if none of the gateways match the federation ID
     assert_eq!(
-        get_gateway_preference(gateways, random_federation_id),
+        get_gateway_preference(gateways, random_federation_id, &no_preferences),
         Some(vetted_gateway_pubkey)
     );
 
@@ -2219,7 +3489,7 @@ fn gateway_preference() {
         vetted_gateway.clone(),
     ];
     assert_eq!(
-        get_gateway_preference(gateways, random_federation_id),
+        get_gateway_preference(gateways, random_federation_id, &no_preferences),
         Some(vetted_gateway_pubkey)
     );
 
@@ -2230,7 +3500,7 @@ fn gateway_preference() {
         unvetted_gateway_high_fee.clone(),
     ];
     assert_eq!(
-        get_gateway_preference(gateways, random_federation_id),
+        get_gateway_preference(gateways, random_federation_id, &no_preferences),
         Some(unvetted_gateway_high_fee_pubkey)
     );
 
@@ -2239,12 +3509,240 @@ fn gateway_preference() {
     let gateways = vec![
         signet_gateway.clone(),
         mainnet_gateway,
-        unvetted_gateway_low_fee,
+        unvetted_gateway_low_fee.clone(),
     ];
     assert_eq!(
-        get_gateway_preference(gateways, random_federation_id),
+        get_gateway_preference(gateways, random_federation_id, &no_preferences),
         Some(signet_gateway.info.gateway_id)
     );
+
+    // Test that a pinned gateway is always chosen and excluded gateways are skipped
+    let pinned_preferences = GatewayPreferences {
+        pinned: Some(unvetted_gateway_low_fee_pubkey.to_string()),
+        excluded: HashSet::from([vetted_gateway_pubkey.to_string()]),
+        last_seen: HashMap::new(),
+        allow_unvetted_fallback: false,
+    };
+    let gateways = vec![
+        vetted_gateway.clone(),
+        unvetted_gateway_high_fee.clone(),
+        unvetted_gateway_low_fee.clone(),
+    ];
+    assert_eq!(
+        get_gateway_preference(gateways.clone(), random_federation_id, &pinned_preferences),
+        Some(unvetted_gateway_low_fee_pubkey)
+    );
+    let excluded_preferences = GatewayPreferences {
+        pinned: None,
+        ..pinned_preferences.clone()
+    };
+    assert_eq!(
+        get_gateway_preference(gateways, random_federation_id, &excluded_preferences),
+        Some(unvetted_gateway_high_fee_pubkey)
+    );
+
+    // Test that payments try the cheapest viable gateways first
+    // and only fall back to the others when allowed
+    let gateways = vec![
+        signet_gateway.clone(),
+        unvetted_gateway_high_fee.clone(),
+        vetted_gateway,
+        unvetted_gateway_low_fee.clone(),
+    ];
+    let ranked = |preferences: &GatewayPreferences| {
+        rank_gateways_for_payment(gateways.clone(), preferences, 1_000_000)
+            .into_iter()
+            .map(|g| g.gateway_id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ranked(&no_preferences),
+        vec![vetted_gateway_pubkey, unvetted_gateway_high_fee_pubkey]
+    );
+    let fallback_preferences = GatewayPreferences {
+        allow_unvetted_fallback: true,
+        ..GatewayPreferences::default()
+    };
+    assert_eq!(
+        ranked(&fallback_preferences),
+        vec![
+            vetted_gateway_pubkey,
+            unvetted_gateway_high_fee_pubkey,
+            unvetted_gateway_low_fee_pubkey,
+            signet_gateway.info.gateway_id,
+        ]
+    );
+    // a pinned gateway is used even when it isn't viable
+    assert_eq!(
+        ranked(&pinned_preferences),
+        vec![
+            unvetted_gateway_low_fee_pubkey,
+            unvetted_gateway_high_fee_pubkey,
+        ]
+    );
+
+    // Test that last seen is only updated when missing or stale
+    let mut preferences = GatewayPreferences::default();
+    let seen = [unvetted_gateway_high_fee.clone()];
+    assert!(mark_gateways_seen(&mut preferences, &seen, 1_000));
+    assert!(!mark_gateways_seen(&mut preferences, &seen, 1_001));
+    assert_eq!(
+        preferences
+            .last_seen
+            .get(&unvetted_gateway_high_fee_pubkey.to_string()),
+        Some(&1_000)
+    );
+    let later = 1_000 + GATEWAY_SEEN_INTERVAL_SECS;
+    assert!(mark_gateways_seen(&mut preferences, &seen, later));
+    assert!(mark_gateways_seen(
+        &mut preferences,
+        &[unvetted_gateway_low_fee],
+        later
+    ));
+    assert!(!mark_gateways_seen(&mut preferences, &[], later));
+}
+
+#[cfg(test)]
+fn federation_health() {
+    use super::*;
+
+    let guardian =
+        |peer_id: u16, session_count: Option<u64>, block_height: Option<u64>| GuardianHealth {
+            peer_id,
+            name: format!("guardian {peer_id}"),
+            url: format!("wss://guardian{peer_id}.example.com"),
+            reachable: session_count.is_some() || block_height.is_some(),
+            session_count,
+            block_height,
+        };
+
+    assert_eq!(consensus_threshold(1), 1);
+    assert_eq!(consensus_threshold(4), 3);
+    assert_eq!(consensus_threshold(7), 5);
+
+    let now = 1_700_000_000;
+
+    // all guardians in sync and no expiry
+    let guardians = vec![
+        guardian(0, Some(100), Some(800_000)),
+        guardian(1, Some(100), Some(800_000)),
+        guardian(2, Some(99), Some(799_999)),
+        guardian(3, Some(100), Some(800_000)),
+    ];
+    assert!(federation_health_warnings(&guardians, 3, None, now).is_empty());
+
+    // one guardian down and one lagging behind
+    let guardians = vec![
+        guardian(0, Some(100), Some(800_000)),
+        guardian(1, None, None),
+        guardian(2, Some(90), Some(800_000)),
+        guardian(3, Some(100), Some(800_000)),
+    ];
+    assert_eq!(
+        federation_health_warnings(&guardians, 3, None, now),
+        vec![
+            FederationHealthWarning::GuardiansOffline {
+                offline: 1,
+                total: 4
+            },
+            FederationHealthWarning::GuardianLagging { peer_id: 2 },
+        ]
+    );
+
+    // too many guardians down to reach consensus
+    let guardians = vec![
+        guardian(0, Some(100), Some(800_000)),
+        guardian(1, None, None),
+        guardian(2, None, None),
+        guardian(3, Some(100), Some(800_000)),
+    ];
+    assert_eq!(
+        federation_health_warnings(&guardians, 3, None, now),
+        vec![FederationHealthWarning::NoConsensus {
+            online: 2,
+            threshold: 3
+        }]
+    );
+
+    // expiry warnings
+    let guardians = vec![guardian(0, Some(100), Some(800_000))];
+    let expiry_timestamp = now + 86400;
+    assert_eq!(
+        federation_health_warnings(&guardians, 1, Some(expiry_timestamp), now),
+        vec![FederationHealthWarning::ExpiringSoon { expiry_timestamp }]
+    );
+    let expiry_timestamp = now - 1;
+    assert_eq!(
+        federation_health_warnings(&guardians, 1, Some(expiry_timestamp), now),
+        vec![FederationHealthWarning::Expired { expiry_timestamp }]
+    );
+    assert!(federation_health_warnings(&guardians, 1, Some(now + 86400 * 365), now).is_empty());
+}
+
+#[cfg(test)]
+fn federation_backup() {
+    use super::*;
+
+    const INVITE_CODE: &str = "fed11qgqzc2nhwden5te0vejkg6tdd9h8gepwvejkg6tdd9h8garhduhx6at5d9h8jmn9wshxxmmd9uqqzgxg6s3evnr6m9zdxr6hxkdkukexpcs3mn7mj3g5pc5dfh63l4tj6g9zk4er";
+    let invite_code = InviteCode::from_str(INVITE_CODE).unwrap();
+    let federation_id = invite_code.federation_id();
+
+    assert_eq!(
+        resync_state_key(&federation_id),
+        format!("resync_state/{federation_id}")
+    );
+
+    let empty = FederationBackup::default();
+    let backup = FederationBackup {
+        federations: vec![invite_code.clone()],
+        timestamp: 1_000,
+    };
+    assert!(empty.same_federations(&FederationBackup::default()));
+    assert!(!empty.same_federations(&backup));
+
+    // only the federations matter, not when the backup was made
+    let later = FederationBackup {
+        federations: vec![invite_code.clone(), invite_code],
+        timestamp: 2_000,
+    };
+    assert!(backup.same_federations(&later));
+}
+
+#[cfg(test)]
+fn deposit_expiry() {
+    use super::*;
+
+    let mut deposit = FederationDeposit {
+        operation_id: "00".repeat(32),
+        federation_id: FederationId::dummy(),
+        address: "bcrt1qxyz".to_string(),
+        expected_amount: Some(10_000),
+        txid: None,
+        amount: None,
+        confirmations: 0,
+        confirmations_required: DEFAULT_DEPOSIT_CONFIRMATIONS,
+        status: DepositStatus::WaitingForTransaction,
+        labels: vec![],
+        created_at: 1_000,
+        expires_at: 1_000 + DEPOSIT_ADDRESS_EXPIRY.as_secs(),
+        last_updated: 1_000,
+    };
+    assert!(!deposit.has_transaction());
+
+    // still waiting for a transaction
+    assert!(!expire_unused_deposit(&mut deposit, deposit.expires_at));
+    assert!(deposit.is_pending());
+
+    // nothing was sent before the address expired
+    assert!(expire_unused_deposit(&mut deposit, deposit.expires_at + 1));
+    assert_eq!(deposit.status, DepositStatus::Expired);
+    assert!(!deposit.is_pending());
+    assert!(!deposit.has_transaction());
+
+    // a deposit with a transaction never expires
+    deposit.status = DepositStatus::WaitingForConfirmation;
+    assert!(!expire_unused_deposit(&mut deposit, u64::MAX));
+    assert!(deposit.has_transaction());
 }
 
 #[cfg(test)]
@@ -2266,6 +3764,114 @@ mod tests {
     fn test_gateway_preference() {
         gateway_preference();
     }
+
+    #[test]
+    fn test_federation_health() {
+        federation_health();
+    }
+
+    #[test]
+    fn test_deposit_expiry() {
+        deposit_expiry();
+    }
+
+    #[test]
+    fn test_federation_backup() {
+        federation_backup();
+    }
+
+    fn ecash_transfer(inbound: bool) -> EcashTransfer {
+        EcashTransfer {
+            operation_id: "00".repeat(32),
+            federation_id: FederationId::from_str(SIGNET_FEDERATION).unwrap(),
+            notes: String::new(),
+            amount_sats: 1_000,
+            inbound,
+            status: HTLCStatus::InFlight,
+            labels: vec![],
+            last_updated: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spend_ecash_outcome() {
+        let logger = Arc::new(MutinyLogger::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // the notes were claimed by the recipient
+        let transfer = process_ecash_outcome(
+            UpdateStreamOrOutcome::Outcome(SpendOOBState::Success),
+            ecash_transfer(false),
+            Some(1_000),
+            stop.clone(),
+            logger.clone(),
+        )
+        .await;
+        assert_eq!(transfer.status, HTLCStatus::Succeeded);
+
+        // the notes were reclaimed before the recipient claimed them
+        let updates = vec![
+            SpendOOBState::Created,
+            SpendOOBState::UserCanceledProcessing,
+            SpendOOBState::UserCanceledSuccess,
+        ];
+        let transfer = process_ecash_outcome(
+            UpdateStreamOrOutcome::UpdateStream(Box::pin(futures::stream::iter(updates))),
+            ecash_transfer(false),
+            Some(1_000),
+            stop,
+            logger,
+        )
+        .await;
+        assert_eq!(transfer.status, HTLCStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_receive_ecash_outcome() {
+        let logger = Arc::new(MutinyLogger::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let updates = vec![
+            ReissueExternalNotesState::Created,
+            ReissueExternalNotesState::Issuing,
+            ReissueExternalNotesState::Done,
+        ];
+        let transfer = process_ecash_outcome(
+            UpdateStreamOrOutcome::UpdateStream(Box::pin(futures::stream::iter(updates))),
+            ecash_transfer(true),
+            Some(1_000),
+            stop.clone(),
+            logger.clone(),
+        )
+        .await;
+        assert_eq!(transfer.status, HTLCStatus::Succeeded);
+
+        // a stream that ends early doesn't keep us waiting, even without a timeout
+        let transfer = process_ecash_outcome(
+            UpdateStreamOrOutcome::UpdateStream(Box::pin(futures::stream::iter(vec![
+                ReissueExternalNotesState::Created,
+            ]))),
+            ecash_transfer(true),
+            None,
+            stop.clone(),
+            logger.clone(),
+        )
+        .await;
+        assert_eq!(transfer.status, HTLCStatus::Pending);
+
+        // notes that were already spent fail to reissue
+        let transfer = process_ecash_outcome(
+            UpdateStreamOrOutcome::Outcome(ReissueExternalNotesState::Failed(
+                "already spent".to_string(),
+            )),
+            ecash_transfer(true),
+            Some(1_000),
+            stop,
+            logger,
+        )
+        .await;
+        assert_eq!(transfer.status, HTLCStatus::Failed);
+    }
 }
 
 #[cfg(test)]
@@ -2291,4 +3897,19 @@ mod wasm_tests {
     fn test_gateway_preference() {
         gateway_preference();
     }
+
+    #[test]
+    fn test_federation_health() {
+        federation_health();
+    }
+
+    #[test]
+    fn test_deposit_expiry() {
+        deposit_expiry();
+    }
+
+    #[test]
+    fn test_federation_backup() {
+        federation_backup();
+    }
 }
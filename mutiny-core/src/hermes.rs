@@ -18,8 +18,8 @@ use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error, log_info, log_warn};
 use lightning_invoice::Bolt11Invoice;
 use nostr::secp256k1::SecretKey;
-use nostr::{nips::nip04::decrypt, Event, JsonUtil, Keys, RelayMessage, Tag, ToBech32};
-use nostr::{prelude::decrypt_received_private_zap_message, EventBuilder};
+use nostr::EventBuilder;
+use nostr::{Event, JsonUtil, Keys, RelayMessage, ToBech32};
 use nostr::{Filter, Kind, Timestamp};
 use nostr_sdk::{Client, RelayPoolNotification};
 use reqwest::Method;
@@ -30,7 +30,8 @@ use url::Url;
 use crate::event::{HTLCStatus, MillisatAmount, PaymentInfo};
 use crate::federation::FedimintClient;
 use crate::labels::LabelStorage;
-use crate::nostr::RELAYS;
+use crate::nostr::zaps::zap_request_sender;
+use crate::nostr::{encryption, RELAYS};
 use crate::storage::persist_payment_info;
 use crate::{
     blindauth::{BlindAuthClient, SignedToken},
@@ -651,7 +652,7 @@ async fn register_name(
     Ok(res)
 }
 
-/// Decrypts a DM using the primary key
+/// Decrypts a NIP-04 or NIP-44 DM using the primary key
 fn decrypt_ecash_notification(
     dm_key: &Keys,
     pubkey: nostr::PublicKey,
@@ -659,7 +660,7 @@ fn decrypt_ecash_notification(
 ) -> Result<EcashNotification, MutinyError> {
     // decrypt the dm first
     let secret = dm_key.secret_key().expect("must have");
-    let decrypted = decrypt(secret, &pubkey, message)?;
+    let decrypted = encryption::decrypt(secret, &pubkey, message)?;
     // parse the dm into an ecash notification
     let notification = serde_json::from_str(&decrypted)?;
     Ok(notification)
@@ -707,29 +708,7 @@ async fn claim_ecash_notification<S: MutinyStorage>(
                 None => (PrivacyLevel::NotAvailable, None, None),
                 Some(zap_req) => {
                     let zap_req = Event::from_json(zap_req)?;
-                    // handle private/anon zaps
-                    let anon = zap_req.iter_tags().find_map(|tag| {
-                        if let Tag::Anon { msg } = tag {
-                            if msg.is_some() {
-                                // an Anon tag with a message is a private zap
-                                // try to decrypt the message and use that as the message
-                                Some(handle_private_zap(&zap_req, profile_key, logger))
-                            } else {
-                                // an Anon tag with no message is an anonymous zap
-                                // the content of the zap is the message
-                                Some((PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None))
-                            }
-                        } else {
-                            None
-                        }
-                    });
-
-                    // handled the anon tag, if there wasn't one, it is a public zap
-                    anon.unwrap_or((
-                        PrivacyLevel::Public,
-                        Some(zap_req.content.clone()),
-                        Some(zap_req.pubkey),
-                    ))
+                    zap_request_sender(&zap_req, profile_key, logger)
                 }
             };
 
@@ -818,34 +797,6 @@ async fn handle_ecash_notification<S: MutinyStorage>(
     Ok(())
 }
 
-fn handle_private_zap(
-    zap_req: &Event,
-    profile_key: Option<&Keys>,
-    logger: &MutinyLogger,
-) -> (PrivacyLevel, Option<String>, Option<nostr::PublicKey>) {
-    let key = match profile_key.and_then(|k| k.secret_key().ok()) {
-        Some(k) => k,
-        None => {
-            log_error!(logger, "No primary key to decrypt private zap");
-            // We can't decrypt the message, treat it as an anonymous zap
-            return (PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None);
-        }
-    };
-    // try to decrypt the message
-    match decrypt_received_private_zap_message(key, zap_req) {
-        Ok(event) => (
-            PrivacyLevel::Private,
-            Some(event.content.clone()),
-            Some(event.pubkey),
-        ),
-        Err(e) => {
-            // if we can't decrypt, treat it like it's an anonymous zap
-            log_error!(logger, "Error decrypting private zap: {e}");
-            (PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None)
-        }
-    }
-}
-
 #[cfg(test)]
 #[cfg(not(target_arch = "wasm32"))]
 mod test {
@@ -25,6 +25,7 @@ use lightning::ln::channelmanager::{
     self, ChainParameters, ChannelManager as LdkChannelManager, ChannelManagerReadArgs,
 };
 use lightning::sign::{InMemorySigner, SpendableOutputDescriptor};
+use lightning::util::config::UserConfig;
 use lightning::util::logger::Logger;
 use lightning::util::persist::Persister;
 use lightning::util::ser::{Readable, ReadableArgs, Writeable};
@@ -216,6 +217,15 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         esplora: &AsyncClient,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         log_debug!(mutiny_logger, "Reading channel manager from storage");
+        let mut config = default_user_config(accept_underpaying_htlcs);
+        // LDK refuses announced channels during the handshake by default,
+        // only allow them if the user turned off private only
+        if let Ok(policy) = self.storage.get_channel_acceptance_policy() {
+            config
+                .channel_handshake_limits
+                .force_announced_channel_preference = policy.private_only;
+        }
+
         let key = self.get_key(CHANNEL_MANAGER_KEY);
         match self.storage.get_data::<VersionedValue>(&key) {
             Ok(Some(versioned_value)) => {
@@ -229,7 +239,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
                 let bytes = FromHex::from_hex(&hex)?;
                 let res = Self::parse_channel_manager(
                     bytes,
-                    accept_underpaying_htlcs,
+                    config,
                     chain_monitor,
                     mutiny_chain,
                     fee_estimator,
@@ -252,7 +262,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
 
                 Self::create_new_channel_manager(
                     network,
-                    accept_underpaying_htlcs,
+                    config,
                     chain_monitor,
                     mutiny_chain,
                     fee_estimator,
@@ -274,7 +284,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
                 let bytes = self.read_value(CHANNEL_MANAGER_KEY)?;
                 Self::parse_channel_manager(
                     bytes,
-                    accept_underpaying_htlcs,
+                    config,
                     chain_monitor,
                     mutiny_chain,
                     fee_estimator,
@@ -290,7 +300,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
     #[allow(clippy::too_many_arguments)]
     fn parse_channel_manager(
         bytes: Vec<u8>,
-        accept_underpaying_htlcs: bool,
+        config: UserConfig,
         chain_monitor: Arc<ChainMonitor<S>>,
         mutiny_chain: Arc<MutinyChain<S>>,
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
@@ -314,7 +324,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             mutiny_chain,
             router,
             mutiny_logger.clone(),
-            default_user_config(accept_underpaying_htlcs),
+            config,
             channel_monitor_mut_references,
         );
 
@@ -340,7 +350,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn create_new_channel_manager(
         network: Network,
-        accept_underpaying_htlcs: bool,
+        config: UserConfig,
         chain_monitor: Arc<ChainMonitor<S>>,
         mutiny_chain: Arc<MutinyChain<S>>,
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
@@ -378,7 +388,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             keys_manager.clone(),
             keys_manager.clone(),
             keys_manager,
-            default_user_config(accept_underpaying_htlcs),
+            config,
             chain_params,
             utils::now().as_secs() as u32,
         );
@@ -11,7 +11,7 @@ extern crate core;
 
 pub mod auth;
 pub mod blindauth;
-mod cashu;
+pub mod cashu;
 mod chain;
 pub mod encrypt;
 pub mod error;
@@ -43,28 +43,39 @@ pub mod vss;
 #[cfg(test)]
 mod test_utils;
 
-use crate::federation::{get_federation_identity, ResyncProgress};
+use crate::blindauth::BlindAuthClient;
+use crate::cashu::{
+    CashuMintBalance, CashuMintQuote, CashuPendingSend, CashuToken, CashuTokenPreview, CashuWallet,
+    MintInfo, NUT_MELT,
+};
+use crate::federation::{
+    get_federation_identity, resync_state_key, FederationBackup, FederationRestoreProgress,
+    FederationResyncStatus, ResyncProgress, FEDERATION_BACKUP_KEY,
+    RECOVERED_FEDERATION_BACKUP_PREFIX_KEY,
+};
 pub use crate::gossip::{GOSSIP_SYNC_TIME_KEY, NETWORK_GRAPH_KEY, PROB_SCORER_KEY};
 pub use crate::keymanager::generate_seed;
 pub use crate::ldkstorage::{CHANNEL_CLOSURE_PREFIX, CHANNEL_MANAGER_KEY, MONITORS_PREFIX_KEY};
+use crate::storage::{ECASH_TRANSFER_PREFIX_KEY, FEDERATIONS_KEY, FEDERATION_DEPOSIT_PREFIX_KEY};
 use crate::utils::spawn;
 use crate::{auth::MutinyAuthClient, hermes::HermesClient, logging::MutinyLogger};
-use crate::{blindauth::BlindAuthClient, cashu::CashuHttpClient};
 use crate::{error::MutinyError, nostr::ReservedProfile};
 use crate::{
-    event::{HTLCStatus, MillisatAmount, PaymentInfo},
+    event::{EventBroadcaster, HTLCStatus, MillisatAmount, MutinyEvent, PaymentInfo},
     onchain::FULL_SYNC_STOP_GAP,
 };
 use crate::{
     federation::{
-        FederationClient, FederationIdentity, FederationIndex, FederationStorage, GatewayFees,
+        DepositStatus, EcashTransfer, FederationClient, FederationDeposit, FederationGateway,
+        FederationHealth, FederationHealthWarning, FederationIdentity, FederationIndex,
+        FederationStorage, GatewayFees,
     },
     labels::{get_contact_key, Contact, LabelStorage},
     nodemanager::NodeBalance,
 };
 use crate::{
     lnurlauth::make_lnurl_auth_connection,
-    nodemanager::{ChannelClosure, MutinyBip21RawMaterials},
+    nodemanager::{ChannelClosure, MutinyBip21RawMaterials, PaymentOptions},
 };
 use crate::{lnurlauth::AuthManager, nostr::MUTINY_PLUS_SUBSCRIPTION_LABEL};
 use crate::{logging::LOGGING_KEY, nodemanager::NodeManagerBuilder};
@@ -75,26 +86,35 @@ use crate::{
 };
 use crate::{
     nostr::primal::{PrimalApi, PrimalClient},
+    nostr::relay_api::{FallbackPrimalClient, NostrApi, RelayApi},
     storage::get_invoice_by_hash,
 };
-use crate::{nostr::NostrManager, utils::sleep};
+use crate::{
+    nostr::{NostrManager, EXTERNAL_WALLET_BALANCE_TTL_SECS},
+    utils::sleep,
+};
 use crate::{
     onchain::get_esplora_url,
     storage::{
         get_payment_hash_from_key, get_transaction_details, list_payment_info,
-        persist_payment_info, update_nostr_contact_list, IndexItem, MutinyStorage, DEVICE_ID_KEY,
-        EXPECTED_NETWORK_KEY, NEED_FULL_SYNC_KEY, ONCHAIN_PREFIX, PAYMENT_INBOUND_PREFIX_KEY,
-        PAYMENT_OUTBOUND_PREFIX_KEY, SUBSCRIPTION_TIMESTAMP, TRANSACTION_DETAILS_PREFIX_KEY,
+        persist_payment_info, read_payment_info, update_nostr_contact_list, IndexItem,
+        MutinyStorage, DEVICE_ID_KEY, EXPECTED_NETWORK_KEY, EXTERNAL_WALLET_INVOICES_KEY,
+        EXTERNAL_WALLET_PAYMENTS_KEY, NEED_FULL_SYNC_KEY, ONCHAIN_PREFIX,
+        PAYMENT_INBOUND_PREFIX_KEY, PAYMENT_OUTBOUND_PREFIX_KEY, SUBSCRIPTION_TIMESTAMP,
+        TRANSACTION_DETAILS_PREFIX_KEY, ZAP_PROVIDER_KEY,
     },
 };
+use ::nostr::nips::nip46::NostrConnectURI;
 use ::nostr::nips::nip47::Method;
+use ::nostr::nips::nip47::NostrWalletConnectURI;
 use ::nostr::nips::nip57;
 #[cfg(target_arch = "wasm32")]
 use ::nostr::prelude::rand::rngs::OsRng;
 use ::nostr::prelude::ZapRequestData;
-#[cfg(target_arch = "wasm32")]
 use ::nostr::Tag;
-use ::nostr::{EventBuilder, EventId, HttpMethod, JsonUtil, Keys, Kind};
+use ::nostr::{
+    ClientMessage, EventBuilder, EventId, HttpMethod, JsonUtil, Keys, Kind, RelayMessage,
+};
 use async_lock::RwLock;
 use bdk_chain::ConfirmationTime;
 use bip39::Mnemonic;
@@ -106,7 +126,9 @@ use bitcoin::{hashes::Hash, Address};
 use esplora_client::AsyncClient;
 pub use fedimint_core;
 use fedimint_core::{api::InviteCode, config::FederationId};
-use futures::{pin_mut, select, FutureExt};
+use fedimint_mint_client::OOBNotes;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::{pin_mut, select, FutureExt, StreamExt};
 use futures_util::join;
 use futures_util::lock::Mutex;
 use hex_conservative::{DisplayHex, FromHex};
@@ -118,11 +140,10 @@ use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error, log_info, log_trace, log_warn};
 pub use lightning_invoice;
 use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+use lnurl::lightning_address::LightningAddress;
+use lnurl::pay::PayResponse;
 use lnurl::{lnurl::LnUrl, AsyncClient as LnUrlClient, LnUrlResponse, Response};
-use moksha_core::primitives::{
-    CurrencyUnit, PostMeltBolt11Request, PostMeltBolt11Response, PostMeltQuoteBolt11Request,
-    PostMeltQuoteBolt11Response,
-};
+use moksha_core::proof::Proof;
 use moksha_core::token::TokenV3;
 pub use nostr_sdk;
 use nostr_sdk::{Client, NostrSigner, RelayPoolNotification};
@@ -136,21 +157,33 @@ use std::time::Duration;
 use std::time::Instant;
 use std::{collections::HashMap, sync::atomic::AtomicBool};
 use std::{str::FromStr, sync::atomic::Ordering};
+use url::Url;
 use uuid::Uuid;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
 
 use crate::labels::LabelItem;
-use crate::nostr::{NostrKeySource, RELAYS};
+use crate::nostr::nwc_client::{
+    preimage_matches, ExternalWallet, ExternalWalletBalance, ExternalWalletInvoice,
+};
+use crate::nostr::zaps::Zap;
+use crate::nostr::NostrKeySource;
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
 pub const DEVICE_LOCK_INTERVAL_SECS: u64 = 30;
 const BITCOIN_PRICE_CACHE_SEC: u64 = 300;
 const DEFAULT_PAYMENT_TIMEOUT: u64 = 30;
+const EXTERNAL_WALLET_CHECK_INTERVAL_SECS: i32 = 30;
+const ZAP_RECEIPT_SYNC_INTERVAL_SECS: i32 = 300;
 const SWAP_LABEL: &str = "SWAP";
 const MELT_CASHU_TOKEN: &str = "Cashu Token Melt";
 const DUST_LIMIT: u64 = 546;
+const FEDERATION_HEALTH_CHECK_INTERVAL_SECS: u64 = 600;
+const FEDERATION_BACKUP_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const FEDERATION_RESTORE_KEY: &str = "federation_restore";
+#[cfg(not(test))]
+const BUNKER_RETRY_INTERVAL_MS: i32 = 60_000;
 
 #[cfg_attr(test, automock)]
 pub trait InvoiceHandler {
@@ -170,6 +203,15 @@ pub trait InvoiceHandler {
         amount: u64,
         labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError>;
+    async fn keysend(
+        &self,
+        to_node: PublicKey,
+        amt_sats: u64,
+        message: Option<String>,
+        preimage: Option<[u8; 32]>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError>;
+    fn list_payments(&self) -> Result<Vec<MutinyInvoice>, MutinyError>;
 }
 
 pub struct LnUrlParams {
@@ -189,22 +231,40 @@ pub struct Plan {
     pub amount_sat: u64,
 }
 
+/// A zap from our zap history, with the invoice that paid it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZapActivity {
+    pub zap: Zap,
+    /// None if we don't have the invoice, such as for zaps sent from another wallet
+    pub invoice: Option<MutinyInvoice>,
+}
+
 #[derive(Copy, Clone)]
 pub struct MutinyBalance {
     pub confirmed: u64,
     pub unconfirmed: u64,
     pub lightning: u64,
     pub federation: u64,
+    pub cashu: u64,
+    /// Balance of external wallets connected over Nostr Wallet Connect
+    pub external: u64,
     pub force_close: u64,
 }
 
 impl MutinyBalance {
-    fn new(ln_balance: NodeBalance, federation_balance: u64) -> Self {
+    fn new(
+        ln_balance: NodeBalance,
+        federation_balance: u64,
+        cashu_balance: u64,
+        external_balance: u64,
+    ) -> Self {
         Self {
             confirmed: ln_balance.confirmed,
             unconfirmed: ln_balance.unconfirmed,
             lightning: ln_balance.lightning,
             federation: federation_balance,
+            cashu: cashu_balance,
+            external: external_balance,
             force_close: ln_balance.force_close,
         }
     }
@@ -221,11 +281,21 @@ pub struct FederationBalances {
     pub balances: Vec<FederationBalance>,
 }
 
+// The federations being restored from backup, saved to the DB
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct FederationRestoreState {
+    federations: Vec<FederationId>,
+    // set once we stop looking for more federations to restore
+    finished: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum ActivityItem {
     OnChain(TransactionDetails),
     Lightning(Box<MutinyInvoice>),
     ChannelClosed(ChannelClosure),
+    Ecash(EcashTransfer),
+    FederationDeposit(FederationDeposit),
 }
 
 /// A wallet transaction
@@ -292,6 +362,13 @@ impl ActivityItem {
                 HTLCStatus::Pending | HTLCStatus::InFlight => None,
             },
             ActivityItem::ChannelClosed(c) => Some(c.timestamp),
+            ActivityItem::Ecash(e) => Some(e.last_updated),
+            ActivityItem::FederationDeposit(d) => match d.status {
+                DepositStatus::WaitingForTransaction | DepositStatus::WaitingForConfirmation => {
+                    None
+                }
+                _ => Some(d.last_updated),
+            },
         }
     }
 
@@ -300,6 +377,8 @@ impl ActivityItem {
             ActivityItem::OnChain(t) => t.labels.clone(),
             ActivityItem::Lightning(i) => i.labels.clone(),
             ActivityItem::ChannelClosed(_) => vec![],
+            ActivityItem::Ecash(e) => e.labels.clone(),
+            ActivityItem::FederationDeposit(d) => d.labels.clone(),
         }
     }
 
@@ -310,6 +389,8 @@ impl ActivityItem {
             }
             ActivityItem::Lightning(_) => false,
             ActivityItem::ChannelClosed(_) => false,
+            ActivityItem::Ecash(_) => false,
+            ActivityItem::FederationDeposit(_) => false,
         }
     }
 }
@@ -570,6 +651,20 @@ impl MutinyInvoice {
     }
 }
 
+/// The result of melting the part of a cashu token from a single mint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CashuMeltResult {
+    pub mint_url: Url,
+    /// The value of the proofs from this mint
+    pub amount: u64,
+    /// The invoice that was paid to us, if the melt succeeded
+    pub invoice: Option<MutinyInvoice>,
+    /// Unused fee reserve the mint returned to our cashu wallet
+    pub change: u64,
+    /// Why the melt failed, if it did
+    pub error: Option<String>,
+}
+
 /// FedimintSweepResult is the result of how much was swept and the fees paid.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FedimintSweepResult {
@@ -942,12 +1037,15 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         let esplora = Arc::new(esplora);
         log_trace!(logger, "finished setting up esplora");
 
+        let event_broadcaster = EventBroadcaster::default();
+
         log_trace!(logger, "setting up node manager");
         let start = Instant::now();
         let mut nm_builder = NodeManagerBuilder::new(self.xprivkey, self.storage.clone())
             .with_config(config.clone());
         nm_builder.with_logger(logger.clone());
         nm_builder.with_esplora(esplora.clone());
+        nm_builder.with_event_broadcaster(event_broadcaster.clone());
         let node_manager = Arc::new(nm_builder.build().await?);
 
         log_trace!(
@@ -973,6 +1071,9 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         // create nostr manager
         log_trace!(logger, "creating nostr client");
         let client = Client::default();
+        // query our relays directly when primal is unavailable
+        let relay_api = RelayApi::new(self.storage.clone(), client.clone(), logger.clone());
+        let primal_client = FallbackPrimalClient::new(primal_client, relay_api, logger.clone());
         let nostr = Arc::new(
             NostrManager::from_mnemonic(
                 self.xprivkey,
@@ -991,6 +1092,23 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         #[cfg(not(test))]
         nostr.connect().await?;
 
+        // connect to a remote signer in the background so startup doesn't wait on it
+        #[cfg(not(test))]
+        {
+            let nostr = nostr.clone();
+            let logger = logger.clone();
+            let stop = stop.clone();
+            utils::spawn(async move {
+                while let Err(e) = nostr.connect_signer().await {
+                    log_warn!(logger, "Failed to connect to remote signer, retrying: {e}");
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    utils::sleep(BUNKER_RETRY_INTERVAL_MS).await;
+                }
+            });
+        }
+
         // create federation module if any exist
         log_trace!(logger, "creating federation modules");
         let federation_storage = self.storage.get_federations()?;
@@ -1003,6 +1121,7 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
                 self.storage.clone(),
                 esplora.clone(),
                 stop.clone(),
+                event_broadcaster.clone(),
                 &logger,
                 self.safe_mode,
             )
@@ -1177,6 +1296,37 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         activity_index.extend(inbound);
         activity_index.extend(outbound);
 
+        // add out of band ecash to the activity index
+        let ecash = self
+            .storage
+            .scan::<EcashTransfer>(ECASH_TRANSFER_PREFIX_KEY, None)?
+            .into_iter()
+            .map(|(k, v)| IndexItem {
+                timestamp: Some(v.last_updated),
+                key: k,
+            })
+            .collect::<Vec<_>>();
+        activity_index.extend(ecash);
+
+        // add deposits into federations that have a transaction waiting on chain
+        let deposits = self
+            .storage
+            .scan::<FederationDeposit>(FEDERATION_DEPOSIT_PREFIX_KEY, None)?
+            .into_iter()
+            .filter_map(|(k, v)| match v.status {
+                DepositStatus::WaitingForConfirmation => Some(IndexItem {
+                    timestamp: None,
+                    key: k,
+                }),
+                DepositStatus::Failed => Some(IndexItem {
+                    timestamp: Some(v.last_updated),
+                    key: k,
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        activity_index.extend(deposits);
+
         // add the activity index to the storage
         {
             let index = self.storage.activity_index();
@@ -1210,11 +1360,12 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
             esplora,
             auth,
             stop,
+            event_broadcaster,
             logger: logger.clone(),
             network,
             skip_hodl_invoices: self.skip_hodl_invoices,
             safe_mode: self.safe_mode,
-            cashu_client: CashuHttpClient::new(),
+            cashu: Arc::new(CashuWallet::new(self.storage.clone(), logger.clone())),
             bitcoin_price_cache: Arc::new(Mutex::new(price_cache)),
         };
         log_trace!(logger, "finished creating mutiny wallet");
@@ -1248,6 +1399,9 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         mw.start_fedimint_background_checker().await;
         log_trace!(logger, "finished starting fedimint background checker");
 
+        // start watching for payments to external wallet invoices
+        mw.start_external_wallet_checker();
+
         // start the blind auth fetching process
         log_trace!(logger, "checking blind tokens");
         mw.check_blind_tokens();
@@ -1256,11 +1410,7 @@ impl<S: MutinyStorage> MutinyWalletBuilder<S> {
         // start the hermes background process
         // get profile key if we have it, we need this to decrypt private zaps
         log_trace!(logger, "getting nostr profile key");
-        let profile_key = match &mw.nostr.nostr_keys.read().await.signer {
-            NostrSigner::Keys(keys) => Some(keys.clone()),
-            #[cfg(target_arch = "wasm32")]
-            NostrSigner::NIP07(_) => None,
-        };
+        let profile_key = mw.nostr.nostr_keys.read().await.keys().cloned();
         log_trace!(logger, "finished getting nostr profile key");
 
         log_trace!(logger, "starting hermes");
@@ -1286,7 +1436,7 @@ pub struct MutinyWallet<S: MutinyStorage> {
     config: MutinyWalletConfig,
     pub(crate) storage: S,
     pub node_manager: Arc<NodeManager<S>>,
-    pub nostr: Arc<NostrManager<S, PrimalClient, nostr_sdk::Client>>,
+    pub nostr: Arc<NostrManager<S, NostrApi<S>, nostr_sdk::Client>>,
     pub federation_storage: Arc<RwLock<FederationStorage>>,
     pub(crate) federations: Arc<RwLock<HashMap<FederationId, Arc<FederationClient<S>>>>>,
     lnurl_client: Arc<LnUrlClient>,
@@ -1296,11 +1446,12 @@ pub struct MutinyWallet<S: MutinyStorage> {
     hermes_client: Option<Arc<HermesClient<S>>>,
     esplora: Arc<AsyncClient>,
     pub stop: Arc<AtomicBool>,
+    event_broadcaster: EventBroadcaster,
     pub logger: Arc<MutinyLogger>,
     network: Network,
     skip_hodl_invoices: bool,
     safe_mode: bool,
-    cashu_client: CashuHttpClient,
+    cashu: Arc<CashuWallet<S>>,
     bitcoin_price_cache: Arc<Mutex<HashMap<String, (f32, Duration)>>>,
 }
 
@@ -1315,6 +1466,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         let mut nm_builder = NodeManagerBuilder::new(self.xprivkey, self.storage.clone())
             .with_config(self.config.clone());
         nm_builder.with_logger(self.logger.clone());
+        nm_builder.with_event_broadcaster(self.event_broadcaster.clone());
 
         // when we restart, gen a new session id
         self.node_manager = Arc::new(nm_builder.build().await?);
@@ -1324,6 +1476,21 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         Ok(())
     }
 
+    /// Subscribes to [`MutinyEvent`]s for payments, channels, on-chain
+    /// confirmations and NWC requests across all nodes and federations.
+    ///
+    /// Events are only sent for things that happen after subscribing.
+    /// Returns the subscription id and the receiver, the receiver's stream ends
+    /// once [`MutinyWallet::unsubscribe_events`] is called or the wallet is stopped.
+    pub fn subscribe_events(&self) -> (u32, UnboundedReceiver<MutinyEvent>) {
+        self.event_broadcaster.subscribe()
+    }
+
+    /// Ends an event subscription created with [`MutinyWallet::subscribe_events`]
+    pub fn unsubscribe_events(&self, id: u32) {
+        self.event_broadcaster.unsubscribe(id)
+    }
+
     /// Starts a background process that will watch for nostr events
     pub(crate) async fn start_nostr(&self) {
         log_trace!(self.logger, "calling start_nostr");
@@ -1368,12 +1535,12 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 let client = nostr_sdk::Client::default();
 
                 client
-                    .add_relays(nostr.get_relays())
+                    .add_relays(nostr.get_relays().await)
                     .await
                     .expect("Failed to add relays");
                 client.connect().await;
 
-                client.subscribe(last_filters.clone(), None).await;
+                let mut subscription_id = client.subscribe(last_filters.clone(), None).await;
 
                 // handle NWC requests
                 let mut notifications = client.notifications();
@@ -1401,6 +1568,11 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                                     if event.verify().is_ok() {
                                         match event.kind {
                                             Kind::WalletConnectRequest => {
+                                                // only tell subscribers about requests from our profiles
+                                                let nwc_request = nostr.is_nwc_request_from_profile(&event).then_some(MutinyEvent::NwcRequest {
+                                                    event_id: event.id,
+                                                    client_pubkey: event.pubkey,
+                                                });
                                                 match nostr.handle_nwc_request(*event, &self_clone).await {
                                                     Ok(Some(event)) => {
                                                         if let Err(e) = client.send_event(event).await {
@@ -1412,12 +1584,20 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                                                         log_error!(logger, "Error handling NWC request: {e}");
                                                     }
                                                 }
+                                                if let Some(nwc_request) = nwc_request {
+                                                    self_clone.event_broadcaster.broadcast(nwc_request);
+                                                }
                                             }
                                             Kind::EncryptedDirectMessage => {
                                                 if let Err(e) = nostr.handle_direct_message(*event, &self_clone).await {
                                                         log_error!(logger, "Error handling dm: {e}");
                                                 }
                                             }
+                                            Kind::GiftWrap => {
+                                                if let Err(e) = nostr.handle_gift_wrap(*event, &self_clone).await {
+                                                        log_error!(logger, "Error handling gift wrapped dm: {e}");
+                                                }
+                                            }
                                             Kind::ContactList => {
                                                 let event_pk = event.pubkey;
                                                 match update_nostr_contact_list(&nostr.storage, *event) {
@@ -1446,10 +1626,29 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                                         }
                                     }
                                 },
-                                Ok(RelayPoolNotification::Message { .. }) => {}, // ignore messages
+                                Ok(RelayPoolNotification::Message { relay_url, message: RelayMessage::Auth { challenge } }) => {
+                                    // authenticate the profiles that use this relay, then resubscribe
+                                    // on it in case it closed our subscription, reusing the same id
+                                    // so it replaces the old one instead of adding another
+                                    let auth_events = nostr.create_nwc_auth_events(&relay_url, &challenge);
+                                    if !auth_events.is_empty() {
+                                        for event in auth_events {
+                                            if let Err(e) = client.send_msg_to([relay_url.clone()], ClientMessage::auth(event)).await {
+                                                log_warn!(logger, "Error authenticating to relay {relay_url}: {e}");
+                                            }
+                                        }
+                                        let req = ClientMessage::req(subscription_id.clone(), last_filters.clone());
+                                        if let Err(e) = client.send_msg_to([relay_url.clone()], req).await {
+                                            log_warn!(logger, "Error resubscribing to relay {relay_url}: {e}");
+                                        }
+                                    }
+                                },
+                                Ok(RelayPoolNotification::Message { .. }) => {}, // ignore other messages
                                 Ok(RelayPoolNotification::Shutdown) => break, // if we disconnect, we restart to reconnect
                                 Ok(RelayPoolNotification::Stop) => {}, // Currently unused
-                                Ok(RelayPoolNotification::RelayStatus { .. }) => {}, // Currently unused
+                                Ok(RelayPoolNotification::RelayStatus { relay_url, status }) => {
+                                    nostr.relay_health.update(relay_url.as_str(), status);
+                                },
                                 Err(_) => break, // if we are erroring we should reconnect
                             }
                         }
@@ -1463,7 +1662,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                             if let Ok(current_filters) = nostr.get_filters().await {
                                 if !utils::compare_filters_vec(&current_filters, &last_filters) {
                                     log_debug!(logger, "subscribing to new nwc filters");
-                                    client.subscribe(current_filters.clone(), None).await;
+                                    subscription_id = client.subscribe(current_filters.clone(), None).await;
                                     last_filters = current_filters;
                                 }
                             }
@@ -1501,6 +1700,52 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                     break;
                 };
             }
+
+            // pick up zap receipts published while we were offline,
+            // then keep checking for new ones
+            loop {
+                if self_clone.stop.load(Ordering::Relaxed) {
+                    break;
+                };
+
+                if let Err(e) = self_clone.sync_zap_receipts().await {
+                    log_error!(self_clone.logger, "Failed to sync zap receipts: {e}");
+                }
+
+                sleep(ZAP_RECEIPT_SYNC_INTERVAL_SECS * 1_000).await;
+            }
+        });
+
+        // send NWC notifications for completed payments
+        let (_, mut events) = self.event_broadcaster.subscribe();
+        let self_clone = self.clone();
+        utils::spawn(async move {
+            while let Some(event) = events.next().await {
+                if self_clone.stop.load(Ordering::Relaxed) {
+                    break;
+                };
+
+                let payment_hash = match event {
+                    MutinyEvent::PaymentReceived { payment_hash, .. }
+                    | MutinyEvent::PaymentSent { payment_hash, .. } => payment_hash,
+                    _ => continue,
+                };
+
+                let invoice = match self_clone.get_invoice_by_hash(&payment_hash).await {
+                    Ok(invoice) => invoice,
+                    Err(e) => {
+                        log_error!(
+                            self_clone.logger,
+                            "Failed to get payment for NWC notification: {e}"
+                        );
+                        continue;
+                    }
+                };
+
+                if let Err(e) = self_clone.nostr.send_nwc_notifications(&invoice).await {
+                    log_error!(self_clone.logger, "Failed to send NWC notifications: {e}");
+                }
+            }
         });
 
         log_trace!(self.logger, "finished calling start_nostr");
@@ -1515,9 +1760,28 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         inv: &Bolt11Invoice,
         amt_sats: Option<u64>,
         labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        self.pay_invoice_with_options(inv, amt_sats, &PaymentOptions::default(), labels)
+            .await
+    }
+
+    /// Pays a lightning invoice like [`MutinyWallet::pay_invoice`] with limits on
+    /// fees, retries, timeout and routing.
+    ///
+    /// Federations only use gateways whose fee is within the max fee, count trying
+    /// another gateway as a retry and share the timeout. They are skipped if the
+    /// payment has to use specific channels.
+    pub async fn pay_invoice_with_options(
+        &self,
+        inv: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        options: &PaymentOptions,
+        labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
         log_trace!(self.logger, "calling pay_invoice");
 
+        options.validate()?;
+
         if inv.network() != self.network {
             return Err(MutinyError::IncorrectNetwork);
         }
@@ -1537,17 +1801,32 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         self.storage
             .set_invoice_labels(inv.clone(), labels.clone())?;
 
-        // Try each federation first
-        let federation_ids = self.list_federation_ids().await?;
+        // Try each federation first, federations can't pick our channels
+        let federation_ids = if options.has_first_hop_constraints() {
+            vec![]
+        } else {
+            self.list_federation_ids().await?
+        };
+        let max_fee_msat = options.max_total_routing_fee_msat(send_msat);
         let mut last_federation_error = None;
         for federation_id in federation_ids {
             if let Some(fedimint_client) = self.federations.read().await.get(&federation_id) {
                 // Check if the federation has enough balance
-                let balance = fedimint_client.get_balance().await?;
+                let balance = match fedimint_client.get_balance().await {
+                    Ok(balance) => balance,
+                    Err(e) => {
+                        log_warn!(
+                            self.logger,
+                            "could not get balance of federation {federation_id}: {e}"
+                        );
+                        continue;
+                    }
+                };
                 if balance >= send_msat / 1_000 {
-                    // Try to pay the invoice using the federation
+                    // Try to pay the invoice using the federation, it skips
+                    // gateways that charge more than the max fee
                     let payment_result = fedimint_client
-                        .pay_invoice(inv.clone(), labels.clone())
+                        .pay_invoice(inv.clone(), options, labels.clone())
                         .await;
                     match payment_result {
                         Ok(r) => {
@@ -1590,9 +1869,11 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             // If federation client is not found, continue to next federation
         }
 
-        // If any balance at all, then fallback to node manager for payment.
+        // If any balance at all, then try our node next.
         // Take the error from the node manager as the priority.
-        let res = if self
+        let mut last_error = last_federation_error;
+        let mut node_error = None;
+        if self
             .node_manager
             .nodes
             .read()
@@ -1603,28 +1884,73 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             .sum::<u64>()
             > 0
         {
-            let res = self
+            match self
                 .node_manager
-                .pay_invoice(None, inv, amt_sats, labels)
-                .await?;
+                .pay_invoice(None, inv, amt_sats, options, labels.clone())
+                .await
+            {
+                Ok(res) => {
+                    // spawn a task to remove the pending invoice if it exists
+                    let nostr_clone = self.nostr.clone();
+                    let payment_hash = *inv.payment_hash();
+                    let logger = self.logger.clone();
+                    utils::spawn(async move {
+                        if let Err(e) = nostr_clone.remove_pending_nwc_invoice(&payment_hash).await
+                        {
+                            log_warn!(logger, "Failed to remove pending NWC invoice: {e}");
+                        }
+                    });
+                    log_trace!(self.logger, "finished calling pay_invoice");
+                    return Ok(res);
+                }
+                // only fall back to external wallets when the node definitely didn't pay
+                Err(e @ MutinyError::RoutingFailed) | Err(e @ MutinyError::InsufficientBalance) => {
+                    log_debug!(self.logger, "could not make payment through node: {e}");
+                    node_error = Some(e);
+                }
+                Err(e) => {
+                    log_trace!(self.logger, "finished calling pay_invoice");
+                    return Err(e);
+                }
+            }
+        }
 
-            // spawn a task to remove the pending invoice if it exists
-            let nostr_clone = self.nostr.clone();
-            let payment_hash = *inv.payment_hash();
-            let logger = self.logger.clone();
-            utils::spawn(async move {
-                if let Err(e) = nostr_clone.remove_pending_nwc_invoice(&payment_hash).await {
-                    log_warn!(logger, "Failed to remove pending NWC invoice: {e}");
+        // Finally try any connected external wallets, they can't pick our channels or limit fees either
+        if !options.has_first_hop_constraints() && max_fee_msat.is_none() {
+            match self
+                .pay_invoice_from_external_wallets(inv, amt_sats, send_msat, labels)
+                .await
+            {
+                Ok(Some(r)) => {
+                    // spawn a task to remove the pending invoice if it exists
+                    let nostr_clone = self.nostr.clone();
+                    let payment_hash = *inv.payment_hash();
+                    let logger = self.logger.clone();
+                    utils::spawn(async move {
+                        if let Err(e) = nostr_clone.remove_pending_nwc_invoice(&payment_hash).await
+                        {
+                            log_warn!(logger, "Failed to remove pending NWC invoice: {e}");
+                        }
+                    });
+                    log_trace!(self.logger, "finished calling pay_invoice");
+                    return Ok(r);
                 }
-            });
+                Ok(None) => {}
+                Err(e @ MutinyError::RoutingFailed) | Err(e @ MutinyError::InsufficientBalance) => {
+                    last_error = Some(e);
+                }
+                // the wallet may still complete it, or it says it paid
+                Err(e) => {
+                    log_trace!(self.logger, "finished calling pay_invoice");
+                    return Err(e);
+                }
+            }
+        }
 
-            Ok(res)
-        } else {
-            Err(last_federation_error.unwrap_or(MutinyError::InsufficientBalance))
-        };
         log_trace!(self.logger, "finished calling pay_invoice");
-
-        res
+        Err(node_error
+            .or(last_error)
+            .unwrap_or(MutinyError::InsufficientBalance))
     }
 
     /// Estimates the lightning fee for a transaction. Amount is either from the invoice
@@ -1730,7 +2056,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             )
         };
 
-        let Ok(address) = self.create_address(labels.clone()).await else {
+        let Ok(address) = self.create_address_for_amount(amount, labels.clone()).await else {
             return Err(MutinyError::WalletOperationFailed);
         };
         log_trace!(self.logger, "finished calling create_bip21");
@@ -1778,7 +2104,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         self.storage
             .set_invoice_labels(bolt_11.clone(), labels.clone())?;
         let pay_result = from_fedimint_client
-            .pay_invoice(bolt_11.clone(), labels)
+            .pay_invoice(bolt_11.clone(), &PaymentOptions::default(), labels)
             .await?;
 
         let remaining_balance = from_fedimint_client.get_balance().await?;
@@ -1894,64 +2220,317 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         res
     }
 
-    pub async fn send_to_address(
+    /// Moves `amount` sats from one federation to another by paying an
+    /// invoice from the receiving federation through the sending federation's
+    /// gateway. The gateway fee is paid on top of the amount.
+    pub async fn transfer_between_federations(
         &self,
-        send_to: Address,
+        from_federation_id: FederationId,
+        to_federation_id: FederationId,
         amount: u64,
-        labels: Vec<String>,
-        fee_rate: Option<f32>,
-    ) -> Result<Txid, MutinyError> {
-        log_trace!(self.logger, "calling send_to_address");
+    ) -> Result<FedimintSweepResult, MutinyError> {
+        log_trace!(self.logger, "calling transfer_between_federations");
 
-        // Try each federation first
-        let federation_ids = self.list_federation_ids().await?;
-        let mut last_federation_error = None;
-        for federation_id in federation_ids {
-            if let Some(fedimint_client) = self.federations.read().await.get(&federation_id) {
-                // Check if the federation has enough balance
-                let balance = fedimint_client.get_balance().await?;
-                if balance >= amount / 1_000 {
-                    match fedimint_client
-                        .send_onchain(send_to.clone(), amount, labels.clone())
-                        .await
-                    {
-                        Ok(t) => {
-                            return Ok(t);
-                        }
-                        Err(e) => match e {
-                            MutinyError::PaymentTimeout => return Err(e),
-                            _ => {
-                                log_warn!(self.logger, "unhandled error: {e}");
-                                last_federation_error = Some(e);
-                            }
-                        },
+        if amount == 0 {
+            return Err(MutinyError::BadAmountError);
+        }
+        if from_federation_id == to_federation_id {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let federation_lock = self.federations.read().await;
+        let from_fedimint_client = federation_lock
+            .get(&from_federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let to_fedimint_client = federation_lock
+            .get(&to_federation_id)
+            .ok_or(MutinyError::NotFound)?;
+
+        let fees = from_fedimint_client.gateway_fee().await?;
+        let outgoing_fee =
+            (calc_routing_fee_msat(amount as f64 * 1_000.0, &fees) / 1_000.0).ceil() as u64;
+        let current_balance = from_fedimint_client.get_balance().await?;
+        if current_balance < amount + outgoing_fee {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        let labels = vec![SWAP_LABEL.to_string()];
+        let invoice = to_fedimint_client
+            .get_invoice(amount, labels.clone())
+            .await?;
+        let bolt11 = invoice.bolt11.ok_or(MutinyError::InvoiceCreationFailed)?;
+        self.storage
+            .set_invoice_labels(bolt11.clone(), labels.clone())?;
+
+        let pay_result = from_fedimint_client
+            .pay_invoice(bolt11, &PaymentOptions::default(), labels)
+            .await?;
+        log_trace!(self.logger, "finished calling transfer_between_federations");
+
+        Ok(FedimintSweepResult {
+            amount,
+            fees: pay_result.fees_paid,
+        })
+    }
+
+    /// Gets the most we want to hold in the given federation, in sats.
+    /// This is the limit set with `set_federation_max_balance`, or otherwise
+    /// the limit the federation advertises, if any.
+    pub async fn get_federation_max_balance(
+        &self,
+        federation_id: FederationId,
+    ) -> Result<Option<u64>, MutinyError> {
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+
+        fedimint_client.max_balance_sats()
+    }
+
+    /// Sets the most we want to hold in the given federation, in sats.
+    /// Passing `None` removes our limit and falls back to the federation's own.
+    pub fn set_federation_max_balance(
+        &self,
+        federation_id: FederationId,
+        max_balance: Option<u64>,
+    ) -> Result<(), MutinyError> {
+        let mut max_balances = self.storage.get_federation_max_balances()?;
+        match max_balance {
+            Some(max) => max_balances.insert(federation_id.to_string(), max),
+            None => max_balances.remove(&federation_id.to_string()),
+        };
+
+        self.storage.set_federation_max_balances(&max_balances)
+    }
+
+    /// Moves funds out of any federation that is over its maximum balance.
+    ///
+    /// Excess funds go to the federations with the most room left. If no
+    /// federation has room, they go to our lightning node, but only when it
+    /// has enough inbound liquidity to avoid opening a new channel.
+    ///
+    /// This also runs in the background alongside the federation health checks.
+    pub async fn rebalance_federations(&self) -> Result<Vec<FedimintSweepResult>, MutinyError> {
+        log_trace!(self.logger, "calling rebalance_federations");
+
+        // leave out any federation we can't read, so the others still get rebalanced
+        let balances = {
+            let federation_lock = self.federations.read().await;
+            let mut balances = Vec::with_capacity(federation_lock.len());
+            for (id, client) in federation_lock.iter() {
+                let balance = match client.get_balance().await {
+                    Ok(balance) => balance,
+                    Err(e) => {
+                        log_warn!(self.logger, "could not get balance of federation {id}: {e}");
+                        continue;
+                    }
+                };
+                let max_balance = match client.max_balance_sats() {
+                    Ok(max) => max,
+                    Err(e) => {
+                        log_warn!(
+                            self.logger,
+                            "could not get max balance of federation {id}: {e}"
+                        );
+                        continue;
                     }
+                };
+                balances.push((*id, balance, max_balance));
+            }
+            balances
+        };
+
+        let mut results = vec![];
+        for rebalance in plan_federation_rebalance(&balances) {
+            let res = match rebalance.to {
+                Some(to) => {
+                    self.transfer_between_federations(rebalance.from, to, rebalance.amount)
+                        .await
                 }
-                // If payment fails or balance is not sufficient, continue to next federation
+                None => {
+                    self.transfer_federation_to_node(rebalance.from, rebalance.amount)
+                        .await
+                }
+            };
+
+            match res {
+                Ok(r) => results.push(r),
+                Err(e) => log_warn!(
+                    self.logger,
+                    "could not move {} sats out of federation {}: {e}",
+                    rebalance.amount,
+                    rebalance.from
+                ),
             }
-            // If federation client is not found, continue to next federation
         }
 
-        // If any balance at all, then fallback to node manager for payment.
-        // Take the error from the node manager as the priority.
-        let b = self.node_manager.get_balance().await?;
-        let res = if b.confirmed + b.unconfirmed > 0 {
-            let res = self
-                .node_manager
-                .send_to_address(send_to, amount, labels, fee_rate)
-                .await?;
-            Ok(res)
-        } else {
-            Err(last_federation_error.unwrap_or(MutinyError::InsufficientBalance))
-        };
-        log_trace!(self.logger, "finished calling send_to_address");
+        log_trace!(self.logger, "finished calling rebalance_federations");
+        Ok(results)
+    }
+
+    /// Lists the lightning gateways registered with a federation, with their fees,
+    /// whether they are vetted, when we last saw them and our preferences for them.
+    pub async fn list_gateways(
+        &self,
+        federation_id: FederationId,
+    ) -> Result<Vec<FederationGateway>, MutinyError> {
+        log_trace!(self.logger, "calling list_gateways");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client.list_gateways().await;
 
+        log_trace!(self.logger, "finished calling list_gateways");
         res
     }
 
-    /// Estimates the onchain fee for a transaction sending to the given address.
-    /// The amount is in satoshis and the fee rate is in sat/vbyte.
-    pub async fn estimate_tx_fee(
+    /// Pins a gateway for a federation so it is always used when available.
+    /// Passing `None` goes back to picking the cheapest gateway per payment.
+    pub async fn pin_gateway(
+        &self,
+        federation_id: FederationId,
+        gateway_id: Option<String>,
+    ) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling pin_gateway");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client.set_pinned_gateway(gateway_id).await;
+
+        log_trace!(self.logger, "finished calling pin_gateway");
+        res
+    }
+
+    /// Excludes a gateway for a federation so it is never used, or allows it again.
+    pub async fn exclude_gateway(
+        &self,
+        federation_id: FederationId,
+        gateway_id: String,
+        excluded: bool,
+    ) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling exclude_gateway");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client
+            .set_gateway_excluded(gateway_id, excluded)
+            .await;
+
+        log_trace!(self.logger, "finished calling exclude_gateway");
+        res
+    }
+
+    /// Sets whether payments from a federation may fall back to unvetted
+    /// gateways that charge suspiciously low fees.
+    pub async fn allow_unvetted_gateways(
+        &self,
+        federation_id: FederationId,
+        allowed: bool,
+    ) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling allow_unvetted_gateways");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client.set_allow_unvetted_gateways(allowed);
+
+        log_trace!(self.logger, "finished calling allow_unvetted_gateways");
+        res
+    }
+
+    async fn transfer_federation_to_node(
+        &self,
+        from_federation_id: FederationId,
+        amount: u64,
+    ) -> Result<FedimintSweepResult, MutinyError> {
+        let inbound: u64 = self
+            .node_manager
+            .list_channels()
+            .await?
+            .iter()
+            .filter(|c| c.is_usable)
+            .map(|c| c.inbound)
+            .sum();
+        if inbound < amount {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        let (invoice, _) = self
+            .node_manager
+            .create_invoice(amount, vec![SWAP_LABEL.to_string()])
+            .await?;
+        let bolt11 = invoice.bolt11.ok_or(MutinyError::InvoiceCreationFailed)?;
+
+        self.sweep_federation_balance_to_invoice(Some(from_federation_id), bolt11)
+            .await
+    }
+
+    pub async fn send_to_address(
+        &self,
+        send_to: Address,
+        amount: u64,
+        labels: Vec<String>,
+        fee_rate: Option<f32>,
+    ) -> Result<Txid, MutinyError> {
+        log_trace!(self.logger, "calling send_to_address");
+
+        // Try each federation first
+        let federation_ids = self.list_federation_ids().await?;
+        let mut last_federation_error = None;
+        for federation_id in federation_ids {
+            if let Some(fedimint_client) = self.federations.read().await.get(&federation_id) {
+                // Check if the federation has enough balance
+                let balance = fedimint_client.get_balance().await?;
+                if balance >= amount / 1_000 {
+                    match fedimint_client
+                        .send_onchain(send_to.clone(), amount, labels.clone())
+                        .await
+                    {
+                        Ok(t) => {
+                            return Ok(t);
+                        }
+                        Err(e) => match e {
+                            MutinyError::PaymentTimeout => return Err(e),
+                            _ => {
+                                log_warn!(self.logger, "unhandled error: {e}");
+                                last_federation_error = Some(e);
+                            }
+                        },
+                    }
+                }
+                // If payment fails or balance is not sufficient, continue to next federation
+            }
+            // If federation client is not found, continue to next federation
+        }
+
+        // If any balance at all, then fallback to node manager for payment.
+        // Take the error from the node manager as the priority.
+        let b = self.node_manager.get_balance().await?;
+        let res = if b.confirmed + b.unconfirmed > 0 {
+            let res = self
+                .node_manager
+                .send_to_address(send_to, amount, labels, fee_rate)
+                .await?;
+            Ok(res)
+        } else {
+            Err(last_federation_error.unwrap_or(MutinyError::InsufficientBalance))
+        };
+        log_trace!(self.logger, "finished calling send_to_address");
+
+        res
+    }
+
+    /// Estimates the onchain fee for a transaction sending to the given address.
+    /// The amount is in satoshis and the fee rate is in sat/vbyte.
+    pub async fn estimate_tx_fee(
         &self,
         destination_address: Address,
         amount: u64,
@@ -2113,6 +2692,14 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     pub async fn create_address(
         &self,
         labels: Vec<String>,
+    ) -> Result<bitcoin::Address, MutinyError> {
+        self.create_address_for_amount(None, labels).await
+    }
+
+    async fn create_address_for_amount(
+        &self,
+        amount: Option<u64>,
+        labels: Vec<String>,
     ) -> Result<bitcoin::Address, MutinyError> {
         log_trace!(self.logger, "calling create_address");
 
@@ -2123,7 +2710,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             let fedimint_client = self.federations.read().await.get(federation_id).cloned();
 
             if let Some(client) = fedimint_client {
-                if let Ok(addr) = client.get_new_address(labels.clone()).await {
+                if let Ok(addr) = client.get_new_address(amount, labels.clone()).await {
                     self.storage.set_address_labels(addr.clone(), labels)?;
                     return Ok(addr);
                 }
@@ -2139,6 +2726,37 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         Ok(addr)
     }
 
+    /// Whether receiving `amount` sats keeps the federation within its maximum balance.
+    /// If we can't tell, we don't receive into it.
+    async fn federation_has_room(&self, client: &FederationClient<S>, amount: u64) -> bool {
+        let max_balance = match client.max_balance_sats() {
+            Ok(max) => max,
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "could not get max balance of federation {}: {e}",
+                    client.fedimint_client.federation_id()
+                );
+                return false;
+            }
+        };
+        let Some(max) = max_balance else {
+            return true;
+        };
+
+        match client.get_balance().await {
+            Ok(balance) => balance + amount <= max,
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "could not get balance of federation {}: {e}",
+                    client.fedimint_client.federation_id()
+                );
+                false
+            }
+        }
+    }
+
     async fn create_lightning_invoice(
         &self,
         amount: u64,
@@ -2146,40 +2764,536 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     ) -> Result<MutinyInvoice, MutinyError> {
         log_trace!(self.logger, "calling create_lightning_invoice");
 
-        // Attempt to create federation invoice if available
+        // Attempt to create federation invoice if available,
+        // unless this would push it over its maximum balance
         let federation_ids = self.list_federation_ids().await?;
         if !federation_ids.is_empty() {
             let federation_id = &federation_ids[0];
             let fedimint_client = self.federations.read().await.get(federation_id).cloned();
 
             if let Some(client) = fedimint_client {
-                if let Ok(inv) = client.get_invoice(amount, labels.clone()).await {
-                    self.storage
-                        .set_invoice_labels(inv.bolt11.clone().expect("just created"), labels)?;
-                    return Ok(inv);
+                if self.federation_has_room(&client, amount).await {
+                    if let Ok(inv) = client.get_invoice(amount, labels.clone()).await {
+                        self.storage.set_invoice_labels(
+                            inv.bolt11.clone().expect("just created"),
+                            labels,
+                        )?;
+                        return Ok(inv);
+                    }
                 }
             }
         }
 
+        // Then any external wallets the user wants to receive into
+        if let Some(inv) = self
+            .create_external_wallet_invoice(amount, labels.clone(), true)
+            .await?
+        {
+            log_trace!(self.logger, "finished calling create_lightning_invoice");
+            return Ok(inv);
+        }
+
         // Fallback to node_manager invoice creation if no federation invoice created
-        let (inv, _fee) = self.node_manager.create_invoice(amount, labels).await?;
+        let node_error = match self
+            .node_manager
+            .create_invoice(amount, labels.clone())
+            .await
+        {
+            Ok((inv, _fee)) => {
+                log_trace!(self.logger, "finished calling create_lightning_invoice");
+                return Ok(inv);
+            }
+            Err(e) => e,
+        };
+
+        // Only use the other external wallets if our node can't make the invoice
+        log_debug!(
+            self.logger,
+            "could not create invoice with node, trying external wallets: {node_error}"
+        );
+        let inv = self
+            .create_external_wallet_invoice(amount, labels, false)
+            .await?
+            .ok_or(node_error)?;
 
         log_trace!(self.logger, "finished calling create_lightning_invoice");
         Ok(inv)
     }
 
     /// Gets the current balance of the wallet.
-    /// This includes both on-chain, lightning funds, and federations.
+    /// This includes on-chain, lightning funds, federations, and cashu mints.
     ///
     /// This will not include any funds in an unconfirmed lightning channel.
+    /// External wallet balances are the last ones we fetched, if those are
+    /// out of date they are fetched again in the background.
     pub async fn get_balance(&self) -> Result<MutinyBalance, MutinyError> {
         log_trace!(self.logger, "calling get_balance");
 
         let ln_balance = self.node_manager.get_balance().await?;
         let federation_balance = self.get_total_federation_balance().await?;
+        let cashu_balance = self.cashu.balance()?;
+
+        let external_balance = self
+            .cached_external_wallet_balances()?
+            .iter()
+            .map(|b| b.balance)
+            .sum();
         log_trace!(self.logger, "finished calling get_balance");
 
-        Ok(MutinyBalance::new(ln_balance, federation_balance))
+        Ok(MutinyBalance::new(
+            ln_balance,
+            federation_balance,
+            cashu_balance,
+            external_balance,
+        ))
+    }
+
+    /// Connects to an external wallet by its `nostr+walletconnect://` URI.
+    /// Its balance is used for payments and invoices alongside our federations and node.
+    pub async fn add_external_wallet(
+        &self,
+        name: String,
+        uri: NostrWalletConnectURI,
+    ) -> Result<ExternalWallet, MutinyError> {
+        log_trace!(self.logger, "calling add_external_wallet");
+
+        let wallet = self.nostr.add_external_wallet(name, uri).await?;
+
+        log_trace!(self.logger, "finished calling add_external_wallet");
+        Ok(wallet)
+    }
+
+    /// Lists the external wallets we have connected to
+    pub fn list_external_wallets(&self) -> Result<Vec<ExternalWallet>, MutinyError> {
+        self.nostr.list_external_wallets()
+    }
+
+    /// Sets whether invoices are created with an external wallet before our node.
+    /// Otherwise the wallet is only used when our node can't create the invoice.
+    pub fn set_external_wallet_prefer_for_invoices(
+        &self,
+        wallet_pubkey: ::nostr::PublicKey,
+        prefer: bool,
+    ) -> Result<ExternalWallet, MutinyError> {
+        self.nostr
+            .set_external_wallet_prefer_for_invoices(wallet_pubkey, prefer)
+    }
+
+    /// Disconnects an external wallet, it will no longer be used for payments
+    pub async fn remove_external_wallet(
+        &self,
+        wallet_pubkey: ::nostr::PublicKey,
+    ) -> Result<(), MutinyError> {
+        self.nostr.remove_external_wallet(wallet_pubkey).await
+    }
+
+    /// Gets the balance of each external wallet that shares it.
+    /// Wallets that can't be reached are left out.
+    pub async fn get_external_wallet_balances(
+        &self,
+    ) -> Result<Vec<ExternalWalletBalance>, MutinyError> {
+        log_trace!(self.logger, "calling get_external_wallet_balances");
+
+        let balances = self.nostr.refresh_external_wallet_balances().await?;
+
+        log_trace!(self.logger, "finished calling get_external_wallet_balances");
+        Ok(balances)
+    }
+
+    /// The last balances we fetched from our external wallets,
+    /// if those are out of date they are fetched again in the background.
+    fn cached_external_wallet_balances(&self) -> Result<Vec<ExternalWalletBalance>, MutinyError> {
+        let cached = self.nostr.cached_external_wallet_balances()?;
+        if cached.last_updated + EXTERNAL_WALLET_BALANCE_TTL_SECS < utils::now().as_secs() {
+            let nostr = self.nostr.clone();
+            let logger = self.logger.clone();
+            utils::spawn(async move {
+                if let Err(e) = nostr.refresh_external_wallet_balances().await {
+                    log_warn!(logger, "Failed to refresh external wallet balances: {e}");
+                }
+            });
+        }
+
+        Ok(cached.balances)
+    }
+
+    /// Tries to pay an invoice from each external wallet that can cover it.
+    ///
+    /// Returns None if no external wallet attempted the payment.
+    async fn pay_invoice_from_external_wallets(
+        &self,
+        inv: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        send_msat: u64,
+        labels: Vec<String>,
+    ) -> Result<Option<MutinyInvoice>, MutinyError> {
+        // the amount is only given to the wallet for amountless invoices
+        let amt_sats = amt_sats.filter(|_| inv.amount_milli_satoshis().is_none());
+
+        let balances = self.cached_external_wallet_balances()?;
+        let mut last_error = None;
+        for wallet in self.nostr.list_external_wallets()? {
+            if !wallet.supports(Method::PayInvoice) {
+                continue;
+            }
+
+            // skip wallets we know can't cover the payment,
+            // wallets we don't have a balance for yet get to try
+            let wallet_pubkey = wallet.wallet_pubkey()?;
+            if balances
+                .iter()
+                .any(|b| b.wallet_pubkey == wallet_pubkey && b.balance < send_msat / 1_000)
+            {
+                continue;
+            }
+
+            let mut stored_payment: MutinyInvoice = inv.clone().into();
+            stored_payment.inbound = false;
+            stored_payment.labels = labels.clone();
+            stored_payment.amount_sats = Some(send_msat / 1_000);
+            stored_payment.status = HTLCStatus::InFlight;
+            stored_payment.last_updated = utils::now().as_secs();
+            let hash = stored_payment.payment_hash.into_32();
+            persist_payment_info(&self.storage, &hash, &stored_payment.clone().into(), false)?;
+
+            // track the payment so we can look it up if we don't get a result
+            let tracked = ExternalWalletInvoice {
+                payment_hash: stored_payment.payment_hash,
+                wallet_pubkey,
+                expire: stored_payment.expire,
+            };
+            self.track_external_wallet_invoice(EXTERNAL_WALLET_PAYMENTS_KEY, tracked)?;
+
+            match self
+                .nostr
+                .pay_external_wallet_invoice(&wallet, inv, amt_sats)
+                .await
+            {
+                Ok(preimage) if !preimage_matches(&preimage, inv.payment_hash()) => {
+                    // the wallet says it paid, so don't risk paying again from another wallet,
+                    // we keep looking the payment up to learn how it ended
+                    log_error!(
+                        self.logger,
+                        "External wallet {} returned an invalid preimage",
+                        wallet.name
+                    );
+                    return Err(MutinyError::Other(anyhow::anyhow!(
+                        "External wallet returned an invalid preimage"
+                    )));
+                }
+                Ok(preimage) => {
+                    stored_payment.status = HTLCStatus::Succeeded;
+                    stored_payment.preimage = Some(preimage);
+                    stored_payment.last_updated = utils::now().as_secs();
+                    persist_payment_info(
+                        &self.storage,
+                        &hash,
+                        &stored_payment.clone().into(),
+                        false,
+                    )?;
+                    self.untrack_external_wallet_invoices(
+                        EXTERNAL_WALLET_PAYMENTS_KEY,
+                        &HashSet::from([stored_payment.payment_hash]),
+                    )?;
+                    self.event_broadcaster.broadcast(MutinyEvent::PaymentSent {
+                        payment_hash: *inv.payment_hash(),
+                        fee_paid_msat: None,
+                        federation_id: None,
+                    });
+                    return Ok(Some(stored_payment));
+                }
+                // the wallet may still complete it, leave it in flight until we look it up
+                Err(MutinyError::PaymentTimeout) => return Err(MutinyError::PaymentTimeout),
+                Err(e) => {
+                    log_debug!(
+                        self.logger,
+                        "could not make payment through external wallet {}: {e}",
+                        wallet.name
+                    );
+                    stored_payment.status = HTLCStatus::Failed;
+                    stored_payment.last_updated = utils::now().as_secs();
+                    persist_payment_info(
+                        &self.storage,
+                        &hash,
+                        &stored_payment.clone().into(),
+                        false,
+                    )?;
+                    self.untrack_external_wallet_invoices(
+                        EXTERNAL_WALLET_PAYMENTS_KEY,
+                        &HashSet::from([stored_payment.payment_hash]),
+                    )?;
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates an invoice with the first external wallet that can make one,
+    /// only looking at wallets whose invoice preference matches `preferred`.
+    ///
+    /// Returns None if no external wallet could create the invoice.
+    async fn create_external_wallet_invoice(
+        &self,
+        amount: u64,
+        labels: Vec<String>,
+        preferred: bool,
+    ) -> Result<Option<MutinyInvoice>, MutinyError> {
+        for wallet in self.nostr.list_external_wallets()? {
+            if !wallet.supports(Method::MakeInvoice) || wallet.prefer_for_invoices != preferred {
+                continue;
+            }
+
+            let bolt11 = match self
+                .nostr
+                .create_external_wallet_invoice(&wallet, amount)
+                .await
+            {
+                Ok(bolt11) if bolt11.network() == self.network => bolt11,
+                Ok(_) => {
+                    log_warn!(
+                        self.logger,
+                        "External wallet {} created an invoice for the wrong network",
+                        wallet.name
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    log_warn!(
+                        self.logger,
+                        "Failed to create invoice with external wallet {}: {e}",
+                        wallet.name
+                    );
+                    continue;
+                }
+            };
+
+            self.storage
+                .set_invoice_labels(bolt11.clone(), labels.clone())?;
+
+            let mut stored_payment: MutinyInvoice = bolt11.into();
+            stored_payment.inbound = true;
+            stored_payment.labels = labels;
+            stored_payment.last_updated = utils::now().as_secs();
+            let hash = stored_payment.payment_hash.into_32();
+            persist_payment_info(&self.storage, &hash, &stored_payment.clone().into(), true)?;
+
+            // track the invoice so we can find out when it is paid
+            let tracked = ExternalWalletInvoice {
+                payment_hash: stored_payment.payment_hash,
+                wallet_pubkey: wallet.wallet_pubkey()?,
+                expire: stored_payment.expire,
+            };
+            self.track_external_wallet_invoice(EXTERNAL_WALLET_INVOICES_KEY, tracked)?;
+
+            return Ok(Some(stored_payment));
+        }
+
+        Ok(None)
+    }
+
+    /// Checks whether the invoices we created with external wallets have been paid
+    async fn check_external_wallet_invoices(&self) -> Result<(), MutinyError> {
+        let pending: Vec<ExternalWalletInvoice> = self
+            .storage
+            .get_data(EXTERNAL_WALLET_INVOICES_KEY)?
+            .unwrap_or_default();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let now = utils::now().as_secs();
+        let mut resolved = HashSet::new();
+        for invoice in pending {
+            // stop tracking invoices from wallets we have removed
+            let Ok(wallet) = self.nostr.get_external_wallet(invoice.wallet_pubkey) else {
+                resolved.insert(invoice.payment_hash);
+                continue;
+            };
+
+            match self
+                .nostr
+                .lookup_external_wallet_invoice(&wallet, &invoice.payment_hash)
+                .await
+            {
+                Ok(result) => {
+                    if let Some(settled_at) = result.settled_at {
+                        let mut stored_payment = get_invoice_by_hash(
+                            &invoice.payment_hash,
+                            &self.storage,
+                            &self.logger,
+                        )?;
+                        stored_payment.status = HTLCStatus::Succeeded;
+                        stored_payment.preimage = result.preimage;
+                        stored_payment.last_updated = settled_at;
+                        persist_payment_info(
+                            &self.storage,
+                            &invoice.payment_hash.into_32(),
+                            &stored_payment.into(),
+                            true,
+                        )?;
+                        self.event_broadcaster
+                            .broadcast(MutinyEvent::PaymentReceived {
+                                payment_hash: invoice.payment_hash,
+                                amount_msat: result.amount,
+                                federation_id: None,
+                            });
+                        resolved.insert(invoice.payment_hash);
+                    } else if invoice.expire < now {
+                        resolved.insert(invoice.payment_hash);
+                    }
+                }
+                Err(e) => {
+                    log_warn!(
+                        self.logger,
+                        "Failed to look up invoice from external wallet {}: {e}",
+                        wallet.name
+                    );
+                    if invoice.expire < now {
+                        resolved.insert(invoice.payment_hash);
+                    }
+                }
+            }
+        }
+
+        self.untrack_external_wallet_invoices(EXTERNAL_WALLET_INVOICES_KEY, &resolved)
+    }
+
+    /// Checks whether payments we made with external wallets,
+    /// that we didn't get a result for, have completed
+    async fn check_external_wallet_payments(&self) -> Result<(), MutinyError> {
+        let pending: Vec<ExternalWalletInvoice> = self
+            .storage
+            .get_data(EXTERNAL_WALLET_PAYMENTS_KEY)?
+            .unwrap_or_default();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let now = utils::now().as_secs();
+        let mut resolved = HashSet::new();
+        for payment in pending {
+            let hash = payment.payment_hash.into_32();
+            let Some(mut payment_info) =
+                read_payment_info(&self.storage, &hash, false, &self.logger)
+            else {
+                resolved.insert(payment.payment_hash);
+                continue;
+            };
+
+            // stop tracking payments from wallets we have removed
+            let Ok(wallet) = self.nostr.get_external_wallet(payment.wallet_pubkey) else {
+                resolved.insert(payment.payment_hash);
+                continue;
+            };
+
+            let settled = match self
+                .nostr
+                .lookup_external_wallet_invoice(&wallet, &payment.payment_hash)
+                .await
+            {
+                Ok(result) => result
+                    .settled_at
+                    .map(|settled_at| (settled_at, result.preimage)),
+                Err(e) => {
+                    log_warn!(
+                        self.logger,
+                        "Failed to look up payment from external wallet {}: {e}",
+                        wallet.name
+                    );
+                    None
+                }
+            };
+
+            match settled {
+                Some((settled_at, preimage)) => {
+                    payment_info.status = HTLCStatus::Succeeded;
+                    payment_info.preimage = preimage
+                        .filter(|p| preimage_matches(p, &payment.payment_hash))
+                        .and_then(|p| FromHex::from_hex(&p).ok());
+                    payment_info.last_update = settled_at;
+                    persist_payment_info(&self.storage, &hash, &payment_info, false)?;
+                    self.event_broadcaster.broadcast(MutinyEvent::PaymentSent {
+                        payment_hash: payment.payment_hash,
+                        fee_paid_msat: None,
+                        federation_id: None,
+                    });
+                    resolved.insert(payment.payment_hash);
+                }
+                // an expired invoice that still hasn't settled won't be paid anymore
+                None if payment.expire < now => {
+                    payment_info.status = HTLCStatus::Failed;
+                    payment_info.last_update = now;
+                    persist_payment_info(&self.storage, &hash, &payment_info, false)?;
+                    resolved.insert(payment.payment_hash);
+                }
+                None => {}
+            }
+        }
+
+        self.untrack_external_wallet_invoices(EXTERNAL_WALLET_PAYMENTS_KEY, &resolved)
+    }
+
+    /// Adds an invoice to the external wallet invoices tracked under `key`
+    fn track_external_wallet_invoice(
+        &self,
+        key: &str,
+        invoice: ExternalWalletInvoice,
+    ) -> Result<(), MutinyError> {
+        let mut pending: Vec<ExternalWalletInvoice> =
+            self.storage.get_data(key)?.unwrap_or_default();
+        pending.push(invoice);
+        self.storage.set_data(key.to_string(), pending, None)
+    }
+
+    /// Stops tracking the given external wallet invoices under `key`
+    fn untrack_external_wallet_invoices(
+        &self,
+        key: &str,
+        resolved: &HashSet<sha256::Hash>,
+    ) -> Result<(), MutinyError> {
+        if resolved.is_empty() {
+            return Ok(());
+        }
+
+        // re-read so we don't drop invoices added while we were checking
+        let mut pending: Vec<ExternalWalletInvoice> =
+            self.storage.get_data(key)?.unwrap_or_default();
+        pending.retain(|i| !resolved.contains(&i.payment_hash));
+        self.storage.set_data(key.to_string(), pending, None)
+    }
+
+    /// Starts a background process that watches for payments to invoices we
+    /// created with external wallets, and for how payments we made with them ended
+    pub(crate) fn start_external_wallet_checker(&self) {
+        let self_clone = self.clone();
+        utils::spawn(async move {
+            loop {
+                if self_clone.stop.load(Ordering::Relaxed) {
+                    break;
+                };
+
+                if let Err(e) = self_clone.check_external_wallet_invoices().await {
+                    log_error!(
+                        self_clone.logger,
+                        "Failed to check external wallet invoices: {e}"
+                    );
+                }
+
+                if let Err(e) = self_clone.check_external_wallet_payments().await {
+                    log_error!(
+                        self_clone.logger,
+                        "Failed to check external wallet payments: {e}"
+                    );
+                }
+
+                sleep(EXTERNAL_WALLET_CHECK_INTERVAL_SECS * 1_000).await;
+            }
+        });
     }
 
     fn get_invoice_internal(
@@ -2292,6 +3406,14 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                         activities.push(ActivityItem::OnChain(tx_details));
                     }
                 }
+            } else if item.key.starts_with(ECASH_TRANSFER_PREFIX_KEY) {
+                if let Some(transfer) = self.storage.get_data::<EcashTransfer>(&item.key)? {
+                    activities.push(ActivityItem::Ecash(transfer));
+                }
+            } else if item.key.starts_with(FEDERATION_DEPOSIT_PREFIX_KEY) {
+                if let Some(deposit) = self.storage.get_data::<FederationDeposit>(&item.key)? {
+                    activities.push(ActivityItem::FederationDeposit(deposit));
+                }
             }
         }
         log_trace!(self.logger, "finished calling get_activity");
@@ -2432,6 +3554,44 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         res
     }
 
+    /// Waits for an outgoing payment to settle and returns it, or the reason it failed.
+    ///
+    /// This is useful after a payment returns [`MutinyError::PaymentTimeout`],
+    /// as parts of it may still be in flight and can still succeed.
+    /// Returns [`MutinyError::PaymentTimeout`] again if it is still in flight after
+    /// `timeout_secs`, which defaults to the normal payment timeout.
+    pub async fn await_payment_result(
+        &self,
+        hash: &sha256::Hash,
+        timeout_secs: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        log_trace!(self.logger, "calling await_payment_result");
+
+        let deadline = utils::now().as_secs() + timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let res = loop {
+            if self.stop.load(Ordering::Relaxed) {
+                break Err(MutinyError::NotRunning);
+            }
+            if utils::now().as_secs() >= deadline {
+                break Err(MutinyError::PaymentTimeout);
+            }
+
+            let invoice = get_invoice_by_hash(hash, &self.storage, &self.logger)?;
+            if invoice.inbound {
+                break Err(MutinyError::NotFound);
+            }
+
+            match invoice.status {
+                HTLCStatus::Succeeded => break Ok(invoice),
+                HTLCStatus::Failed => break Err(MutinyError::RoutingFailed),
+                HTLCStatus::Pending | HTLCStatus::InFlight => sleep(250).await,
+            }
+        };
+        log_trace!(self.logger, "finished calling await_payment_result");
+
+        res
+    }
+
     /// Checks whether or not the user is subscribed to Mutiny+.
     /// Submits a NWC string to keep the subscription active if not expired.
     ///
@@ -2668,15 +3828,16 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     pub async fn change_nostr_keys(
         &self,
         keys: Option<Keys>,
+        bunker: Option<NostrConnectURI>,
         #[cfg(target_arch = "wasm32")] extension_pk: Option<::nostr::PublicKey>,
     ) -> Result<::nostr::PublicKey, MutinyError> {
         log_trace!(self.logger, "calling change_nostr_keys");
 
         #[cfg(target_arch = "wasm32")]
-        let source = utils::build_nostr_key_source(keys, extension_pk)?;
+        let source = utils::build_nostr_key_source(keys, bunker, extension_pk)?;
 
         #[cfg(not(target_arch = "wasm32"))]
-        let source = utils::build_nostr_key_source(keys)?;
+        let source = utils::build_nostr_key_source(keys, bunker)?;
 
         let new_pk = self.nostr.change_nostr_keys(source, self.xprivkey).await?;
 
@@ -2691,6 +3852,12 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     pub async fn sync_nostr(&self) -> Result<(), MutinyError> {
         log_trace!(self.logger, "calling sync_nostr");
 
+        // pick up relay list changes from other clients first,
+        // so we are connected to the relays we should be using
+        if let Err(e) = self.nostr.sync_relay_list().await {
+            log_warn!(self.logger, "Failed to sync relay list: {e}");
+        }
+
         let npub = self.nostr.get_npub().await;
         let contacts_fut = self.sync_nostr_contacts(npub);
         let profile_fut = self.sync_nostr_profile();
@@ -2827,8 +3994,28 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             }
         }
 
+        // include NIP-17 messages, these can't be fetched from primal
+        match self
+            .nostr
+            .get_private_dm_conversation(npub, limit, until, since)
+            .await
+        {
+            Ok(private) => {
+                let private = private.into_iter().map(|dm| DirectMessage {
+                    from: dm.from,
+                    to: dm.to,
+                    message: dm.message,
+                    date: dm.created_at,
+                    event_id: dm.id,
+                });
+                messages.extend(private);
+            }
+            Err(e) => log_warn!(self.logger, "Failed to get NIP-17 dms: {e}"),
+        }
+
         // sort messages, newest first
         messages.sort_by(|a, b| b.cmp(a));
+        messages.truncate(limit as usize);
 
         log_trace!(self.logger, "finished calling get_dm_conversation");
         Ok(messages)
@@ -2843,6 +4030,9 @@ impl<S: MutinyStorage> MutinyWallet<S> {
 
         self.node_manager.stop().await?;
 
+        // end event subscriptions so their listeners finish
+        self.event_broadcaster.close();
+
         // stop the indexeddb object to close db connection
         if self.storage.connected().unwrap_or(false) {
             log_debug!(self.logger, "stopping storage");
@@ -2983,9 +4173,14 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             self.esplora.clone(),
             federation_code,
             self.stop.clone(),
+            self.event_broadcaster.clone(),
             self.safe_mode,
         )
         .await;
+
+        if res.is_ok() {
+            self.spawn_federation_backup();
+        }
         log_trace!(self.logger, "finished calling new_federation");
 
         res
@@ -3067,6 +4262,8 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 }
             }
         }
+        drop(federations_guard);
+        self.spawn_federation_backup();
         log_trace!(self.logger, "finshed calling remove_federation");
 
         Ok(())
@@ -3113,41 +4310,399 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         Ok(FederationBalances { balances })
     }
 
+    /// Checks on the guardians of every federation we are in, reporting which are
+    /// reachable, their consensus session and block height, the federation's
+    /// module versions and any warnings, such as an upcoming expiry.
+    pub async fn get_federation_health(&self) -> Result<Vec<FederationHealth>, MutinyError> {
+        log_trace!(self.logger, "calling get_federation_health");
+
+        // don't hold the lock while waiting on guardians
+        let federations: Vec<Arc<FederationClient<S>>> =
+            self.federations.read().await.values().cloned().collect();
+        let health = futures::future::join_all(federations.iter().map(|f| f.get_health())).await;
+
+        log_trace!(self.logger, "finished calling get_federation_health");
+        Ok(health)
+    }
+
+    /// Lists on-chain deposits into our federations that a transaction was seen for,
+    /// newest first, with the transaction, its confirmations and whether the
+    /// federation has credited it yet.
+    pub async fn list_federation_deposits(&self) -> Result<Vec<FederationDeposit>, MutinyError> {
+        log_trace!(self.logger, "calling list_federation_deposits");
+
+        let federations: Vec<Arc<FederationClient<S>>> =
+            self.federations.read().await.values().cloned().collect();
+        let mut deposits = vec![];
+        for f in federations {
+            deposits.extend(f.list_deposits().await?);
+        }
+        deposits.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        log_trace!(self.logger, "finished calling list_federation_deposits");
+        Ok(deposits)
+    }
+
+    /// Takes ecash out of a federation so it can be handed to someone directly,
+    /// for example as a string or QR code. The amount is in sats.
+    ///
+    /// Notes that are not claimed within a week are reclaimed.
+    pub async fn spend_ecash(
+        &self,
+        federation_id: FederationId,
+        amount: u64,
+        labels: Vec<String>,
+    ) -> Result<EcashTransfer, MutinyError> {
+        log_trace!(self.logger, "calling spend_ecash");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .get(&federation_id)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client.spend_ecash(amount, labels).await;
+
+        log_trace!(self.logger, "finished calling spend_ecash");
+        res
+    }
+
+    /// Claims ecash notes that were handed to us directly into the federation
+    /// they are from. We need to have joined that federation already.
+    pub async fn receive_ecash(
+        &self,
+        notes: OOBNotes,
+        labels: Vec<String>,
+    ) -> Result<EcashTransfer, MutinyError> {
+        log_trace!(self.logger, "calling receive_ecash");
+
+        let federation_lock = self.federations.read().await;
+        let fedimint_client = federation_lock
+            .iter()
+            .find(|(id, _)| id.to_prefix() == notes.federation_id_prefix())
+            .map(|(_, c)| c)
+            .ok_or(MutinyError::NotFound)?;
+        let res = fedimint_client.receive_ecash(notes, labels).await;
+
+        log_trace!(self.logger, "finished calling receive_ecash");
+        res
+    }
+
     pub async fn resync_federation(&self, federation_id: FederationId) -> Result<(), MutinyError> {
         if !self.safe_mode {
             // cannot safely run unless in safe mode
             return Err(MutinyError::AlreadyRunning);
         }
 
-        let invite_code = self
-            .federation_storage
-            .read()
-            .await
-            .federations
-            .values()
-            .find(|f| f.federation_code.federation_id() == federation_id)
-            .ok_or(MutinyError::NotFound)?
-            .federation_code
-            .clone();
-
-        FederationClient::start_resync(
-            invite_code,
+        let invite_code = self
+            .federation_storage
+            .read()
+            .await
+            .federations
+            .values()
+            .find(|f| f.federation_code.federation_id() == federation_id)
+            .ok_or(MutinyError::NotFound)?
+            .federation_code
+            .clone();
+
+        FederationClient::start_resync(
+            invite_code,
+            self.xprivkey,
+            self.storage.clone(),
+            self.network,
+            self.logger.clone(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub fn get_federation_resync_progress(
+        &self,
+        federation_id: FederationId,
+    ) -> Result<Option<ResyncProgress>, MutinyError> {
+        self.storage.get_data(resync_state_key(&federation_id))
+    }
+
+    /// Backs up the list of federations we are in to each federation,
+    /// encrypted so only our seed can read it. Each federation also gets
+    /// a backup of our ecash so recovering it is quick, this is refreshed
+    /// in the background about once a day.
+    ///
+    /// The list itself is already kept in VSS with the rest of our federation storage.
+    pub async fn backup_federations(&self) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling backup_federations");
+
+        let backup = self.current_federation_backup().await;
+        let res = self.backup_to_federations(&backup).await;
+
+        log_trace!(self.logger, "finished calling backup_federations");
+        res
+    }
+
+    // Only backs up when the federations we are in changed since the last backup,
+    // or when the last backup is old enough that its ecash is out of date
+    async fn backup_federations_if_needed(&self) -> Result<(), MutinyError> {
+        let backup = self.current_federation_backup().await;
+        let last_backup: Option<FederationBackup> = self.storage.get_data(FEDERATION_BACKUP_KEY)?;
+        if last_backup.is_some_and(|b| {
+            b.same_federations(&backup)
+                && backup.timestamp.saturating_sub(b.timestamp) < FEDERATION_BACKUP_INTERVAL_SECS
+        }) {
+            return Ok(());
+        }
+
+        self.backup_to_federations(&backup).await
+    }
+
+    async fn current_federation_backup(&self) -> FederationBackup {
+        FederationBackup {
+            federations: self
+                .federation_storage
+                .read()
+                .await
+                .federations
+                .values()
+                .map(|f| f.federation_code.clone())
+                .collect(),
+            timestamp: utils::now().as_secs(),
+        }
+    }
+
+    async fn backup_to_federations(&self, backup: &FederationBackup) -> Result<(), MutinyError> {
+        let federations: Vec<Arc<FederationClient<S>>> =
+            self.federations.read().await.values().cloned().collect();
+        let mut backed_up = true;
+        for f in federations {
+            if let Err(e) = f.backup_to_federation(backup).await {
+                log_warn!(
+                    self.logger,
+                    "could not back up to federation {}: {e}",
+                    f.fedimint_client.federation_id()
+                );
+                backed_up = false;
+            }
+        }
+
+        // remember what we backed up so we only do it again once it changes or gets old,
+        // a failed federation gets retried next time
+        if backed_up {
+            self.storage
+                .set_data(FEDERATION_BACKUP_KEY.to_string(), backup, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn spawn_federation_backup(&self) {
+        let self_clone = self.clone();
+        utils::spawn(async move {
+            if let Err(e) = self_clone.backup_federations().await {
+                log_error!(self_clone.logger, "could not back up federations: {e}");
+            }
+        });
+    }
+
+    /// Restores the federations we were in using just our seed.
+    ///
+    /// Federations are found in our VSS storage and in the backups each
+    /// recovered federation holds. Each one is added back to our list and
+    /// recovered. This can only run in safe mode, use
+    /// `get_federation_restore_progress` to follow along and restart
+    /// normally once it is done.
+    ///
+    /// Returns the federations that started restoring, ones that failed
+    /// are logged and can be retried by calling this again.
+    pub async fn restore_federations(&self) -> Result<Vec<FederationId>, MutinyError> {
+        log_trace!(self.logger, "calling restore_federations");
+
+        if !self.safe_mode {
+            // cannot safely run unless in safe mode
+            return Err(MutinyError::AlreadyRunning);
+        }
+
+        let mut invite_codes: Vec<InviteCode> = self
+            .federation_storage
+            .read()
+            .await
+            .federations
+            .values()
+            .map(|f| f.federation_code.clone())
+            .collect();
+        if let Some(vss) = self.storage.vss_client() {
+            match vss.get_object(FEDERATIONS_KEY).await {
+                Ok(obj) => match serde_json::from_value::<FederationStorage>(obj.value) {
+                    Ok(federations) => invite_codes.extend(
+                        federations
+                            .federations
+                            .into_values()
+                            .map(|f| f.federation_code),
+                    ),
+                    Err(e) => log_warn!(self.logger, "could not read federations from VSS: {e}"),
+                },
+                Err(e) => log_warn!(self.logger, "could not get federations from VSS: {e}"),
+            }
+        }
+
+        // a federation we can't restore shouldn't keep us from restoring the rest
+        let mut state = FederationRestoreState::default();
+        let mut attempted = HashSet::new();
+        for code in invite_codes {
+            let federation_id = code.federation_id();
+            if !attempted.insert(federation_id) {
+                continue;
+            }
+            match self.restore_federation(code).await {
+                Ok(()) => state.federations.push(federation_id),
+                Err(e) => {
+                    log_error!(
+                        self.logger,
+                        "could not restore federation {federation_id}: {e}"
+                    )
+                }
+            }
+        }
+        self.storage
+            .set_data(FEDERATION_RESTORE_KEY.to_string(), &state, None)?;
+
+        // keep going with the federations found in the backups of the ones we recover
+        let self_clone = self.clone();
+        utils::spawn(async move {
+            self_clone.restore_discovered_federations().await;
+        });
+
+        log_trace!(self.logger, "finished calling restore_federations");
+        Ok(state.federations)
+    }
+
+    async fn restore_federation(&self, federation_code: InviteCode) -> Result<(), MutinyError> {
+        // make sure it is in our list so we join it when we restart normally
+        {
+            let mut federation_storage = self.federation_storage.write().await;
+            if !federation_storage
+                .federations
+                .values()
+                .any(|f| f.federation_code == federation_code)
+            {
+                federation_storage.federations.insert(
+                    Uuid::new_v4().to_string(),
+                    FederationIndex {
+                        federation_code: federation_code.clone(),
+                    },
+                );
+                federation_storage.version += 1;
+                self.storage
+                    .insert_federations(federation_storage.clone())
+                    .await?;
+            }
+        }
+
+        let federation_id = federation_code.federation_id();
+        let res = FederationClient::start_resync(
+            federation_code,
             self.xprivkey,
             self.storage.clone(),
             self.network,
             self.logger.clone(),
         )
-        .await?;
+        .await;
 
-        Ok(())
+        // don't leave progress behind that will never finish
+        if res.is_err() {
+            self.storage.delete(&[resync_state_key(&federation_id)])?;
+        }
+
+        res
     }
 
-    pub fn get_federation_resync_progress(
+    async fn restore_discovered_federations(&self) {
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let mut state = match self
+                .storage
+                .get_data::<FederationRestoreState>(FEDERATION_RESTORE_KEY)
+            {
+                Ok(Some(state)) => state,
+                _ => break,
+            };
+
+            let mut waiting = false;
+            let mut discovered: Vec<InviteCode> = vec![];
+            for federation_id in state.federations.iter() {
+                if let Ok(Some(_)) = self.get_federation_resync_progress(*federation_id) {
+                    waiting = true;
+                    continue;
+                }
+
+                let key = format!("{RECOVERED_FEDERATION_BACKUP_PREFIX_KEY}{federation_id}");
+                if let Ok(Some(backup)) = self.storage.get_data::<FederationBackup>(key) {
+                    discovered.extend(backup.federations);
+                }
+            }
+
+            let mut started = false;
+            for code in discovered {
+                let federation_id = code.federation_id();
+                if state.federations.contains(&federation_id) {
+                    continue;
+                }
+
+                log_info!(
+                    self.logger,
+                    "restoring federation {federation_id} found in a federation backup"
+                );
+                if let Err(e) = self.restore_federation(code).await {
+                    log_error!(self.logger, "could not restore federation: {e}");
+                }
+                state.federations.push(federation_id);
+                started = true;
+            }
+
+            if !started && !waiting {
+                state.finished = true;
+            }
+            if let Err(e) = self
+                .storage
+                .set_data(FEDERATION_RESTORE_KEY.to_string(), &state, None)
+            {
+                log_error!(self.logger, "could not save federation restore state: {e}");
+            }
+            if state.finished {
+                break;
+            }
+
+            sleep(5_000).await;
+        }
+    }
+
+    /// Gets the combined progress of restoring our federations from backup,
+    /// if a restore has been started.
+    pub fn get_federation_restore_progress(
         &self,
-        federation_id: FederationId,
-    ) -> Result<Option<ResyncProgress>, MutinyError> {
-        let storage_key = format!("resync_state/{federation_id}");
-        self.storage.get_data(storage_key)
+    ) -> Result<Option<FederationRestoreProgress>, MutinyError> {
+        let Some(state) = self
+            .storage
+            .get_data::<FederationRestoreState>(FEDERATION_RESTORE_KEY)?
+        else {
+            return Ok(None);
+        };
+
+        let mut progress = FederationRestoreProgress::default();
+        for federation_id in state.federations {
+            let resync = self.get_federation_resync_progress(federation_id)?;
+            if let Some(ref r) = resync {
+                progress.total += r.total;
+                progress.complete += r.complete;
+            }
+            progress.federations.push(FederationResyncStatus {
+                federation_id,
+                progress: resync,
+            });
+        }
+        progress.done = state.finished && progress.federations.iter().all(|f| f.progress.is_none());
+
+        Ok(Some(progress))
     }
 
     /// Starts a background process that will check pending fedimint operations
@@ -3188,6 +4743,56 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                     log_error!(logger, "could not list federations: {e}")
                 }
             }
+            drop(federation_lock);
+        });
+
+        // periodically check on the federations' guardians, alerting only when
+        // a warning first shows up, and keep them within their maximum balances
+        let self_clone = self.clone();
+        utils::spawn(async move {
+            let mut last_warnings: HashMap<FederationId, Vec<FederationHealthWarning>> =
+                HashMap::new();
+            loop {
+                if self_clone.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                match self_clone.get_federation_health().await {
+                    Ok(health) => {
+                        for h in health {
+                            let previous = last_warnings.remove(&h.federation_id);
+                            for warning in h.warnings.iter() {
+                                if previous.as_ref().is_some_and(|p| p.contains(warning)) {
+                                    continue;
+                                }
+                                self_clone.event_broadcaster.broadcast(
+                                    MutinyEvent::FederationHealthWarning {
+                                        federation_id: h.federation_id,
+                                        warning: warning.clone(),
+                                    },
+                                );
+                            }
+                            last_warnings.insert(h.federation_id, h.warnings);
+                        }
+                    }
+                    Err(e) => {
+                        log_error!(self_clone.logger, "could not check federation health: {e}")
+                    }
+                }
+
+                // move funds out of any federation that went over its maximum balance
+                if let Err(e) = self_clone.rebalance_federations().await {
+                    log_error!(self_clone.logger, "could not rebalance federations: {e}");
+                }
+
+                // back up to our federations if we joined or left any,
+                // and keep the ecash in the backups up to date
+                if let Err(e) = self_clone.backup_federations_if_needed().await {
+                    log_error!(self_clone.logger, "could not back up federations: {e}");
+                }
+
+                sleep((FEDERATION_HEALTH_CHECK_INTERVAL_SECS * 1_000) as i32).await;
+            }
         });
 
         log_trace!(
@@ -3257,9 +4862,11 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 // if user's npub is given, do an anon zap
                 let (zap_request, comment) = match zap_npub {
                     Some(zap_npub) => {
+                        // the receipt should go where the recipient and we will see it
+                        let relays = self.nostr.get_zap_relays(zap_npub).await;
                         let data = ZapRequestData {
                             public_key: zap_npub,
-                            relays: RELAYS.iter().map(|r| (*r).into()).collect(),
+                            relays: relays.into_iter().map(|r| r.into()).collect(),
                             message: comment.unwrap_or_default(),
                             amount: Some(msats),
                             lnurl: Some(lnurl.encode()),
@@ -3270,23 +4877,20 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                         let event = match privacy_level {
                             PrivacyLevel::Public => {
                                 self.nostr
-                                    .nostr_keys
-                                    .read()
-                                    .await
-                                    .signer
+                                    .signer()
+                                    .await?
                                     .sign_event_builder(EventBuilder::public_zap_request(data))
                                     .await?
                             }
                             PrivacyLevel::Private => {
                                 // if we have access to the keys, use those
-                                // otherwise need to implement ourselves to use with NIP-07
-                                let signer = &self.nostr.nostr_keys.read().await.signer;
+                                // otherwise need to implement ourselves to use with NIP-07 or NIP-46
+                                let signer = &self.nostr.signer().await?;
                                 match signer {
                                     NostrSigner::Keys(keys) => {
                                         nip57::private_zap_request(data, keys)?
                                     }
-                                    #[cfg(target_arch = "wasm32")]
-                                    NostrSigner::NIP07(_) => {
+                                    _ => {
                                         // Generate encryption key
                                         // Since we are not doing deterministically, we will
                                         // not be able to decrypt this ourself in the future.
@@ -3332,7 +4936,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                             }
                         };
 
-                        (Some(event.as_json()), None)
+                        (Some(event), None)
                     }
                     None => {
                         // PrivacyLevel only applicable to zaps, without
@@ -3347,7 +4951,12 @@ impl<S: MutinyStorage> MutinyWallet<S> {
 
                 let invoice = self
                     .lnurl_client
-                    .get_invoice(&pay, msats, zap_request, comment.as_deref())
+                    .get_invoice(
+                        &pay,
+                        msats,
+                        zap_request.as_ref().map(|e| e.as_json()),
+                        comment.as_deref(),
+                    )
                     .await?;
 
                 let invoice = Bolt11Invoice::from_str(invoice.invoice())?;
@@ -3376,6 +4985,17 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                         );
                         persist_payment_info(&self.storage, &hash, &inv.clone().into(), false)?;
                     }
+
+                    // save the zap so we can match it to its receipt
+                    if let (Some(zap_request), Some(zap_npub)) = (zap_request, zap_npub) {
+                        if let Err(e) = self
+                            .save_sent_zap(&pay, &invoice, zap_request, zap_npub, privacy_level)
+                            .await
+                        {
+                            log_warn!(self.logger, "Failed to save sent zap: {e}");
+                        }
+                    }
+
                     Ok(inv)
                 } else {
                     log_error!(self.logger, "LNURL return invoice with incorrect amount");
@@ -3390,6 +5010,123 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         res
     }
 
+    /// Saves a zap we paid to our zap history
+    async fn save_sent_zap(
+        &self,
+        pay: &PayResponse,
+        invoice: &Bolt11Invoice,
+        zap_request: ::nostr::Event,
+        recipient: ::nostr::PublicKey,
+        privacy_level: PrivacyLevel,
+    ) -> Result<(), MutinyError> {
+        // receipts must be signed by the provider's nostr key, if it supports zaps
+        let provider = zap_provider(pay);
+
+        // anonymous zaps are signed with a throwaway key
+        let sender = match privacy_level {
+            PrivacyLevel::Anonymous => None,
+            _ => Some(self.nostr.get_npub().await),
+        };
+        let contact_id = self
+            .storage
+            .get_contact_for_npub(recipient)?
+            .map(|(id, _)| id);
+
+        let message = match privacy_level {
+            // private zaps have the message encrypted in the anon tag
+            PrivacyLevel::Private => None,
+            _ => Some(zap_request.content.clone()).filter(|m| !m.is_empty()),
+        };
+        let zapped_event = zap_request.iter_tags().find_map(|tag| match tag {
+            Tag::Event { event_id, .. } => Some(*event_id),
+            _ => None,
+        });
+
+        self.nostr.save_sent_zap(Zap {
+            payment_hash: *invoice.payment_hash(),
+            inbound: false,
+            amount_msats: invoice.amount_milli_satoshis().unwrap_or_default(),
+            sender,
+            recipient,
+            message,
+            privacy_level,
+            zapped_event,
+            zap_request_id: zap_request.id,
+            provider,
+            receipt_id: None,
+            contact_id,
+            created_at: zap_request.created_at.as_u64(),
+        })
+    }
+
+    /// Fetches and verifies receipts for the zaps we sent and received
+    pub async fn sync_zap_receipts(&self) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling sync_zap_receipts");
+
+        let provider = self.get_zap_provider().await?;
+        self.nostr.sync_zap_receipts(provider).await?;
+
+        log_trace!(self.logger, "finished calling sync_zap_receipts");
+        Ok(())
+    }
+
+    /// Gets the key the LNURL provider of the lightning address in our
+    /// profile signs zap receipts with.
+    /// Uses the last key we saw if the provider can't be reached.
+    async fn get_zap_provider(&self) -> Result<Option<::nostr::PublicKey>, MutinyError> {
+        let Some(lud16) = self.nostr.get_profile()?.lud16 else {
+            return Ok(None);
+        };
+        let Ok(address) = LightningAddress::from_str(&lud16) else {
+            return Ok(None);
+        };
+
+        let cached = self
+            .storage
+            .get_data::<(String, ::nostr::PublicKey)>(ZAP_PROVIDER_KEY)?
+            .filter(|(cached, _)| *cached == lud16)
+            .map(|(_, provider)| provider);
+
+        let provider = match self.lnurl_client.make_request(&address.lnurlp_url()).await {
+            Ok(LnUrlResponse::LnUrlPayResponse(pay)) => zap_provider(&pay),
+            Ok(_) => None,
+            Err(e) => {
+                log_warn!(self.logger, "Could not reach our LNURL provider: {e}");
+                return Ok(cached);
+            }
+        };
+
+        match provider {
+            Some(provider) => {
+                self.storage
+                    .set_data(ZAP_PROVIDER_KEY.to_string(), (lud16, provider), None)?
+            }
+            None => self.storage.delete(&[ZAP_PROVIDER_KEY])?,
+        }
+
+        Ok(provider)
+    }
+
+    /// Gets our zap history, newest first.
+    /// Each zap is linked to its invoice if we have it.
+    pub async fn get_zap_history(&self) -> Result<Vec<ZapActivity>, MutinyError> {
+        log_trace!(self.logger, "calling get_zap_history");
+
+        let history = self
+            .nostr
+            .get_zaps()?
+            .into_iter()
+            .map(|zap| {
+                let invoice =
+                    get_invoice_by_hash(&zap.payment_hash, &self.storage, &self.logger).ok();
+                ZapActivity { zap, invoice }
+            })
+            .collect();
+
+        log_trace!(self.logger, "finished calling get_zap_history");
+        Ok(history)
+    }
+
     /// Calls upon a LNURL and withdraws from it.
     /// This will fail if the LNURL is not a LNURL withdrawal.
     pub async fn lnurl_withdraw(
@@ -3447,76 +5184,222 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     }
 
     /// Calls upon a Cashu mint and redeems/melts the token.
+    ///
+    /// Proofs are grouped by mint and each mint is melted in one go, so a
+    /// failure at one mint does not affect the others. Returns a result for
+    /// each mint, or an error if none of them could be melted.
     pub async fn melt_cashu_token(
         &self,
-        token_v3: TokenV3,
-    ) -> Result<Vec<MutinyInvoice>, MutinyError> {
+        token: CashuToken,
+    ) -> Result<Vec<CashuMeltResult>, MutinyError> {
         log_trace!(self.logger, "calling melt_cashu_token");
 
-        let mut invoices: Vec<MutinyInvoice> = Vec::with_capacity(token_v3.tokens.len());
+        if !token.is_sat() {
+            log_warn!(self.logger, "can't melt {} tokens", token.unit());
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        let token_v3 = token.token;
 
+        let mut mints: Vec<(Url, Vec<Proof>)> = Vec::with_capacity(token_v3.tokens.len());
         for token in token_v3.tokens {
             let mint_url = match token.mint {
                 Some(url) => url,
                 None => return Err(MutinyError::EmptyMintURLError),
             };
 
-            let total_proofs_amount = token.proofs.total_amount();
-            let mut invoice_pct = 0.99;
-            // create invoice for 1% less than proofs amount
-            let mut invoice_amount = total_proofs_amount as f64 * invoice_pct;
-            let mut mutiny_invoice: MutinyInvoice;
-            let mut mutiny_invoice_str: Bolt11Invoice;
-            let mut melt_quote_res: PostMeltQuoteBolt11Response;
-
-            loop {
-                mutiny_invoice = self
-                    .create_invoice(invoice_amount as u64, vec![MELT_CASHU_TOKEN.to_string()])
-                    .await?;
+            match mints.iter_mut().find(|(url, _)| *url == mint_url) {
+                Some((_, proofs)) => proofs.extend(token.proofs.proofs()),
+                None => mints.push((mint_url, token.proofs.proofs())),
+            }
+        }
 
-                mutiny_invoice_str = mutiny_invoice
-                    .bolt11
-                    .clone()
-                    .expect("The invoice should have BOLT11");
+        let mut results = Vec::with_capacity(mints.len());
+        let mut last_error = None;
+        for (mint_url, proofs) in mints {
+            let amount = proofs.iter().map(|p| p.amount).sum();
+            let result = match self.melt_cashu_proofs(&mint_url, proofs).await {
+                Ok((invoice, change)) => CashuMeltResult {
+                    mint_url,
+                    amount,
+                    invoice: Some(invoice),
+                    change,
+                    error: None,
+                },
+                Err(e) => {
+                    log_warn!(self.logger, "could not melt token from {mint_url}: {e}");
+                    let error = Some(e.to_string());
+                    last_error = Some(e);
+                    CashuMeltResult {
+                        mint_url,
+                        amount,
+                        invoice: None,
+                        change: 0,
+                        error,
+                    }
+                }
+            };
+            results.push(result);
+        }
 
-                let quote_request = PostMeltQuoteBolt11Request {
-                    request: mutiny_invoice_str.to_string(),
-                    unit: CurrencyUnit::Sat,
-                };
+        if let Some(e) = last_error {
+            if results.iter().all(|r| r.error.is_some()) {
+                return Err(e);
+            }
+        }
+        log_trace!(self.logger, "finished calling melt_cashu_token");
 
-                melt_quote_res = self
-                    .cashu_client
-                    .post_melt_quote_bolt11(&mint_url, quote_request)
-                    .await?;
+        Ok(results)
+    }
 
-                if melt_quote_res.amount + melt_quote_res.fee_reserve > total_proofs_amount {
-                    // if invoice created was too big, lower amount
-                    invoice_pct -= 0.01;
-                    invoice_amount *= invoice_pct;
-                } else {
-                    break;
-                }
+    /// Melts proofs from a single mint into an invoice from our node.
+    /// Returns the paid invoice and the fee reserve that came back as change.
+    async fn melt_cashu_proofs(
+        &self,
+        mint_url: &Url,
+        proofs: Vec<Proof>,
+    ) -> Result<(MutinyInvoice, u64), MutinyError> {
+        let total_proofs_amount: u64 = proofs.iter().map(|p| p.amount).sum();
+
+        // make sure the mint can pay us before creating any invoices,
+        // if it won't tell us we try anyway
+        match self.cashu.get_mint_info(mint_url).await {
+            Ok(info) if !info.supports_nut(NUT_MELT) => {
+                return Err(MutinyError::CashuMintUnsupported);
             }
+            Ok(_) => {}
+            Err(e) => log_warn!(self.logger, "could not get info for mint {mint_url}: {e}"),
+        }
 
-            let melt_request = PostMeltBolt11Request {
-                quote: melt_quote_res.quote,
-                inputs: token.proofs,
-                outputs: vec![],
-            };
+        // leave room for the fee reserve the mint quotes before creating our invoice,
+        // any of it the mint doesn't use comes back as change
+        let fee_reserve = self
+            .cashu
+            .quote_fee_reserve(mint_url, total_proofs_amount, self.network)
+            .await?;
+        let invoice_amount = total_proofs_amount
+            .checked_sub(fee_reserve)
+            .filter(|a| *a > 0)
+            .ok_or(MutinyError::InsufficientBalance)?;
+        let invoice = self
+            .create_invoice(invoice_amount, vec![MELT_CASHU_TOKEN.to_string()])
+            .await?;
+        let bolt11 = invoice
+            .bolt11
+            .clone()
+            .ok_or(MutinyError::InvoiceCreationFailed)?;
+        let melt_quote = self.cashu.melt_quote(mint_url, &bolt11).await?;
+        if melt_quote.amount + melt_quote.fee_reserve > total_proofs_amount {
+            // the reserve for a smaller payment shouldn't be higher, but a mint could do it
+            log_warn!(
+                self.logger,
+                "mint {mint_url} wants a fee reserve of {} sats, more than the {fee_reserve} sats it quoted",
+                melt_quote.fee_reserve
+            );
+            return Err(MutinyError::InsufficientBalance);
+        }
 
-            let post_melt_bolt11_response: PostMeltBolt11Response = self
-                .cashu_client
-                .post_melt_bolt11(&mint_url, melt_request)
-                .await?;
+        let change = self
+            .cashu
+            .melt_proofs(mint_url, &melt_quote, proofs)
+            .await?;
 
-            if post_melt_bolt11_response.paid {
-                mutiny_invoice = self.get_invoice(&mutiny_invoice_str).await?;
-                invoices.push(mutiny_invoice);
-            }
-        }
-        log_trace!(self.logger, "finished calling melt_cashu_token");
+        let invoice = self.get_invoice(&bolt11).await?;
+
+        Ok((invoice, change))
+    }
+
+    /// Gets our cashu balance at each mint we hold ecash from.
+    pub fn list_cashu_balances(&self) -> Result<Vec<CashuMintBalance>, MutinyError> {
+        self.cashu.list_balances()
+    }
+
+    /// Lists the requests we have made to mint cashu ecash.
+    pub fn list_cashu_mint_quotes(&self) -> Result<Vec<CashuMintQuote>, MutinyError> {
+        self.cashu.list_mint_quotes()
+    }
+
+    /// Asks a cashu mint for an invoice that mints us ecash once paid.
+    /// Call `mint_cashu` after it is paid to claim the ecash.
+    pub async fn create_cashu_mint_quote(
+        &self,
+        mint_url: Url,
+        amount: u64,
+    ) -> Result<CashuMintQuote, MutinyError> {
+        self.cashu.create_mint_quote(mint_url, amount).await
+    }
 
-        Ok(invoices)
+    /// Claims the ecash for a mint quote if its invoice has been paid.
+    pub async fn mint_cashu(&self, quote: String) -> Result<CashuMintQuote, MutinyError> {
+        self.cashu.mint(&quote).await
+    }
+
+    /// Pays a cashu mint from our lightning balance to mint `amount` sats of ecash.
+    pub async fn fund_cashu_mint(
+        &self,
+        mint_url: Url,
+        amount: u64,
+    ) -> Result<CashuMintQuote, MutinyError> {
+        log_trace!(self.logger, "calling fund_cashu_mint");
+
+        let quote = self.cashu.create_mint_quote(mint_url, amount).await?;
+        self.pay_invoice(&quote.bolt11, None, vec![]).await?;
+        let quote = self.cashu.mint(&quote.quote).await?;
+
+        log_trace!(self.logger, "finished calling fund_cashu_mint");
+        Ok(quote)
+    }
+
+    /// Decodes a cashu token of any supported version to show what it holds
+    /// without claiming it.
+    pub fn decode_cashu_token(&self, token: String) -> Result<CashuTokenPreview, MutinyError> {
+        Ok(CashuToken::from_str(&token)?.preview())
+    }
+
+    /// Gets what a cashu mint supports from its `/v1/info`.
+    pub async fn get_cashu_mint_info(&self, mint_url: Url) -> Result<MintInfo, MutinyError> {
+        self.cashu.get_mint_info(&mint_url).await
+    }
+
+    /// Claims a cashu token into our wallet, returns the amount received.
+    pub async fn receive_cashu_token(&self, token: CashuToken) -> Result<u64, MutinyError> {
+        self.cashu.receive(token).await
+    }
+
+    /// Creates a cashu token worth `amount` sats from our ecash at the given mint.
+    /// It is kept as a pending send until claimed so it can be reclaimed.
+    pub async fn send_cashu_token(
+        &self,
+        mint_url: Url,
+        amount: u64,
+    ) -> Result<TokenV3, MutinyError> {
+        self.cashu.send(mint_url, amount).await
+    }
+
+    /// Lists the cashu tokens we have sent and whether they were claimed.
+    pub fn list_cashu_pending_sends(&self) -> Result<Vec<CashuPendingSend>, MutinyError> {
+        self.cashu.list_pending_sends()
+    }
+
+    /// Takes the ecash of an unclaimed cashu token we sent back into our wallet,
+    /// returns the amount reclaimed.
+    pub async fn reclaim_cashu_token(&self, id: String) -> Result<u64, MutinyError> {
+        self.cashu.reclaim_pending_send(&id).await
+    }
+
+    /// Moves `amount` sats of ecash from one cashu mint to another.
+    pub async fn transfer_cashu(
+        &self,
+        from: Url,
+        to: Url,
+        amount: u64,
+    ) -> Result<CashuMintQuote, MutinyError> {
+        self.cashu.transfer(&from, to, amount).await
+    }
+
+    /// Asks our cashu mints which of our proofs are spent and drops them,
+    /// returns the amount removed from our balance.
+    pub async fn check_cashu_proofs(&self) -> Result<u64, MutinyError> {
+        self.cashu.check_proofs().await
     }
 
     pub async fn check_available_lnurl_name(&self, name: String) -> Result<bool, MutinyError> {
@@ -3764,14 +5647,41 @@ impl<S: MutinyStorage> InvoiceHandler for MutinyWallet<S> {
     ) -> Result<MutinyInvoice, MutinyError> {
         self.create_lightning_invoice(amount, labels).await
     }
+
+    async fn keysend(
+        &self,
+        to_node: PublicKey,
+        amt_sats: u64,
+        message: Option<String>,
+        preimage: Option<[u8; 32]>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        self.node_manager
+            .keysend(
+                None,
+                to_node,
+                amt_sats,
+                message,
+                preimage,
+                &PaymentOptions::default(),
+                labels,
+            )
+            .await
+    }
+
+    fn list_payments(&self) -> Result<Vec<MutinyInvoice>, MutinyError> {
+        self.list_invoices()
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn create_federations<S: MutinyStorage>(
     federation_storage: FederationStorage,
     c: &MutinyWalletConfig,
     storage: S,
     esplora: Arc<AsyncClient>,
     stop: Arc<AtomicBool>,
+    event_broadcaster: EventBroadcaster,
     logger: &Arc<MutinyLogger>,
     safe_mode: bool,
 ) -> Result<Arc<RwLock<HashMap<FederationId, Arc<FederationClient<S>>>>>, MutinyError> {
@@ -3785,6 +5695,7 @@ async fn create_federations<S: MutinyStorage>(
             esplora.clone(),
             c.network,
             stop.clone(),
+            event_broadcaster.clone(),
             logger.clone(),
             safe_mode,
         )
@@ -3811,6 +5722,7 @@ pub(crate) async fn create_new_federation<S: MutinyStorage>(
     esplora: Arc<AsyncClient>,
     federation_code: InviteCode,
     stop: Arc<AtomicBool>,
+    event_broadcaster: EventBroadcaster,
     safe_mode: bool,
 ) -> Result<FederationIdentity, MutinyError> {
     // Begin with a mutex lock so that nothing else can
@@ -3842,6 +5754,7 @@ pub(crate) async fn create_new_federation<S: MutinyStorage>(
         esplora,
         network,
         stop.clone(),
+        event_broadcaster,
         logger.clone(),
         safe_mode,
     )
@@ -3896,6 +5809,13 @@ struct NostrBuildResult {
     data: Vec<Value>,
 }
 
+/// Gets the nostr key an LNURL provider signs zap receipts with, if it supports zaps
+fn zap_provider(pay: &PayResponse) -> Option<::nostr::PublicKey> {
+    pay.nostr_pubkey
+        .filter(|_| pay.allows_nostr.unwrap_or(false))
+        .and_then(|pk| ::nostr::PublicKey::from_slice(&pk.serialize()).ok())
+}
+
 // max amount that can be spent through a gateway
 fn max_spendable_amount(current_balance_sat: u64, routing_fees: &GatewayFees) -> Option<u64> {
     let current_balance_msat = current_balance_sat as f64 * 1_000.0;
@@ -3946,6 +5866,65 @@ fn calc_routing_fee_msat(amt_msat: f64, routing_fees: &GatewayFees) -> f64 {
     routing_fees.base_msat as f64 + prop_fee_msat
 }
 
+/// A move of funds out of a federation that is over its maximum balance.
+/// A `to` of `None` means the funds go to our lightning node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FederationRebalance {
+    from: FederationId,
+    to: Option<FederationId>,
+    amount: u64,
+}
+
+/// Works out how to bring every federation back under its maximum balance.
+/// Takes each federation's id, balance and maximum balance, all in sats.
+fn plan_federation_rebalance(
+    balances: &[(FederationId, u64, Option<u64>)],
+) -> Vec<FederationRebalance> {
+    let mut room: Vec<(FederationId, u64)> = balances
+        .iter()
+        .map(|(id, balance, max)| {
+            let room = max.map(|m| m.saturating_sub(*balance)).unwrap_or(u64::MAX);
+            (*id, room)
+        })
+        .collect();
+
+    let mut plan = vec![];
+    for (from, balance, max) in balances {
+        let Some(max) = max else { continue };
+        let mut excess = balance.saturating_sub(*max);
+
+        while excess > 0 {
+            let target = room
+                .iter_mut()
+                .filter(|(id, room)| id != from && *room > 0)
+                .max_by_key(|(_, room)| *room);
+
+            match target {
+                Some((to, room)) => {
+                    let amount = excess.min(*room);
+                    *room -= amount;
+                    excess -= amount;
+                    plan.push(FederationRebalance {
+                        from: *from,
+                        to: Some(*to),
+                        amount,
+                    });
+                }
+                None => {
+                    plan.push(FederationRebalance {
+                        from: *from,
+                        to: None,
+                        amount: excess,
+                    });
+                    excess = 0;
+                }
+            }
+        }
+    }
+
+    plan
+}
+
 #[cfg(test)]
 fn max_routing_fee_amount() {
     let initial_budget = 1;
@@ -4052,11 +6031,63 @@ fn max_routing_fee_amount() {
 #[cfg(not(target_arch = "wasm32"))]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn test_max_routing_fee_amount() {
         max_routing_fee_amount();
     }
+
+    #[test]
+    fn test_plan_federation_rebalance() {
+        let fed_a = FederationId::from_str(&"a".repeat(64)).unwrap();
+        let fed_b = FederationId::from_str(&"b".repeat(64)).unwrap();
+        let fed_c = FederationId::from_str(&"c".repeat(64)).unwrap();
+
+        // nothing over its limit, nothing to do
+        let balances = [(fed_a, 1_000, Some(2_000)), (fed_b, 5_000, None)];
+        assert!(plan_federation_rebalance(&balances).is_empty());
+
+        // excess goes to the federation with the most room first
+        let balances = [
+            (fed_a, 10_000, Some(4_000)),
+            (fed_b, 1_000, Some(3_000)),
+            (fed_c, 0, Some(5_000)),
+        ];
+        assert_eq!(
+            plan_federation_rebalance(&balances),
+            vec![
+                FederationRebalance {
+                    from: fed_a,
+                    to: Some(fed_c),
+                    amount: 5_000,
+                },
+                FederationRebalance {
+                    from: fed_a,
+                    to: Some(fed_b),
+                    amount: 1_000,
+                },
+            ]
+        );
+
+        // whatever doesn't fit in another federation goes to the node
+        let balances = [(fed_a, 10_000, Some(4_000)), (fed_b, 1_000, Some(3_000))];
+        assert_eq!(
+            plan_federation_rebalance(&balances),
+            vec![
+                FederationRebalance {
+                    from: fed_a,
+                    to: Some(fed_b),
+                    amount: 2_000,
+                },
+                FederationRebalance {
+                    from: fed_a,
+                    to: None,
+                    amount: 4_000,
+                },
+            ]
+        );
+    }
 }
 
 #[cfg(test)]
@@ -4422,7 +6453,7 @@ mod tests {
         // change signer, can just use npub for test
         let ben =
             parse_npub("npub1u8lnhlw5usp3t9vmpz60ejpyt649z33hu82wc2hpv6m5xdqmuxhs46turz").unwrap();
-        mw.change_nostr_keys(Some(Keys::from_public_key(ben)), None)
+        mw.change_nostr_keys(Some(Keys::from_public_key(ben)), None, None)
             .await
             .unwrap();
 
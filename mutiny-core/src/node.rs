@@ -15,7 +15,7 @@ use crate::{
     ldkstorage::{MutinyNodePersister, PhantomChannelManager},
     logging::MutinyLogger,
     lsp::{AnyLsp, FeeRequest, Lsp},
-    nodemanager::NodeIndex,
+    nodemanager::{NodeIndex, PaymentOptions},
     onchain::OnChainWallet,
     peermanager::{GossipMessageHandler, PeerManagerImpl},
     utils::{self, sleep},
@@ -40,7 +40,7 @@ use esplora_client::AsyncClient;
 use futures_util::lock::Mutex;
 use hex_conservative::DisplayHex;
 use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet};
-use lightning::ln::channelmanager::ChannelDetails;
+use lightning::ln::channelmanager::{ChannelDetails, CounterpartyForwardingInfo};
 use lightning::ln::PaymentSecret;
 use lightning::onion_message::messenger::OnionMessenger as LdkOnionMessenger;
 use lightning::routing::scoring::ProbabilisticScoringDecayParameters;
@@ -58,7 +58,7 @@ use lightning::{
     routing::{
         gossip,
         gossip::NodeId,
-        router::{DefaultRouter, PaymentParameters, RouteParameters},
+        router::{DefaultRouter, PaymentParameters, RouteHop, RouteParameters, Router as _},
     },
     util::{
         config::{ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig},
@@ -66,7 +66,7 @@ use lightning::{
     },
 };
 use lightning::{
-    ln::channelmanager::{RecipientOnionFields, RetryableSendFailure},
+    ln::channelmanager::{PaymentSendFailure, RecipientOnionFields, RetryableSendFailure},
     routing::scoring::ProbabilisticScoringFeeParameters,
     util::config::ChannelConfig,
 };
@@ -95,6 +95,7 @@ use web_time::Instant;
 
 const INITIAL_RECONNECTION_DELAY: u64 = 10;
 const MAX_RECONNECTION_DELAY: u64 = 60;
+const DEFAULT_PAYMENT_RETRY_ATTEMPTS: u32 = 15;
 
 pub(crate) type BumpTxEventHandler<S: MutinyStorage> = BumpTransactionEventHandler<
     Arc<MutinyChain<S>>,
@@ -982,6 +983,7 @@ impl<S: MutinyStorage> NodeBuilder<S> {
             channel_manager,
             chain_monitor,
             fee_estimator,
+            router,
             network,
             persister,
             wallet,
@@ -1006,6 +1008,7 @@ pub(crate) struct Node<S: MutinyStorage> {
     pub channel_manager: Arc<PhantomChannelManager<S>>,
     pub chain_monitor: Arc<ChainMonitor<S>>,
     pub fee_estimator: Arc<MutinyFeeEstimator<S>>,
+    router: Arc<Router>,
     network: Network,
     pub persister: Arc<MutinyNodePersister<S>>,
     wallet: Arc<OnChainWallet<S>>,
@@ -1479,16 +1482,57 @@ impl<S: MutinyStorage> Node<S> {
         res
     }
 
-    fn retry_strategy() -> Retry {
-        Retry::Attempts(15)
+    /// The options never set both a timeout and retry attempts, see [`PaymentOptions::validate`]
+    fn retry_strategy(options: &PaymentOptions) -> Retry {
+        // LDK's timeout based retries need a system clock, which wasm doesn't have,
+        // there we retry the default number of times and abandon the payment once it times out
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(timeout_secs) = options.timeout_secs {
+            return Retry::Timeout(Duration::from_secs(timeout_secs));
+        }
+
+        Retry::Attempts(
+            options
+                .retry_attempts
+                .unwrap_or(DEFAULT_PAYMENT_RETRY_ATTEMPTS),
+        )
+    }
+
+    /// Applies the path and first hop limits from the payment options.
+    fn apply_payment_options(
+        &self,
+        mut payment_params: PaymentParameters,
+        options: &PaymentOptions,
+    ) -> PaymentParameters {
+        if let Some(max_path_count) = options.max_path_count {
+            payment_params.max_path_count = max_path_count;
+        }
+
+        // LDK has no way to pick the first hops when sending a payment, so
+        // we mark the channels we can't use as failed so the router skips them
+        if options.has_first_hop_constraints() {
+            let excluded = self
+                .channel_manager
+                .list_channels()
+                .into_iter()
+                .filter(|c| {
+                    c.funding_txo
+                        .is_some_and(|txo| !options.allows_first_hop(&txo.into_bitcoin_outpoint()))
+                })
+                .filter_map(|c| c.get_outbound_payment_scid());
+            payment_params.previously_failed_channels.extend(excluded);
+        }
+
+        payment_params
     }
 
     /// init_invoice_payment sends off the payment but does not wait for results
-    /// use pay_invoice_with_timeout to wait for results
+    /// use pay_invoice_with_options to wait for results
     pub async fn init_invoice_payment(
         &self,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
+        options: &PaymentOptions,
     ) -> Result<(PaymentId, PaymentHash), MutinyError> {
         log_trace!(self.logger, "calling init_invoice_payment");
 
@@ -1553,7 +1597,7 @@ impl<S: MutinyStorage> Node<S> {
             }
             let amount_msats = amt_sats.unwrap() * 1_000;
             (
-                self.pay_invoice_internal(invoice, amount_msats),
+                self.pay_invoice_internal(invoice, amount_msats, options),
                 amount_msats,
             )
         } else {
@@ -1562,7 +1606,7 @@ impl<S: MutinyStorage> Node<S> {
             }
             let amount_msats = invoice.amount_milli_satoshis().unwrap();
             (
-                self.pay_invoice_internal(invoice, amount_msats),
+                self.pay_invoice_internal(invoice, amount_msats, options),
                 amount_msats,
             )
         };
@@ -1610,6 +1654,7 @@ impl<S: MutinyStorage> Node<S> {
         &self,
         invoice: &Bolt11Invoice,
         amount_msats: u64,
+        options: &PaymentOptions,
     ) -> Result<PaymentId, RetryableSendFailure> {
         let payment_id = PaymentId(invoice.payment_hash().into_32());
         let payment_hash = PaymentHash((*invoice.payment_hash()).into_32());
@@ -1627,10 +1672,12 @@ impl<S: MutinyStorage> Node<S> {
                 .with_bolt11_features(features.clone())
                 .unwrap();
         }
+        let payment_params = self.apply_payment_options(payment_params, options);
         let route_params = RouteParameters {
             payment_params,
             final_value_msat: amount_msats,
-            max_total_routing_fee_msat: None, // main change from LDK, we just want payment to succeed
+            // main change from LDK, unless a limit is given we just want payment to succeed
+            max_total_routing_fee_msat: options.max_total_routing_fee_msat(amount_msats),
         };
 
         self.channel_manager
@@ -1640,7 +1687,7 @@ impl<S: MutinyStorage> Node<S> {
                 recipient_onion,
                 payment_id,
                 route_params,
-                Self::retry_strategy(),
+                Self::retry_strategy(options),
             )
             .map(|_| payment_id)
     }
@@ -1685,29 +1732,32 @@ impl<S: MutinyStorage> Node<S> {
         }
     }
 
-    pub async fn pay_invoice_with_timeout(
+    pub async fn pay_invoice_with_options(
         &self,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
-        timeout_secs: Option<u64>,
+        options: &PaymentOptions,
         labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
-        log_trace!(self.logger, "calling pay_invoice_with_timeout");
+        log_trace!(self.logger, "calling pay_invoice_with_options");
 
         // initiate payment
-        let (payment_id, payment_hash) = self.init_invoice_payment(invoice, amt_sats).await?;
-        let timeout: u64 = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let (payment_id, payment_hash) = self
+            .init_invoice_payment(invoice, amt_sats, options)
+            .await?;
+        let timeout: u64 = options.timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
 
         let res = self
             .await_payment(payment_id, payment_hash, timeout, labels)
             .await;
-        log_trace!(self.logger, "finished calling pay_invoice_with_timeout");
+        log_trace!(self.logger, "finished calling pay_invoice_with_options");
 
         res
     }
 
     /// init_keysend_payment sends off the payment but does not wait for results
-    /// use keysend_with_timeout to wait for results
+    /// use keysend_with_options to wait for results
+    #[allow(clippy::too_many_arguments)]
     pub async fn init_keysend_payment(
         &self,
         to_node: PublicKey,
@@ -1715,10 +1765,14 @@ impl<S: MutinyStorage> Node<S> {
         message: Option<String>,
         labels: Vec<String>,
         payment_id: PaymentId,
+        preimage: PaymentPreimage,
+        options: &PaymentOptions,
     ) -> Result<MutinyInvoice, MutinyError> {
         log_trace!(self.logger, "calling init_keysend_payment");
 
-        let amt_msats = amt_sats * 1_000;
+        let amt_msats = amt_sats
+            .checked_mul(1_000)
+            .ok_or(MutinyError::InvalidArgumentsError)?;
 
         // check if we have enough balance to send
         let channels = self.channel_manager.list_channels();
@@ -1755,15 +1809,12 @@ impl<S: MutinyStorage> Node<S> {
         getrandom::getrandom(&mut entropy).map_err(|_| MutinyError::SeedGenerationFailed)?;
         let payment_secret = PaymentSecret(entropy);
 
-        let mut entropy = [0u8; 32];
-        getrandom::getrandom(&mut entropy).map_err(|_| MutinyError::SeedGenerationFailed)?;
-        let preimage = PaymentPreimage(entropy);
-
         let payment_params = PaymentParameters::for_keysend(to_node, 40, false);
+        let payment_params = self.apply_payment_options(payment_params, options);
         let route_params: RouteParameters = RouteParameters {
             final_value_msat: amt_msats,
             payment_params,
-            max_total_routing_fee_msat: None,
+            max_total_routing_fee_msat: options.max_total_routing_fee_msat(amt_msats),
         };
 
         let recipient_onion = if let Some(msg) = message {
@@ -1783,7 +1834,7 @@ impl<S: MutinyStorage> Node<S> {
             recipient_onion,
             payment_id,
             route_params,
-            Self::retry_strategy(),
+            Self::retry_strategy(options),
         );
 
         let payment_hash = PaymentHash(Sha256::hash(&preimage.0).into_32());
@@ -1831,32 +1882,234 @@ impl<S: MutinyStorage> Node<S> {
         res
     }
 
-    pub async fn keysend_with_timeout(
+    /// Sends a keysend payment and waits for the result.
+    /// A random preimage is used unless one is given.
+    pub async fn keysend_with_options(
         &self,
         to_node: PublicKey,
         amt_sats: u64,
         message: Option<String>,
+        preimage: Option<[u8; 32]>,
         labels: Vec<String>,
-        timeout_secs: Option<u64>,
+        options: &PaymentOptions,
     ) -> Result<MutinyInvoice, MutinyError> {
-        log_trace!(self.logger, "calling keysend_with_timeout");
+        log_trace!(self.logger, "calling keysend_with_options");
 
         let mut entropy = [0u8; 32];
         getrandom::getrandom(&mut entropy).map_err(|_| MutinyError::SeedGenerationFailed)?;
         let payment_id = PaymentId(entropy);
 
+        let preimage = match preimage {
+            Some(preimage) => PaymentPreimage(preimage),
+            None => {
+                let mut entropy = [0u8; 32];
+                getrandom::getrandom(&mut entropy)
+                    .map_err(|_| MutinyError::SeedGenerationFailed)?;
+                PaymentPreimage(entropy)
+            }
+        };
+
         // initiate payment
         let pay = self
-            .init_keysend_payment(to_node, amt_sats, message, labels.clone(), payment_id)
+            .init_keysend_payment(
+                to_node,
+                amt_sats,
+                message,
+                labels.clone(),
+                payment_id,
+                preimage,
+                options,
+            )
             .await?;
 
-        let timeout: u64 = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let timeout: u64 = options.timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
         let payment_hash = PaymentHash(pay.payment_hash.into_32());
 
         let res = self
             .await_payment(payment_id, payment_hash, timeout, labels)
             .await;
-        log_trace!(self.logger, "finished calling keysend_with_timeout");
+        log_trace!(self.logger, "finished calling keysend_with_options");
+
+        res
+    }
+
+    /// Moves liquidity by paying `receiver` out of `from_channel` and back in through
+    /// `to_channel`. The receiver can be this node, for a circular rebalance, or another
+    /// one of our nodes. `to_channel` should be the channel as seen by the receiver.
+    ///
+    /// The amount and max fee should be in satoshis.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn rebalance(
+        &self,
+        receiver: &Node<S>,
+        from_channel: &ChannelDetails,
+        to_channel: &ChannelDetails,
+        amt_sats: u64,
+        max_fee_sats: Option<u64>,
+        timeout_secs: Option<u64>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        log_trace!(self.logger, "calling rebalance");
+
+        if from_channel.channel_id == to_channel.channel_id {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        if !from_channel.is_usable || !to_channel.is_usable {
+            log_error!(self.logger, "cannot rebalance, channels are not usable");
+            return Err(MutinyError::RoutingFailed);
+        }
+
+        let amt_msat = amt_sats
+            .checked_mul(1_000)
+            .ok_or(MutinyError::InvalidArgumentsError)?;
+        if from_channel.next_outbound_htlc_limit_msat < amt_msat {
+            return Err(MutinyError::InsufficientBalance);
+        }
+        if to_channel.inbound_capacity_msat < amt_msat {
+            log_error!(
+                self.logger,
+                "not enough inbound capacity in channel {} to rebalance",
+                to_channel.channel_id
+            );
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        // LDK will not find a route back to ourselves, so we route to the counterparty
+        // of `to_channel` and add the final hop into the receiver manually
+        let forwarding_info = to_channel
+            .counterparty
+            .forwarding_info
+            .clone()
+            .ok_or_else(|| {
+                log_error!(
+                    self.logger,
+                    "no forwarding info for channel {}",
+                    to_channel.channel_id
+                );
+                MutinyError::RoutingFailed
+            })?;
+        let last_hop_scid = to_channel
+            .get_inbound_payment_scid()
+            .ok_or(MutinyError::RoutingFailed)?;
+        let (last_hop_fee_msat, max_total_routing_fee_msat) =
+            rebalance_fees(amt_msat, &forwarding_info, max_fee_sats).map_err(|e| {
+                log_error!(
+                    self.logger,
+                    "could not fit rebalance fees in max fee of {max_fee_sats:?} sats: {e}"
+                );
+                e
+            })?;
+
+        let invoice = receiver
+            .create_internal_invoice(Some(amt_sats), None, None, labels.clone())
+            .await?;
+        let min_final_cltv_expiry_delta = invoice.min_final_cltv_expiry_delta() as u32;
+
+        let mut payment_params = PaymentParameters::from_node_id(
+            to_channel.counterparty.node_id,
+            forwarding_info.cltv_expiry_delta as u32 + min_final_cltv_expiry_delta,
+        );
+        // we only add the final hop to a single path
+        payment_params.max_path_count = 1;
+        let route_params = RouteParameters {
+            payment_params,
+            final_value_msat: amt_msat
+                .checked_add(last_hop_fee_msat)
+                .ok_or(MutinyError::InvalidArgumentsError)?,
+            max_total_routing_fee_msat,
+        };
+
+        let mut route = self
+            .router
+            .find_route(
+                &self.pubkey,
+                &route_params,
+                Some(&[from_channel]),
+                self.channel_manager.compute_inflight_htlcs(),
+            )
+            .map_err(|e| {
+                log_error!(self.logger, "could not find rebalance route: {}", e.err);
+                MutinyError::RoutingFailed
+            })?;
+
+        let path = route.paths.first_mut().ok_or(MutinyError::RoutingFailed)?;
+        if let Some(hop) = path.hops.last_mut() {
+            hop.fee_msat = last_hop_fee_msat;
+            hop.cltv_expiry_delta = forwarding_info.cltv_expiry_delta as u32;
+        }
+        path.hops.push(RouteHop {
+            pubkey: receiver.pubkey,
+            node_features: receiver.channel_manager.node_features(),
+            short_channel_id: last_hop_scid,
+            channel_features: receiver.channel_manager.channel_features(),
+            fee_msat: amt_msat,
+            cltv_expiry_delta: min_final_cltv_expiry_delta,
+            maybe_announced_channel: to_channel.is_public,
+        });
+        route.route_params = None;
+
+        log_debug!(
+            self.logger,
+            "Rebalancing {amt_msat} msats from channel {} to channel {} with {} msats in fees",
+            from_channel.channel_id,
+            to_channel.channel_id,
+            route.get_total_fees()
+        );
+
+        let payment_hash = PaymentHash(invoice.payment_hash().into_32());
+        let payment_id = PaymentId(payment_hash.0);
+        let mut recipient_onion = RecipientOnionFields::secret_only(*invoice.payment_secret());
+        recipient_onion.payment_metadata = invoice.payment_metadata().cloned();
+
+        let mut payment_info = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::InFlight,
+            amt_msat: MillisatAmount(Some(amt_msat)),
+            fee_paid_msat: None,
+            bolt11: Some(invoice.clone()),
+            payee_pubkey: None,
+            privacy_level: PrivacyLevel::NotAvailable,
+            last_update: utils::now().as_secs(),
+        };
+        persist_payment_info(
+            &self.persister.storage,
+            &payment_hash.0,
+            &payment_info,
+            false,
+        )?;
+
+        match self.channel_manager.send_payment_with_route(
+            &route,
+            payment_hash,
+            recipient_onion,
+            payment_id,
+        ) {
+            // a partial failure means some of the payment is in flight, wait for the result
+            Ok(_) | Err(PaymentSendFailure::PartialFailure { .. }) => {}
+            Err(e) => {
+                log_error!(self.logger, "failed to send rebalance payment: {e:?}");
+                payment_info.status = HTLCStatus::Failed;
+                persist_payment_info(
+                    &self.persister.storage,
+                    &payment_hash.0,
+                    &payment_info,
+                    false,
+                )?;
+
+                return match e {
+                    PaymentSendFailure::DuplicatePayment => Err(MutinyError::NonUniquePaymentHash),
+                    _ => Err(MutinyError::RoutingFailed),
+                };
+            }
+        }
+
+        let timeout: u64 = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let res = self
+            .await_payment(payment_id, payment_hash, timeout, labels)
+            .await;
+        log_trace!(self.logger, "finished calling rebalance");
 
         res
     }
@@ -2181,6 +2434,39 @@ fn map_sending_failure(
     }
 }
 
+/// Works out the fee for the manually added last hop of a rebalance and how much of
+/// the max fee is left for the rest of the route. Amounts are in msats, the max fee in sats.
+fn rebalance_fees(
+    amt_msat: u64,
+    forwarding_info: &CounterpartyForwardingInfo,
+    max_fee_sats: Option<u64>,
+) -> Result<(u64, Option<u64>), MutinyError> {
+    let proportional_fee_msat = amt_msat
+        .checked_mul(forwarding_info.fee_proportional_millionths as u64)
+        .ok_or(MutinyError::InvalidArgumentsError)?
+        / 1_000_000;
+    let last_hop_fee_msat = proportional_fee_msat
+        .checked_add(forwarding_info.fee_base_msat as u64)
+        .ok_or(MutinyError::InvalidArgumentsError)?;
+
+    let max_total_routing_fee_msat = match max_fee_sats {
+        Some(max_fee) => {
+            let max_fee_msat = max_fee
+                .checked_mul(1_000)
+                .ok_or(MutinyError::InvalidArgumentsError)?;
+            // the last hop fee alone is above the max fee
+            Some(
+                max_fee_msat
+                    .checked_sub(last_hop_fee_msat)
+                    .ok_or(MutinyError::RoutingFailed)?,
+            )
+        }
+        None => None,
+    };
+
+    Ok((last_hop_fee_msat, max_total_routing_fee_msat))
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn start_reconnection_handling<S: MutinyStorage>(
     storage: &S,
@@ -2566,6 +2852,45 @@ mod tests {
     use lightning_invoice::Bolt11InvoiceDescription;
     use std::str::FromStr;
 
+    fn dummy_channel_details() -> ChannelDetails {
+        ChannelDetails {
+            channel_id: ChannelId::new_zero(),
+            counterparty: ChannelCounterparty {
+                node_id: PublicKey::from_slice(&[2; 33]).unwrap(), // dummy value
+                features: InitFeatures::empty(),
+                unspendable_punishment_reserve: 0,
+                forwarding_info: None,
+                outbound_htlc_minimum_msat: None,
+                outbound_htlc_maximum_msat: None,
+            },
+            funding_txo: None,
+            channel_type: None,
+            short_channel_id: None,
+            outbound_scid_alias: None,
+            inbound_scid_alias: None,
+            channel_value_satoshis: 0,
+            unspendable_punishment_reserve: None,
+            user_channel_id: 0,
+            feerate_sat_per_1000_weight: None,
+            balance_msat: 0,
+            outbound_capacity_msat: 0,
+            next_outbound_htlc_limit_msat: 0,
+            next_outbound_htlc_minimum_msat: 0,
+            inbound_capacity_msat: 0,
+            confirmations_required: None,
+            confirmations: None,
+            force_close_spend_delay: None,
+            is_outbound: false,
+            is_channel_ready: false,
+            channel_shutdown_state: None,
+            is_usable: false,
+            is_public: false,
+            inbound_htlc_minimum_msat: None,
+            inbound_htlc_maximum_msat: None,
+            config: None,
+        }
+    }
+
     #[test]
     fn test_parse_peer_info() {
         log!("test parse peer info");
@@ -2613,42 +2938,7 @@ mod tests {
             MutinyError::NonUniquePaymentHash
         );
 
-        let mut channel_details = ChannelDetails {
-            channel_id: ChannelId::new_zero(),
-            counterparty: ChannelCounterparty {
-                node_id: PublicKey::from_slice(&[2; 33]).unwrap(), // dummy value
-                features: InitFeatures::empty(),
-                unspendable_punishment_reserve: 0,
-                forwarding_info: None,
-                outbound_htlc_minimum_msat: None,
-                outbound_htlc_maximum_msat: None,
-            },
-            funding_txo: None,
-            channel_type: None,
-            short_channel_id: None,
-            outbound_scid_alias: None,
-            inbound_scid_alias: None,
-            channel_value_satoshis: 0,
-            unspendable_punishment_reserve: None,
-            user_channel_id: 0,
-            feerate_sat_per_1000_weight: None,
-            balance_msat: 0,
-            outbound_capacity_msat: 0,
-            next_outbound_htlc_limit_msat: 0,
-            next_outbound_htlc_minimum_msat: 0,
-            inbound_capacity_msat: 0,
-            confirmations_required: None,
-            confirmations: None,
-            force_close_spend_delay: None,
-            is_outbound: false,
-            is_channel_ready: false,
-            channel_shutdown_state: None,
-            is_usable: false,
-            is_public: false,
-            inbound_htlc_minimum_msat: None,
-            inbound_htlc_maximum_msat: None,
-            config: None,
-        };
+        let mut channel_details = dummy_channel_details();
 
         assert_eq!(
             map_sending_failure(
@@ -2703,6 +2993,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rebalance_fees() {
+        let forwarding_info = CounterpartyForwardingInfo {
+            fee_base_msat: 1_000,
+            fee_proportional_millionths: 100,
+            cltv_expiry_delta: 144,
+        };
+
+        let (last_hop_fee, remaining) =
+            rebalance_fees(10_000_000, &forwarding_info, Some(10)).unwrap();
+        assert_eq!(last_hop_fee, 2_000);
+        assert_eq!(remaining, Some(8_000));
+
+        let (_, remaining) = rebalance_fees(10_000_000, &forwarding_info, None).unwrap();
+        assert_eq!(remaining, None);
+
+        // last hop fee is above the max fee
+        assert_eq!(
+            rebalance_fees(10_000_000, &forwarding_info, Some(1)),
+            Err(MutinyError::RoutingFailed)
+        );
+
+        // amounts that would overflow are rejected
+        assert_eq!(
+            rebalance_fees(u64::MAX, &forwarding_info, None),
+            Err(MutinyError::InvalidArgumentsError)
+        );
+        assert_eq!(
+            rebalance_fees(10_000_000, &forwarding_info, Some(u64::MAX)),
+            Err(MutinyError::InvalidArgumentsError)
+        );
+    }
+
     #[tokio::test]
     async fn test_create_node() {
         let storage = MemoryStorage::default();
@@ -2757,7 +3080,7 @@ mod tests {
         let invoice = node.create_invoice(10_000, None, vec![]).await.unwrap().0;
 
         let result = node
-            .pay_invoice_with_timeout(&invoice, None, None, vec![])
+            .pay_invoice_with_options(&invoice, None, &PaymentOptions::default(), vec![])
             .await;
 
         match result {
@@ -2767,6 +3090,77 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_rebalance_invalid_args() {
+        let node = create_node(MemoryStorage::default()).await;
+        let receiver = create_node(MemoryStorage::default()).await;
+
+        let mut from_channel = dummy_channel_details();
+        from_channel.is_usable = true;
+        from_channel.next_outbound_htlc_limit_msat = u64::MAX;
+        let mut to_channel = from_channel.clone();
+        to_channel.channel_id = ChannelId::from_bytes([1; 32]);
+        to_channel.inbound_capacity_msat = u64::MAX;
+
+        // can't rebalance into the same channel
+        let result = node
+            .rebalance(
+                &receiver,
+                &from_channel,
+                &from_channel,
+                10_000,
+                None,
+                None,
+                vec![],
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::InvalidArgumentsError);
+
+        // amounts that overflow when converted to msats are rejected
+        let result = node
+            .rebalance(
+                &receiver,
+                &from_channel,
+                &to_channel,
+                u64::MAX,
+                None,
+                None,
+                vec![],
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::InvalidArgumentsError);
+
+        // not enough balance
+        from_channel.next_outbound_htlc_limit_msat = 1_000;
+        let result = node
+            .rebalance(
+                &receiver,
+                &from_channel,
+                &to_channel,
+                10_000,
+                None,
+                None,
+                vec![],
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::InsufficientBalance);
+
+        // unusable channels
+        from_channel.is_usable = false;
+        let result = node
+            .rebalance(
+                &receiver,
+                &from_channel,
+                &to_channel,
+                10_000,
+                None,
+                None,
+                vec![],
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::RoutingFailed);
+    }
+
     #[tokio::test]
     async fn test_await_payment() {
         let storage = MemoryStorage::default();
@@ -2857,7 +3251,7 @@ mod wasm_test {
         storage::get_invoice_by_hash,
     };
     use crate::{labels::LabelStorage, logging::MutinyLogger};
-    use crate::{HTLCStatus, PrivacyLevel};
+    use crate::{nodemanager::PaymentOptions, HTLCStatus, PrivacyLevel};
     use itertools::Itertools;
     use lightning::ln::channelmanager::PaymentId;
     use lightning::ln::PaymentHash;
@@ -2936,7 +3330,7 @@ mod wasm_test {
         let invoice = node.create_invoice(10_000, None, vec![]).await.unwrap().0;
 
         let result = node
-            .pay_invoice_with_timeout(&invoice, None, None, vec![])
+            .pay_invoice_with_options(&invoice, None, &PaymentOptions::default(), vec![])
             .await;
 
         match result {
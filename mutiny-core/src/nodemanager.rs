@@ -9,6 +9,7 @@ use crate::{auth::MutinyAuthClient, TransactionDetails};
 use crate::{
     chain::MutinyChain,
     error::MutinyError,
+    event::EventBroadcaster,
     fees::MutinyFeeEstimator,
     gossip,
     gossip::{fetch_updated_gossip, get_rgs_url},
@@ -64,6 +65,8 @@ use url::Url;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
 
+const REBALANCE_LABEL: &str = "Rebalance";
+
 // This is the NodeStorage object saved to the DB
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct NodeStorage {
@@ -232,6 +235,204 @@ impl Ord for ChannelClosure {
     }
 }
 
+/// Rules for accepting inbound channel open requests.
+///
+/// Channels from our LSP are always accepted as zero-conf and are not
+/// subject to the policy, so that just in time channels keep working.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelAcceptancePolicy {
+    /// If set, only peers in this list can open channels to us
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_peers: Option<Vec<PublicKey>>,
+    /// Peers we trust to open zero-conf channels to us
+    #[serde(default)]
+    pub zero_conf_peers: Vec<PublicKey>,
+    /// Minimum channel size in satoshis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_channel_size: Option<u64>,
+    /// Maximum channel size in satoshis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_channel_size: Option<u64>,
+    /// Only accept anchor channels
+    #[serde(default)]
+    pub anchors_required: bool,
+    /// Only accept unannounced channels, on by default. LDK enforces this during
+    /// the channel handshake, before the channel is accepted, so changes to it
+    /// take effect once the node is restarted.
+    #[serde(default = "default_private_only")]
+    pub private_only: bool,
+}
+
+impl Default for ChannelAcceptancePolicy {
+    fn default() -> Self {
+        Self {
+            allowed_peers: None,
+            zero_conf_peers: vec![],
+            min_channel_size: None,
+            max_channel_size: None,
+            anchors_required: false,
+            private_only: default_private_only(),
+        }
+    }
+}
+
+fn default_private_only() -> bool {
+    true
+}
+
+/// The reason an inbound channel open request was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelRejectionReason {
+    PeerNotAllowed,
+    ChannelTooSmall,
+    ChannelTooLarge,
+    AnchorsRequired,
+}
+
+impl core::fmt::Display for ChannelRejectionReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ChannelRejectionReason::PeerNotAllowed => write!(f, "Peer is not allowed"),
+            ChannelRejectionReason::ChannelTooSmall => write!(f, "Channel is too small"),
+            ChannelRejectionReason::ChannelTooLarge => write!(f, "Channel is too large"),
+            ChannelRejectionReason::AnchorsRequired => {
+                write!(f, "Channel is not an anchor channel")
+            }
+        }
+    }
+}
+
+impl ChannelAcceptancePolicy {
+    /// Checks the policy is consistent before saving it.
+    pub fn validate(&self) -> Result<(), MutinyError> {
+        match (self.min_channel_size, self.max_channel_size) {
+            (Some(min), Some(max)) if min > max => Err(MutinyError::InvalidArgumentsError),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates an inbound channel open request against the policy.
+    ///
+    /// Returns whether the channel should be accepted as zero-conf,
+    /// or the reason it should be rejected.
+    pub fn evaluate(
+        &self,
+        counterparty_node_id: &PublicKey,
+        funding_satoshis: u64,
+        is_anchor: bool,
+    ) -> Result<bool, ChannelRejectionReason> {
+        if self
+            .allowed_peers
+            .as_ref()
+            .is_some_and(|peers| !peers.contains(counterparty_node_id))
+        {
+            return Err(ChannelRejectionReason::PeerNotAllowed);
+        }
+
+        if self
+            .min_channel_size
+            .is_some_and(|min| funding_satoshis < min)
+        {
+            return Err(ChannelRejectionReason::ChannelTooSmall);
+        }
+
+        if self
+            .max_channel_size
+            .is_some_and(|max| funding_satoshis > max)
+        {
+            return Err(ChannelRejectionReason::ChannelTooLarge);
+        }
+
+        if self.anchors_required && !is_anchor {
+            return Err(ChannelRejectionReason::AnchorsRequired);
+        }
+
+        Ok(self.zero_conf_peers.contains(counterparty_node_id))
+    }
+}
+
+/// Options for sending a lightning payment from one of our nodes.
+///
+/// The defaults try as hard as possible to get the payment through.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PaymentOptions {
+    /// Maximum routing fee in satoshis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_sats: Option<u64>,
+    /// Maximum routing fee in parts per million of the amount sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fee_ppm: Option<u64>,
+    /// How many times to retry the payment over different routes.
+    /// Can't be combined with `timeout_secs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_attempts: Option<u32>,
+    /// How long to keep trying the payment, in seconds. After this no new attempts
+    /// are made, but parts of the payment that are already in flight may still settle.
+    /// Can't be combined with `retry_attempts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Maximum number of paths the payment can be split across
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_path_count: Option<u8>,
+    /// Channels, by funding outpoint, that can not be used for the first hop
+    #[serde(default)]
+    pub excluded_first_hops: Vec<OutPoint>,
+    /// Channels, by funding outpoint, that the first hop must be one of.
+    /// If empty, any channel can be used.
+    #[serde(default)]
+    pub required_first_hops: Vec<OutPoint>,
+}
+
+impl PaymentOptions {
+    /// Checks the options are consistent before using them.
+    pub fn validate(&self) -> Result<(), MutinyError> {
+        if self.max_path_count == Some(0) || self.timeout_secs == Some(0) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        // retries are either counted or timed, not both
+        if self.retry_attempts.is_some() && self.timeout_secs.is_some() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        if self
+            .required_first_hops
+            .iter()
+            .any(|c| self.excluded_first_hops.contains(c))
+        {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    /// Whether the payment has to go through specific channels of ours.
+    pub(crate) fn has_first_hop_constraints(&self) -> bool {
+        !self.excluded_first_hops.is_empty() || !self.required_first_hops.is_empty()
+    }
+
+    /// Whether the channel with the given funding outpoint can be used for the first hop.
+    pub(crate) fn allows_first_hop(&self, funding_txo: &OutPoint) -> bool {
+        !self.excluded_first_hops.contains(funding_txo)
+            && (self.required_first_hops.is_empty()
+                || self.required_first_hops.contains(funding_txo))
+    }
+
+    /// The maximum total routing fee for sending `amount_msat`, taking the
+    /// lowest of the absolute and proportional limits.
+    pub(crate) fn max_total_routing_fee_msat(&self, amount_msat: u64) -> Option<u64> {
+        let ppm_fee = self
+            .max_fee_ppm
+            .map(|ppm| (amount_msat as u128 * ppm as u128 / 1_000_000) as u64);
+        let abs_fee = self.max_fee_sats.map(|fee| fee.saturating_mul(1_000));
+
+        match (abs_fee, ppm_fee) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
 pub struct NodeBalance {
     pub confirmed: u64,
     pub unconfirmed: u64,
@@ -245,6 +446,7 @@ pub struct NodeManagerBuilder<S: MutinyStorage> {
     esplora: Option<Arc<AsyncClient>>,
     config: Option<MutinyWalletConfig>,
     stop: Option<Arc<AtomicBool>>,
+    event_broadcaster: Option<EventBroadcaster>,
     logger: Option<Arc<MutinyLogger>>,
 }
 
@@ -256,6 +458,7 @@ impl<S: MutinyStorage> NodeManagerBuilder<S> {
             esplora: None,
             config: None,
             stop: None,
+            event_broadcaster: None,
             logger: None,
         }
     }
@@ -277,6 +480,10 @@ impl<S: MutinyStorage> NodeManagerBuilder<S> {
         self.logger = Some(logger);
     }
 
+    pub fn with_event_broadcaster(&mut self, event_broadcaster: EventBroadcaster) {
+        self.event_broadcaster = Some(event_broadcaster);
+    }
+
     /// Creates a new [NodeManager] with the given parameters.
     /// The mnemonic seed is read from storage, unless one is provided.
     /// If no mnemonic is provided, a new one is generated and stored.
@@ -319,7 +526,7 @@ impl<S: MutinyStorage> NodeManagerBuilder<S> {
         log_trace!(logger, "finished creating fee estimator");
 
         log_trace!(logger, "creating on chain wallet");
-        let wallet = Arc::new(OnChainWallet::new(
+        let mut wallet = OnChainWallet::new(
             self.xprivkey,
             self.storage.clone(),
             c.network,
@@ -327,7 +534,9 @@ impl<S: MutinyStorage> NodeManagerBuilder<S> {
             fee_estimator.clone(),
             stop.clone(),
             logger.clone(),
-        )?);
+        )?;
+        wallet.event_broadcaster = self.event_broadcaster.clone().unwrap_or_default();
+        let wallet = Arc::new(wallet);
         log_trace!(logger, "finished creating on chain wallet");
 
         log_trace!(logger, "creating chain");
@@ -1413,6 +1622,30 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(())
     }
 
+    /// Returns the policy used to accept inbound channel open requests.
+    pub fn get_channel_acceptance_policy(&self) -> Result<ChannelAcceptancePolicy, MutinyError> {
+        self.storage.get_channel_acceptance_policy()
+    }
+
+    /// Sets the policy used to accept inbound channel open requests.
+    /// This takes effect for the next open request, no restart is needed,
+    /// except for `private_only` which applies once the node is restarted.
+    pub fn set_channel_acceptance_policy(
+        &self,
+        policy: ChannelAcceptancePolicy,
+    ) -> Result<(), MutinyError> {
+        log_trace!(self.logger, "calling set_channel_acceptance_policy");
+
+        policy.validate()?;
+        let res = self.storage.set_channel_acceptance_policy(&policy);
+        log_trace!(
+            self.logger,
+            "finished calling set_channel_acceptance_policy"
+        );
+
+        res
+    }
+
     /// Attempts to connect to a peer using either a specified node or the first available node.
     pub async fn connect_to_peer(
         &self,
@@ -1549,13 +1782,15 @@ impl<S: MutinyStorage> NodeManager<S> {
         self_node_pubkey: Option<&PublicKey>,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
+        options: &PaymentOptions,
         labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
         log_trace!(self.logger, "calling pay_invoice");
 
+        options.validate()?;
         let node = self.get_node_by_key_or_first(self_node_pubkey).await?;
         let res = node
-            .pay_invoice_with_timeout(invoice, amt_sats, None, labels)
+            .pay_invoice_with_options(invoice, amt_sats, options, labels)
             .await;
         log_trace!(self.logger, "finished calling pay_invoice");
 
@@ -1563,21 +1798,25 @@ impl<S: MutinyStorage> NodeManager<S> {
     }
 
     /// Sends a spontaneous payment to a node from either a specified node or the first available node.
-    /// The amount should be in satoshis.
+    /// The amount should be in satoshis. A random preimage is used unless one is given.
+    #[allow(clippy::too_many_arguments)]
     pub async fn keysend(
         &self,
         self_node_pubkey: Option<&PublicKey>,
         to_node: PublicKey,
         amt_sats: u64,
         message: Option<String>,
+        preimage: Option<[u8; 32]>,
+        options: &PaymentOptions,
         labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
         log_trace!(self.logger, "calling keysend");
 
+        options.validate()?;
         let node = self.get_node_by_key_or_first(self_node_pubkey).await?;
         log_debug!(self.logger, "Keysending to {to_node}");
         let res = node
-            .keysend_with_timeout(to_node, amt_sats, message, labels, None)
+            .keysend_with_options(to_node, amt_sats, message, preimage, labels, options)
             .await;
         log_trace!(self.logger, "finished calling keysend");
 
@@ -1744,15 +1983,7 @@ impl<S: MutinyStorage> NodeManager<S> {
             return Err(MutinyError::ChannelClosingFailed);
         }
 
-        let nodes = self.nodes.read().await;
-        let channel_opt: Option<(Arc<Node<S>>, ChannelDetails)> =
-            nodes.iter().find_map(|(_, n)| {
-                n.channel_manager
-                    .list_channels()
-                    .iter()
-                    .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
-                    .map(|c| (n.clone(), c.clone()))
-            });
+        let channel_opt = self.find_channel(outpoint).await;
 
         let res = match channel_opt {
             Some((node, channel)) => {
@@ -1831,6 +2062,119 @@ impl<S: MutinyStorage> NodeManager<S> {
         res
     }
 
+    /// Finds the channel with the given funding outpoint and the node it belongs to.
+    async fn find_channel(&self, outpoint: &OutPoint) -> Option<(Arc<Node<S>>, ChannelDetails)> {
+        let nodes = self.nodes.read().await;
+        nodes.iter().find_map(|(_, n)| {
+            n.channel_manager
+                .list_channels()
+                .iter()
+                .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
+                .map(|c| (n.clone(), c.clone()))
+        })
+    }
+
+    /// Moves liquidity between two of our channels by paying ourselves out of
+    /// `from_channel` and back in through `to_channel`, avoiding an on-chain round trip.
+    /// The channels are identified by their funding outpoints and can belong to
+    /// different nodes in the manager.
+    ///
+    /// The amount and max fee should be in satoshis. If no max fee is given, any fee is accepted.
+    pub async fn rebalance(
+        &self,
+        from_channel: &OutPoint,
+        to_channel: &OutPoint,
+        amount: u64,
+        max_fee: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        log_trace!(self.logger, "calling rebalance");
+
+        let (from_node, from) = self.find_channel(from_channel).await.ok_or_else(|| {
+            log_error!(
+                self.logger,
+                "Channel not found with this transaction: {from_channel}"
+            );
+            MutinyError::NotFound
+        })?;
+        let (to_node, to) = self.find_channel(to_channel).await.ok_or_else(|| {
+            log_error!(
+                self.logger,
+                "Channel not found with this transaction: {to_channel}"
+            );
+            MutinyError::NotFound
+        })?;
+
+        let res = from_node
+            .rebalance(
+                &to_node,
+                &from,
+                &to,
+                amount,
+                max_fee,
+                None,
+                vec![REBALANCE_LABEL.to_string()],
+            )
+            .await;
+        log_trace!(self.logger, "finished calling rebalance");
+
+        res
+    }
+
+    /// Sends funds from one of our nodes to another over lightning. This uses the
+    /// sending node's channel with the most outbound liquidity and the receiving node's
+    /// channel with the most inbound liquidity. This is useful for emptying a node's
+    /// channels before it is archived.
+    ///
+    /// The amount and max fee should be in satoshis. If no max fee is given, any fee is accepted.
+    pub async fn transfer_between_nodes(
+        &self,
+        from_node: &PublicKey,
+        to_node: &PublicKey,
+        amount: u64,
+        max_fee: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        log_trace!(self.logger, "calling transfer_between_nodes");
+
+        if from_node == to_node {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let (sender, receiver) = {
+            let nodes = self.nodes.read().await;
+            let sender = nodes.get(from_node).cloned().ok_or(MutinyError::NotFound)?;
+            let receiver = nodes.get(to_node).cloned().ok_or(MutinyError::NotFound)?;
+            (sender, receiver)
+        };
+
+        let from = sender
+            .channel_manager
+            .list_usable_channels()
+            .into_iter()
+            .max_by_key(|c| c.next_outbound_htlc_limit_msat)
+            .ok_or(MutinyError::InsufficientBalance)?;
+        let to = receiver
+            .channel_manager
+            .list_usable_channels()
+            .into_iter()
+            .max_by_key(|c| c.inbound_capacity_msat)
+            .ok_or(MutinyError::InsufficientBalance)?;
+
+        let res = sender
+            .rebalance(
+                &receiver,
+                &from,
+                &to,
+                amount,
+                max_fee,
+                None,
+                vec![REBALANCE_LABEL.to_string()],
+            )
+            .await;
+        log_trace!(self.logger, "finished calling transfer_between_nodes");
+
+        res
+    }
+
     /// Lists all the channels for all the nodes in the node manager.
     pub async fn list_channels(&self) -> Result<Vec<MutinyChannel>, MutinyError> {
         log_trace!(self.logger, "calling list_channels");
@@ -2101,16 +2445,19 @@ pub fn create_lsp_config(
 mod tests {
     use crate::{
         encrypt::encryption_key_from_pass,
-        nodemanager::{ChannelClosure, MutinyInvoice, NodeManager, TransactionDetails},
+        nodemanager::{
+            ChannelAcceptancePolicy, ChannelClosure, ChannelRejectionReason, MutinyInvoice,
+            NodeManager, PaymentOptions, TransactionDetails,
+        },
         ActivityItem, MutinyWalletConfigBuilder, PrivacyLevel,
     };
-    use crate::{keymanager::generate_seed, nodemanager::NodeManagerBuilder};
+    use crate::{error::MutinyError, keymanager::generate_seed, nodemanager::NodeManagerBuilder};
     use bdk::chain::ConfirmationTime;
     use bitcoin::bip32::ExtendedPrivKey;
     use bitcoin::hashes::hex::FromHex;
     use bitcoin::hashes::{sha256, Hash};
     use bitcoin::secp256k1::{PublicKey, ThirtyTwoByteHash};
-    use bitcoin::{absolute, Network, Transaction, TxOut, Txid};
+    use bitcoin::{absolute, Network, OutPoint, Transaction, TxOut, Txid};
     use hex_conservative::DisplayHex;
     use lightning::ln::PaymentHash;
     use lightning_invoice::Bolt11Invoice;
@@ -2262,6 +2609,57 @@ mod tests {
         assert_eq!(tx.labels, labels);
     }
 
+    #[test]
+    async fn rebalance_and_transfer_errors() {
+        let test_name = "rebalance_and_transfer_errors";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher), None);
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let network = Network::Regtest;
+        let xpriv = ExtendedPrivKey::new_master(network, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfigBuilder::new(xpriv)
+            .with_network(network)
+            .build();
+        let nm = NodeManagerBuilder::new(xpriv, storage.clone())
+            .with_config(c)
+            .build()
+            .await
+            .expect("node manager should initialize");
+
+        let first = nm.new_node().await.expect("should create new node").pubkey;
+        let second = nm.new_node().await.expect("should create new node").pubkey;
+
+        // unknown channels
+        let outpoint = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+        let result = nm.rebalance(&outpoint, &outpoint, 10_000, None).await;
+        assert_eq!(result.unwrap_err(), MutinyError::NotFound);
+
+        // can't transfer to the same node
+        let result = nm
+            .transfer_between_nodes(&first, &first, 10_000, None)
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::InvalidArgumentsError);
+
+        // unknown node
+        let unknown = PublicKey::from_slice(&[2; 33]).unwrap();
+        let result = nm
+            .transfer_between_nodes(&first, &unknown, 10_000, None)
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::NotFound);
+
+        // no channels to send over
+        let result = nm
+            .transfer_between_nodes(&first, &second, 10_000, None)
+            .await;
+        assert_eq!(result.unwrap_err(), MutinyError::InsufficientBalance);
+    }
+
     #[test]
     fn test_bolt11_payment_info_into_mutiny_invoice() {
         let preimage: [u8; 32] =
@@ -2399,6 +2797,127 @@ mod tests {
         assert_eq!(deserialized, expected);
     }
 
+    #[test]
+    fn test_channel_acceptance_policy() {
+        let peer = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let other = PublicKey::from_str(
+            "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+
+        // default policy accepts everything, without zero conf
+        let policy = ChannelAcceptancePolicy::default();
+        assert_eq!(policy.evaluate(&peer, 100_000, false), Ok(false));
+        // and keeps LDK refusing announced channels, even for policies saved before the field
+        assert!(policy.private_only);
+        let stored: ChannelAcceptancePolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(stored, policy);
+
+        let policy = ChannelAcceptancePolicy {
+            allowed_peers: Some(vec![peer]),
+            zero_conf_peers: vec![peer],
+            min_channel_size: Some(50_000),
+            max_channel_size: Some(1_000_000),
+            anchors_required: true,
+            private_only: false,
+        };
+        assert!(policy.validate().is_ok());
+        assert_eq!(policy.evaluate(&peer, 100_000, true), Ok(true));
+        assert_eq!(
+            policy.evaluate(&other, 100_000, true),
+            Err(ChannelRejectionReason::PeerNotAllowed)
+        );
+        assert_eq!(
+            policy.evaluate(&peer, 10_000, true),
+            Err(ChannelRejectionReason::ChannelTooSmall)
+        );
+        assert_eq!(
+            policy.evaluate(&peer, 10_000_000, true),
+            Err(ChannelRejectionReason::ChannelTooLarge)
+        );
+        assert_eq!(
+            policy.evaluate(&peer, 100_000, false),
+            Err(ChannelRejectionReason::AnchorsRequired)
+        );
+
+        let invalid = ChannelAcceptancePolicy {
+            min_channel_size: Some(1_000_000),
+            max_channel_size: Some(50_000),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+
+        let serialized = serde_json::to_string(&policy).unwrap();
+        let deserialized: ChannelAcceptancePolicy = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, policy);
+    }
+
+    #[test]
+    fn test_payment_options() {
+        let channel_a = OutPoint::from_str(
+            "a3c1c8ae33d07b8f2b0cc84a1ba4e43e8b5f40e63b5d4ae1f50cfc4d0e0b7d1a:0",
+        )
+        .unwrap();
+        let channel_b = OutPoint::from_str(
+            "a3c1c8ae33d07b8f2b0cc84a1ba4e43e8b5f40e63b5d4ae1f50cfc4d0e0b7d1a:1",
+        )
+        .unwrap();
+
+        // defaults have no limits
+        let options = PaymentOptions::default();
+        assert!(options.validate().is_ok());
+        assert!(!options.has_first_hop_constraints());
+        assert!(options.allows_first_hop(&channel_a));
+        assert_eq!(options.max_total_routing_fee_msat(1_000_000), None);
+
+        // the lowest fee limit wins
+        let options = PaymentOptions {
+            max_fee_sats: Some(10),
+            max_fee_ppm: Some(5_000),
+            ..Default::default()
+        };
+        assert_eq!(options.max_total_routing_fee_msat(1_000_000), Some(5_000));
+        assert_eq!(options.max_total_routing_fee_msat(10_000_000), Some(10_000));
+
+        let options = PaymentOptions {
+            required_first_hops: vec![channel_a],
+            ..Default::default()
+        };
+        assert!(options.has_first_hop_constraints());
+        assert!(options.allows_first_hop(&channel_a));
+        assert!(!options.allows_first_hop(&channel_b));
+
+        let options = PaymentOptions {
+            excluded_first_hops: vec![channel_a],
+            ..Default::default()
+        };
+        assert!(!options.allows_first_hop(&channel_a));
+        assert!(options.allows_first_hop(&channel_b));
+
+        let invalid = PaymentOptions {
+            excluded_first_hops: vec![channel_a],
+            required_first_hops: vec![channel_a],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+
+        let invalid = PaymentOptions {
+            max_path_count: Some(0),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+
+        let invalid = PaymentOptions {
+            retry_attempts: Some(3),
+            timeout_secs: Some(30),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
     #[test]
     fn test_sort_activity_item() {
         let preimage: [u8; 32] =
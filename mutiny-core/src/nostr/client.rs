@@ -1,6 +1,10 @@
+use crate::utils;
 use nostr::{Event, EventBuilder, EventId, Filter, PublicKey, SubscriptionId};
 use nostr_sdk::client::Error;
-use nostr_sdk::{NostrSigner, SubscribeAutoCloseOptions};
+use nostr_sdk::{NostrSigner, RelayStatus, SubscribeAutoCloseOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 #[cfg_attr(test, mockall::automock)]
@@ -35,6 +39,9 @@ pub trait NostrClient {
     ) -> Result<Vec<Event>, Error>;
 
     async fn set_signer(&self, signer: Option<NostrSigner>);
+
+    /// Creates a client with its own relay pool, for relays we don't want in ours
+    fn new_pool(&self) -> Self;
 }
 
 impl NostrClient for nostr_sdk::Client {
@@ -106,4 +113,141 @@ impl NostrClient for nostr_sdk::Client {
     async fn set_signer(&self, signer: Option<NostrSigner>) {
         self.set_signer(signer).await
     }
+
+    fn new_pool(&self) -> Self {
+        nostr_sdk::Client::default()
+    }
+}
+
+/// Connection health of a relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayHealth {
+    pub url: String,
+    pub connected: bool,
+    /// Time the relay last connected or disconnected, in seconds since epoch
+    pub last_change: u64,
+    /// Number of times we have lost our connection to the relay
+    pub disconnects: u32,
+}
+
+/// Tracks the connection health of our relays so we can
+/// fail over to other relays when one is down
+#[derive(Debug, Clone, Default)]
+pub struct RelayHealthTracker {
+    relays: Arc<RwLock<HashMap<String, RelayHealth>>>,
+}
+
+impl RelayHealthTracker {
+    /// Records a status change reported by the relay pool
+    pub fn update(&self, url: &str, status: RelayStatus) {
+        let connected = match status {
+            RelayStatus::Connected => true,
+            RelayStatus::Disconnected | RelayStatus::Stopped | RelayStatus::Terminated => false,
+            // still connecting, wait until we know how it went
+            _ => return,
+        };
+
+        let url = normalize_relay_url(url);
+        let mut relays = self.relays.write().unwrap();
+        let health = relays.entry(url.clone()).or_insert_with(|| RelayHealth {
+            url,
+            connected,
+            last_change: 0,
+            disconnects: 0,
+        });
+
+        if health.last_change != 0 && health.connected == connected {
+            return;
+        }
+
+        if health.last_change != 0 && !connected {
+            health.disconnects += 1;
+        }
+        health.connected = connected;
+        health.last_change = utils::now().as_secs();
+    }
+
+    /// If the relay is usable, relays we haven't heard from yet are assumed to be
+    pub fn is_healthy(&self, url: &str) -> bool {
+        self.relays
+            .read()
+            .unwrap()
+            .get(&normalize_relay_url(url))
+            .map_or(true, |h| h.connected)
+    }
+
+    /// Picks the healthy relays out of the given ones.
+    ///
+    /// If none of them are healthy all of them are returned,
+    /// so we still try to reach them rather than giving up.
+    pub fn select(&self, relays: Vec<String>) -> Vec<String> {
+        let healthy: Vec<String> = relays
+            .iter()
+            .filter(|r| self.is_healthy(r))
+            .cloned()
+            .collect();
+
+        if healthy.is_empty() {
+            relays
+        } else {
+            healthy
+        }
+    }
+
+    /// The health of every relay we have heard from
+    pub fn get_health(&self) -> Vec<RelayHealth> {
+        let mut health: Vec<RelayHealth> = self.relays.read().unwrap().values().cloned().collect();
+        health.sort_by(|a, b| a.url.cmp(&b.url));
+        health
+    }
+}
+
+/// Relay urls come from users and the relay pool with and without trailing slashes
+fn normalize_relay_url(url: &str) -> String {
+    url.trim_end_matches('/').to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relay_health() {
+        let tracker = RelayHealthTracker::default();
+        let primary = "wss://relay.mutinywallet.com".to_string();
+        let backup = "wss://relay.damus.io".to_string();
+        let relays = vec![primary.clone(), backup.clone()];
+
+        // unknown relays are assumed healthy
+        assert_eq!(tracker.select(relays.clone()), relays);
+
+        tracker.update("wss://relay.mutinywallet.com/", RelayStatus::Connected);
+        tracker.update("wss://relay.damus.io/", RelayStatus::Connected);
+        assert_eq!(tracker.select(relays.clone()), relays);
+
+        // fail over to the backup when the primary goes down
+        tracker.update("wss://relay.mutinywallet.com/", RelayStatus::Disconnected);
+        assert!(!tracker.is_healthy(&primary));
+        assert_eq!(tracker.select(relays.clone()), vec![backup.clone()]);
+
+        // connecting doesn't change anything until it succeeds
+        tracker.update("wss://relay.mutinywallet.com/", RelayStatus::Connecting);
+        assert!(!tracker.is_healthy(&primary));
+
+        // if everything is down, still try them all
+        tracker.update("wss://relay.damus.io/", RelayStatus::Terminated);
+        assert_eq!(tracker.select(relays.clone()), relays);
+
+        tracker.update("wss://relay.mutinywallet.com/", RelayStatus::Connected);
+        assert_eq!(tracker.select(relays), vec![primary]);
+
+        let health = tracker.get_health();
+        assert_eq!(health.len(), 2);
+        assert_eq!(health[0].url, "wss://relay.damus.io");
+        assert_eq!(health[0].disconnects, 1);
+        assert!(!health[0].connected);
+        assert_eq!(health[1].url, "wss://relay.mutinywallet.com");
+        assert_eq!(health[1].disconnects, 1);
+        assert!(health[1].connected);
+    }
 }
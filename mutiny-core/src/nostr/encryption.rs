@@ -0,0 +1,123 @@
+use crate::error::MutinyError;
+use nostr::nips::{nip04, nip44};
+use nostr::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Encryption schemes we support for NWC and direct messages
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EncryptionScheme {
+    /// NIP-04, deprecated but still used by older clients
+    #[default]
+    Nip04,
+    /// NIP-44 version 2
+    Nip44V2,
+}
+
+impl EncryptionScheme {
+    /// Schemes we can use, in order of preference
+    pub const SUPPORTED: [EncryptionScheme; 2] =
+        [EncryptionScheme::Nip44V2, EncryptionScheme::Nip04];
+
+    /// Detects the scheme of an encrypted payload.
+    ///
+    /// NIP-04 payloads always carry an `?iv=` suffix, which can never
+    /// appear in the base64 encoding used by NIP-44.
+    pub fn detect(payload: &str) -> Self {
+        if payload.contains("?iv=") {
+            EncryptionScheme::Nip04
+        } else {
+            EncryptionScheme::Nip44V2
+        }
+    }
+}
+
+impl fmt::Display for EncryptionScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionScheme::Nip04 => write!(f, "nip04"),
+            EncryptionScheme::Nip44V2 => write!(f, "nip44_v2"),
+        }
+    }
+}
+
+impl FromStr for EncryptionScheme {
+    type Err = MutinyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nip04" => Ok(EncryptionScheme::Nip04),
+            "nip44_v2" => Ok(EncryptionScheme::Nip44V2),
+            _ => Err(MutinyError::InvalidArgumentsError),
+        }
+    }
+}
+
+/// Encrypts `content` for `public_key` with the given scheme
+pub fn encrypt<T: AsRef<[u8]>>(
+    scheme: EncryptionScheme,
+    secret_key: &SecretKey,
+    public_key: &PublicKey,
+    content: T,
+) -> Result<String, MutinyError> {
+    match scheme {
+        EncryptionScheme::Nip04 => Ok(nip04::encrypt(secret_key, public_key, content)?),
+        EncryptionScheme::Nip44V2 => Ok(nip44::encrypt(
+            secret_key,
+            public_key,
+            content,
+            nip44::Version::V2,
+        )?),
+    }
+}
+
+/// Decrypts a payload from `public_key`, detecting whether it uses NIP-04 or NIP-44
+pub fn decrypt(
+    secret_key: &SecretKey,
+    public_key: &PublicKey,
+    payload: &str,
+) -> Result<String, MutinyError> {
+    match EncryptionScheme::detect(payload) {
+        EncryptionScheme::Nip04 => Ok(nip04::decrypt(secret_key, public_key, payload)?),
+        EncryptionScheme::Nip44V2 => Ok(nip44::decrypt(secret_key, public_key, payload)?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nostr::Keys;
+
+    #[test]
+    fn test_round_trip_both_schemes() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+
+        for scheme in EncryptionScheme::SUPPORTED {
+            let payload = encrypt(
+                scheme,
+                alice.secret_key().unwrap(),
+                &bob.public_key(),
+                "hello",
+            )
+            .unwrap();
+            assert_eq!(EncryptionScheme::detect(&payload), scheme);
+
+            let decrypted =
+                decrypt(bob.secret_key().unwrap(), &alice.public_key(), &payload).unwrap();
+            assert_eq!(decrypted, "hello");
+        }
+    }
+
+    #[test]
+    fn test_scheme_strings() {
+        for scheme in EncryptionScheme::SUPPORTED {
+            assert_eq!(
+                EncryptionScheme::from_str(&scheme.to_string()).unwrap(),
+                scheme
+            );
+        }
+        assert!(EncryptionScheme::from_str("nip44_v1").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+/// Points for a recommendation from someone we follow
+const DIRECT_FOLLOW_POINTS: i64 = 100;
+/// Points for a recommendation from someone a person we follow follows
+const FOLLOW_OF_FOLLOW_POINTS: i64 = 50;
+/// Points for a recommendation from a trusted user outside our follow graph
+const TRUSTED_USER_POINTS: i64 = 25;
+
+/// A recommender's account needs to be this old for full points
+pub(crate) const FULL_AGE_DAYS: u64 = 365;
+/// When nostr started (2020-11-01), events claiming to be older are backdated
+pub(crate) const NOSTR_EPOCH_SECS: u64 = 1_604_188_800;
+/// Window we count a recommender's notes in to see how active they are
+pub(crate) const RECENT_ACTIVITY_SECS: u64 = 30 * 86_400;
+/// A recommender needs this many recent notes for full points
+pub(crate) const FULL_ACTIVITY_NOTES: u32 = 10;
+
+/// Points for each guardian after the first
+const GUARDIAN_POINTS: i64 = 20;
+const MAX_GUARDIAN_POINTS: i64 = 100;
+
+/// Penalty for a federation that has expired, enough to sink it to the bottom
+const EXPIRED_PENALTY: i64 = 1_000;
+/// Penalty for a federation that expires soon
+const EXPIRING_PENALTY: i64 = 100;
+/// Federations expiring within this many seconds get the expiring penalty
+const EXPIRING_SOON_SECS: u64 = 30 * 86_400;
+/// Penalty for a federation we couldn't connect to
+const UNREACHABLE_PENALTY: i64 = 200;
+
+/// What we know about someone that recommended a federation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RecommenderInfo {
+    /// Follow graph distance, 1 for people we follow, 2 for the people they follow,
+    /// None for trusted users outside our follow graph
+    pub distance: Option<u8>,
+    /// Time of their oldest event we have seen, in seconds since epoch
+    pub first_seen: Option<u64>,
+    /// Notes they have published in the last 30 days
+    pub recent_notes: u32,
+}
+
+/// How much a recommendation counts towards a federation's score
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecommenderScore {
+    pub npub: nostr::PublicKey,
+    /// Follow graph distance, 1 for people we follow, 2 for the people they follow,
+    /// None for trusted users outside our follow graph
+    pub distance: Option<u8>,
+    /// How long we have known of their account, in days
+    pub age_days: Option<u64>,
+    /// Notes they have published in the last 30 days
+    pub recent_notes: u32,
+    pub points: i64,
+}
+
+impl RecommenderScore {
+    /// Weights a recommendation by who it is from. Half of the points come from
+    /// the follow graph distance alone, the rest from the recommender's
+    /// account age and recent activity, so new or inactive accounts count less.
+    ///
+    /// The account age comes from event timestamps, which the author picks,
+    /// so a new account can backdate its events to look old. Times from before
+    /// nostr existed or in the future are ignored, but anything in between is
+    /// taken at face value, which is why age is only a quarter of the points.
+    pub(crate) fn new(npub: nostr::PublicKey, info: RecommenderInfo, now: u64) -> Self {
+        let base = match info.distance {
+            Some(1) => DIRECT_FOLLOW_POINTS,
+            Some(_) => FOLLOW_OF_FOLLOW_POINTS,
+            None => TRUSTED_USER_POINTS,
+        };
+
+        let age_days = info
+            .first_seen
+            .filter(|first_seen| (NOSTR_EPOCH_SECS..=now).contains(first_seen))
+            .map(|first_seen| (now - first_seen) / 86_400);
+        let age_pct = age_days.map_or(0, |days| days.min(FULL_AGE_DAYS) * 100 / FULL_AGE_DAYS);
+        let activity_pct =
+            info.recent_notes.min(FULL_ACTIVITY_NOTES) as u64 * 100 / FULL_ACTIVITY_NOTES as u64;
+
+        let points = base * (200 + age_pct as i64 + activity_pct as i64) / 400;
+
+        Self {
+            npub,
+            distance: info.distance,
+            age_days,
+            recent_notes: info.recent_notes,
+            points,
+        }
+    }
+}
+
+/// Breakdown of how much we trust a discovered federation, higher is better
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederationScore {
+    pub total: i64,
+    /// Points from recommendations, weighted by who made them
+    pub recommendations: i64,
+    /// Points for having more guardians, a single guardian gets none
+    pub guardians: i64,
+    /// Points lost because the federation has expired or expires soon
+    pub expiry_penalty: i64,
+    /// Points lost because we couldn't reach the federation
+    pub unreachable_penalty: i64,
+    /// What each recommendation was worth, highest first
+    pub recommenders: Vec<RecommenderScore>,
+}
+
+impl FederationScore {
+    pub(crate) fn new(
+        mut recommenders: Vec<RecommenderScore>,
+        guardians: Option<usize>,
+        reachable: Option<bool>,
+        expire_timestamp: Option<u64>,
+        now: u64,
+    ) -> Self {
+        recommenders.sort_by(|a, b| b.points.cmp(&a.points));
+        let recommendation_points = recommenders.iter().map(|r| r.points).sum();
+
+        let guardian_points = guardians.map_or(0, |count| {
+            (count.saturating_sub(1) as i64 * GUARDIAN_POINTS).min(MAX_GUARDIAN_POINTS)
+        });
+
+        let expiry_penalty = match expire_timestamp {
+            Some(expiry) if expiry <= now => EXPIRED_PENALTY,
+            Some(expiry) if expiry - now < EXPIRING_SOON_SECS => EXPIRING_PENALTY,
+            _ => 0,
+        };
+
+        // only penalize if we checked, unknown isn't unreachable
+        let unreachable_penalty = match reachable {
+            Some(false) => UNREACHABLE_PENALTY,
+            _ => 0,
+        };
+
+        Self {
+            total: recommendation_points + guardian_points - expiry_penalty - unreachable_penalty,
+            recommendations: recommendation_points,
+            guardians: guardian_points,
+            expiry_penalty,
+            unreachable_penalty,
+            recommenders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nostr::Keys;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_recommender_score() {
+        let npub = Keys::generate().public_key();
+
+        // an old active account we follow gets full points
+        let info = RecommenderInfo {
+            distance: Some(1),
+            first_seen: Some(NOW - 2 * 365 * 86_400),
+            recent_notes: 50,
+        };
+        let score = RecommenderScore::new(npub, info, NOW);
+        assert_eq!(score.points, DIRECT_FOLLOW_POINTS);
+        assert_eq!(score.age_days, Some(730));
+
+        // a brand new inactive account gets half
+        let info = RecommenderInfo {
+            distance: Some(1),
+            first_seen: Some(NOW),
+            recent_notes: 0,
+        };
+        assert_eq!(RecommenderScore::new(npub, info, NOW).points, 50);
+
+        // times from before nostr or the future are backdated, so get no age points
+        for first_seen in [0, NOSTR_EPOCH_SECS - 1, NOW + 1] {
+            let info = RecommenderInfo {
+                distance: Some(1),
+                first_seen: Some(first_seen),
+                recent_notes: 0,
+            };
+            let score = RecommenderScore::new(npub, info, NOW);
+            assert_eq!(score.age_days, None);
+            assert_eq!(score.points, 50);
+        }
+
+        // further away in the follow graph counts less
+        let info = RecommenderInfo {
+            distance: Some(2),
+            first_seen: Some(NOW - 365 * 86_400),
+            recent_notes: 10,
+        };
+        assert_eq!(
+            RecommenderScore::new(npub, info, NOW).points,
+            FOLLOW_OF_FOLLOW_POINTS
+        );
+        let info = RecommenderInfo {
+            distance: None,
+            ..info
+        };
+        assert_eq!(
+            RecommenderScore::new(npub, info, NOW).points,
+            TRUSTED_USER_POINTS
+        );
+    }
+
+    #[test]
+    fn test_federation_score() {
+        let recommender = |points| RecommenderScore {
+            npub: Keys::generate().public_key(),
+            distance: Some(1),
+            age_days: None,
+            recent_notes: 0,
+            points,
+        };
+
+        let score = FederationScore::new(
+            vec![recommender(50), recommender(100)],
+            Some(4),
+            Some(true),
+            None,
+            NOW,
+        );
+        assert_eq!(score.recommendations, 150);
+        assert_eq!(score.guardians, 60);
+        assert_eq!(score.total, 210);
+        assert_eq!(score.recommenders[0].points, 100);
+
+        // single guardian federations get no guardian points
+        let score = FederationScore::new(vec![recommender(100)], Some(1), None, None, NOW);
+        assert_eq!(score.guardians, 0);
+        assert_eq!(score.total, 100);
+
+        // expiring and unreachable federations are penalized
+        let score = FederationScore::new(
+            vec![recommender(100)],
+            None,
+            Some(false),
+            Some(NOW + 86_400),
+            NOW,
+        );
+        assert_eq!(score.expiry_penalty, EXPIRING_PENALTY);
+        assert_eq!(score.unreachable_penalty, UNREACHABLE_PENALTY);
+        assert_eq!(score.total, 100 - EXPIRING_PENALTY - UNREACHABLE_PENALTY);
+
+        let score = FederationScore::new(vec![recommender(100)], None, None, Some(NOW), NOW);
+        assert_eq!(score.expiry_penalty, EXPIRED_PENALTY);
+        assert!(score.total < 0);
+    }
+}
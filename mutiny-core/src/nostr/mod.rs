@@ -1,38 +1,56 @@
-use crate::federation::FederationMetaConfig;
+use crate::federation::{fetch_external_meta, merge_values};
 use crate::labels::Contact;
 use crate::logging::MutinyLogger;
-use crate::nostr::client::NostrClient;
+use crate::nostr::client::{NostrClient, RelayHealth, RelayHealthTracker};
+use crate::nostr::encryption::{self, EncryptionScheme};
+use crate::nostr::federation_score::{
+    FederationScore, RecommenderInfo, RecommenderScore, FULL_ACTIVITY_NOTES, FULL_AGE_DAYS,
+    NOSTR_EPOCH_SECS, RECENT_ACTIVITY_SECS,
+};
 use crate::nostr::nip49::{NIP49BudgetPeriod, NIP49URI};
+use crate::nostr::nip65::{
+    get_relay_lists, select_outbox_relays, select_shared_outbox_relays, OutboxPool, RelayList,
+};
 use crate::nostr::nwc::{
     check_valid_nwc_invoice, BudgetPeriod, BudgetedSpendingConditions, NostrWalletConnect,
     NwcProfile, NwcProfileTag, PendingNwcInvoice, Profile, SingleUseSpendingConditions,
-    SpendingConditions, PENDING_NWC_EVENTS_KEY,
+    SpendingConditions, SpendingRules, PENDING_NWC_EVENTS_KEY,
+};
+use crate::nostr::nwc_client::{
+    CachedExternalWalletBalances, ExternalWallet, ExternalWalletBalance,
 };
 use crate::nostr::primal::PrimalApi;
-use crate::storage::{update_nostr_contact_list, MutinyStorage, NOSTR_CONTACT_LIST};
-use crate::utils::fetch_with_timeout;
+use crate::nostr::zaps::{receipt_payment_hash, verify_zap_receipt, zap_request_sender, Zap};
+use crate::storage::{
+    get_invoice_by_hash, update_nostr_contact_list, MutinyStorage,
+    DISCOVERED_FEDERATION_CHECKS_KEY, EXTERNAL_WALLETS_KEY, EXTERNAL_WALLET_BALANCES_KEY,
+    LAST_ZAP_RECEIPT_SYNC_TIME_KEY, NIP17_DMS_KEY, NIP17_PEERS_KEY, NOSTR_BUNKER_USER_KEY,
+    NOSTR_CONTACT_LIST, NOSTR_RELAY_LIST_KEY, ZAPS_KEY,
+};
 use crate::{error::MutinyError, utils::get_random_bip32_child_index};
-use crate::{labels::LabelStorage, InvoiceHandler};
+use crate::{labels::LabelStorage, InvoiceHandler, MutinyInvoice};
 use crate::{utils, HTLCStatus};
 use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::{Secp256k1, Signing};
 use bitcoin::{hashes::hex::FromHex, secp256k1::ThirtyTwoByteHash, Network};
+use chrono::Utc;
 use fedimint_core::api::InviteCode;
 use fedimint_core::config::{ClientConfig, FederationId};
-use futures::{pin_mut, select, FutureExt};
+use futures::{pin_mut, select, FutureExt, StreamExt};
 use futures_util::lock::Mutex;
 use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error, log_info, log_warn};
 use lightning_invoice::Bolt11Invoice;
 use lnurl::lnurl::LnUrl;
+use nostr::nips::nip46::NostrConnectURI;
 use nostr::nips::nip47::*;
 use nostr::prelude::{Coordinate, EventIdOrCoordinate};
 use nostr::{
-    nips::nip04::{decrypt, encrypt},
     Alphabet, Event, EventBuilder, EventId, Filter, JsonUtil, Keys, Kind, Metadata, SecretKey,
-    SingleLetterTag, Tag, TagKind, Timestamp,
+    SingleLetterTag, Tag, TagKind, Timestamp, UnsignedEvent,
 };
+use nostr_sdk::prelude::Nip46Signer;
 use nostr_sdk::{Client, NostrSigner, RelayPoolNotification};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -43,25 +61,80 @@ use std::{str::FromStr, sync::atomic::AtomicBool};
 use url::Url;
 
 mod client;
+pub mod encryption;
+pub mod federation_score;
 pub mod nip49;
+pub mod nip65;
 pub mod nwc;
+pub mod nwc_client;
 pub(crate) mod primal;
+pub(crate) mod relay_api;
+pub mod zaps;
 
 const PROFILE_ACCOUNT_INDEX: u32 = 0;
 const NWC_ACCOUNT_INDEX: u32 = 1;
 pub(crate) const SERVICE_ACCOUNT_INDEX: u32 = 2;
 
 pub(crate) const HERMES_CHAIN_INDEX: u32 = 0;
+const BUNKER_CHAIN_INDEX: u32 = 1;
 
 const USER_NWC_PROFILE_START_INDEX: u32 = 1000;
 
 /// The number of trusted users we query for mint recommendations
 const NUM_TRUSTED_USERS: u32 = 1_000;
 
+/// Max people our contacts follow we look for federation recommendations from
+const MAX_FOLLOWS_OF_FOLLOWS: usize = 1_000;
+
+/// Max relays of the people our contacts follow we connect to while discovering federations
+const MAX_FOLLOWS_OF_FOLLOWS_RELAYS: usize = 20;
+
+/// How long we wait for a federation's config before calling it unreachable
+const FEDERATION_CONFIG_TIMEOUT_MS: i32 = 5_000;
+
+/// How long we reuse what we learned from a discovered federation's config
+const FEDERATION_CHECK_TTL_SECS: u64 = 6 * 60 * 60;
+
+/// Max federation configs or relay queries we run at once while discovering federations
+const MAX_CONCURRENT_DISCOVERY_REQUESTS: usize = 10;
+
+/// How many recommenders we look up in a single relay query
+const RECOMMENDERS_PER_QUERY: usize = 10;
+
 const NWC_STORAGE_KEY: &str = "nwc_profiles";
 
+/// How long to wait for a NIP-46 remote signer to respond
+const BUNKER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for an external wallet to respond to a request
+const EXTERNAL_WALLET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for an external wallet to complete a payment
+const EXTERNAL_WALLET_PAYMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long we show a cached external wallet balance before fetching it again
+pub(crate) const EXTERNAL_WALLET_BALANCE_TTL_SECS: u64 = 60;
+
+/// Kind of the unsigned chat message inside a NIP-17 gift wrap
+const PRIVATE_DM_KIND: u64 = 14;
+
+/// Kind of the relay list a user publishes to receive NIP-17 DMs
+const DM_RELAY_LIST_KIND: u64 = 10050;
+
+/// Gift wraps and seals are backdated up to two days to hide when they were sent
+const GIFT_WRAP_MAX_BACKDATE_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// Number of gift wraps to request at a time when syncing NIP-17 DMs
+const PRIVATE_DM_PAGE_SIZE: usize = 500;
+
+/// Max unwrapped NIP-17 DMs we keep in storage, older ones are dropped
+const MAX_CACHED_PRIVATE_DMS: usize = 1_000;
+
 const DEFAULT_RELAY: &str = "wss://relay.mutinywallet.com";
 
+/// How far back to look for zap receipts the first time we sync them
+const ZAP_RECEIPT_LOOKBACK_SECS: u64 = 30 * 24 * 60 * 60;
+
 /// Reserved profiles that are used internally.
 /// Must not exceed `USER_NWC_PROFILE_START_INDEX`
 pub enum ReservedProfile {
@@ -104,21 +177,81 @@ pub enum NostrKeySource {
     /// Get keys from NIP-07 extension
     #[cfg(target_arch = "wasm32")]
     Extension(nostr::PublicKey),
+    /// Sign with a NIP-46 remote signer
+    Bunker(NostrConnectURI),
 }
 
 /// Keys we use to sign nostr events
 #[derive(Clone)]
 pub struct NostrKeys {
-    /// Signer for the primary key
-    pub signer: NostrSigner,
+    /// Signer for the primary key, `None` until a remote signer has been connected
+    pub signer: Option<NostrSigner>,
+    /// Remote signer the primary key lives on, if any
+    bunker: Option<RemoteSigner>,
     /// Public key for the signer
     pub public_key: nostr::PublicKey,
 }
 
+/// A NIP-46 remote signer
+#[derive(Clone)]
+struct RemoteSigner {
+    uri: NostrConnectURI,
+    app_keys: Keys,
+}
+
+/// The user's public key as reported by a remote signer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BunkerUser {
+    signer: nostr::PublicKey,
+    user: nostr::PublicKey,
+}
+
+impl RemoteSigner {
+    /// The user's public key if we have connected to this remote signer before
+    fn user_public_key(
+        &self,
+        storage: &impl MutinyStorage,
+    ) -> Result<Option<nostr::PublicKey>, MutinyError> {
+        let user: Option<BunkerUser> = storage.get_data(NOSTR_BUNKER_USER_KEY)?;
+        Ok(user
+            .filter(|u| u.signer == self.uri.public_key)
+            .map(|u| u.user))
+    }
+
+    /// Connects to the remote signer and saves the user's public key it reports
+    async fn connect(
+        &self,
+        storage: &impl MutinyStorage,
+    ) -> Result<(NostrSigner, nostr::PublicKey), MutinyError> {
+        let nip46 = Nip46Signer::new(
+            self.uri.relay_url.clone(),
+            self.app_keys.clone(),
+            Some(self.uri.public_key),
+            BUNKER_TIMEOUT,
+        )
+        .await
+        .map_err(|_| MutinyError::NostrError)?;
+        // the remote signer's key isn't necessarily the user's key, ask for it
+        let user = nip46
+            .signer_public_key()
+            .await
+            .map_err(|_| MutinyError::NostrError)?;
+
+        let bunker_user = BunkerUser {
+            signer: self.uri.public_key,
+            user,
+        };
+        storage.set_data(NOSTR_BUNKER_USER_KEY.to_string(), bunker_user, None)?;
+
+        Ok((NostrSigner::nip46(nip46), user))
+    }
+}
+
 impl NostrKeys {
-    pub(crate) fn from_key_source(
+    pub(crate) async fn from_key_source(
         key_source: NostrKeySource,
         xprivkey: ExtendedPrivKey,
+        storage: &impl MutinyStorage,
     ) -> Result<Self, MutinyError> {
         // use provided nsec, otherwise generate it from seed
         let (signer, public_key) = match key_source {
@@ -145,9 +278,49 @@ impl NostrKeys {
                 let signer = NostrSigner::NIP07(nip07);
                 (signer, public_key)
             }
+            NostrKeySource::Bunker(uri) => {
+                // derive the app key from our seed so the remote signer
+                // still recognizes us after a restart
+                let app_keys = derive_nostr_key(
+                    &Secp256k1::new(),
+                    xprivkey,
+                    SERVICE_ACCOUNT_INDEX,
+                    Some(BUNKER_CHAIN_INDEX),
+                    None,
+                )?;
+                let bunker = RemoteSigner { uri, app_keys };
+                // if we already know the user's key, don't wait on the remote signer,
+                // it is connected once the keys are installed
+                if let Some(public_key) = bunker.user_public_key(storage)? {
+                    return Ok(NostrKeys {
+                        signer: None,
+                        public_key,
+                        bunker: Some(bunker),
+                    });
+                }
+
+                let (signer, public_key) = bunker.connect(storage).await?;
+                return Ok(NostrKeys {
+                    signer: Some(signer),
+                    public_key,
+                    bunker: Some(bunker),
+                });
+            }
         };
 
-        Ok(NostrKeys { signer, public_key })
+        Ok(NostrKeys {
+            signer: Some(signer),
+            bunker: None,
+            public_key,
+        })
+    }
+
+    /// The keys for the primary key if we hold them ourselves
+    pub(crate) fn keys(&self) -> Option<&Keys> {
+        match &self.signer {
+            Some(NostrSigner::Keys(keys)) => Some(keys),
+            _ => None,
+        }
     }
 }
 
@@ -165,6 +338,8 @@ pub struct NostrManager<S: MutinyStorage, P: PrimalApi, C: NostrClient> {
     pending_nwc_lock: Arc<Mutex<()>>,
     /// Lock for following and unfollowing npubs
     follow_lock: Arc<Mutex<()>>,
+    /// Lock for connecting to a remote signer
+    signer_lock: Arc<Mutex<()>>,
     /// Logger
     pub logger: Arc<MutinyLogger>,
     /// Atomic stop signal
@@ -173,6 +348,48 @@ pub struct NostrManager<S: MutinyStorage, P: PrimalApi, C: NostrClient> {
     pub client: C,
     /// Primal client
     pub primal_client: P,
+    /// Connection health of our relays
+    pub relay_health: RelayHealthTracker,
+    /// Clients for our external wallets, so their relays stay out of our pool
+    external_wallet_clients: Arc<RwLock<HashMap<nostr::PublicKey, Arc<C>>>>,
+    /// Whether we are fetching the balances of our external wallets
+    refreshing_external_balances: Arc<AtomicBool>,
+}
+
+/// A NIP-17 direct message after it has been unwrapped
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PrivateDirectMessage {
+    /// Id of the unsigned message inside the gift wrap
+    pub id: EventId,
+    pub from: nostr::PublicKey,
+    pub to: nostr::PublicKey,
+    pub message: String,
+    /// Time the message was written, gift wraps themselves are backdated
+    pub created_at: u64,
+}
+
+/// NIP-17 messages we have already unwrapped.
+///
+/// The messages are stored decrypted, in plaintext, so only the newest
+/// [`MAX_CACHED_PRIVATE_DMS`] are kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+struct PrivateDmCache {
+    /// Time of the last sync with our relays
+    last_sync: Option<u64>,
+    /// Gift wraps we have processed that a sync could still return, with their timestamps
+    seen_wraps: HashMap<EventId, u64>,
+    messages: Vec<PrivateDirectMessage>,
+}
+
+/// What we learned from a discovered federation's config
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct FederationConfigCheck {
+    reachable: bool,
+    guardians: Option<usize>,
+    expire_timestamp: Option<u64>,
+    metadata: Option<Metadata>,
+    /// When we downloaded the config
+    checked_at: u64,
 }
 
 /// A fedimint we discovered on nostr
@@ -194,6 +411,15 @@ pub struct NostrDiscoveredFedimint {
     pub recommendations: Vec<Contact>,
     /// Timestamp when this fedimint expires
     pub expire_timestamp: Option<u64>,
+    /// Number of guardians, None if we couldn't get the federation's config
+    #[serde(default)]
+    pub guardians: Option<usize>,
+    /// If we could get the federation's config, None if we haven't tried
+    #[serde(default)]
+    pub reachable: Option<bool>,
+    /// How much we trust this fedimint, based on who recommends it and its config
+    #[serde(default)]
+    pub score: FederationScore,
 }
 
 impl PartialOrd for NostrDiscoveredFedimint {
@@ -204,11 +430,12 @@ impl PartialOrd for NostrDiscoveredFedimint {
 
 impl Ord for NostrDiscoveredFedimint {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // sort mints by most recommended then by oldest
+        // sort mints by highest score, then most recommended, then oldest
         other
-            .recommendations
-            .len()
-            .cmp(&self.recommendations.len())
+            .score
+            .total
+            .cmp(&self.score.total)
+            .then_with(|| other.recommendations.len().cmp(&self.recommendations.len()))
             .then_with(|| {
                 // use .unwrap_or(u64::MAX) to make sure we sort by oldest if created_at is None
                 self.created_at
@@ -224,54 +451,92 @@ impl NostrDiscoveredFedimint {
         if self.metadata.is_none() && !self.invite_codes.is_empty() {
             let code = self.invite_codes.first().unwrap();
             if let Ok(config) = ClientConfig::download_from_invite_code(code).await {
-                // need to handle this case where the meta_external_url is set and we fetch it externally
-                let external = config.global.meta.get("meta_external_url");
-                match external {
-                    Some(url) => {
-                        let http_client = reqwest::Client::new();
-                        let request = http_client.request(reqwest::Method::GET, url);
-
-                        if let Ok(r) = fetch_with_timeout(
-                            &http_client,
-                            request.build().expect("should build req"),
-                        )
-                        .await
-                        {
-                            if let Ok(config) = r.json::<FederationMetaConfig>().await {
-                                let config = config.federations.get(&self.id.to_string()).cloned();
-                                self.expire_timestamp = config.as_ref().and_then(|f| {
-                                    f.federation_expiry_timestamp
-                                        .clone()
-                                        .and_then(|t| t.parse().ok())
-                                });
-                                let metadata = config.map(|f| Metadata {
-                                    name: f.federation_name.clone(),
-                                    display_name: f.federation_name,
-                                    picture: f.federation_icon_url,
-                                    ..Default::default()
-                                });
-                                self.metadata = metadata;
-                            }
-                        }
-                    }
-                    None => {
-                        self.metadata = Some(Metadata {
-                            name: config.global.meta.get("federation_name").cloned(),
-                            display_name: config.global.meta.get("federation_name").cloned(),
-                            picture: config.global.meta.get("federation_icon_url").cloned(),
-                            ..Default::default()
-                        });
-                        self.expire_timestamp = config
-                            .global
-                            .meta
-                            .get("expire_timestamp")
-                            .and_then(|s| s.parse().ok());
-                    }
-                };
+                self.apply_config_meta(&config).await;
             }
         }
     }
 
+    /// Downloads the federation's config to learn how many guardians it has
+    /// and when it expires. Federations we can't get it from in time are unreachable.
+    pub async fn check_config(&mut self) {
+        let Some(code) = self.invite_codes.first().cloned() else {
+            return;
+        };
+
+        let download_fut = ClientConfig::download_from_invite_code(&code).fuse();
+        let delay_fut = Box::pin(utils::sleep(FEDERATION_CONFIG_TIMEOUT_MS)).fuse();
+        pin_mut!(download_fut, delay_fut);
+        let config = select! {
+            config = download_fut => config.ok(),
+            _ = delay_fut => None,
+        };
+
+        self.reachable = Some(config.is_some());
+        if let Some(config) = config {
+            self.guardians = Some(config.global.api_endpoints.len());
+            self.apply_config_meta(&config).await;
+        }
+    }
+
+    /// Fills in the metadata and expiry we don't have yet from the federation's meta,
+    /// read the same way as for federations we have joined
+    async fn apply_config_meta(&mut self, config: &ClientConfig) {
+        let meta = &config.global.meta;
+        let external = match meta.get("meta_external_url") {
+            Some(url) => fetch_external_meta(url, &self.id).await.ok().flatten(),
+            None => None,
+        };
+
+        if self.expire_timestamp.is_none() {
+            self.expire_timestamp = merge_values(
+                meta.get("federation_expiry_timestamp").cloned(),
+                external
+                    .as_ref()
+                    .and_then(|m| m.federation_expiry_timestamp.clone()),
+            )
+            .and_then(|t| t.parse().ok());
+        }
+        if self.metadata.is_none() {
+            let name = merge_values(
+                meta.get("federation_name").cloned(),
+                external.as_ref().and_then(|m| m.federation_name.clone()),
+            );
+            let picture = merge_values(
+                meta.get("federation_icon_url").cloned(),
+                external.and_then(|m| m.federation_icon_url),
+            );
+            self.metadata = Some(Metadata {
+                name: name.clone(),
+                display_name: name,
+                picture,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// What we learned from checking the federation's config, None if we haven't checked it
+    fn config_check(&self, now: u64) -> Option<FederationConfigCheck> {
+        Some(FederationConfigCheck {
+            reachable: self.reachable?,
+            guardians: self.guardians,
+            expire_timestamp: self.expire_timestamp,
+            metadata: self.metadata.clone(),
+            checked_at: now,
+        })
+    }
+
+    /// Uses what we learned from an earlier check of the federation's config
+    fn apply_config_check(&mut self, check: &FederationConfigCheck) {
+        self.reachable = Some(check.reachable);
+        self.guardians = check.guardians;
+        if self.expire_timestamp.is_none() {
+            self.expire_timestamp = check.expire_timestamp;
+        }
+        if self.metadata.is_none() {
+            self.metadata = check.metadata.clone();
+        }
+    }
+
     pub fn merge(&mut self, other: &Self) {
         // merge metadata
         if let Some(other) = other.metadata.as_ref() {
@@ -329,7 +594,7 @@ impl NostrDiscoveredFedimint {
 impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
     /// Connect to the nostr relays
     pub async fn connect(&self) -> Result<(), MutinyError> {
-        self.client.add_relays(self.get_relays()).await?;
+        self.client.add_relays(self.get_relays().await).await?;
         self.client.connect().await;
 
         Ok(())
@@ -341,11 +606,58 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
 
     /// Export the primary key's secret key if available
     pub async fn export_nsec(&self) -> Option<SecretKey> {
-        match &self.nostr_keys.read().await.signer {
-            NostrSigner::Keys(keys) => keys.secret_key().ok().cloned(),
-            #[cfg(target_arch = "wasm32")]
-            NostrSigner::NIP07(_) => None,
+        let nostr_keys = self.nostr_keys.read().await;
+        nostr_keys
+            .keys()
+            .and_then(|keys| keys.secret_key().ok().cloned())
+    }
+
+    /// Gets the signer for our primary key, connecting to the remote signer
+    /// if that hasn't happened yet
+    pub(crate) async fn signer(&self) -> Result<NostrSigner, MutinyError> {
+        if let Some(signer) = self.nostr_keys.read().await.signer.clone() {
+            return Ok(signer);
+        }
+
+        let _lock = self.signer_lock.lock().await;
+        // someone else may have connected while we waited
+        let bunker = {
+            let nostr_keys = self.nostr_keys.read().await;
+            if let Some(signer) = nostr_keys.signer.clone() {
+                return Ok(signer);
+            }
+            nostr_keys.bunker.clone().ok_or(MutinyError::NostrError)?
+        };
+
+        log_debug!(self.logger, "connecting to remote signer");
+        let (signer, public_key) = bunker.connect(&self.storage).await.map_err(|e| {
+            log_error!(self.logger, "Failed to connect to remote signer: {e}");
+            e
+        })?;
+
+        let mut nostr_keys = self.nostr_keys.write().await;
+        // keys were changed while we were connecting
+        let current = nostr_keys.bunker.as_ref().map(|b| b.uri.public_key);
+        if current != Some(bunker.uri.public_key) {
+            return Err(MutinyError::NostrError);
+        }
+        if nostr_keys.public_key != public_key {
+            log_error!(
+                self.logger,
+                "Remote signer now signs for {public_key} instead of {}",
+                nostr_keys.public_key
+            );
+            return Err(MutinyError::NostrError);
         }
+        self.client.set_signer(Some(signer.clone())).await;
+        nostr_keys.signer = Some(signer.clone());
+
+        Ok(signer)
+    }
+
+    /// Connects our client to a remote signer, called once after the keys are installed
+    pub(crate) async fn connect_signer(&self) -> Result<(), MutinyError> {
+        self.signer().await.map(|_| ())
     }
 
     /// Change our active nostr keys to the given keys
@@ -355,16 +667,24 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         xprivkey: ExtendedPrivKey,
     ) -> Result<nostr::PublicKey, MutinyError> {
         // see if we can build new nostr keys first
-        let new_nostr_keys = NostrKeys::from_key_source(key_source, xprivkey)?;
+        let mut new_nostr_keys =
+            NostrKeys::from_key_source(key_source, xprivkey, &self.storage).await?;
+
+        // make sure a remote signer is reachable before switching to it
+        if new_nostr_keys.signer.is_none() {
+            if let Some(bunker) = new_nostr_keys.bunker.as_ref() {
+                let (signer, public_key) = bunker.connect(&self.storage).await?;
+                new_nostr_keys.signer = Some(signer);
+                new_nostr_keys.public_key = public_key;
+            }
+        }
         let new_pk = new_nostr_keys.public_key;
 
         // get lock on signer
         let mut nostr_keys = self.nostr_keys.write().await;
 
         // change our client's signer
-        self.client
-            .set_signer(Some(new_nostr_keys.signer.clone()))
-            .await;
+        self.client.set_signer(new_nostr_keys.signer.clone()).await;
 
         // change our signer
         *nostr_keys = new_nostr_keys;
@@ -374,22 +694,28 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         self.storage.delete_nostr_caches()?;
 
         // update filters
-        let dm_filter = self.get_dm_filter().await?;
-        let profile = self.get_contacts_list_filter().await?;
-        self.client.subscribe(vec![dm_filter, profile], None).await;
+        let mut filters = self.get_dm_filters().await?;
+        filters.push(self.get_contacts_list_filter().await?);
+        self.client.subscribe(filters, None).await;
 
         Ok(new_pk)
     }
 
-    pub fn get_relays(&self) -> Vec<String> {
+    pub async fn get_relays(&self) -> Vec<String> {
+        let relay_list = self
+            .get_relay_list()
+            .await
+            .map(|list| list.relays.into_iter().map(|r| r.url).collect())
+            .unwrap_or_else(|_| Vec::new());
         let mut relays: Vec<String> = self
             .nwc
             .read()
             .unwrap()
             .iter()
             .filter(|x| x.profile.active())
-            .map(|x| x.profile.relay.clone())
+            .flat_map(|x| x.profile.relays())
             .chain(RELAYS.iter().map(|x| x.to_string()))
+            .chain(relay_list)
             .collect();
 
         // remove duplicates
@@ -422,8 +748,8 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         Ok(vec)
     }
 
-    /// Filters for getting DMs from our contacts
-    async fn get_dm_filter(&self) -> Result<Filter, MutinyError> {
+    /// Filters for getting NIP-04 DMs from our contacts and NIP-17 DMs sent to us
+    async fn get_dm_filters(&self) -> Result<Vec<Filter>, MutinyError> {
         let contacts = self.storage.get_contacts()?;
         let last_sync_time = self.storage.get_dm_sync_time(false)?;
         let npubs: HashSet<nostr::PublicKey> =
@@ -446,7 +772,18 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             .pubkey(pk)
             .since(time_stamp);
 
-        Ok(received_dm_filter)
+        // gift wraps are backdated to hide when they were sent, so look further back
+        let gift_wrap_since = Timestamp::from(
+            time_stamp
+                .as_u64()
+                .saturating_sub(GIFT_WRAP_MAX_BACKDATE_SECS),
+        );
+        let gift_wrap_filter = Filter::new()
+            .kind(Kind::GiftWrap)
+            .pubkey(pk)
+            .since(gift_wrap_since);
+
+        Ok(vec![received_dm_filter, gift_wrap_filter])
     }
 
     /// Filter for getting updates to our nostr contacts list
@@ -475,9 +812,10 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
 
     pub async fn get_filters(&self) -> Result<Vec<Filter>, MutinyError> {
         let mut nwc = self.get_nwc_filters()?;
-        let dm = self.get_dm_filter().await?;
+        let dms = self.get_dm_filters().await?;
         let contacts_list = self.get_contacts_list_filter().await?;
-        nwc.extend([dm, contacts_list]);
+        nwc.extend(dms);
+        nwc.push(contacts_list);
 
         Ok(nwc)
     }
@@ -588,13 +926,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 })
                 .collect();
             let builder = EventBuilder::new(Kind::ContactList, json!(content).to_string(), tags);
-            let event = self
-                .nostr_keys
-                .read()
-                .await
-                .signer
-                .sign_event_builder(builder)
-                .await?;
+            let event = self.signer().await?.sign_event_builder(builder).await?;
 
             self.client.send_event(event.clone()).await?;
 
@@ -715,13 +1047,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             }
         };
 
-        let event = self
-            .nostr_keys
-            .read()
-            .await
-            .signer
-            .sign_event_builder(builder)
-            .await?;
+        let event = self.signer().await?.sign_event_builder(builder).await?;
         let event_id = self.client.send_event(event.clone()).await?;
 
         update_nostr_contact_list(&self.storage, event)?;
@@ -773,13 +1099,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 }
 
                 let builder = EventBuilder::new(Kind::ContactList, content, tags);
-                let event = self
-                    .nostr_keys
-                    .read()
-                    .await
-                    .signer
-                    .sign_event_builder(builder)
-                    .await?;
+                let event = self.signer().await?.sign_event_builder(builder).await?;
                 let event_id = self.client.send_event(event.clone()).await?;
 
                 update_nostr_contact_list(&self.storage, event)?;
@@ -1013,86 +1333,30 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         Ok(nwc_profile)
     }
 
-    pub fn get_nwc_profile(&self, index: u32) -> Result<NwcProfile, MutinyError> {
-        let profiles = self.nwc.read().unwrap();
-
-        let nwc = profiles
-            .iter()
-            .find(|nwc| nwc.profile.index == index)
-            .ok_or(MutinyError::NotFound)?;
-
-        Ok(nwc.nwc_profile())
-    }
-
-    pub(crate) fn nostr_wallet_auth(
+    /// Sets the rules checked before every payment a profile makes.
+    /// Payments already counted towards a rate limit are kept.
+    pub fn set_nwc_profile_spending_rules(
         &self,
-        profile_type: ProfileType,
-        uri: NIP49URI,
-        budget: Option<BudgetedSpendingConditions>,
-        tag: NwcProfileTag,
-        commands: Vec<Method>,
+        profile_index: u32,
+        mut rules: SpendingRules,
     ) -> Result<NwcProfile, MutinyError> {
-        let spending_conditions = match uri.budget {
-            None => match budget {
-                None => SpendingConditions::RequireApproval,
-                Some(budget) => SpendingConditions::Budget(budget),
-            },
-            Some(uri_budget) => {
-                // make sure we don't have 2 budgets
-                if budget.is_some() {
-                    return Err(MutinyError::InvalidArgumentsError);
-                }
-
-                SpendingConditions::Budget(BudgetedSpendingConditions {
-                    budget: uri_budget.amount,
-                    single_max: None,
-                    payments: vec![],
-                    period: match uri_budget.time_period {
-                        NIP49BudgetPeriod::Daily => BudgetPeriod::Day,
-                        NIP49BudgetPeriod::Weekly => BudgetPeriod::Week,
-                        NIP49BudgetPeriod::Monthly => BudgetPeriod::Month,
-                        NIP49BudgetPeriod::Yearly => BudgetPeriod::Year,
-                    },
-                })
-            }
-        };
-
-        let mut profiles = self.nwc.try_write()?;
-
-        let (name, index, child_key_index) = get_next_nwc_index(profile_type, &profiles)?;
+        let mut profiles = self.nwc.write().unwrap();
 
-        let label = match uri.identity {
-            Some(identity) => {
-                let contacts = self.storage.get_contacts()?;
-                contacts.into_iter().find_map(|(id, c)| {
-                    if c.npub == Some(identity) {
-                        Some(id)
-                    } else {
-                        None
-                    }
-                })
-            }
-            None => None,
-        };
+        let nwc = profiles
+            .iter_mut()
+            .find(|nwc| nwc.profile.index == profile_index)
+            .ok_or(MutinyError::NotFound)?;
 
-        let profile = Profile {
-            name,
-            index,
-            client_key: Some(uri.public_key),
-            child_key_index,
-            relay: DEFAULT_RELAY.to_string(), // override with our relay
-            enabled: None,
-            archived: None,
-            spending_conditions,
-            commands: Some(commands),
-            tag,
-            label,
-        };
+        if let (Some(new), Some(old)) = (
+            rules.rate_limit.as_mut(),
+            nwc.profile.spending_rules.rate_limit.as_ref(),
+        ) {
+            new.payments = old.payments.clone();
+        }
 
-        let nwc = NostrWalletConnect::new(&Secp256k1::new(), self.xprivkey, profile)?;
+        nwc.profile.spending_rules = rules;
 
-        profiles.push(nwc.clone());
-        profiles.sort_by_key(|nwc| nwc.profile.index);
+        let nwc_profile = nwc.nwc_profile();
 
         // save to storage
         {
@@ -1104,49 +1368,912 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 .set_data(NWC_STORAGE_KEY.to_string(), profiles, None)?;
         }
 
-        Ok(nwc.nwc_profile())
+        Ok(nwc_profile)
     }
 
-    /// Creates a new NWC profile and saves to storage
-    pub(crate) fn create_new_nwc_profile_internal(
+    /// Sets which NIP-47 commands a profile is allowed to use.
+    /// This will also broadcast the updated info event to the relay.
+    pub async fn set_nwc_profile_commands(
         &self,
-        profile_type: ProfileType,
-        spending_conditions: SpendingConditions,
-        tag: NwcProfileTag,
+        profile_index: u32,
         commands: Vec<Method>,
     ) -> Result<NwcProfile, MutinyError> {
-        log_info!(self.logger, "Creating new internal nwc profile");
+        self.update_nwc_capabilities(profile_index, |profile| profile.commands = Some(commands))
+            .await
+    }
 
-        let mut profiles = self.nwc.try_write()?;
+    /// Enables or disables NIP-47 notifications for a profile.
+    /// This will also broadcast the updated info event to the relay.
+    pub async fn set_nwc_profile_notifications(
+        &self,
+        profile_index: u32,
+        enabled: bool,
+    ) -> Result<NwcProfile, MutinyError> {
+        self.update_nwc_capabilities(profile_index, |profile| profile.notifications = enabled)
+            .await
+    }
 
-        let (name, index, child_key_index) = get_next_nwc_index(profile_type, &profiles)?;
+    /// Sets the extra relays a profile uses alongside its primary relay,
+    /// and which of its relays require NIP-42 authentication.
+    /// This will also broadcast the updated info event to the relays.
+    pub async fn set_nwc_profile_relays(
+        &self,
+        profile_index: u32,
+        relays: Vec<String>,
+        auth_relays: Vec<String>,
+    ) -> Result<NwcProfile, MutinyError> {
+        for relay in relays.iter().chain(auth_relays.iter()) {
+            let url = Url::parse(relay).map_err(|_| MutinyError::InvalidArgumentsError)?;
+            if url.scheme() != "wss" && url.scheme() != "ws" {
+                return Err(MutinyError::InvalidArgumentsError);
+            }
+        }
 
-        let profile = Profile {
-            name,
-            index,
-            child_key_index,
-            relay: DEFAULT_RELAY.to_string(),
-            enabled: None,
-            archived: None,
-            spending_conditions,
-            tag,
-            client_key: None,
-            label: None,
-            commands: Some(commands),
-        };
-        let nwc = NostrWalletConnect::new(&Secp256k1::new(), self.xprivkey, profile)?;
+        self.connect_relays(&relays).await?;
+        self.connect_relays(&auth_relays).await?;
 
-        profiles.push(nwc.clone());
-        profiles.sort_by_key(|nwc| nwc.profile.index);
+        self.update_nwc_capabilities(profile_index, |profile| {
+            profile.relays = relays;
+            profile.auth_relays = auth_relays;
+        })
+        .await
+    }
 
-        // save to storage
-        {
-            let profiles = profiles
-                .iter()
-                .map(|x| x.profile.clone())
-                .collect::<Vec<_>>();
-            self.storage
-                .set_data(NWC_STORAGE_KEY.to_string(), profiles, None)?;
+    /// Adds the relays to our client and connects to any new ones
+    async fn connect_relays(&self, relays: &[String]) -> Result<(), MutinyError> {
+        for relay in relays {
+            if self.client.add_relay(relay).await? {
+                self.client.connect_relay(relay).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Our NIP-65 relay list, the default relays if we haven't published one
+    pub async fn get_relay_list(&self) -> Result<RelayList, MutinyError> {
+        let npub = self.get_npub().await;
+        // the stored list may be of other nostr keys we used before
+        let event = self
+            .storage
+            .get_data::<Event>(NOSTR_RELAY_LIST_KEY)?
+            .filter(|e| e.pubkey == npub);
+        match event {
+            Some(event) => RelayList::from_event(&event),
+            None => Ok(RelayList::from_relays(&RELAYS)),
+        }
+    }
+
+    /// Publishes a new NIP-65 relay list and connects to its relays
+    pub async fn set_relay_list(&self, list: RelayList) -> Result<RelayList, MutinyError> {
+        list.validate()?;
+
+        let relays: Vec<String> = list.relays.iter().map(|r| r.url.clone()).collect();
+        self.connect_relays(&relays).await?;
+
+        let event = self
+            .client
+            .sign_event_builder(EventBuilder::new(Kind::RelayList, "", list.tags()))
+            .await?;
+        self.client.send_event(event.clone()).await?;
+        self.storage
+            .set_data(NOSTR_RELAY_LIST_KEY.to_string(), event, None)?;
+
+        Ok(list)
+    }
+
+    /// Fetches our latest NIP-65 relay list and connects to its relays
+    pub async fn sync_relay_list(&self) -> Result<RelayList, MutinyError> {
+        let npub = self.get_npub().await;
+        let filter = Filter::new().kind(Kind::RelayList).author(npub);
+        let events = self
+            .client
+            .get_events_of(vec![filter], Some(Duration::from_secs(5)))
+            .await?;
+
+        // a list saved for keys we used before doesn't count
+        let current = self
+            .storage
+            .get_data::<Event>(NOSTR_RELAY_LIST_KEY)?
+            .filter(|c| c.pubkey == npub);
+        let newest = events
+            .into_iter()
+            .filter(|e| e.kind == Kind::RelayList && e.pubkey == npub && e.verify().is_ok())
+            .max_by_key(|e| e.created_at)
+            .filter(|e| current.map_or(true, |c| e.created_at > c.created_at));
+
+        if let Some(event) = newest {
+            let list = RelayList::from_event(&event)?;
+            let relays: Vec<String> = list.relays.iter().map(|r| r.url.clone()).collect();
+            self.connect_relays(&relays).await?;
+            self.storage
+                .set_data(NOSTR_RELAY_LIST_KEY.to_string(), event, None)?;
+        }
+
+        self.get_relay_list().await
+    }
+
+    /// Gets the relays a user reads from, where events for them should be sent.
+    /// Empty if they don't have a relay list.
+    async fn get_inbox_relays(&self, npub: nostr::PublicKey) -> Vec<String> {
+        match get_relay_lists(&self.storage, &self.client, vec![npub], &self.logger).await {
+            Ok(lists) => select_outbox_relays(lists.values(), true),
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get relays of {npub}: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Connects to the relays the given users publish to, in a pool apart from ours
+    async fn connect_outbox_pool(&self, npubs: Vec<nostr::PublicKey>) -> OutboxPool<C> {
+        let relays = match get_relay_lists(&self.storage, &self.client, npubs, &self.logger).await {
+            Ok(lists) => select_outbox_relays(lists.values(), false),
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get outbox relays: {e}");
+                Vec::new()
+            }
+        };
+
+        OutboxPool::connect(&self.client, relays, &self.logger).await
+    }
+
+    /// Sends events to the given relays of another user, without adding them to our pool
+    async fn send_to_inbox(&self, relays: Vec<String>, events: Vec<Event>) {
+        let pool = OutboxPool::connect(&self.client, relays, &self.logger).await;
+        for event in events {
+            if let Err(e) = pool.send_event(event).await {
+                log_warn!(self.logger, "Failed to send event to inbox relays: {e}");
+            }
+        }
+        pool.disconnect(&self.logger).await;
+    }
+
+    /// Relays a zap request should ask for the receipt to be published to,
+    /// the recipient's read relays so they see it and ours so we do.
+    pub(crate) async fn get_zap_relays(&self, recipient: nostr::PublicKey) -> Vec<String> {
+        let mut relays = self.get_inbox_relays(recipient).await;
+        match self.get_relay_list().await {
+            Ok(list) => relays.extend(list.read_relays()),
+            Err(_) => relays.extend(RELAYS.iter().map(|r| r.to_string())),
+        }
+        relays.sort();
+        relays.dedup();
+
+        relays
+    }
+
+    /// Creates NIP-42 auth events for every profile that authenticates to the relay
+    pub fn create_nwc_auth_events(&self, relay: &Url, challenge: &str) -> Vec<Event> {
+        self.nwc
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|nwc| match nwc.create_auth_event(relay, challenge) {
+                Ok(event) => event,
+                Err(e) => {
+                    log_error!(
+                        self.logger,
+                        "Failed to create auth event for profile {}: {e}",
+                        nwc.profile.index
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The connection health of the relays we have connected to
+    pub fn get_relay_health(&self) -> Vec<RelayHealth> {
+        self.relay_health.get_health()
+    }
+
+    /// Updates a profile, saves it and broadcasts its new info event
+    async fn update_nwc_capabilities(
+        &self,
+        profile_index: u32,
+        update: impl FnOnce(&mut Profile),
+    ) -> Result<NwcProfile, MutinyError> {
+        let (nwc_profile, info_event, relays) = {
+            let mut profiles = self.nwc.write().unwrap();
+
+            let nwc = profiles
+                .iter_mut()
+                .find(|nwc| nwc.profile.index == profile_index)
+                .ok_or(MutinyError::NotFound)?;
+
+            update(&mut nwc.profile);
+
+            let nwc_profile = nwc.nwc_profile();
+            let info_event = nwc.create_nwc_info_event().ok();
+            let relays = self.relay_health.select(nwc.profile.relays());
+
+            // save to storage
+            {
+                let profiles = profiles
+                    .iter()
+                    .map(|x| x.profile.clone())
+                    .collect::<Vec<_>>();
+                self.storage
+                    .set_data(NWC_STORAGE_KEY.to_string(), profiles, None)?;
+            }
+
+            (nwc_profile, info_event, relays)
+        };
+
+        if let Some(info_event) = info_event {
+            self.client
+                .send_event_to(relays, info_event)
+                .await
+                .map_err(|e| {
+                    MutinyError::Other(anyhow::anyhow!("Failed to send info event: {e:?}"))
+                })?;
+        }
+
+        Ok(nwc_profile)
+    }
+
+    /// Sends a NIP-47 notification about a completed payment to every
+    /// active profile that has notifications enabled and made the payment.
+    pub async fn send_nwc_notifications(&self, invoice: &MutinyInvoice) -> Result<(), MutinyError> {
+        let events = {
+            let profiles = self.nwc.read().unwrap();
+            profiles
+                .iter()
+                .filter_map(|nwc| match nwc.create_nwc_notification_event(invoice) {
+                    Ok(event) => event.map(|e| (self.relay_health.select(nwc.profile.relays()), e)),
+                    Err(e) => {
+                        log_error!(
+                            self.logger,
+                            "Failed to create NWC notification for profile {}: {e}",
+                            nwc.profile.index
+                        );
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // one profile's relays being down shouldn't stop the others from being notified
+        for (relays, event) in events {
+            match self.client.send_event_to(relays, event).await {
+                Ok(id) => log_debug!(self.logger, "Sent NWC notification: {id}"),
+                Err(e) => log_error!(self.logger, "Failed to send NWC notification: {e:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves a zap we sent so it can be matched to its receipt
+    pub(crate) fn save_sent_zap(&self, zap: Zap) -> Result<(), MutinyError> {
+        let mut zaps: Vec<Zap> = self.storage.get_data(ZAPS_KEY)?.unwrap_or_default();
+        zaps.retain(|z| z.payment_hash != zap.payment_hash);
+        zaps.push(zap);
+        self.storage.set_data(ZAPS_KEY.to_string(), zaps, None)
+    }
+
+    /// Lists the zaps we have sent and received, newest first
+    pub fn get_zaps(&self) -> Result<Vec<Zap>, MutinyError> {
+        let mut zaps: Vec<Zap> = self.storage.get_data(ZAPS_KEY)?.unwrap_or_default();
+        zaps.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(zaps)
+    }
+
+    /// Fetches the zap receipts for zaps we sent and zaps sent to us.
+    ///
+    /// Receipts for zaps we sent must be signed by the recipient's LNURL provider.
+    /// Receipts for zaps sent to us must be signed by our own LNURL `provider`
+    /// and are only trusted if they are for an invoice of ours that was paid,
+    /// those are added to our zap history. Without a provider we can't check
+    /// who signed them, so they are picked up on a later sync.
+    pub async fn sync_zap_receipts(
+        &self,
+        provider: Option<nostr::PublicKey>,
+    ) -> Result<(), MutinyError> {
+        let mut zaps: Vec<Zap> = self.storage.get_data(ZAPS_KEY)?.unwrap_or_default();
+        let npub = self.get_npub().await;
+
+        let now = utils::now().as_secs();
+        // receipts we can't match yet hold back the sync time, so we look at them again
+        let mut synced_to = now;
+        let mut filters = vec![];
+        if let Some(provider) = provider {
+            let since = match self
+                .storage
+                .get_data::<u64>(LAST_ZAP_RECEIPT_SYNC_TIME_KEY)?
+            {
+                Some(time) => time,
+                None => now.saturating_sub(ZAP_RECEIPT_LOOKBACK_SECS),
+            };
+            filters.push(
+                Filter::new()
+                    .kind(Kind::ZapReceipt)
+                    .author(provider)
+                    .pubkey(npub)
+                    .since(Timestamp::from(since)),
+            );
+        }
+
+        // look for receipts of the zaps we sent that haven't been matched yet
+        let unmatched = zaps
+            .iter()
+            .filter(|z| !z.inbound && z.receipt_id.is_none())
+            .collect::<Vec<_>>();
+        if let Some(oldest) = unmatched.iter().map(|z| z.created_at).min() {
+            let recipients = unmatched
+                .iter()
+                .map(|z| z.recipient)
+                .collect::<HashSet<_>>();
+            filters.push(
+                Filter::new()
+                    .kind(Kind::ZapReceipt)
+                    .pubkeys(recipients)
+                    .since(Timestamp::from(oldest)),
+            );
+        }
+
+        if filters.is_empty() {
+            return Ok(());
+        }
+
+        let events = self
+            .client
+            .get_events_of(filters, Some(Duration::from_secs(10)))
+            .await?;
+
+        let profile_key = self.nostr_keys.read().await.keys().cloned();
+
+        for event in events {
+            let Some(payment_hash) = receipt_payment_hash(&event) else {
+                continue;
+            };
+
+            // receipt for a zap we sent
+            if let Some(zap) = zaps.iter_mut().find(|z| z.payment_hash == payment_hash) {
+                if zap.inbound || zap.receipt_id.is_some() {
+                    continue;
+                }
+
+                match verify_zap_receipt(&event, zap.provider) {
+                    Ok(receipt) if receipt.zap_request.id == zap.zap_request_id => {
+                        zap.receipt_id = Some(receipt.id);
+                    }
+                    Ok(_) => {
+                        log_warn!(self.logger, "Zap receipt {} is for another zap", event.id)
+                    }
+                    Err(e) => log_warn!(self.logger, "Invalid zap receipt {}: {e}", event.id),
+                }
+                continue;
+            }
+
+            // receipt for a zap sent to us
+            let Some(provider) = provider else {
+                continue;
+            };
+            let receipt = match verify_zap_receipt(&event, Some(provider)) {
+                Ok(receipt) if receipt.recipient == npub => receipt,
+                Ok(_) => continue,
+                Err(e) => {
+                    log_warn!(self.logger, "Invalid zap receipt {}: {e}", event.id);
+                    continue;
+                }
+            };
+
+            let Ok(invoice) = get_invoice_by_hash(&payment_hash, &self.storage, &self.logger)
+            else {
+                log_debug!(
+                    self.logger,
+                    "Zap receipt {} is for an invoice we don't have yet",
+                    event.id
+                );
+                synced_to = synced_to.min(event.created_at.as_u64());
+                continue;
+            };
+            if !invoice.inbound {
+                log_warn!(
+                    self.logger,
+                    "Zap receipt {} does not match our invoice",
+                    event.id
+                );
+                continue;
+            }
+            if invoice.status != HTLCStatus::Succeeded {
+                log_debug!(
+                    self.logger,
+                    "Zap receipt {} is for an invoice that isn't paid yet",
+                    event.id
+                );
+                synced_to = synced_to.min(event.created_at.as_u64());
+                continue;
+            }
+
+            let (privacy_level, message, sender) =
+                zap_request_sender(&receipt.zap_request, profile_key.as_ref(), &self.logger);
+            let contact_id = match sender {
+                Some(sender) => self.storage.get_contact_for_npub(sender)?.map(|(id, _)| id),
+                None => None,
+            };
+
+            // tag the invoice with the contact if it isn't already
+            if let Some(id) = contact_id.as_ref() {
+                if !invoice.labels.contains(id) {
+                    let mut labels = invoice.labels.clone();
+                    labels.insert(0, id.clone());
+                    self.storage
+                        .set_invoice_labels(receipt.bolt11.clone(), labels)?;
+                }
+            }
+
+            zaps.push(Zap {
+                payment_hash,
+                inbound: true,
+                amount_msats: receipt.amount_msats,
+                sender,
+                recipient: npub,
+                message: message.filter(|m| !m.is_empty()),
+                privacy_level,
+                zapped_event: receipt.zapped_event(),
+                zap_request_id: receipt.zap_request.id,
+                provider: Some(event.pubkey),
+                receipt_id: Some(receipt.id),
+                contact_id,
+                created_at: receipt.created_at,
+            });
+        }
+
+        self.storage.set_data(ZAPS_KEY.to_string(), zaps, None)?;
+        if provider.is_some() {
+            // don't keep retrying receipts past the lookback, their invoices aren't coming
+            let synced_to = synced_to.max(now.saturating_sub(ZAP_RECEIPT_LOOKBACK_SECS));
+            self.storage
+                .set_data(LAST_ZAP_RECEIPT_SYNC_TIME_KEY.to_string(), synced_to, None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Connects to an external wallet by its `nostr+walletconnect://` URI
+    /// so it can be used as a funding source.
+    pub async fn add_external_wallet(
+        &self,
+        name: String,
+        uri: NostrWalletConnectURI,
+    ) -> Result<ExternalWallet, MutinyError> {
+        let mut wallets = self.list_external_wallets()?;
+        if wallets
+            .iter()
+            .any(|w| w.wallet_pubkey().ok() == Some(uri.public_key))
+        {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let client = self.client.new_pool();
+        let methods = nwc_client::get_wallet_methods(&client, &uri).await?;
+        let wallet = ExternalWallet {
+            name,
+            uri: uri.to_string(),
+            methods,
+            prefer_for_invoices: false,
+        };
+
+        wallets.push(wallet.clone());
+        self.storage
+            .set_data(EXTERNAL_WALLETS_KEY.to_string(), wallets, None)?;
+        self.external_wallet_clients
+            .write()
+            .unwrap()
+            .insert(uri.public_key, Arc::new(client));
+        // make sure the new wallet's balance shows up
+        self.expire_external_wallet_balances()?;
+
+        Ok(wallet)
+    }
+
+    /// Lists the external wallets we have connected to
+    pub fn list_external_wallets(&self) -> Result<Vec<ExternalWallet>, MutinyError> {
+        Ok(self
+            .storage
+            .get_data(EXTERNAL_WALLETS_KEY)?
+            .unwrap_or_default())
+    }
+
+    pub fn get_external_wallet(
+        &self,
+        wallet_pubkey: nostr::PublicKey,
+    ) -> Result<ExternalWallet, MutinyError> {
+        self.list_external_wallets()?
+            .into_iter()
+            .find(|w| w.wallet_pubkey().ok() == Some(wallet_pubkey))
+            .ok_or(MutinyError::NotFound)
+    }
+
+    /// Sets whether invoices are created with an external wallet before our node
+    pub fn set_external_wallet_prefer_for_invoices(
+        &self,
+        wallet_pubkey: nostr::PublicKey,
+        prefer: bool,
+    ) -> Result<ExternalWallet, MutinyError> {
+        let mut wallets = self.list_external_wallets()?;
+        let wallet = wallets
+            .iter_mut()
+            .find(|w| w.wallet_pubkey().ok() == Some(wallet_pubkey))
+            .ok_or(MutinyError::NotFound)?;
+        wallet.prefer_for_invoices = prefer;
+        let wallet = wallet.clone();
+
+        self.storage
+            .set_data(EXTERNAL_WALLETS_KEY.to_string(), wallets, None)?;
+
+        Ok(wallet)
+    }
+
+    /// Disconnects an external wallet, it will no longer be used for payments
+    pub async fn remove_external_wallet(
+        &self,
+        wallet_pubkey: nostr::PublicKey,
+    ) -> Result<(), MutinyError> {
+        let mut wallets = self.list_external_wallets()?;
+        wallets.retain(|w| w.wallet_pubkey().ok() != Some(wallet_pubkey));
+        self.storage
+            .set_data(EXTERNAL_WALLETS_KEY.to_string(), wallets, None)?;
+
+        let client = self
+            .external_wallet_clients
+            .write()
+            .unwrap()
+            .remove(&wallet_pubkey);
+        if let Some(client) = client {
+            if let Err(e) = client.disconnect().await {
+                log_warn!(self.logger, "Failed to disconnect external wallet: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the client for an external wallet, each wallet gets its own relay pool
+    fn external_wallet_client(&self, wallet: &ExternalWallet) -> Result<Arc<C>, MutinyError> {
+        let wallet_pubkey = wallet.wallet_pubkey()?;
+        let client = self
+            .external_wallet_clients
+            .write()
+            .unwrap()
+            .entry(wallet_pubkey)
+            .or_insert_with(|| Arc::new(self.client.new_pool()))
+            .clone();
+        Ok(client)
+    }
+
+    /// Sends a request to an external wallet over its own client
+    async fn send_external_wallet_request(
+        &self,
+        wallet: &ExternalWallet,
+        request: Request,
+        timeout: Duration,
+    ) -> Result<ResponseResult, MutinyError> {
+        let client = self.external_wallet_client(wallet)?;
+        nwc_client::send_request(client.as_ref(), &wallet.nwc_uri()?, request, timeout).await
+    }
+
+    /// Gets the balance of an external wallet in sats
+    pub(crate) async fn get_external_wallet_balance(
+        &self,
+        wallet: &ExternalWallet,
+    ) -> Result<u64, MutinyError> {
+        if !wallet.supports(Method::GetBalance) {
+            return Err(MutinyError::NotFound);
+        }
+
+        let request = Request {
+            method: Method::GetBalance,
+            params: RequestParams::GetBalance,
+        };
+        match self
+            .send_external_wallet_request(wallet, request, EXTERNAL_WALLET_TIMEOUT)
+            .await?
+        {
+            ResponseResult::GetBalance(res) => Ok(res.balance / 1_000),
+            _ => Err(MutinyError::NostrError),
+        }
+    }
+
+    /// The last balances we fetched from our external wallets,
+    /// leaving out wallets that have since been removed
+    pub(crate) fn cached_external_wallet_balances(
+        &self,
+    ) -> Result<CachedExternalWalletBalances, MutinyError> {
+        let mut cached: CachedExternalWalletBalances = self
+            .storage
+            .get_data(EXTERNAL_WALLET_BALANCES_KEY)?
+            .unwrap_or_default();
+
+        let wallets: HashSet<nostr::PublicKey> = self
+            .list_external_wallets()?
+            .iter()
+            .filter_map(|w| w.wallet_pubkey().ok())
+            .collect();
+        cached
+            .balances
+            .retain(|b| wallets.contains(&b.wallet_pubkey));
+
+        Ok(cached)
+    }
+
+    fn expire_external_wallet_balances(&self) -> Result<(), MutinyError> {
+        let mut cached = self.cached_external_wallet_balances()?;
+        cached.last_updated = 0;
+        self.storage
+            .set_data(EXTERNAL_WALLET_BALANCES_KEY.to_string(), cached, None)
+    }
+
+    /// Fetches the balance of each external wallet that shares it and caches them.
+    /// Wallets that can't be reached are left out.
+    ///
+    /// If the balances are already being fetched this returns the cached balances.
+    pub(crate) async fn refresh_external_wallet_balances(
+        &self,
+    ) -> Result<Vec<ExternalWalletBalance>, MutinyError> {
+        if self
+            .refreshing_external_balances
+            .swap(true, Ordering::Relaxed)
+        {
+            return Ok(self.cached_external_wallet_balances()?.balances);
+        }
+
+        let result = self.fetch_external_wallet_balances().await;
+        self.refreshing_external_balances
+            .store(false, Ordering::Relaxed);
+        result
+    }
+
+    async fn fetch_external_wallet_balances(
+        &self,
+    ) -> Result<Vec<ExternalWalletBalance>, MutinyError> {
+        let mut balances = vec![];
+        for wallet in self.list_external_wallets()? {
+            if !wallet.supports(Method::GetBalance) {
+                continue;
+            }
+
+            match self.get_external_wallet_balance(&wallet).await {
+                Ok(balance) => balances.push(ExternalWalletBalance {
+                    name: wallet.name.clone(),
+                    wallet_pubkey: wallet.wallet_pubkey()?,
+                    balance,
+                }),
+                Err(e) => {
+                    log_warn!(
+                        self.logger,
+                        "Failed to get balance of external wallet {}: {e}",
+                        wallet.name
+                    );
+                }
+            }
+        }
+
+        let cached = CachedExternalWalletBalances {
+            balances: balances.clone(),
+            last_updated: utils::now().as_secs(),
+        };
+        self.storage
+            .set_data(EXTERNAL_WALLET_BALANCES_KEY.to_string(), cached, None)?;
+
+        Ok(balances)
+    }
+
+    /// Pays an invoice from an external wallet, returning the preimage.
+    ///
+    /// Returns [`MutinyError::PaymentTimeout`] if the wallet doesn't answer in time,
+    /// it may still complete the payment.
+    pub(crate) async fn pay_external_wallet_invoice(
+        &self,
+        wallet: &ExternalWallet,
+        invoice: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+    ) -> Result<String, MutinyError> {
+        let request = Request::pay_invoice(PayInvoiceRequestParams {
+            id: None,
+            invoice: invoice.to_string(),
+            amount: amt_sats.map(|a| a * 1_000),
+        });
+        let result = self
+            .send_external_wallet_request(wallet, request, EXTERNAL_WALLET_PAYMENT_TIMEOUT)
+            .await
+            .map_err(|e| match e {
+                MutinyError::ConnectionFailed => MutinyError::PaymentTimeout,
+                e => e,
+            })?;
+        match result {
+            ResponseResult::PayInvoice(res) => Ok(res.preimage),
+            _ => Err(MutinyError::NostrError),
+        }
+    }
+
+    /// Asks an external wallet for an invoice of `amount_sats`
+    pub(crate) async fn create_external_wallet_invoice(
+        &self,
+        wallet: &ExternalWallet,
+        amount_sats: u64,
+    ) -> Result<Bolt11Invoice, MutinyError> {
+        let request = Request::make_invoice(MakeInvoiceRequestParams {
+            amount: amount_sats * 1_000,
+            description: None,
+            description_hash: None,
+            expiry: None,
+        });
+        match self
+            .send_external_wallet_request(wallet, request, EXTERNAL_WALLET_TIMEOUT)
+            .await?
+        {
+            ResponseResult::MakeInvoice(res) => {
+                Bolt11Invoice::from_str(&res.invoice).map_err(|_| MutinyError::InvoiceInvalid)
+            }
+            _ => Err(MutinyError::NostrError),
+        }
+    }
+
+    /// Looks up an invoice we created with an external wallet
+    pub(crate) async fn lookup_external_wallet_invoice(
+        &self,
+        wallet: &ExternalWallet,
+        payment_hash: &sha256::Hash,
+    ) -> Result<LookupInvoiceResponseResult, MutinyError> {
+        let request = Request::lookup_invoice(LookupInvoiceRequestParams {
+            payment_hash: Some(payment_hash.to_string()),
+            bolt11: None,
+        });
+        match self
+            .send_external_wallet_request(wallet, request, EXTERNAL_WALLET_TIMEOUT)
+            .await?
+        {
+            ResponseResult::LookupInvoice(res) => Ok(res),
+            _ => Err(MutinyError::NostrError),
+        }
+    }
+
+    pub fn get_nwc_profile(&self, index: u32) -> Result<NwcProfile, MutinyError> {
+        let profiles = self.nwc.read().unwrap();
+
+        let nwc = profiles
+            .iter()
+            .find(|nwc| nwc.profile.index == index)
+            .ok_or(MutinyError::NotFound)?;
+
+        Ok(nwc.nwc_profile())
+    }
+
+    pub(crate) fn nostr_wallet_auth(
+        &self,
+        profile_type: ProfileType,
+        uri: NIP49URI,
+        budget: Option<BudgetedSpendingConditions>,
+        tag: NwcProfileTag,
+        commands: Vec<Method>,
+    ) -> Result<NwcProfile, MutinyError> {
+        let spending_conditions = match uri.budget {
+            None => match budget {
+                None => SpendingConditions::RequireApproval,
+                Some(budget) => SpendingConditions::Budget(budget),
+            },
+            Some(uri_budget) => {
+                // make sure we don't have 2 budgets
+                if budget.is_some() {
+                    return Err(MutinyError::InvalidArgumentsError);
+                }
+
+                SpendingConditions::Budget(BudgetedSpendingConditions {
+                    budget: uri_budget.amount,
+                    single_max: None,
+                    payments: vec![],
+                    period: match uri_budget.time_period {
+                        NIP49BudgetPeriod::Daily => BudgetPeriod::Day,
+                        NIP49BudgetPeriod::Weekly => BudgetPeriod::Week,
+                        NIP49BudgetPeriod::Monthly => BudgetPeriod::Month,
+                        NIP49BudgetPeriod::Yearly => BudgetPeriod::Year,
+                    },
+                })
+            }
+        };
+
+        let mut profiles = self.nwc.try_write()?;
+
+        let (name, index, child_key_index) = get_next_nwc_index(profile_type, &profiles)?;
+
+        let label = match uri.identity {
+            Some(identity) => {
+                let contacts = self.storage.get_contacts()?;
+                contacts.into_iter().find_map(|(id, c)| {
+                    if c.npub == Some(identity) {
+                        Some(id)
+                    } else {
+                        None
+                    }
+                })
+            }
+            None => None,
+        };
+
+        let profile = Profile {
+            name,
+            index,
+            client_key: Some(uri.public_key),
+            child_key_index,
+            relay: DEFAULT_RELAY.to_string(), // override with our relay
+            enabled: None,
+            archived: None,
+            spending_conditions,
+            commands: Some(commands),
+            tag,
+            label,
+            notifications: true,
+            spending_rules: Default::default(),
+            relays: vec![],
+            auth_relays: vec![],
+        };
+
+        let nwc = NostrWalletConnect::new(&Secp256k1::new(), self.xprivkey, profile)?;
+
+        profiles.push(nwc.clone());
+        profiles.sort_by_key(|nwc| nwc.profile.index);
+
+        // save to storage
+        {
+            let profiles = profiles
+                .iter()
+                .map(|x| x.profile.clone())
+                .collect::<Vec<_>>();
+            self.storage
+                .set_data(NWC_STORAGE_KEY.to_string(), profiles, None)?;
+        }
+
+        Ok(nwc.nwc_profile())
+    }
+
+    /// Creates a new NWC profile and saves to storage
+    pub(crate) fn create_new_nwc_profile_internal(
+        &self,
+        profile_type: ProfileType,
+        spending_conditions: SpendingConditions,
+        tag: NwcProfileTag,
+        commands: Vec<Method>,
+    ) -> Result<NwcProfile, MutinyError> {
+        log_info!(self.logger, "Creating new internal nwc profile");
+
+        let mut profiles = self.nwc.try_write()?;
+
+        let (name, index, child_key_index) = get_next_nwc_index(profile_type, &profiles)?;
+
+        let profile = Profile {
+            name,
+            index,
+            child_key_index,
+            relay: DEFAULT_RELAY.to_string(),
+            enabled: None,
+            archived: None,
+            spending_conditions,
+            tag,
+            client_key: None,
+            label: None,
+            commands: Some(commands),
+            notifications: true,
+            spending_rules: Default::default(),
+            relays: vec![],
+            auth_relays: vec![],
+        };
+        let nwc = NostrWalletConnect::new(&Secp256k1::new(), self.xprivkey, profile)?;
+
+        profiles.push(nwc.clone());
+        profiles.sort_by_key(|nwc| nwc.profile.index);
+
+        // save to storage
+        {
+            let profiles = profiles
+                .iter()
+                .map(|x| x.profile.clone())
+                .collect::<Vec<_>>();
+            self.storage
+                .set_data(NWC_STORAGE_KEY.to_string(), profiles, None)?;
         }
 
         Ok(nwc.nwc_profile())
@@ -1163,11 +2290,9 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
     ) -> Result<NwcProfile, MutinyError> {
         let profile =
             self.create_new_nwc_profile_internal(profile_type, spending_conditions, tag, commands)?;
-        // add relay if needed
-        let needs_connect = self.client.add_relay(profile.relay.as_str()).await?;
-        if needs_connect {
-            self.client.connect_relay(profile.relay.as_str()).await?;
-        }
+        // add relays if needed
+        let relays = profile.profile().relays();
+        self.connect_relays(&relays).await?;
 
         let info_event = self.nwc.read().unwrap().iter().find_map(|nwc| {
             if nwc.profile.index == profile.index {
@@ -1179,7 +2304,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
 
         if let Some(info_event) = info_event {
             self.client
-                .send_event_to(vec![profile.relay.to_string()], info_event)
+                .send_event_to(self.relay_health.select(relays), info_event)
                 .await
                 .map_err(|e| {
                     MutinyError::Other(anyhow::anyhow!("Failed to send info event: {e:?}"))
@@ -1310,7 +2435,8 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         nwc: NostrWalletConnect,
         inv: PendingNwcInvoice,
     ) -> Result<EventId, MutinyError> {
-        let encrypted = encrypt(
+        let encrypted = encryption::encrypt(
+            inv.encryption,
             nwc.server_key.secret_key().unwrap(),
             &nwc.client_pubkey(),
             resp.as_json(),
@@ -1330,7 +2456,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
 
         let event_id = self
             .client
-            .send_event_to(vec![nwc.profile.relay.clone()], response)
+            .send_event_to(self.relay_health.select(nwc.profile.relays()), response)
             .await
             .map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to send info event: {e:?}")))?;
 
@@ -1375,8 +2501,12 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         let (nwc, inv) = self.find_nwc_data(&hash)?;
 
         let event_id = match nwc {
-            Some(nwc) => {
+            Some(mut nwc) => {
                 let resp = nwc.pay_nwc_invoice(invoice_handler, &inv.invoice).await?;
+                // approved payments count towards the profile's rate limit too
+                if nwc.profile.spending_rules.record_payment(Utc::now()) {
+                    self.save_nwc_profile(nwc.clone())?;
+                }
                 Some(self.broadcast_nwc_response(resp, nwc, inv).await?)
             }
             None => {
@@ -1470,7 +2600,8 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 let (nwc, inv) = self.find_nwc_data(invoice.invoice.payment_hash())?;
 
                 if let Some(nwc) = nwc {
-                    let encrypted = encrypt(
+                    let encrypted = encryption::encrypt(
+                        inv.encryption,
                         nwc.server_key.secret_key().unwrap(),
                         &nwc.client_pubkey(),
                         resp.as_json(),
@@ -1555,13 +2686,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             anyhow::bail!("Not a direct message");
         } else if event.pubkey == self.nostr_keys.read().await.public_key {
             // don't process our own messages but we should update the last used time
-            if let Some((id, mut contact)) = self.storage.get_contact_for_npub(event.pubkey)? {
-                let created_at = event.created_at.as_u64();
-                if contact.last_used < created_at {
-                    contact.last_used = created_at;
-                    self.storage.edit_contact(id, contact)?;
-                }
-            }
+            self.update_contact_last_used(event.pubkey, event.created_at.as_u64())?;
 
             return Ok(());
         }
@@ -1575,16 +2700,74 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         let decrypted = self.decrypt_dm(event.pubkey, &event.content).await?;
 
         // update contact's last_updated
-        if let Some((id, mut contact)) = self.storage.get_contact_for_npub(event.pubkey)? {
-            let created_at = event.created_at.as_u64();
+        self.update_contact_last_used(event.pubkey, event.created_at.as_u64())?;
+
+        self.save_dm_invoice(event.id, event.pubkey, &decrypted, invoice_handler)
+            .await
+    }
+
+    /// Handles a NIP-17 gift wrapped direct message. Like NIP-04 DMs, only messages
+    /// from our contacts are checked for invoices.
+    pub async fn handle_gift_wrap(
+        &self,
+        event: Event,
+        invoice_handler: &impl InvoiceHandler,
+    ) -> anyhow::Result<()> {
+        if event.kind != Kind::GiftWrap {
+            anyhow::bail!("Not a gift wrap");
+        }
+
+        let dm = self.unwrap_gift_wrap(&event).await?;
+
+        // our own copy of a message we sent
+        if dm.from == self.get_npub().await {
+            self.update_contact_last_used(dm.to, dm.created_at)?;
+            return Ok(());
+        }
+
+        log_debug!(self.logger, "processing gift wrapped dm: {}", dm.id);
+
+        // update sync time
+        self.storage
+            .set_dm_sync_time(event.created_at.as_u64(), false)?;
+
+        // they can read NIP-17, so reply to them with it
+        self.add_nip17_peer(dm.from)?;
+
+        if self.storage.get_contact_for_npub(dm.from)?.is_none() {
+            return Ok(());
+        }
+        self.update_contact_last_used(dm.from, dm.created_at)?;
+
+        self.save_dm_invoice(dm.id, dm.from, &dm.message, invoice_handler)
+            .await
+    }
+
+    fn update_contact_last_used(
+        &self,
+        npub: nostr::PublicKey,
+        created_at: u64,
+    ) -> Result<(), MutinyError> {
+        if let Some((id, mut contact)) = self.storage.get_contact_for_npub(npub)? {
             if contact.last_used < created_at {
                 contact.last_used = created_at;
                 self.storage.edit_contact(id, contact)?;
             }
         }
 
+        Ok(())
+    }
+
+    /// Looks for an invoice in a dm and adds the first valid one to our pending invoices
+    async fn save_dm_invoice(
+        &self,
+        event_id: EventId,
+        sender: nostr::PublicKey,
+        message: &str,
+        invoice_handler: &impl InvoiceHandler,
+    ) -> anyhow::Result<()> {
         // loop through dm to check for invoice
-        for word in decrypted.split_whitespace() {
+        for word in message.split_whitespace() {
             // ignore word if too short
             if word.len() > 15 {
                 let invoice_request_param = match bitcoin_waila::PaymentParams::from_str(word) {
@@ -1610,8 +2793,15 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                             return Ok(());
                         }
                     };
-                self.save_pending_nwc_invoice(None, event.id, event.pubkey, invoice, None)
-                    .await?;
+                self.save_pending_nwc_invoice(
+                    None,
+                    event_id,
+                    sender,
+                    invoice,
+                    None,
+                    EncryptionScheme::default(),
+                )
+                .await?;
 
                 return Ok(());
             }
@@ -1627,6 +2817,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         event_pk: nostr::PublicKey,
         invoice: Bolt11Invoice,
         identifier: Option<String>,
+        encryption: EncryptionScheme,
     ) -> anyhow::Result<()> {
         let pending = PendingNwcInvoice {
             index: profile_index,
@@ -1634,6 +2825,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             event_id,
             pubkey: event_pk,
             identifier,
+            encryption,
         };
         self.pending_nwc_lock.lock().await;
 
@@ -1652,33 +2844,48 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
         Ok(())
     }
 
+    /// Finds the profile a NWC request is addressed to
+    fn find_nwc_for_request(&self, event: &Event) -> anyhow::Result<Option<NostrWalletConnect>> {
+        // Need to find the p tag, not the client pubkey because there can be duplicates
+        // of the same client pubkey but we guarantee that the p tag is unique.
+        let p_tag = event
+            .tags
+            .iter()
+            .find_map(|tag| {
+                if let Tag::PublicKey {
+                    public_key,
+                    uppercase: false,
+                    ..
+                } = tag
+                {
+                    Some(*public_key)
+                } else {
+                    None
+                }
+            })
+            .ok_or(anyhow::anyhow!("No P tag found"))?;
+
+        let vec = self.nwc.read().unwrap();
+        Ok(vec.iter().find(|nwc| nwc.server_pubkey() == p_tag).cloned())
+    }
+
+    /// Whether a NWC request is addressed to one of our profiles and signed by its client
+    pub(crate) fn is_nwc_request_from_profile(&self, event: &Event) -> bool {
+        event.kind == Kind::WalletConnectRequest
+            && event.verify().is_ok()
+            && self
+                .find_nwc_for_request(event)
+                .ok()
+                .flatten()
+                .is_some_and(|nwc| nwc.client_pubkey() == event.pubkey)
+    }
+
     pub async fn handle_nwc_request(
         &self,
         event: Event,
         invoice_handler: &impl InvoiceHandler,
     ) -> anyhow::Result<Option<Event>> {
-        let nwc = {
-            let vec = self.nwc.read().unwrap();
-            // Need to find the p tag, not the client pubkey because there can be duplicates
-            // of the same client pubkey but we guarantee that the p tag is unique.
-            let p_tag = event
-                .tags
-                .iter()
-                .find_map(|tag| {
-                    if let Tag::PublicKey {
-                        public_key,
-                        uppercase: false,
-                        ..
-                    } = tag
-                    {
-                        Some(*public_key)
-                    } else {
-                        None
-                    }
-                })
-                .ok_or(anyhow::anyhow!("No P tag found"))?;
-            vec.iter().find(|nwc| nwc.server_pubkey() == p_tag).cloned()
-        };
+        let nwc = self.find_nwc_for_request(&event)?;
 
         self.storage.set_nwc_sync_time(event.created_at.as_u64())?;
 
@@ -1784,7 +2991,12 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 amount: None,
             }),
         };
-        let encrypted = encrypt(&nwc.secret, &nwc.public_key, req.as_json())?;
+        let encrypted = encryption::encrypt(
+            EncryptionScheme::Nip04,
+            &nwc.secret,
+            &nwc.public_key,
+            req.as_json(),
+        )?;
         let p_tag = Tag::public_key(nwc.public_key);
         let request_event =
             EventBuilder::new(Kind::WalletConnectRequest, encrypted, [p_tag]).to_event(&secret)?;
@@ -1843,7 +3055,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                                 }
                             });
                             if has_e_tag && event.kind == Kind::WalletConnectResponse && event.verify().is_ok() {
-                                let decrypted = decrypt(&nwc.secret, &nwc.public_key, &event.content)?;
+                                let decrypted = encryption::decrypt(&nwc.secret, &nwc.public_key, &event.content)?;
                                 let resp: Response = serde_json::from_str(&decrypted)?;
 
                                 if resp.result_type == Method::PayInvoice {
@@ -1879,42 +3091,345 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 }
             }
         }
-
-        client.disconnect().await?;
-
-        Ok(None)
+
+        client.disconnect().await?;
+
+        Ok(None)
+    }
+
+    /// Decrypts a DM using the primary key, it may be NIP-04 or NIP-44 encrypted
+    pub async fn decrypt_dm(
+        &self,
+        pubkey: nostr::PublicKey,
+        message: &str,
+    ) -> Result<String, MutinyError> {
+        match &self.signer().await? {
+            NostrSigner::Keys(key) => {
+                let secret = key.secret_key().expect("must have");
+                encryption::decrypt(secret, &pubkey, message)
+            }
+            #[cfg(target_arch = "wasm32")]
+            NostrSigner::NIP07(nip07) => {
+                let decrypted = match EncryptionScheme::detect(message) {
+                    EncryptionScheme::Nip04 => nip07.nip04_decrypt(pubkey, message).await?,
+                    EncryptionScheme::Nip44V2 => nip07.nip44_decrypt(pubkey, message).await?,
+                };
+                Ok(decrypted)
+            }
+            NostrSigner::NIP46(nip46) => {
+                let decrypted = match EncryptionScheme::detect(message) {
+                    EncryptionScheme::Nip04 => nip46.nip04_decrypt(pubkey, message).await,
+                    EncryptionScheme::Nip44V2 => nip46.nip44_decrypt(pubkey, message).await,
+                };
+                decrypted.map_err(|_| MutinyError::NostrError)
+            }
+        }
     }
 
-    /// Decrypts a DM using the primary key
-    pub async fn decrypt_dm(
+    /// Encrypts a message with the given scheme using the primary key
+    async fn encrypt_dm(
         &self,
+        scheme: EncryptionScheme,
         pubkey: nostr::PublicKey,
-        message: &str,
+        content: String,
     ) -> Result<String, MutinyError> {
-        // todo we should handle NIP-44 as well
-        match &self.nostr_keys.read().await.signer {
+        match &self.signer().await? {
             NostrSigner::Keys(key) => {
                 let secret = key.secret_key().expect("must have");
-                let decrypted = decrypt(secret, &pubkey, message)?;
-                Ok(decrypted)
+                encryption::encrypt(scheme, secret, &pubkey, content)
             }
             #[cfg(target_arch = "wasm32")]
             NostrSigner::NIP07(nip07) => {
-                let decrypted = nip07.nip04_decrypt(pubkey, message).await?;
-                Ok(decrypted)
+                let encrypted = match scheme {
+                    EncryptionScheme::Nip04 => nip07.nip04_encrypt(pubkey, content).await?,
+                    EncryptionScheme::Nip44V2 => nip07.nip44_encrypt(pubkey, content).await?,
+                };
+                Ok(encrypted)
+            }
+            NostrSigner::NIP46(nip46) => {
+                let encrypted = match scheme {
+                    EncryptionScheme::Nip04 => nip46.nip04_encrypt(pubkey, content).await,
+                    EncryptionScheme::Nip44V2 => nip46.nip44_encrypt(pubkey, content).await,
+                };
+                encrypted.map_err(|_| MutinyError::NostrError)
             }
         }
     }
 
+    /// Sends a DM. Peers that support NIP-17 get a gift wrapped message,
+    /// everyone else gets a legacy NIP-04 message.
     pub async fn send_dm(
         &self,
         pubkey: nostr::PublicKey,
         message: String,
     ) -> Result<EventId, MutinyError> {
-        let event_id = self.client.send_direct_msg(pubkey, message, None).await?;
+        // they may not read from our relays, so it goes to theirs too
+        let inbox = self.get_inbox_relays(pubkey).await;
+
+        if self.supports_nip17(pubkey).await {
+            return self.send_private_dm(pubkey, message, inbox).await;
+        }
+
+        let encrypted = self
+            .encrypt_dm(EncryptionScheme::Nip04, pubkey, message)
+            .await?;
+        let builder = EventBuilder::new(
+            Kind::EncryptedDirectMessage,
+            encrypted,
+            [Tag::public_key(pubkey)],
+        );
+        let event = self.client.sign_event_builder(builder).await?;
+        let event_id = self.client.send_event(event.clone()).await?;
+        self.send_to_inbox(inbox, vec![event]).await;
+
+        Ok(event_id)
+    }
+
+    /// Sends a NIP-17 DM, a copy is also wrapped for us so it shows in our history
+    async fn send_private_dm(
+        &self,
+        receiver: nostr::PublicKey,
+        message: String,
+        inbox: Vec<String>,
+    ) -> Result<EventId, MutinyError> {
+        let sender = self.get_npub().await;
+        let rumor = EventBuilder::new(
+            Kind::from(PRIVATE_DM_KIND),
+            message,
+            [Tag::public_key(receiver)],
+        )
+        .to_unsigned_event(sender);
+
+        let gift_wrap = self.gift_wrap(receiver, &rumor).await?;
+        let own_copy = self.gift_wrap(sender, &rumor).await?;
+
+        let event_id = self.client.send_event(gift_wrap.clone()).await?;
+        self.send_to_inbox(inbox, vec![gift_wrap]).await;
+        if let Err(e) = self.client.send_event(own_copy).await {
+            log_warn!(self.logger, "Failed to send copy of dm to ourselves: {e}");
+        }
+
         Ok(event_id)
     }
 
+    /// Seals a rumor with our key, then wraps it with a one-time key for the receiver
+    async fn gift_wrap(
+        &self,
+        receiver: nostr::PublicKey,
+        rumor: &UnsignedEvent,
+    ) -> Result<Event, MutinyError> {
+        let sealed = self
+            .encrypt_dm(EncryptionScheme::Nip44V2, receiver, rumor.as_json())
+            .await?;
+        let seal = self
+            .client
+            .sign_event_builder(
+                EventBuilder::new(Kind::Seal, sealed, []).custom_created_at(backdated_timestamp()),
+            )
+            .await?;
+
+        let ephemeral = Keys::generate();
+        let wrapped = encryption::encrypt(
+            EncryptionScheme::Nip44V2,
+            ephemeral.secret_key().expect("just generated"),
+            &receiver,
+            seal.as_json(),
+        )?;
+
+        EventBuilder::new(Kind::GiftWrap, wrapped, [Tag::public_key(receiver)])
+            .custom_created_at(backdated_timestamp())
+            .to_event(&ephemeral)
+            .map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to create event: {e:?}")))
+    }
+
+    /// Opens a NIP-17 gift wrap sent to us, verifying the seal was signed by the author
+    pub(crate) async fn unwrap_gift_wrap(
+        &self,
+        event: &Event,
+    ) -> Result<PrivateDirectMessage, MutinyError> {
+        let seal = Event::from_json(self.decrypt_dm(event.pubkey, &event.content).await?)
+            .map_err(|_| MutinyError::NostrError)?;
+        if seal.kind != Kind::Seal || seal.verify().is_err() {
+            return Err(MutinyError::NostrError);
+        }
+
+        let rumor = UnsignedEvent::from_json(self.decrypt_dm(seal.pubkey, &seal.content).await?)
+            .map_err(|_| MutinyError::NostrError)?;
+        // the seal's signer must be the author, otherwise anyone could impersonate them
+        if rumor.pubkey != seal.pubkey || rumor.kind != Kind::from(PRIVATE_DM_KIND) {
+            return Err(MutinyError::NostrError);
+        }
+
+        let to = rumor
+            .tags
+            .iter()
+            .find_map(|tag| match tag {
+                Tag::PublicKey { public_key, .. } => Some(*public_key),
+                _ => None,
+            })
+            .ok_or(MutinyError::NostrError)?;
+
+        Ok(PrivateDirectMessage {
+            id: rumor.id,
+            from: rumor.pubkey,
+            to,
+            message: rumor.content,
+            created_at: rumor.created_at.as_u64(),
+        })
+    }
+
+    /// Gets the NIP-17 DMs between us and the given npub, newest first.
+    ///
+    /// Unwrapping gift wraps is expensive so the rumors are cached and only
+    /// gift wraps newer than the last sync are fetched, a page at a time.
+    pub(crate) async fn get_private_dm_conversation(
+        &self,
+        npub: nostr::PublicKey,
+        limit: u64,
+        until: Option<u64>,
+        since: Option<u64>,
+    ) -> Result<Vec<PrivateDirectMessage>, MutinyError> {
+        let cache = self.sync_private_dms().await?;
+
+        let self_key = self.get_npub().await;
+        let mut messages: Vec<PrivateDirectMessage> = cache
+            .messages
+            .into_iter()
+            .filter(|dm| {
+                (dm.from == npub && dm.to == self_key) || (dm.from == self_key && dm.to == npub)
+            })
+            .filter(|dm| until.map_or(true, |until| dm.created_at <= until))
+            .filter(|dm| since.map_or(true, |since| dm.created_at >= since))
+            .collect();
+
+        messages.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        messages.truncate(limit as usize);
+
+        Ok(messages)
+    }
+
+    /// Fetches the gift wraps we haven't seen yet and adds their rumors to the cache
+    async fn sync_private_dms(&self) -> Result<PrivateDmCache, MutinyError> {
+        let mut cache: PrivateDmCache = self.storage.get_data(NIP17_DMS_KEY)?.unwrap_or_default();
+
+        let self_key = self.get_npub().await;
+        let now = utils::now().as_secs();
+        // gift wraps are backdated, so look further back than our last sync
+        let since = cache
+            .last_sync
+            .map(|t| Timestamp::from(t.saturating_sub(GIFT_WRAP_MAX_BACKDATE_SECS)));
+
+        let mut until: Option<Timestamp> = None;
+        loop {
+            let mut filter = Filter::new()
+                .kind(Kind::GiftWrap)
+                .pubkey(self_key)
+                .limit(PRIVATE_DM_PAGE_SIZE);
+            if let Some(since) = since {
+                filter = filter.since(since);
+            }
+            if let Some(until) = until {
+                filter = filter.until(until);
+            }
+
+            let events = self
+                .client
+                .get_events_of(vec![filter], Some(Duration::from_secs(5)))
+                .await?;
+
+            let page_len = events.len();
+            let mut new_events = 0;
+            for event in events {
+                until = Some(until.map_or(event.created_at, |u| u.min(event.created_at)));
+                if cache
+                    .seen_wraps
+                    .insert(event.id, event.created_at.as_u64())
+                    .is_some()
+                {
+                    continue;
+                }
+                new_events += 1;
+
+                if event.verify().is_err() {
+                    continue;
+                }
+
+                // skip anything we can't open, just a bad dm
+                let Ok(dm) = self.unwrap_gift_wrap(&event).await else {
+                    continue;
+                };
+
+                // we wrap our own messages to ourselves too, so a rumor can show up twice
+                if !cache.messages.iter().any(|m| m.id == dm.id) {
+                    cache.messages.push(dm);
+                }
+            }
+
+            // stop once a page comes back short or only has events we've already seen
+            if page_len < PRIVATE_DM_PAGE_SIZE || new_events == 0 {
+                break;
+            }
+        }
+
+        // the next sync won't look past this, so older wraps don't need remembering
+        let next_since = now.saturating_sub(GIFT_WRAP_MAX_BACKDATE_SECS);
+        cache
+            .seen_wraps
+            .retain(|_, created_at| *created_at >= next_since);
+        cache
+            .messages
+            .sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        cache.messages.truncate(MAX_CACHED_PRIVATE_DMS);
+        cache.last_sync = Some(now);
+        self.storage
+            .set_data(NIP17_DMS_KEY.to_string(), &cache, None)?;
+
+        Ok(cache)
+    }
+
+    /// Whether a peer can read NIP-17 DMs, either because they have sent us one
+    /// or because they publish a DM relay list
+    async fn supports_nip17(&self, pubkey: nostr::PublicKey) -> bool {
+        let peers: HashSet<nostr::PublicKey> = self
+            .storage
+            .get_data(NIP17_PEERS_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        if peers.contains(&pubkey) {
+            return true;
+        }
+
+        let filter = Filter::new()
+            .kind(Kind::from(DM_RELAY_LIST_KIND))
+            .author(pubkey)
+            .limit(1);
+        match self
+            .client
+            .get_events_of(vec![filter], Some(Duration::from_secs(5)))
+            .await
+        {
+            Ok(events) if !events.is_empty() => {
+                if let Err(e) = self.add_nip17_peer(pubkey) {
+                    log_warn!(self.logger, "Failed to save NIP-17 peer: {e}");
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn add_nip17_peer(&self, pubkey: nostr::PublicKey) -> Result<(), MutinyError> {
+        let mut peers: HashSet<nostr::PublicKey> =
+            self.storage.get_data(NIP17_PEERS_KEY)?.unwrap_or_default();
+        if peers.insert(pubkey) {
+            self.storage
+                .set_data(NIP17_PEERS_KEY.to_string(), peers, None)?;
+        }
+
+        Ok(())
+    }
+
     /// Creates a recommendation event for a federation
     pub(crate) async fn create_recommend_federation_event(
         &self,
@@ -2063,6 +3578,32 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             }
         };
 
+        // recommendations are published to our contacts' write relays
+        let mut outbox = self
+            .connect_outbox_pool(npubs.keys().copied().collect())
+            .await;
+
+        // the people our contacts follow are the next step out in our follow graph
+        let follows_of_follows = self.get_follows_of_follows(&npubs, &outbox).await;
+
+        // they publish to their own relays, too many to connect to all of them,
+        // so add the ones most of them write to
+        let follows_of_follows_lists = get_relay_lists(
+            &self.storage,
+            &self.client,
+            follows_of_follows.iter().copied().collect(),
+            &self.logger,
+        )
+        .await;
+        match follows_of_follows_lists {
+            Ok(lists) => {
+                let relays =
+                    select_shared_outbox_relays(lists.values(), MAX_FOLLOWS_OF_FOLLOWS_RELAYS);
+                outbox.add_relays(&self.client, relays, &self.logger).await;
+            }
+            Err(e) => log_warn!(self.logger, "Failed to get follows of follows relays: {e}"),
+        }
+
         let network_str = network_to_string(network);
 
         // filter for finding mint announcements
@@ -2077,6 +3618,11 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             .kind(Kind::from(38000))
             .custom_tag(SingleLetterTag::lowercase(Alphabet::K), ["38173"])
             .authors(primal_trusted_users.keys().copied());
+        // filter for finding federation recommendations from people our contacts follow
+        let follows_of_follows_recommendations = Filter::new()
+            .kind(Kind::from(38000))
+            .custom_tag(SingleLetterTag::lowercase(Alphabet::K), ["38173"])
+            .authors(follows_of_follows.iter().copied());
         // filter for finding federation recommendations from random people
         let recommendations = Filter::new()
             .kind(Kind::from(38000))
@@ -2084,18 +3630,26 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             .limit(NUM_TRUSTED_USERS as usize);
 
         // fetch events
-        let events = self
-            .client
+        let events = outbox
             .get_events_of(
+                &self.client,
                 vec![
                     mints,
                     contacts_recommendations,
                     trusted_recommendations,
+                    follows_of_follows_recommendations,
                     recommendations,
                 ],
                 Some(Duration::from_secs(5)),
             )
-            .await?;
+            .await;
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                outbox.disconnect(&self.logger).await;
+                return Err(e.into());
+            }
+        };
 
         let mut mints: HashMap<FederationId, NostrDiscoveredFedimint> = HashMap::new();
 
@@ -2155,6 +3709,9 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                     metadata,
                     recommendations: vec![], // we'll add these in the next step
                     expire_timestamp: None,
+                    guardians: None,
+                    reachable: None,
+                    score: FederationScore::default(),
                 };
 
                 match mints.get_mut(&federation_id) {
@@ -2169,6 +3726,9 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             }
         }
 
+        // everyone that recommended a federation, so we can look up how much to trust them
+        let mut recommender_npubs: HashSet<nostr::PublicKey> = HashSet::new();
+
         // add on contact recommendations to mints
         for event in events {
             // only process federation recommendations
@@ -2178,10 +3738,15 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             }
 
             // try to get the contact from our npubs, otherwise use the primal trusted users
+            // or the people our contacts follow
             let contact = match npubs.get(&event.pubkey) {
                 Some(contact) => contact.clone(),
                 None => match primal_trusted_users.get(&event.pubkey).cloned() {
                     Some(contact) => contact,
+                    None if follows_of_follows.contains(&event.pubkey) => Contact {
+                        npub: Some(event.pubkey),
+                        ..Default::default()
+                    },
                     None => {
                         // if we don't have the contact, skip
                         // this could be a spam account that we shouldn't trust
@@ -2227,6 +3792,8 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                 .filter_map(|tag| parse_invite_code_from_tag(tag, &federation_id))
                 .collect::<Vec<_>>();
 
+            recommender_npubs.insert(event.pubkey);
+
             // todo read `a` tag recommendations as well
 
             match mints.iter_mut().find(|(_, m)| m.id == federation_id) {
@@ -2247,6 +3814,9 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
                             metadata: None,
                             recommendations: vec![contact],
                             expire_timestamp: None,
+                            guardians: None,
+                            reachable: None,
+                            score: FederationScore::default(),
                         };
                         mints.insert(federation_id, mint);
                     }
@@ -2286,29 +3856,232 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             mint.recommendations.dedup_by(|a, b| a.npub == b.npub);
         }
 
-        // sort mints by most recommended then by oldest
+        // score mints by who recommends them and their config
+        let recommenders = self
+            .get_recommender_info(recommender_npubs, &npubs, &follows_of_follows, &outbox)
+            .await;
+        outbox.disconnect(&self.logger).await;
+
+        // check which federations are reachable and how many guardians they have
+        self.check_federation_configs(&mut mints, &recommenders)
+            .await;
+
+        let now = utils::now().as_secs();
+        for mint in mints.values_mut() {
+            let scores = mint
+                .recommendations
+                .iter()
+                .filter_map(|c| c.npub)
+                .filter_map(|npub| {
+                    let info = recommenders.get(&npub)?;
+                    Some(RecommenderScore::new(npub, *info, now))
+                })
+                .collect();
+            mint.score = FederationScore::new(
+                scores,
+                mint.guardians,
+                mint.reachable,
+                mint.expire_timestamp,
+                now,
+            );
+        }
+
+        // sort mints by highest score, then most recommended, then oldest
         let mut mints: Vec<NostrDiscoveredFedimint> = mints.into_values().collect();
         mints.sort();
 
-        // try to get federation info from client config if not in event
-        // todo this currently retries which takes forever with bad mints, need to wait for next release of fedimint
-        // let futures = mints
-        //     .iter_mut()
-        //     .map(|mint| mint.try_fetch_metadata())
-        //     .collect::<Vec<_>>();
-        // join_all(futures).await;
-
-        // remove mints that expire within the 30 days and ones we couldn't fetch metadata for
-        // let days_30_from_now = utils::now() + Duration::from_secs(86_400 * 30);
-        // mints.retain(|m| {
-        //     m.metadata.is_some()
-        //         && (m.expire_timestamp.is_none()
-        //             || m.expire_timestamp.unwrap() > days_30_from_now.as_secs())
-        // });
-
         Ok(mints)
     }
 
+    /// Checks the configs of the federations recommended by people we score,
+    /// reusing checks from the last [`FEDERATION_CHECK_TTL_SECS`]
+    async fn check_federation_configs(
+        &self,
+        mints: &mut HashMap<FederationId, NostrDiscoveredFedimint>,
+        recommenders: &HashMap<nostr::PublicKey, RecommenderInfo>,
+    ) {
+        let now = utils::now().as_secs();
+        let mut checks: HashMap<FederationId, FederationConfigCheck> =
+            match self.storage.get_data(DISCOVERED_FEDERATION_CHECKS_KEY) {
+                Ok(checks) => checks.unwrap_or_default(),
+                Err(e) => {
+                    log_warn!(self.logger, "Failed to get federation checks: {e}");
+                    HashMap::new()
+                }
+            };
+        checks.retain(|_, check| check.checked_at + FEDERATION_CHECK_TTL_SECS > now);
+
+        let to_check = mints
+            .values_mut()
+            .filter(|mint| {
+                mint.recommendations
+                    .iter()
+                    .filter_map(|c| c.npub)
+                    .any(|npub| recommenders.contains_key(&npub))
+            })
+            .filter_map(|mint| match checks.get(&mint.id) {
+                Some(check) => {
+                    mint.apply_config_check(check);
+                    None
+                }
+                None => Some(mint),
+            })
+            .collect::<Vec<_>>();
+
+        let checked = futures::stream::iter(to_check)
+            .map(|mint| async move {
+                mint.check_config().await;
+                mint.config_check(now).map(|check| (mint.id, check))
+            })
+            .buffer_unordered(MAX_CONCURRENT_DISCOVERY_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+        checks.extend(checked.into_iter().flatten());
+
+        if let Err(e) =
+            self.storage
+                .set_data(DISCOVERED_FEDERATION_CHECKS_KEY.to_string(), checks, None)
+        {
+            log_warn!(self.logger, "Failed to save federation checks: {e}");
+        }
+    }
+
+    /// Gets the people our contacts follow that we don't, from our contacts' contact lists
+    async fn get_follows_of_follows(
+        &self,
+        contacts: &HashMap<nostr::PublicKey, Contact>,
+        outbox: &OutboxPool<C>,
+    ) -> HashSet<nostr::PublicKey> {
+        if contacts.is_empty() {
+            return HashSet::new();
+        }
+
+        let filter = Filter::new()
+            .kind(Kind::ContactList)
+            .authors(contacts.keys().copied());
+        let events = match outbox
+            .get_events_of(&self.client, vec![filter], Some(Duration::from_secs(5)))
+            .await
+        {
+            Ok(events) => events,
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get contacts' contact lists: {e}");
+                return HashSet::new();
+            }
+        };
+
+        let npub = self.get_npub().await;
+        events
+            .iter()
+            .filter(|e| e.kind == Kind::ContactList && contacts.contains_key(&e.pubkey))
+            .flat_map(|e| {
+                e.iter_tags().filter_map(|tag| match tag {
+                    Tag::PublicKey {
+                        public_key,
+                        uppercase: false,
+                        ..
+                    } => Some(*public_key),
+                    _ => None,
+                })
+            })
+            .filter(|pk| *pk != npub && !contacts.contains_key(pk))
+            .take(MAX_FOLLOWS_OF_FOLLOWS)
+            .collect()
+    }
+
+    /// Looks up how far away in our follow graph, how old, and how active
+    /// the people that recommended federations are
+    async fn get_recommender_info(
+        &self,
+        recommenders: HashSet<nostr::PublicKey>,
+        contacts: &HashMap<nostr::PublicKey, Contact>,
+        follows_of_follows: &HashSet<nostr::PublicKey>,
+        outbox: &OutboxPool<C>,
+    ) -> HashMap<nostr::PublicKey, RecommenderInfo> {
+        let now = utils::now().as_secs();
+        let recent_since = now.saturating_sub(RECENT_ACTIVITY_SECS);
+        let npubs: Vec<nostr::PublicKey> = recommenders.iter().copied().collect();
+
+        // recent notes show they are active, any event from before
+        // the full age window shows their account is old enough.
+        // each recommender gets their own filters so busy accounts can't crowd out the rest
+        let until = Timestamp::from(now.saturating_sub(FULL_AGE_DAYS * 86_400));
+        let queries = npubs.chunks(RECOMMENDERS_PER_QUERY).map(|chunk| {
+            let filters = chunk
+                .iter()
+                .flat_map(|npub| {
+                    let recent = Filter::new()
+                        .kind(Kind::TextNote)
+                        .author(*npub)
+                        .since(Timestamp::from(recent_since))
+                        .limit(FULL_ACTIVITY_NOTES as usize);
+                    // nothing can be older than nostr itself, those are backdated
+                    let old = Filter::new()
+                        .kinds([Kind::TextNote, Kind::Repost, Kind::Reaction])
+                        .author(*npub)
+                        .since(Timestamp::from(NOSTR_EPOCH_SECS))
+                        .until(until)
+                        .limit(1);
+                    [recent, old]
+                })
+                .collect();
+            outbox.get_events_of(&self.client, filters, Some(Duration::from_secs(5)))
+        });
+        let results = futures::stream::iter(queries)
+            .buffer_unordered(MAX_CONCURRENT_DISCOVERY_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+
+        // oldest event we've seen from each recommender, to tell how old their account is.
+        // the recommendations themselves don't count, anyone can backdate a recommendation
+        let mut first_seen: HashMap<nostr::PublicKey, u64> = HashMap::new();
+        let mut recent_notes: HashMap<nostr::PublicKey, u32> = HashMap::new();
+        for result in results {
+            let events = match result {
+                Ok(events) => events,
+                Err(e) => {
+                    log_warn!(self.logger, "Failed to get recommender activity: {e}");
+                    continue;
+                }
+            };
+            for event in events {
+                if !recommenders.contains(&event.pubkey) {
+                    continue;
+                }
+                let created_at = event.created_at.as_u64();
+                if !(NOSTR_EPOCH_SECS..=now).contains(&created_at) {
+                    continue;
+                }
+                first_seen
+                    .entry(event.pubkey)
+                    .and_modify(|seen| *seen = (*seen).min(created_at))
+                    .or_insert(created_at);
+                if event.kind == Kind::TextNote && created_at >= recent_since {
+                    *recent_notes.entry(event.pubkey).or_default() += 1;
+                }
+            }
+        }
+
+        recommenders
+            .into_iter()
+            .map(|npub| {
+                let distance = if contacts.contains_key(&npub) {
+                    Some(1)
+                } else if follows_of_follows.contains(&npub) {
+                    Some(2)
+                } else {
+                    None
+                };
+                let info = RecommenderInfo {
+                    distance,
+                    first_seen: first_seen.get(&npub).copied(),
+                    recent_notes: recent_notes.get(&npub).copied().unwrap_or_default(),
+                };
+                (npub, info)
+            })
+            .collect()
+    }
+
     /// Creates a new NostrManager
     pub async fn from_mnemonic(
         xprivkey: ExtendedPrivKey,
@@ -2321,7 +4094,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
     ) -> Result<Self, MutinyError> {
         let context = Secp256k1::new();
 
-        let nostr_keys = NostrKeys::from_key_source(key_source, xprivkey)?;
+        let nostr_keys = NostrKeys::from_key_source(key_source, xprivkey, &storage).await?;
 
         // get from storage
         let profiles: Vec<Profile> = storage.get_data(NWC_STORAGE_KEY)?.unwrap_or_default();
@@ -2332,7 +4105,7 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             .map(|profile| NostrWalletConnect::new(&context, xprivkey, profile).unwrap())
             .collect();
 
-        client.set_signer(Some(nostr_keys.signer.clone())).await;
+        client.set_signer(nostr_keys.signer.clone()).await;
 
         Ok(Self {
             xprivkey,
@@ -2341,10 +4114,14 @@ impl<S: MutinyStorage, P: PrimalApi, C: NostrClient> NostrManager<S, P, C> {
             storage,
             pending_nwc_lock: Arc::new(Mutex::new(())),
             follow_lock: Arc::new(Mutex::new(())),
+            signer_lock: Arc::new(Mutex::new(())),
             primal_client: primal_api,
             logger,
             stop,
             client,
+            relay_health: RelayHealthTracker::default(),
+            external_wallet_clients: Arc::new(RwLock::new(HashMap::new())),
+            refreshing_external_balances: Arc::new(AtomicBool::new(false)),
         })
     }
 }
@@ -2461,15 +4238,24 @@ fn is_federation_recommendation_event(event: &Event) -> bool {
         })
 }
 
+/// A random time in the last two days, used for gift wraps and seals
+fn backdated_timestamp() -> Timestamp {
+    let offset = bitcoin::secp256k1::rand::random::<u64>() % GIFT_WRAP_MAX_BACKDATE_SECS;
+    Timestamp::from(Timestamp::now().as_u64().saturating_sub(offset))
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::event::{MillisatAmount, PaymentInfo};
     use crate::nostr::client::MockNostrClient;
+    use crate::nostr::nip65::RelayListEntry;
     use crate::nostr::primal::{MockPrimalApi, TrustedUser};
+    use crate::storage::persist_payment_info;
     use crate::storage::MemoryStorage;
     use crate::utils::now;
-    use crate::MockInvoiceHandler;
+    use crate::{MockInvoiceHandler, PrivacyLevel};
     use bip39::Mnemonic;
     use bitcoin::bip32::ExtendedPrivKey;
     use bitcoin::Network;
@@ -2478,9 +4264,11 @@ mod test {
     use lightning::ln::PaymentSecret;
     use lightning_invoice::{Bolt11Invoice, Currency, InvoiceBuilder};
     use mockall::predicate::eq;
+    use nostr::nips::nip57::{anonymous_zap_request, ZapRequestData};
     use nostr::prelude::rand;
     use nostr::prelude::rand::prelude::SliceRandom;
     use nostr::SubscriptionId;
+    use nostr_sdk::RelayStatus;
     use std::str::FromStr;
 
     const EXPIRED_INVOICE: &str = "lnbc923720n1pj9nr6zpp5xmvlq2u5253htn52mflh2e6gn7pk5ht0d4qyhc62fadytccxw7hqhp5l4s6qwh57a7cwr7zrcz706qx0qy4eykcpr8m8dwz08hqf362egfscqzzsxqzfvsp5pr7yjvcn4ggrf6fq090zey0yvf8nqvdh2kq7fue0s0gnm69evy6s9qyyssqjyq0fwjr22eeg08xvmz88307yqu8tqqdjpycmermks822fpqyxgshj8hvnl9mkh6srclnxx0uf4ugfq43d66ak3rrz4dqcqd23vxwpsqf7dmhm";
@@ -2525,13 +4313,13 @@ mod test {
             .return_const(MutinyLogger::default());
         inv_handler.expect_skip_hodl_invoices().return_const(true);
 
-        #[allow(irrefutable_let_patterns)] // need this because enum with single variant
-        let nostr_keys =
-            if let NostrSigner::Keys(ref keys) = nostr_manager.nostr_keys.read().await.signer {
-                keys.clone()
-            } else {
-                panic!("unexpected keys")
-            };
+        let nostr_keys = nostr_manager
+            .nostr_keys
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .expect("unexpected keys");
         let user = Keys::generate();
 
         // make sure non-invoice is not added
@@ -2734,6 +4522,10 @@ mod test {
             commands: None,
             tag: Default::default(),
             label: None,
+            notifications: false,
+            spending_rules: Default::default(),
+            relays: vec![],
+            auth_relays: vec![],
         };
         let mut profiles = nostr_manager.nwc.write().unwrap();
         let nwc = NostrWalletConnect::new(
@@ -2852,31 +4644,154 @@ mod test {
             )
             .unwrap();
 
-        assert_eq!(profile.name, name);
-        assert_eq!(profile.index, 1000);
+        assert_eq!(profile.name, name);
+        assert_eq!(profile.index, 1000);
+        assert_eq!(profile.relay.as_str(), DEFAULT_RELAY);
+
+        profile.relay = "wss://relay.damus.io".to_string();
+
+        nostr_manager.edit_nwc_profile(profile).unwrap();
+
+        let profiles = nostr_manager.profiles();
+        assert_eq!(profiles.len(), 1);
+        // check this stuff is the same
+        assert_eq!(profiles[0].name, name);
+        assert_eq!(profiles[0].index, 1000);
+        // check this is different
+        assert_eq!(profiles[0].relay.as_str(), "wss://relay.damus.io");
+
+        let profiles: Vec<Profile> = nostr_manager
+            .storage
+            .get_data(NWC_STORAGE_KEY)
+            .unwrap()
+            .unwrap_or_default();
+
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, name);
+        assert_eq!(profiles[0].index, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_nwc_profile_relays() {
+        let mut nostr_manager = create_nostr_manager().await;
+        nostr_manager
+            .client
+            .expect_add_relay()
+            .times(2)
+            .returning(|_| Ok(true));
+        nostr_manager
+            .client
+            .expect_connect_relay()
+            .times(2)
+            .returning(|_| Ok(()));
+
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::default(),
+                Default::default(),
+                vec![Method::PayInvoice],
+            )
+            .unwrap();
+
+        // the primary relay is down, so the info event goes to the backup
+        nostr_manager
+            .relay_health
+            .update(&format!("{DEFAULT_RELAY}/"), RelayStatus::Disconnected);
+        nostr_manager
+            .client
+            .expect_send_event_to()
+            .once()
+            .withf(|relays, _| relays == &vec!["wss://relay.damus.io".to_string()])
+            .returning(|_, e| Ok(e.id));
+
+        let backup = "wss://relay.damus.io".to_string();
+        let auth = "wss://auth.example.com".to_string();
+        let profile = nostr_manager
+            .set_nwc_profile_relays(profile.index, vec![backup.clone()], vec![auth.clone()])
+            .await
+            .unwrap();
         assert_eq!(profile.relay.as_str(), DEFAULT_RELAY);
+        assert_eq!(profile.relays, vec![backup.clone()]);
+        assert_eq!(profile.auth_relays, vec![auth.clone()]);
+
+        // the uri lists every relay
+        let uri = profile.nwc_uri.unwrap();
+        assert!(uri.contains("relay=wss%3A%2F%2Frelay.damus.io"));
+        assert!(uri.contains("relay=wss%3A%2F%2Fauth.example.com"));
+        assert!(NostrWalletConnectURI::from_str(&uri).is_ok());
+
+        let relays = nostr_manager.get_relays().await;
+        assert!(relays.contains(&backup));
+        assert!(relays.contains(&auth));
+
+        // only the auth relay gets an auth event
+        let auth_url = Url::parse(&auth).unwrap();
+        let events = nostr_manager.create_nwc_auth_events(&auth_url, "challenge");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, Kind::Authentication);
+        let backup_url = Url::parse(&backup).unwrap();
+        assert!(nostr_manager
+            .create_nwc_auth_events(&backup_url, "challenge")
+            .is_empty());
+
+        // invalid relays are rejected
+        assert!(nostr_manager
+            .set_nwc_profile_relays(
+                profile.index,
+                vec!["https://example.com".to_string()],
+                vec![]
+            )
+            .await
+            .is_err());
+    }
 
-        profile.relay = "wss://relay.damus.io".to_string();
-
-        nostr_manager.edit_nwc_profile(profile).unwrap();
+    #[tokio::test]
+    async fn test_send_nwc_notifications() {
+        let mut nostr_manager = create_nostr_manager().await;
 
-        let profiles = nostr_manager.profiles();
-        assert_eq!(profiles.len(), 1);
-        // check this stuff is the same
-        assert_eq!(profiles[0].name, name);
-        assert_eq!(profiles[0].index, 1000);
-        // check this is different
-        assert_eq!(profiles[0].relay.as_str(), "wss://relay.damus.io");
+        let mut names = vec![];
+        for name in ["first", "second"] {
+            let profile = nostr_manager
+                .create_new_nwc_profile_internal(
+                    ProfileType::Normal {
+                        name: name.to_string(),
+                    },
+                    SpendingConditions::default(),
+                    Default::default(),
+                    vec![Method::PayInvoice],
+                )
+                .unwrap();
+            names.push(profile.name);
+        }
 
-        let profiles: Vec<Profile> = nostr_manager
-            .storage
-            .get_data(NWC_STORAGE_KEY)
-            .unwrap()
-            .unwrap_or_default();
+        // the first profile's relays fail, the second still gets its notification
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        nostr_manager
+            .client
+            .expect_send_event_to()
+            .times(2)
+            .returning(move |_, e| {
+                if calls_clone.fetch_add(1, Ordering::Relaxed) == 0 {
+                    Err(nostr_sdk::client::Error::SignerNotConfigured)
+                } else {
+                    Ok(e.id)
+                }
+            });
 
-        assert_eq!(profiles.len(), 1);
-        assert_eq!(profiles[0].name, name);
-        assert_eq!(profiles[0].index, 1000);
+        let invoice = MutinyInvoice {
+            status: HTLCStatus::Succeeded,
+            labels: names,
+            ..Default::default()
+        };
+        nostr_manager
+            .send_nwc_notifications(&invoice)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
     }
 
     #[tokio::test]
@@ -2933,6 +4848,7 @@ mod test {
 			event_id: EventId::from_slice(&[0; 32]).unwrap(),
 			pubkey: nostr::PublicKey::from_str("552a9d06810f306bfc085cb1e1c26102554138a51fa3a7fdf98f5b03a945143a").unwrap(),
 			identifier: None,
+			encryption: EncryptionScheme::Nip04,
 		};
 
         // add dummy to storage
@@ -2962,6 +4878,9 @@ mod test {
             metadata: None,
             recommendations: vec![Contact::default(), Contact::default()],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let most_recommendations = NostrDiscoveredFedimint {
@@ -2973,6 +4892,9 @@ mod test {
             metadata: None,
             recommendations: vec![Contact::default(), Contact::default()],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let one_recommendation = NostrDiscoveredFedimint {
@@ -2984,6 +4906,9 @@ mod test {
             metadata: None,
             recommendations: vec![Contact::default()],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let one_recommendation_no_time = NostrDiscoveredFedimint {
@@ -2995,6 +4920,9 @@ mod test {
             metadata: None,
             recommendations: vec![Contact::default()],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let no_recommendations = NostrDiscoveredFedimint {
@@ -3006,6 +4934,9 @@ mod test {
             metadata: None,
             recommendations: vec![],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let no_recommendations_or_time = NostrDiscoveredFedimint {
@@ -3017,6 +4948,9 @@ mod test {
             metadata: None,
             recommendations: vec![],
             expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
         };
 
         let mut vec = vec![
@@ -3041,6 +4975,13 @@ mod test {
         ];
 
         assert_eq!(vec, expected);
+
+        // a higher score beats more recommendations
+        let mut high_score = expected[3].clone();
+        high_score.score.total = 500;
+        let mut vec = vec![expected[0].clone(), high_score.clone()];
+        vec.sort();
+        assert_eq!(vec[0], high_score);
     }
 
     #[tokio::test]
@@ -3088,6 +5029,248 @@ mod test {
         assert!(is_federation_recommendation_event(&event));
     }
 
+    #[tokio::test]
+    async fn test_relay_list() {
+        let mut nostr_manager = create_nostr_manager().await;
+        let keys = nostr_manager
+            .nostr_keys
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .expect("expected derived keys");
+
+        // defaults to our default relays
+        let list = nostr_manager.get_relay_list().await.unwrap();
+        assert_eq!(list, RelayList::from_relays(&RELAYS));
+
+        let outbox = "wss://outbox.example.com".to_string();
+        let list = RelayList {
+            relays: vec![
+                RelayListEntry {
+                    url: "wss://relay.damus.io".to_string(),
+                    read: true,
+                    write: true,
+                },
+                RelayListEntry {
+                    url: outbox.clone(),
+                    read: false,
+                    write: true,
+                },
+            ],
+        };
+
+        nostr_manager
+            .client
+            .expect_add_relay()
+            .times(2)
+            .returning(|_| Ok(true));
+        nostr_manager
+            .client
+            .expect_connect_relay()
+            .times(2)
+            .returning(|_| Ok(()));
+        let signing_keys = keys.clone();
+        nostr_manager
+            .client
+            .expect_sign_event_builder()
+            .once()
+            .returning(move |b| Ok(b.to_event(&signing_keys).unwrap()));
+        nostr_manager
+            .client
+            .expect_send_event()
+            .once()
+            .returning(|e| Ok(e.id));
+
+        nostr_manager.set_relay_list(list.clone()).await.unwrap();
+        assert_eq!(nostr_manager.get_relay_list().await.unwrap(), list);
+        assert!(nostr_manager.get_relays().await.contains(&outbox));
+
+        // invalid relay lists are rejected
+        let invalid = RelayList::from_relays(&["https://relay.damus.io"]);
+        assert!(nostr_manager.set_relay_list(invalid).await.is_err());
+
+        // a stored list of other nostr keys isn't ours
+        let other = EventBuilder::new(Kind::RelayList, "", list.tags())
+            .to_event(&Keys::generate())
+            .unwrap();
+        nostr_manager
+            .storage
+            .set_data(NOSTR_RELAY_LIST_KEY.to_string(), other, None)
+            .unwrap();
+        assert_eq!(
+            nostr_manager.get_relay_list().await.unwrap(),
+            RelayList::from_relays(&RELAYS)
+        );
+        let signed = EventBuilder::new(Kind::RelayList, "", list.tags())
+            .to_event(&keys)
+            .unwrap();
+        nostr_manager
+            .storage
+            .set_data(NOSTR_RELAY_LIST_KEY.to_string(), signed, None)
+            .unwrap();
+
+        // older relay lists from relays are ignored
+        let older = EventBuilder::new(
+            Kind::RelayList,
+            "",
+            RelayList::from_relays(&["wss://old.example.com"]).tags(),
+        )
+        .custom_created_at(Timestamp::from(0))
+        .to_event(&keys)
+        .unwrap();
+        nostr_manager
+            .client
+            .expect_get_events_of()
+            .once()
+            .returning(move |_, _| Ok(vec![older.clone()]));
+        assert_eq!(nostr_manager.sync_relay_list().await.unwrap(), list);
+    }
+
+    #[tokio::test]
+    async fn test_private_dm_sync() {
+        let mut nostr_manager = create_nostr_manager().await;
+        let npub = Keys::generate().public_key();
+
+        // first sync has nothing to go on, so fetches everything a page at a time
+        nostr_manager
+            .client
+            .expect_get_events_of()
+            .once()
+            .withf(|filters, _| {
+                filters[0].since.is_none() && filters[0].limit == Some(PRIVATE_DM_PAGE_SIZE)
+            })
+            .returning(|_, _| Ok(vec![]));
+        let dms = nostr_manager
+            .get_private_dm_conversation(npub, 10, None, None)
+            .await
+            .unwrap();
+        assert!(dms.is_empty());
+
+        let cache: PrivateDmCache = nostr_manager
+            .storage
+            .get_data(NIP17_DMS_KEY)
+            .unwrap()
+            .unwrap();
+        let last_sync = cache.last_sync.unwrap();
+
+        // later syncs only look back as far as a gift wrap could be backdated
+        nostr_manager.client.checkpoint();
+        nostr_manager
+            .client
+            .expect_get_events_of()
+            .once()
+            .withf(move |filters, _| {
+                filters[0].since == Some(Timestamp::from(last_sync - GIFT_WRAP_MAX_BACKDATE_SECS))
+            })
+            .returning(|_, _| Ok(vec![]));
+        nostr_manager
+            .get_private_dm_conversation(npub, 10, None, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_zap_receipts_checks_provider() {
+        let mut nostr_manager = create_nostr_manager().await;
+        let npub = nostr_manager.get_npub().await;
+        let provider = Keys::generate();
+        let imposter = Keys::generate();
+
+        // a zap to us for an invoice of ours that was paid
+        let request = anonymous_zap_request(ZapRequestData {
+            public_key: npub,
+            relays: vec![],
+            message: String::new(),
+            amount: Some(21_000),
+            lnurl: None,
+            event_id: None,
+            event_coordinate: None,
+        })
+        .unwrap();
+        let secp = Secp256k1::new();
+        let sk = bitcoin::secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let bolt11 = InvoiceBuilder::new(Currency::Regtest)
+            .description_hash(sha256::Hash::hash(request.as_json().as_bytes()))
+            .duration_since_epoch(now())
+            .payment_hash(sha256::Hash::hash(&[1; 32]))
+            .payment_secret(PaymentSecret([0; 32]))
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(21_000)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &sk))
+            .unwrap();
+        let mut info = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(21_000)),
+            fee_paid_msat: None,
+            payee_pubkey: None,
+            bolt11: Some(bolt11.clone()),
+            privacy_level: PrivacyLevel::default(),
+            last_update: now().as_secs(),
+        };
+        persist_payment_info(
+            &nostr_manager.storage,
+            &bolt11.payment_hash().into_32(),
+            &info,
+            true,
+        )
+        .unwrap();
+
+        let forged = EventBuilder::zap_receipt(bolt11.to_string(), None, &request)
+            .to_event(&imposter)
+            .unwrap();
+        let receipt = EventBuilder::zap_receipt(bolt11.to_string(), None, &request)
+            .custom_created_at(Timestamp::from(now().as_secs() - 60))
+            .to_event(&provider)
+            .unwrap();
+
+        // without our provider there is nothing to check receipts against
+        nostr_manager.sync_zap_receipts(None).await.unwrap();
+        assert!(nostr_manager.get_zaps().unwrap().is_empty());
+
+        let events = vec![forged, receipt.clone()];
+        nostr_manager
+            .client
+            .expect_get_events_of()
+            .times(2)
+            .returning(move |_, _| Ok(events.clone()));
+
+        // the receipt arrived before we saw the payment, so we look at it again next time
+        nostr_manager
+            .sync_zap_receipts(Some(provider.public_key()))
+            .await
+            .unwrap();
+        assert!(nostr_manager.get_zaps().unwrap().is_empty());
+        let synced_to: u64 = nostr_manager
+            .storage
+            .get_data(LAST_ZAP_RECEIPT_SYNC_TIME_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(synced_to, receipt.created_at.as_u64());
+
+        // once it is paid, receipts that weren't signed by our provider are ignored
+        info.status = HTLCStatus::Succeeded;
+        persist_payment_info(
+            &nostr_manager.storage,
+            &bolt11.payment_hash().into_32(),
+            &info,
+            true,
+        )
+        .unwrap();
+        nostr_manager
+            .sync_zap_receipts(Some(provider.public_key()))
+            .await
+            .unwrap();
+
+        let zaps = nostr_manager.get_zaps().unwrap();
+        assert_eq!(zaps.len(), 1);
+        assert!(zaps[0].inbound);
+        assert_eq!(zaps[0].provider, Some(provider.public_key()));
+        assert_eq!(zaps[0].receipt_id, Some(receipt.id));
+    }
+
     #[tokio::test]
     async fn test_discover_federations() {
         let npub = nostr::PublicKey::from_hex(
@@ -3166,6 +5349,183 @@ mod test {
         assert!(!ben.name.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_check_federation_configs() {
+        let nostr_manager = create_nostr_manager().await;
+        let now = now().as_secs();
+
+        let scored = Keys::generate().public_key();
+        let unscored = Keys::generate().public_key();
+        let recommenders = HashMap::from([(scored, RecommenderInfo::default())]);
+
+        let invite_code = InviteCode::from_str(INVITE_CODE).unwrap();
+        let mint = |id: FederationId, npub: nostr::PublicKey| NostrDiscoveredFedimint {
+            invite_codes: vec![invite_code.clone()],
+            id,
+            pubkey: None,
+            event_id: None,
+            created_at: None,
+            metadata: None,
+            recommendations: vec![Contact {
+                npub: Some(npub),
+                ..Default::default()
+            }],
+            expire_timestamp: None,
+            guardians: None,
+            reachable: None,
+            score: FederationScore::default(),
+        };
+        let recommended = invite_code.federation_id();
+        let ignored = FederationId::from_str(
+            "1111111111111111111111111111111111111111111111111111111111111111",
+        )
+        .unwrap();
+        let stale = FederationId::from_str(
+            "2222222222222222222222222222222222222222222222222222222222222222",
+        )
+        .unwrap();
+        let mut mints = HashMap::from([
+            (recommended, mint(recommended, scored)),
+            (ignored, mint(ignored, unscored)),
+        ]);
+
+        let check = FederationConfigCheck {
+            reachable: true,
+            guardians: Some(4),
+            expire_timestamp: Some(now + 86_400),
+            metadata: Some(Metadata::new().name("Mutinynet")),
+            checked_at: now,
+        };
+        let stale_check = FederationConfigCheck {
+            checked_at: now - FEDERATION_CHECK_TTL_SECS,
+            ..check.clone()
+        };
+        let checks = HashMap::from([(recommended, check.clone()), (stale, stale_check)]);
+        nostr_manager
+            .storage
+            .set_data(DISCOVERED_FEDERATION_CHECKS_KEY.to_string(), checks, None)
+            .unwrap();
+
+        nostr_manager
+            .check_federation_configs(&mut mints, &recommenders)
+            .await;
+
+        // the fresh check is reused instead of downloading the config
+        let mint = mints.get(&recommended).unwrap();
+        assert_eq!(mint.reachable, Some(true));
+        assert_eq!(mint.guardians, Some(4));
+        assert_eq!(mint.expire_timestamp, check.expire_timestamp);
+        assert_eq!(mint.metadata, check.metadata);
+
+        // federations only recommended by people we don't score aren't checked
+        assert_eq!(mints.get(&ignored).unwrap().reachable, None);
+
+        // stale checks are dropped
+        let checks: HashMap<FederationId, FederationConfigCheck> = nostr_manager
+            .storage
+            .get_data(DISCOVERED_FEDERATION_CHECKS_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(checks, HashMap::from([(recommended, check)]));
+    }
+
+    #[tokio::test]
+    async fn test_external_wallets() {
+        let mut nostr_manager = create_nostr_manager().await;
+
+        let wallet_keys = Keys::generate();
+        let info = EventBuilder::new(Kind::WalletConnectInfo, "pay_invoice get_balance", [])
+            .to_event(&wallet_keys)
+            .unwrap();
+        // the wallet gets its own client so its relay stays out of our pool
+        let mut wallet_client = MockNostrClient::new();
+        wallet_client.expect_add_relay().returning(|_| Ok(true));
+        wallet_client.expect_connect_relay().returning(|_| Ok(()));
+        wallet_client
+            .expect_get_events_of()
+            .once()
+            .return_once(move |_, _| Ok(vec![info]));
+        wallet_client
+            .expect_disconnect()
+            .once()
+            .returning(|| Ok(()));
+        nostr_manager
+            .client
+            .expect_new_pool()
+            .once()
+            .return_once(move || wallet_client);
+
+        let uri = NostrWalletConnectURI::new(
+            wallet_keys.public_key(),
+            Url::from_str("wss://relay.example.com").unwrap(),
+            Keys::generate().secret_key().unwrap().clone(),
+            None,
+        );
+        let wallet = nostr_manager
+            .add_external_wallet("home node".to_string(), uri.clone())
+            .await
+            .unwrap();
+        assert_eq!(wallet.methods, vec![Method::PayInvoice, Method::GetBalance]);
+        assert!(!wallet.prefer_for_invoices);
+        assert_eq!(nostr_manager.list_external_wallets().unwrap(), vec![wallet]);
+
+        // can't add the same wallet twice
+        assert!(nostr_manager
+            .add_external_wallet("again".to_string(), uri)
+            .await
+            .is_err());
+
+        let wallet = nostr_manager
+            .set_external_wallet_prefer_for_invoices(wallet_keys.public_key(), true)
+            .unwrap();
+        assert!(wallet.prefer_for_invoices);
+        assert_eq!(nostr_manager.list_external_wallets().unwrap(), vec![wallet]);
+
+        // a new wallet means the cached balances need fetching again
+        let balance = ExternalWalletBalance {
+            name: "home node".to_string(),
+            wallet_pubkey: wallet_keys.public_key(),
+            balance: 1_000,
+        };
+        assert_eq!(
+            nostr_manager
+                .cached_external_wallet_balances()
+                .unwrap()
+                .last_updated,
+            0
+        );
+        nostr_manager
+            .storage
+            .set_data(
+                EXTERNAL_WALLET_BALANCES_KEY.to_string(),
+                CachedExternalWalletBalances {
+                    balances: vec![balance.clone()],
+                    last_updated: now().as_secs(),
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            nostr_manager
+                .cached_external_wallet_balances()
+                .unwrap()
+                .balances,
+            vec![balance]
+        );
+
+        nostr_manager
+            .remove_external_wallet(wallet_keys.public_key())
+            .await
+            .unwrap();
+        assert!(nostr_manager.list_external_wallets().unwrap().is_empty());
+        // removed wallets don't count towards our balance
+        assert!(nostr_manager
+            .cached_external_wallet_balances()
+            .unwrap()
+            .balances
+            .is_empty());
+    }
+
     #[tokio::test]
     async fn test_create_recommendation_event() {
         let mut nostr_manager = create_nostr_manager().await;
@@ -3290,6 +5650,76 @@ mod test {
         assert_eq!(list.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_bunker_key_source() {
+        let xprivkey = ExtendedPrivKey::new_master(Network::Bitcoin, &[0; 32]).unwrap();
+        let storage = MemoryStorage::new(None, None, None);
+        let remote = Keys::generate().public_key();
+        let user = Keys::generate().public_key();
+        // nothing is listening here, so this only works if we don't connect right away
+        let uri = NostrConnectURI::new(
+            remote,
+            Url::from_str("wss://relay.example.com").unwrap(),
+            "Mutiny",
+        );
+
+        // we connected to this remote signer before and it told us the user's key
+        let bunker_user = BunkerUser {
+            signer: remote,
+            user,
+        };
+        storage
+            .set_data(NOSTR_BUNKER_USER_KEY.to_string(), bunker_user, None)
+            .unwrap();
+
+        let keys =
+            NostrKeys::from_key_source(NostrKeySource::Bunker(uri.clone()), xprivkey, &storage)
+                .await
+                .unwrap();
+        assert_eq!(keys.public_key, user);
+        assert!(keys.signer.is_none());
+        assert!(keys.keys().is_none());
+
+        // the app key comes from our seed so the remote signer recognizes us after a restart
+        let again =
+            NostrKeys::from_key_source(NostrKeySource::Bunker(uri.clone()), xprivkey, &storage)
+                .await
+                .unwrap();
+        assert_eq!(
+            keys.bunker.unwrap().app_keys.public_key(),
+            again.bunker.unwrap().app_keys.public_key()
+        );
+
+        // the client doesn't get a signer until we connect
+        let mut client = MockNostrClient::new();
+        client
+            .expect_set_signer()
+            .withf(|signer| signer.is_none())
+            .once()
+            .return_const(());
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Bunker(uri),
+            storage.clone(),
+            MockPrimalApi::new(),
+            client,
+            Arc::new(MutinyLogger::default()),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(nostr_manager.get_npub().await, user);
+        assert!(nostr_manager.export_nsec().await.is_none());
+
+        // derived keys can sign right away
+        let keys = NostrKeys::from_key_source(NostrKeySource::Derived, xprivkey, &storage)
+            .await
+            .unwrap();
+        assert!(keys.signer.is_some());
+        assert!(keys.bunker.is_none());
+        assert_eq!(keys.keys().unwrap().public_key(), keys.public_key);
+    }
+
     #[tokio::test]
     async fn test_change_nostr_keys() {
         let mut nostr_manager = create_nostr_manager().await;
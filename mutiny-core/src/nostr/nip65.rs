@@ -0,0 +1,645 @@
+use crate::error::MutinyError;
+use crate::logging::MutinyLogger;
+use crate::nostr::client::NostrClient;
+use crate::storage::{MutinyStorage, RELAY_CACHE_PREFIX_KEY};
+use crate::utils;
+use futures::join;
+use lightning::log_warn;
+use lightning::util::logger::Logger;
+use nostr::{
+    Alphabet, Event, EventId, Filter, Kind, PublicKey, SingleLetterTag, Tag, TagKind, Url,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Max relays of each user we connect to, so a long relay list
+/// can't make us connect to every relay out there
+pub(crate) const MAX_OUTBOX_RELAYS: usize = 3;
+
+/// How long we use a cached relay list before checking for a newer one
+pub(crate) const RELAY_LIST_TTL_SECS: u64 = 24 * 60 * 60;
+
+pub(crate) fn relay_list_key(npub: &PublicKey) -> String {
+    format!("{RELAY_CACHE_PREFIX_KEY}relay_list/{}", npub.to_hex())
+}
+
+/// A relay in a NIP-65 relay list
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RelayListEntry {
+    pub url: String,
+    /// The user reads events that mention them from this relay
+    pub read: bool,
+    /// The user publishes their events to this relay
+    pub write: bool,
+}
+
+/// A user's NIP-65 relay list, kind 10002
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayList {
+    pub relays: Vec<RelayListEntry>,
+}
+
+impl RelayList {
+    /// Parses the `r` tags of a kind 10002 event.
+    /// A relay without a marker is used for both reading and writing.
+    pub fn from_event(event: &Event) -> Result<Self, MutinyError> {
+        if event.kind != Kind::RelayList {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let mut relays: Vec<RelayListEntry> = Vec::new();
+        for tag in event.iter_tags() {
+            let values = tag.as_vec();
+            if values.first().map(String::as_str) != Some("r") {
+                continue;
+            }
+            let Some(url) = values.get(1).filter(|url| is_relay_url(url)) else {
+                continue;
+            };
+            let (read, write) = match values.get(2).map(String::as_str) {
+                Some("read") => (true, false),
+                Some("write") => (false, true),
+                _ => (true, true),
+            };
+
+            match relays.iter_mut().find(|r| r.url == *url) {
+                Some(entry) => {
+                    entry.read |= read;
+                    entry.write |= write;
+                }
+                None => relays.push(RelayListEntry {
+                    url: url.clone(),
+                    read,
+                    write,
+                }),
+            }
+        }
+
+        Ok(Self { relays })
+    }
+
+    /// Relay list that uses the given relays for reading and writing
+    pub fn from_relays(relays: &[&str]) -> Self {
+        Self {
+            relays: relays
+                .iter()
+                .map(|url| RelayListEntry {
+                    url: url.to_string(),
+                    read: true,
+                    write: true,
+                })
+                .collect(),
+        }
+    }
+
+    /// Makes sure every relay is a websocket url and is used for something
+    pub fn validate(&self) -> Result<(), MutinyError> {
+        let valid = self
+            .relays
+            .iter()
+            .all(|r| is_relay_url(&r.url) && (r.read || r.write));
+        if self.relays.is_empty() || !valid {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    pub fn tags(&self) -> Vec<Tag> {
+        self.relays
+            .iter()
+            .map(|r| {
+                let mut values = vec![r.url.clone()];
+                match (r.read, r.write) {
+                    (true, false) => values.push("read".to_string()),
+                    (false, true) => values.push("write".to_string()),
+                    _ => {}
+                }
+                Tag::Generic(
+                    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::R)),
+                    values,
+                )
+            })
+            .collect()
+    }
+
+    /// Relays the user reads from, where events for them should be sent
+    pub fn read_relays(&self) -> Vec<String> {
+        self.relays
+            .iter()
+            .filter(|r| r.read)
+            .map(|r| r.url.clone())
+            .collect()
+    }
+
+    /// Relays the user writes to, where their events should be fetched from
+    pub fn write_relays(&self) -> Vec<String> {
+        self.relays
+            .iter()
+            .filter(|r| r.write)
+            .map(|r| r.url.clone())
+            .collect()
+    }
+}
+
+fn is_relay_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.scheme() == "wss" || url.scheme() == "ws")
+}
+
+/// A user's relay list and when we last checked for a newer one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CachedRelayList {
+    /// `None` if the user had no relay list when we checked
+    pub event: Option<Event>,
+    /// Time we last asked relays for a newer list, in seconds since epoch
+    pub checked_at: u64,
+}
+
+/// Gets the relay lists we have cached for the given users
+pub(crate) fn get_cached_relay_lists<S: MutinyStorage>(
+    storage: &S,
+    npubs: &[PublicKey],
+) -> Result<HashMap<PublicKey, CachedRelayList>, MutinyError> {
+    let mut lists = HashMap::with_capacity(npubs.len());
+    for npub in npubs {
+        if let Some(list) = storage.get_data::<CachedRelayList>(relay_list_key(npub))? {
+            lists.insert(*npub, list);
+        }
+    }
+
+    Ok(lists)
+}
+
+/// Caches relay list events, keeping the newest one for each user.
+/// The lists of the `checked` users are marked as checked at `now`,
+/// and users without any list are remembered as having none.
+pub(crate) fn cache_relay_lists<S: MutinyStorage>(
+    storage: &S,
+    events: Vec<Event>,
+    checked: &[PublicKey],
+    now: u64,
+) -> Result<HashMap<PublicKey, Event>, MutinyError> {
+    let mut newest: HashMap<PublicKey, Event> = HashMap::new();
+    for event in events {
+        if event.kind != Kind::RelayList
+            || !checked.contains(&event.pubkey)
+            || event.verify().is_err()
+        {
+            continue;
+        }
+        if newest
+            .get(&event.pubkey)
+            .map_or(true, |current| event.created_at > current.created_at)
+        {
+            newest.insert(event.pubkey, event);
+        }
+    }
+
+    let mut lists = get_cached_relay_lists(storage, checked)?;
+    for npub in checked {
+        let event = newest.remove(npub);
+        match lists.get_mut(npub) {
+            Some(current) => {
+                let newer = event.as_ref().is_some_and(|event| {
+                    current
+                        .event
+                        .as_ref()
+                        .map_or(true, |c| event.created_at > c.created_at)
+                });
+                if newer {
+                    current.event = event;
+                }
+                current.checked_at = now;
+            }
+            // remember users without a relay list too, so we don't keep asking
+            None => {
+                lists.insert(
+                    *npub,
+                    CachedRelayList {
+                        event,
+                        checked_at: now,
+                    },
+                );
+            }
+        }
+    }
+
+    let items = lists
+        .iter()
+        .map(|(npub, list)| (relay_list_key(npub), list))
+        .collect::<Vec<_>>();
+    if !items.is_empty() {
+        storage.set(items)?;
+    }
+
+    Ok(lists
+        .into_iter()
+        .filter_map(|(npub, list)| list.event.map(|event| (npub, event)))
+        .collect())
+}
+
+/// Gets the relay lists of the given users. Lists we don't have cached,
+/// or haven't checked in [`RELAY_LIST_TTL_SECS`], are fetched from our relays,
+/// only asking for lists newer than the ones we have.
+/// If the relays can't be reached we use the cached lists.
+pub(crate) async fn get_relay_lists<S: MutinyStorage, C: NostrClient>(
+    storage: &S,
+    client: &C,
+    npubs: Vec<PublicKey>,
+    logger: &MutinyLogger,
+) -> Result<HashMap<PublicKey, Event>, MutinyError> {
+    let cached = get_cached_relay_lists(storage, &npubs)?;
+    let now = utils::now().as_secs();
+
+    let stale = |npub: &PublicKey| {
+        cached
+            .get(npub)
+            .map_or(true, |list| list.checked_at + RELAY_LIST_TTL_SECS <= now)
+    };
+    let (stale_lists, missing): (Vec<PublicKey>, Vec<PublicKey>) = npubs
+        .iter()
+        .filter(|npub| stale(npub))
+        .copied()
+        .partition(|npub| cached.get(npub).is_some_and(|list| list.event.is_some()));
+
+    // users we have no list for get all of theirs, the others only newer ones
+    let mut filters = Vec::with_capacity(2);
+    if !missing.is_empty() {
+        filters.push(Filter::new().kind(Kind::RelayList).authors(missing));
+    }
+    let since = stale_lists
+        .iter()
+        .filter_map(|npub| cached.get(npub).and_then(|list| list.event.as_ref()))
+        .map(|event| event.created_at)
+        .min();
+    if let Some(since) = since {
+        filters.push(
+            Filter::new()
+                .kind(Kind::RelayList)
+                .authors(stale_lists)
+                .since(since),
+        );
+    }
+
+    let lists = cached
+        .into_iter()
+        .filter_map(|(npub, list)| list.event.map(|event| (npub, event)))
+        .collect();
+    if filters.is_empty() {
+        return Ok(lists);
+    }
+
+    match client
+        .get_events_of(filters, Some(Duration::from_secs(5)))
+        .await
+    {
+        Ok(events) => cache_relay_lists(storage, events, &npubs, now),
+        Err(e) => {
+            log_warn!(logger, "Failed to fetch relay lists, using cache: {e}");
+            Ok(lists)
+        }
+    }
+}
+
+/// Picks some of each user's outbox relays,
+/// their write relays or their read relays if `read` is set
+pub(crate) fn select_outbox_relays<'a>(
+    lists: impl IntoIterator<Item = &'a Event>,
+    read: bool,
+) -> Vec<String> {
+    let mut relays: Vec<String> = lists
+        .into_iter()
+        .filter_map(|event| RelayList::from_event(event).ok())
+        .flat_map(|list| {
+            let relays = if read {
+                list.read_relays()
+            } else {
+                list.write_relays()
+            };
+            relays.into_iter().take(MAX_OUTBOX_RELAYS)
+        })
+        .collect();
+    relays.sort();
+    relays.dedup();
+
+    relays
+}
+
+/// Picks the write relays shared by the most users, at most `max` of them,
+/// for when there are too many users to connect to all of their relays
+pub(crate) fn select_shared_outbox_relays<'a>(
+    lists: impl IntoIterator<Item = &'a Event>,
+    max: usize,
+) -> Vec<String> {
+    let mut users: HashMap<String, usize> = HashMap::new();
+    for list in lists
+        .into_iter()
+        .filter_map(|event| RelayList::from_event(event).ok())
+    {
+        let relays: HashSet<String> = list
+            .write_relays()
+            .into_iter()
+            .take(MAX_OUTBOX_RELAYS)
+            .collect();
+        for relay in relays {
+            *users.entry(relay).or_default() += 1;
+        }
+    }
+
+    let mut relays: Vec<(String, usize)> = users.into_iter().collect();
+    relays.sort_by(|(a, a_users), (b, b_users)| b_users.cmp(a_users).then_with(|| a.cmp(b)));
+    relays
+        .into_iter()
+        .take(max)
+        .map(|(relay, _)| relay)
+        .collect()
+}
+
+/// A relay pool for other users' outbox relays.
+///
+/// It is kept apart from our own pool so those relays never get our
+/// subscriptions, and is disconnected once we're done with it.
+pub(crate) struct OutboxPool<C: NostrClient> {
+    pool: Option<C>,
+}
+
+impl<C: NostrClient> OutboxPool<C> {
+    /// Connects to the given relays in a new pool made from `client`.
+    /// Relays we can't connect to are skipped.
+    pub async fn connect(client: &C, relays: Vec<String>, logger: &MutinyLogger) -> Self {
+        let mut pool = Self { pool: None };
+        pool.add_relays(client, relays, logger).await;
+        pool
+    }
+
+    /// Connects to more relays in this pool.
+    /// Relays we can't connect to are skipped.
+    pub async fn add_relays(&mut self, client: &C, relays: Vec<String>, logger: &MutinyLogger) {
+        if relays.is_empty() {
+            return;
+        }
+
+        let pool = self.pool.get_or_insert_with(|| client.new_pool());
+        for relay in relays.iter() {
+            let res = match pool.add_relay(relay).await {
+                Ok(_) => pool.connect_relay(relay).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                log_warn!(logger, "Failed to connect to outbox relay {relay}: {e}");
+            }
+        }
+    }
+
+    /// Gets events from our relays and the outbox relays.
+    /// The outbox relays are best effort, only errors from our relays are returned.
+    pub async fn get_events_of(
+        &self,
+        client: &C,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>, nostr_sdk::client::Error> {
+        let Some(pool) = self.pool.as_ref() else {
+            return client.get_events_of(filters, timeout).await;
+        };
+
+        let (ours, theirs) = join!(
+            client.get_events_of(filters.clone(), timeout),
+            pool.get_events_of(filters, timeout)
+        );
+        let mut events = ours?;
+        let known: HashSet<EventId> = events.iter().map(|e| e.id).collect();
+        events.extend(
+            theirs
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|e| !known.contains(&e.id)),
+        );
+
+        Ok(events)
+    }
+
+    /// Sends an event to the outbox relays
+    pub async fn send_event(&self, event: Event) -> Result<(), nostr_sdk::client::Error> {
+        if let Some(pool) = self.pool.as_ref() {
+            pool.send_event(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Disconnects from the outbox relays
+    pub async fn disconnect(self, logger: &MutinyLogger) {
+        if let Some(pool) = self.pool {
+            if let Err(e) = pool.disconnect().await {
+                log_warn!(logger, "Failed to disconnect from outbox relays: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nostr::client::MockNostrClient;
+    use crate::storage::MemoryStorage;
+    use nostr::{EventBuilder, Keys, Timestamp};
+
+    fn relay_list_event(keys: &Keys, relay: &str, created_at: u64) -> Event {
+        EventBuilder::new(Kind::RelayList, "", RelayList::from_relays(&[relay]).tags())
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_relay_list() {
+        let keys = Keys::generate();
+        let list = RelayList {
+            relays: vec![
+                RelayListEntry {
+                    url: "wss://relay.damus.io".to_string(),
+                    read: true,
+                    write: true,
+                },
+                RelayListEntry {
+                    url: "wss://inbox.example.com".to_string(),
+                    read: true,
+                    write: false,
+                },
+                RelayListEntry {
+                    url: "wss://outbox.example.com".to_string(),
+                    read: false,
+                    write: true,
+                },
+            ],
+        };
+        assert!(list.validate().is_ok());
+
+        let event = EventBuilder::new(Kind::RelayList, "", list.tags())
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            event.tags[1].as_vec(),
+            vec!["r", "wss://inbox.example.com", "read"]
+        );
+
+        let parsed = RelayList::from_event(&event).unwrap();
+        assert_eq!(parsed, list);
+        assert_eq!(
+            parsed.read_relays(),
+            vec!["wss://relay.damus.io", "wss://inbox.example.com"]
+        );
+        assert_eq!(
+            parsed.write_relays(),
+            vec!["wss://relay.damus.io", "wss://outbox.example.com"]
+        );
+
+        // only websocket relays are allowed
+        let invalid = RelayList::from_relays(&["https://relay.damus.io"]);
+        assert!(invalid.validate().is_err());
+        assert!(RelayList::default().validate().is_err());
+
+        // other kinds are not relay lists
+        let event = EventBuilder::new(Kind::TextNote, "", list.tags())
+            .to_event(&keys)
+            .unwrap();
+        assert!(RelayList::from_event(&event).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_relay_lists() {
+        let storage = MemoryStorage::new(None, None, None);
+        let logger = MutinyLogger::default();
+        let keys = Keys::generate();
+        let npub = keys.public_key();
+
+        // lists we don't have are fetched
+        let old = relay_list_event(&keys, "wss://old.example.com", 1_000);
+        let mut client = MockNostrClient::new();
+        let events = vec![old.clone()];
+        client
+            .expect_get_events_of()
+            .once()
+            .withf(|filters, _| filters.len() == 1 && filters[0].since.is_none())
+            .returning(move |_, _| Ok(events.clone()));
+        let lists = get_relay_lists(&storage, &client, vec![npub], &logger)
+            .await
+            .unwrap();
+        assert_eq!(lists.get(&npub), Some(&old));
+
+        // recently checked lists come from the cache
+        let client = MockNostrClient::new();
+        let lists = get_relay_lists(&storage, &client, vec![npub], &logger)
+            .await
+            .unwrap();
+        assert_eq!(lists.get(&npub), Some(&old));
+
+        // once the cached list is stale, only newer lists are asked for
+        let mut cached = get_cached_relay_lists(&storage, &[npub])
+            .unwrap()
+            .remove(&npub)
+            .unwrap();
+        cached.checked_at -= RELAY_LIST_TTL_SECS;
+        storage
+            .set_data(relay_list_key(&npub), cached, None)
+            .unwrap();
+
+        let new = relay_list_event(&keys, "wss://new.example.com", 2_000);
+        let mut client = MockNostrClient::new();
+        let events = vec![new.clone()];
+        client
+            .expect_get_events_of()
+            .once()
+            .withf(move |filters, _| {
+                filters.len() == 1 && filters[0].since == Some(Timestamp::from(1_000))
+            })
+            .returning(move |_, _| Ok(events.clone()));
+        let lists = get_relay_lists(&storage, &client, vec![npub], &logger)
+            .await
+            .unwrap();
+        assert_eq!(lists.get(&npub), Some(&new));
+        let cached = get_cached_relay_lists(&storage, &[npub]).unwrap();
+        assert_eq!(cached.get(&npub).unwrap().event, Some(new));
+
+        // users without a relay list are remembered, so they aren't asked for again
+        let other = Keys::generate().public_key();
+        let mut client = MockNostrClient::new();
+        client
+            .expect_get_events_of()
+            .once()
+            .returning(|_, _| Ok(vec![]));
+        let lists = get_relay_lists(&storage, &client, vec![other], &logger)
+            .await
+            .unwrap();
+        assert!(lists.is_empty());
+        let client = MockNostrClient::new();
+        let lists = get_relay_lists(&storage, &client, vec![other], &logger)
+            .await
+            .unwrap();
+        assert!(lists.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_outbox_pool() {
+        let logger = MutinyLogger::default();
+        let keys = Keys::generate();
+        let ours = relay_list_event(&keys, "wss://ours.example.com", 1_000);
+        let theirs = relay_list_event(&keys, "wss://theirs.example.com", 2_000);
+
+        // no relays, no pool
+        let client = MockNostrClient::new();
+        let pool = OutboxPool::connect(&client, vec![], &logger).await;
+        assert!(pool.pool.is_none());
+
+        // outbox relays go in their own pool, not ours
+        let mut pool = MockNostrClient::new();
+        pool.expect_add_relay().once().returning(|_| Ok(true));
+        pool.expect_connect_relay().once().returning(|_| Ok(()));
+        let events = vec![ours.clone(), theirs.clone()];
+        pool.expect_get_events_of()
+            .once()
+            .returning(move |_, _| Ok(events.clone()));
+        pool.expect_disconnect().once().returning(|| Ok(()));
+
+        let mut client = MockNostrClient::new();
+        client.expect_new_pool().return_once(move || pool);
+        let events = vec![ours.clone()];
+        client
+            .expect_get_events_of()
+            .once()
+            .returning(move |_, _| Ok(events.clone()));
+
+        let outbox = OutboxPool::connect(
+            &client,
+            vec!["wss://outbox.example.com".to_string()],
+            &logger,
+        )
+        .await;
+        let events = outbox.get_events_of(&client, vec![], None).await.unwrap();
+        assert_eq!(events, vec![ours, theirs]);
+        outbox.disconnect(&logger).await;
+    }
+
+    #[test]
+    fn test_select_shared_outbox_relays() {
+        let list = |relays: &[&str]| {
+            EventBuilder::new(Kind::RelayList, "", RelayList::from_relays(relays).tags())
+                .to_event(&Keys::generate())
+                .unwrap()
+        };
+        let lists = vec![
+            list(&["wss://c.example.com", "wss://a.example.com"]),
+            list(&["wss://a.example.com", "wss://b.example.com"]),
+            list(&["wss://b.example.com", "wss://a.example.com"]),
+        ];
+
+        // the relays most users share come first
+        assert_eq!(
+            select_shared_outbox_relays(&lists, 2),
+            vec!["wss://a.example.com", "wss://b.example.com"]
+        );
+        assert_eq!(select_shared_outbox_relays(&lists, 10).len(), 3);
+        assert!(select_shared_outbox_relays(&lists, 0).is_empty());
+    }
+}
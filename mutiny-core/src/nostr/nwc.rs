@@ -1,27 +1,28 @@
 use crate::error::MutinyError;
 use crate::event::HTLCStatus;
 use crate::nostr::client::NostrClient;
+use crate::nostr::encryption::{self, EncryptionScheme};
 use crate::nostr::nip49::NIP49Confirmation;
 use crate::nostr::primal::PrimalApi;
 use crate::nostr::{derive_nwc_keys, NostrManager};
 use crate::storage::MutinyStorage;
 use crate::utils;
-use crate::InvoiceHandler;
+use crate::{InvoiceHandler, MutinyInvoice};
 use anyhow::anyhow;
 use bitcoin::bip32::ExtendedPrivKey;
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::secp256k1::{Secp256k1, Signing, ThirtyTwoByteHash};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, Signing, ThirtyTwoByteHash};
 use bitcoin::Network;
-use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
 use core::fmt;
 use hex_conservative::DisplayHex;
 use itertools::Itertools;
 use lightning::util::logger::Logger;
 use lightning::{log_error, log_warn};
 use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
-use nostr::nips::nip04::{decrypt, encrypt};
 use nostr::nips::nip47::*;
-use nostr::{Event, EventBuilder, EventId, Filter, JsonUtil, Keys, Kind, Tag, Timestamp};
+use nostr::{Event, EventBuilder, EventId, Filter, JsonUtil, Keys, Kind, Tag, TagKind, Timestamp};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::str::FromStr;
@@ -29,6 +30,48 @@ use url::Url;
 
 pub(crate) const PENDING_NWC_EVENTS_KEY: &str = "pending_nwc_events";
 
+/// Tag used to negotiate the encryption scheme of NWC requests and responses
+const NWC_ENCRYPTION_TAG: &str = "encryption";
+
+/// Event kind for NIP-47 notifications
+pub(crate) const NWC_NOTIFICATION_KIND: Kind = Kind::Custom(23_196);
+
+/// Tag used to advertise the notification types we send
+const NWC_NOTIFICATIONS_TAG: &str = "notifications";
+
+/// Types of NIP-47 notifications we send to connected apps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationType {
+    /// A payment was received
+    PaymentReceived,
+    /// A payment we sent succeeded
+    PaymentSent,
+}
+
+impl NotificationType {
+    pub const SUPPORTED: [NotificationType; 2] = [
+        NotificationType::PaymentReceived,
+        NotificationType::PaymentSent,
+    ];
+}
+
+impl fmt::Display for NotificationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotificationType::PaymentReceived => write!(f, "payment_received"),
+            NotificationType::PaymentSent => write!(f, "payment_sent"),
+        }
+    }
+}
+
+/// Content of a NIP-47 notification event, before encryption
+#[derive(Debug, Clone, Serialize)]
+struct Notification {
+    notification_type: NotificationType,
+    notification: LookupInvoiceResponseResult,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SingleUseSpendingConditions {
     pub payment_hash: Option<String>,
@@ -60,6 +103,34 @@ pub enum BudgetPeriod {
     Seconds(u64),
 }
 
+impl BudgetPeriod {
+    /// The start of the period that `now` falls in
+    fn start(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            BudgetPeriod::Day => now.date_naive().and_hms_opt(0, 0, 0).unwrap(),
+            BudgetPeriod::Week => (now
+                - Duration::days((now.weekday().num_days_from_sunday()) as i64))
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+            BudgetPeriod::Month => now
+                .date_naive()
+                .with_day(1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            BudgetPeriod::Year => NaiveDateTime::new(
+                now.date_naive().with_ordinal(1).unwrap(),
+                chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+            ),
+            BudgetPeriod::Seconds(secs) => now
+                .checked_sub_signed(Duration::seconds(*secs as i64))
+                .unwrap()
+                .naive_utc(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BudgetedSpendingConditions {
     /// Amount in sats for the allotted budget period
@@ -74,44 +145,31 @@ pub struct BudgetedSpendingConditions {
 
 impl BudgetedSpendingConditions {
     pub fn add_payment(&mut self, invoice: &Bolt11Invoice) {
+        self.add_tracked_payment(
+            invoice.amount_milli_satoshis().unwrap_or_default() / 1_000,
+            invoice.payment_hash().into_32().to_lower_hex_string(),
+        );
+    }
+
+    /// Tracks a payment that was not made from an invoice, such as a keysend
+    pub fn add_tracked_payment(&mut self, amt: u64, hash: String) {
         let time = utils::now().as_secs();
-        let payment = TrackedPayment {
-            time,
-            amt: invoice.amount_milli_satoshis().unwrap_or_default() / 1_000,
-            hash: invoice.payment_hash().into_32().to_lower_hex_string(),
-        };
+        let payment = TrackedPayment { time, amt, hash };
 
         self.payments.push(payment);
     }
 
     pub fn remove_payment(&mut self, invoice: &Bolt11Invoice) {
         let hex = invoice.payment_hash().into_32().to_lower_hex_string();
-        self.payments.retain(|p| p.hash != hex);
+        self.remove_tracked_payment(&hex);
+    }
+
+    pub fn remove_tracked_payment(&mut self, hash: &str) {
+        self.payments.retain(|p| p.hash != hash);
     }
 
     fn clean_old_payments(&mut self, now: DateTime<Utc>) {
-        let period_start = match self.period {
-            BudgetPeriod::Day => now.date_naive().and_hms_opt(0, 0, 0).unwrap(),
-            BudgetPeriod::Week => (now
-                - Duration::days((now.weekday().num_days_from_sunday()) as i64))
-            .date_naive()
-            .and_hms_opt(0, 0, 0)
-            .unwrap(),
-            BudgetPeriod::Month => now
-                .date_naive()
-                .with_day(1)
-                .unwrap()
-                .and_hms_opt(0, 0, 0)
-                .unwrap(),
-            BudgetPeriod::Year => NaiveDateTime::new(
-                now.date_naive().with_ordinal(1).unwrap(),
-                chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-            ),
-            BudgetPeriod::Seconds(secs) => now
-                .checked_sub_signed(Duration::seconds(secs as i64))
-                .unwrap()
-                .naive_utc(),
-        };
+        let period_start = self.period.start(now);
 
         self.payments
             .retain(|p| p.time > period_start.timestamp() as u64)
@@ -143,6 +201,213 @@ impl Default for SpendingConditions {
     }
 }
 
+/// A payee that can be allowed or denied by [`SpendingRules`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Payee {
+    /// A lightning node, by its public key.
+    ///
+    /// NWC requests only give us an invoice, which can't prove which LNURL
+    /// domain issued it, so payees are only matched by node.
+    Node(PublicKey),
+}
+
+/// Which payees a profile can pay
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PayeeFilter {
+    /// Only these payees can be paid
+    Allow(Vec<Payee>),
+    /// These payees can never be paid
+    Deny(Vec<Payee>),
+}
+
+/// Limits how many payments a profile can make in a period
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Maximum number of payments in the period
+    pub max_payments: u32,
+    pub period: BudgetPeriod,
+    /// Time in seconds since epoch of each payment made
+    #[serde(default)]
+    pub payments: Vec<u64>,
+}
+
+/// Hours of the day, in UTC, that a profile can make payments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AllowedHours {
+    /// First hour payments are allowed, 0-23
+    pub start: u8,
+    /// Hour payments stop being allowed, exclusive.
+    /// If this is before `start` the window wraps past midnight,
+    /// if it is equal to `start` every hour is allowed.
+    pub end: u8,
+}
+
+impl AllowedHours {
+    fn contains(&self, hour: u8) -> bool {
+        match self.start.cmp(&self.end) {
+            Ordering::Less => hour >= self.start && hour < self.end,
+            Ordering::Greater => hour >= self.start || hour < self.end,
+            Ordering::Equal => true,
+        }
+    }
+}
+
+/// Rules checked before every payment a profile makes,
+/// on top of its [`SpendingConditions`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SpendingRules {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payees: Option<PayeeFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    /// Time in seconds since epoch after which the profile can no longer pay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_hours: Option<AllowedHours>,
+    /// Payments above this amount in sats need approval,
+    /// smaller ones are paid automatically within any budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval_threshold: Option<u64>,
+}
+
+/// The spending rule that blocked a payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendingRule {
+    Expiry,
+    AllowedHours,
+    PayeeAllowlist,
+    PayeeDenylist,
+    RateLimit,
+}
+
+impl fmt::Display for SpendingRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpendingRule::Expiry => write!(f, "expiry"),
+            SpendingRule::AllowedHours => write!(f, "allowed_hours"),
+            SpendingRule::PayeeAllowlist => write!(f, "payee_allowlist"),
+            SpendingRule::PayeeDenylist => write!(f, "payee_denylist"),
+            SpendingRule::RateLimit => write!(f, "rate_limit"),
+        }
+    }
+}
+
+impl SpendingRule {
+    /// The NIP-47 error to respond with, naming the rule that blocked the payment
+    fn error(&self) -> NIP47Error {
+        let (code, reason) = match self {
+            SpendingRule::Expiry => (ErrorCode::Restricted, "profile has expired"),
+            SpendingRule::AllowedHours => (
+                ErrorCode::Restricted,
+                "payments are not allowed at this time",
+            ),
+            SpendingRule::PayeeAllowlist => {
+                (ErrorCode::Restricted, "payee is not on the allowlist")
+            }
+            SpendingRule::PayeeDenylist => (ErrorCode::Restricted, "payee is on the denylist"),
+            SpendingRule::RateLimit => (ErrorCode::RateLimited, "too many payments"),
+        };
+
+        nip47_error(code, format!("Blocked by spending rule {self}: {reason}"))
+    }
+}
+
+/// Who a payment is going to, as far as we can tell
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PaymentPayee {
+    pub node: Option<PublicKey>,
+}
+
+impl PaymentPayee {
+    pub(crate) fn from_invoice(invoice: &Bolt11Invoice) -> Self {
+        Self {
+            node: Some(invoice.recover_payee_pub_key()),
+        }
+    }
+
+    fn matches(&self, payee: &Payee) -> bool {
+        match payee {
+            Payee::Node(pk) => self.node.as_ref() == Some(pk),
+        }
+    }
+}
+
+impl SpendingRules {
+    /// Checks the rules that apply to any payment request, regardless of who it pays
+    pub(crate) fn check_request(&self, now: DateTime<Utc>) -> Result<(), SpendingRule> {
+        if self
+            .expires_at
+            .is_some_and(|expires_at| now.timestamp() as u64 >= expires_at)
+        {
+            return Err(SpendingRule::Expiry);
+        }
+
+        if self
+            .allowed_hours
+            .is_some_and(|hours| !hours.contains(now.hour() as u8))
+        {
+            return Err(SpendingRule::AllowedHours);
+        }
+
+        Ok(())
+    }
+
+    /// Checks all the rules for paying `payee`
+    pub(crate) fn check_payment(
+        &mut self,
+        payee: &PaymentPayee,
+        now: DateTime<Utc>,
+    ) -> Result<(), SpendingRule> {
+        self.check_request(now)?;
+
+        match &self.payees {
+            Some(PayeeFilter::Allow(allowed)) if !allowed.iter().any(|p| payee.matches(p)) => {
+                return Err(SpendingRule::PayeeAllowlist);
+            }
+            Some(PayeeFilter::Deny(denied)) if denied.iter().any(|p| payee.matches(p)) => {
+                return Err(SpendingRule::PayeeDenylist);
+            }
+            _ => {}
+        }
+
+        if let Some(rate_limit) = self.rate_limit.as_mut() {
+            let period_start = rate_limit.period.start(now).timestamp() as u64;
+            rate_limit.payments.retain(|time| *time > period_start);
+            if rate_limit.payments.len() >= rate_limit.max_payments as usize {
+                return Err(SpendingRule::RateLimit);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts a payment that was made towards the rate limit.
+    ///
+    /// Returns true if the rules changed and need to be saved.
+    pub(crate) fn record_payment(&mut self, now: DateTime<Utc>) -> bool {
+        match self.rate_limit.as_mut() {
+            Some(rate_limit) => {
+                rate_limit.payments.push(now.timestamp() as u64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// If a payment of `sats` must be approved by the user
+    pub fn needs_approval(&self, sats: u64) -> bool {
+        self.approval_threshold
+            .is_some_and(|threshold| sats > threshold)
+    }
+
+    /// If a payment of `sats` is small enough to pay without approval
+    pub fn auto_pays(&self, sats: u64) -> bool {
+        self.approval_threshold
+            .is_some_and(|threshold| sats <= threshold)
+    }
+}
+
 /// Type of Nostr Wallet Connect profile
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NwcProfileTag {
@@ -190,6 +455,18 @@ pub(crate) struct Profile {
     pub tag: NwcProfileTag,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub label: Option<String>,
+    /// Send NIP-47 notifications to this profile
+    #[serde(default)]
+    pub notifications: bool,
+    /// Rules checked before every payment, on top of the spending conditions
+    #[serde(default)]
+    pub spending_rules: SpendingRules,
+    /// Extra relays used alongside `relay`, so the profile keeps working if one is down
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<String>,
+    /// Relays that require NIP-42 authentication, we authenticate with the profile's key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_relays: Vec<String>,
 }
 
 impl Profile {
@@ -202,6 +479,17 @@ impl Profile {
         }
     }
 
+    /// All the relays this profile uses, starting with its primary relay
+    pub fn relays(&self) -> Vec<String> {
+        let mut relays = vec![self.relay.clone()];
+        for relay in self.relays.iter().chain(self.auth_relays.iter()) {
+            if !relays.contains(relay) {
+                relays.push(relay.clone());
+            }
+        }
+        relays
+    }
+
     /// Returns the available commands for this profile
     pub fn available_commands(&self) -> &[Method] {
         // if None this is an old profile and we should only allow pay invoice
@@ -229,6 +517,8 @@ pub(crate) struct NostrWalletConnect {
     /// Mutiny will use this key to decrypt messages from the nostr client.
     pub(crate) server_key: Keys,
     pub(crate) profile: Profile,
+    /// Encryption used by the request being handled, its responses use the same
+    pub(crate) encryption: EncryptionScheme,
 }
 
 impl NostrWalletConnect {
@@ -253,6 +543,7 @@ impl NostrWalletConnect {
             client_key,
             server_key,
             profile,
+            encryption: EncryptionScheme::default(),
         })
     }
 
@@ -268,6 +559,40 @@ impl NostrWalletConnect {
         }
     }
 
+    /// The NWC URI with a `relay` param for every relay the profile uses.
+    /// Clients that only read one relay will use the primary relay.
+    pub fn get_nwc_uri_string(&self) -> anyhow::Result<Option<String>> {
+        let Some(uri) = self.get_nwc_uri()? else {
+            return Ok(None);
+        };
+
+        let mut uri = uri.to_string();
+        for relay in self.profile.relays().iter().skip(1) {
+            let encoded: String = url::form_urlencoded::byte_serialize(relay.as_bytes()).collect();
+            uri.push_str(&format!("&relay={encoded}"));
+        }
+
+        Ok(Some(uri))
+    }
+
+    /// Creates the NIP-42 auth event for a relay that asked us to authenticate.
+    ///
+    /// Returns None if this profile does not authenticate to the relay.
+    pub fn create_auth_event(&self, relay: &Url, challenge: &str) -> anyhow::Result<Option<Event>> {
+        let is_auth_relay = self
+            .profile
+            .auth_relays
+            .iter()
+            .filter_map(|r| Url::parse(r).ok())
+            .any(|r| &r == relay);
+        if !self.profile.active() || !is_auth_relay {
+            return Ok(None);
+        }
+
+        let event = EventBuilder::auth(challenge, relay.clone()).to_event(&self.server_key)?;
+        Ok(Some(event))
+    }
+
     pub fn client_pubkey(&self) -> nostr::PublicKey {
         self.client_key.public_key()
     }
@@ -286,17 +611,90 @@ impl NostrWalletConnect {
 
     /// Create Nostr Wallet Connect Info event
     pub fn create_nwc_info_event(&self) -> anyhow::Result<Event> {
-        let commands = self
+        let mut capabilities = self
             .profile
             .available_commands()
             .iter()
             .map(|c| c.to_string())
-            .join(" ");
-        let info =
-            EventBuilder::new(Kind::WalletConnectInfo, commands, []).to_event(&self.server_key)?;
+            .collect::<Vec<_>>();
+        let mut tags = vec![Tag::Generic(
+            TagKind::Custom(NWC_ENCRYPTION_TAG.to_string()),
+            vec![EncryptionScheme::SUPPORTED.iter().join(" ")],
+        )];
+        if self.profile.notifications {
+            capabilities.push(NWC_NOTIFICATIONS_TAG.to_string());
+            tags.push(Tag::Generic(
+                TagKind::Custom(NWC_NOTIFICATIONS_TAG.to_string()),
+                vec![NotificationType::SUPPORTED.iter().join(" ")],
+            ));
+        }
+        let info = EventBuilder::new(Kind::WalletConnectInfo, capabilities.join(" "), tags)
+            .to_event(&self.server_key)?;
         Ok(info)
     }
 
+    /// Create a NIP-47 notification event for a completed payment.
+    ///
+    /// Returns None if this profile should not be notified about the payment,
+    /// only payments made through the profile are sent, same as list_transactions.
+    pub fn create_nwc_notification_event(
+        &self,
+        invoice: &MutinyInvoice,
+    ) -> anyhow::Result<Option<Event>> {
+        if !self.profile.active()
+            || !self.profile.notifications
+            || invoice.status != HTLCStatus::Succeeded
+        {
+            return Ok(None);
+        }
+
+        let label = self
+            .profile
+            .label
+            .clone()
+            .unwrap_or(self.profile.name.clone());
+        if !invoice.labels.contains(&label) {
+            return Ok(None);
+        }
+
+        let notification_type = if invoice.inbound {
+            NotificationType::PaymentReceived
+        } else {
+            NotificationType::PaymentSent
+        };
+        let notification = Notification {
+            notification_type,
+            notification: nwc_transaction(invoice.clone()),
+        };
+
+        // kind 23196 notifications are always NIP-04 encrypted
+        let content = encryption::encrypt(
+            EncryptionScheme::Nip04,
+            self.server_key.secret_key()?,
+            &self.client_pubkey(),
+            serde_json::to_string(&notification)?,
+        )?;
+
+        let event = EventBuilder::new(
+            NWC_NOTIFICATION_KIND,
+            content,
+            [Tag::public_key(self.client_pubkey())],
+        )
+        .to_event(&self.server_key)?;
+
+        Ok(Some(event))
+    }
+
+    /// Encrypts a response with the encryption of the request being handled
+    fn encrypt_content(&self, content: String) -> anyhow::Result<String> {
+        Ok(encryption::encrypt(
+            self.encryption,
+            self.server_key.secret_key()?,
+            &self.client_key.public_key(),
+            content,
+        )?)
+    }
+
     /// Create Nostr Wallet Auth Confirmation event
     pub fn create_auth_confirmation_event(
         &self,
@@ -321,7 +719,9 @@ impl NostrWalletConnect {
             commands,
             relay,
         };
-        let content = encrypt(
+        // NIP-49 confirmations are always NIP-04 encrypted
+        let content = encryption::encrypt(
+            EncryptionScheme::Nip04,
             self.server_key.secret_key()?,
             &self.client_pubkey(),
             serde_json::to_string(&json)?,
@@ -343,17 +743,23 @@ impl NostrWalletConnect {
             .clone()
             .unwrap_or(self.profile.name.clone());
         match node.pay_invoice(invoice, None, vec![label]).await {
-            Ok(inv) => {
-                // preimage should be set after a successful payment
-                let preimage = inv.preimage.expect("preimage not set");
-                Ok(Response {
+            Ok(inv) => match paid_preimage(&inv) {
+                Ok(preimage) => Ok(Response {
                     result_type: Method::PayInvoice,
                     error: None,
                     result: Some(ResponseResult::PayInvoice(PayInvoiceResponseResult {
                         preimage,
                     })),
-                })
-            }
+                }),
+                Err(error) => {
+                    log_error!(node.logger(), "paid invoice without getting a preimage");
+                    Ok(Response {
+                        result_type: Method::PayInvoice,
+                        error: Some(error),
+                        result: None,
+                    })
+                }
+            },
             Err(e) => {
                 log_error!(node.logger(), "failed to pay invoice: {e}");
                 Err(e)
@@ -376,6 +782,7 @@ impl NostrWalletConnect {
                 event_pk,
                 invoice,
                 identifier,
+                self.encryption,
             )
             .await
     }
@@ -398,7 +805,12 @@ impl NostrWalletConnect {
             result: None,
         };
 
-        let encrypted = encrypt(server_key, &client_pubkey, content.as_json())?;
+        let encrypted = encryption::encrypt(
+            self.encryption,
+            server_key,
+            &client_pubkey,
+            content.as_json(),
+        )?;
 
         let p_tag = Tag::public_key(event.pubkey);
         let e_tag = Tag::event(event.id);
@@ -424,7 +836,29 @@ impl NostrWalletConnect {
         if event.kind == Kind::WalletConnectRequest && event.pubkey == client_pubkey {
             let server_key = self.server_key.secret_key()?;
 
-            let decrypted = decrypt(server_key, &client_pubkey, &event.content)?;
+            // respond with the same encryption the request used
+            self.encryption = match request_encryption(&event) {
+                Ok(scheme) => scheme,
+                Err(value) => {
+                    // answer with the method that was asked for if we can read it
+                    let method = encryption::decrypt(server_key, &client_pubkey, &event.content)
+                        .ok()
+                        .and_then(|decrypted| Request::from_json(decrypted).ok())
+                        .map(|req| req.method)
+                        .unwrap_or(Method::PayInvoice);
+                    self.encryption = EncryptionScheme::Nip04;
+                    return self
+                        .get_skipped_error_event(
+                            &event,
+                            method,
+                            ErrorCode::NotImplemented,
+                            format!("Unsupported encryption: {value}"),
+                        )
+                        .map(Some);
+                }
+            };
+
+            let decrypted = encryption::decrypt(server_key, &client_pubkey, &event.content)?;
             let req: Request = match Request::from_json(decrypted) {
                 Ok(req) => req,
                 Err(e) => {
@@ -467,9 +901,64 @@ impl NostrWalletConnect {
                     .map(Some);
             }
 
+            // payments must follow the profile's spending rules
+            if matches!(
+                req.method,
+                Method::PayInvoice
+                    | Method::MultiPayInvoice
+                    | Method::PayKeysend
+                    | Method::MultiPayKeysend
+            ) {
+                if let Err(rule) = self.profile.spending_rules.check_request(Utc::now()) {
+                    log_warn!(
+                        nostr_manager.logger,
+                        "NWC request blocked by spending rule {rule}"
+                    );
+                    let error = rule.error();
+                    return self
+                        .get_skipped_error_event(&event, req.method, error.code, error.message)
+                        .map(Some);
+                }
+            }
+
             result = match req.params {
                 RequestParams::PayInvoice(params) => {
                     self.handle_pay_invoice_request(
+                        event,
+                        node,
+                        nostr_manager,
+                        params,
+                        Method::PayInvoice,
+                        &mut needs_delete,
+                        &mut needs_save,
+                    )
+                    .await?
+                }
+                RequestParams::MultiPayInvoice(params) => {
+                    self.handle_multi_pay_invoice_request(
+                        event,
+                        node,
+                        nostr_manager,
+                        params,
+                        &mut needs_delete,
+                        &mut needs_save,
+                    )
+                    .await?
+                }
+                RequestParams::PayKeysend(params) => {
+                    self.handle_pay_keysend_request(
+                        &event,
+                        node,
+                        nostr_manager,
+                        params,
+                        Method::PayKeysend,
+                        &mut needs_delete,
+                        &mut needs_save,
+                    )
+                    .await?
+                }
+                RequestParams::MultiPayKeysend(params) => {
+                    self.handle_multi_pay_keysend_request(
                         event,
                         node,
                         nostr_manager,
@@ -487,9 +976,12 @@ impl NostrWalletConnect {
                     self.handle_lookup_invoice_request(event, node, params)
                         .await?
                 }
+                RequestParams::ListTransactions(params) => {
+                    self.handle_list_transactions_request(event, node, params)
+                        .await?
+                }
                 RequestParams::GetBalance => self.handle_get_balance_request(event).await?,
                 RequestParams::GetInfo => self.handle_get_info_request(event, node).await?,
-                _ => return Err(anyhow!("Invalid request params for {}", req.method)),
             };
         }
 
@@ -538,11 +1030,7 @@ impl NostrWalletConnect {
             })),
         };
 
-        let encrypted = encrypt(
-            self.server_key.secret_key()?,
-            &self.client_key.public_key(),
-            content.as_json(),
-        )?;
+        let encrypted = self.encrypt_content(content.as_json())?;
 
         let p_tag = Tag::public_key(event.pubkey);
         let e_tag = Tag::event(event.id);
@@ -574,11 +1062,7 @@ impl NostrWalletConnect {
             })),
         };
 
-        let encrypted = encrypt(
-            self.server_key.secret_key()?,
-            &self.client_key.public_key(),
-            content.as_json(),
-        )?;
+        let encrypted = self.encrypt_content(content.as_json())?;
 
         let p_tag = Tag::public_key(event.pubkey);
         let e_tag = Tag::event(event.id);
@@ -622,11 +1106,7 @@ impl NostrWalletConnect {
                     })),
                 };
 
-                let encrypted = encrypt(
-                    self.server_key.secret_key()?,
-                    &self.client_key.public_key(),
-                    content.as_json(),
-                )?;
+                let encrypted = self.encrypt_content(content.as_json())?;
 
                 let p_tag = Tag::public_key(event.pubkey);
                 let e_tag = Tag::event(event.id);
@@ -670,55 +1150,7 @@ impl NostrWalletConnect {
                 result: None,
             },
             Some(invoice) => {
-                let transaction_type = if invoice.inbound {
-                    Some(TransactionType::Incoming)
-                } else {
-                    Some(TransactionType::Outgoing)
-                };
-
-                let (description, description_hash) = match invoice.bolt11.as_ref() {
-                    None => (None, None),
-                    Some(invoice) => match invoice.description() {
-                        Bolt11InvoiceDescription::Direct(desc) => (Some(desc.to_string()), None),
-                        Bolt11InvoiceDescription::Hash(hash) => (None, Some(hash.0.to_string())),
-                    },
-                };
-
-                // try to get created_at from invoice,
-                // if it is not set, use last_updated as that's our closest approximation
-                let created_at = invoice
-                    .bolt11
-                    .as_ref()
-                    .map(|b| b.duration_since_epoch().as_secs())
-                    .unwrap_or(invoice.last_updated);
-
-                let settled_at = if invoice.status == HTLCStatus::Succeeded {
-                    Some(invoice.last_updated)
-                } else {
-                    None
-                };
-
-                // only reveal preimage if it is settled
-                let preimage = if invoice.status == HTLCStatus::Succeeded {
-                    invoice.preimage
-                } else {
-                    None
-                };
-
-                let result = LookupInvoiceResponseResult {
-                    transaction_type,
-                    invoice: invoice.bolt11.map(|i| i.to_string()),
-                    description,
-                    description_hash,
-                    preimage,
-                    payment_hash: invoice.payment_hash.into_32().to_lower_hex_string(),
-                    amount: invoice.amount_sats.map(|a| a * 1_000).unwrap_or(0),
-                    fees_paid: invoice.fees_paid.map(|a| a * 1_000).unwrap_or(0),
-                    created_at,
-                    expires_at: invoice.expire,
-                    settled_at,
-                    metadata: Default::default(),
-                };
+                let result = nwc_transaction(invoice);
 
                 Response {
                     result_type: Method::LookupInvoice,
@@ -728,11 +1160,7 @@ impl NostrWalletConnect {
             }
         };
 
-        let encrypted = encrypt(
-            self.server_key.secret_key()?,
-            &self.client_key.public_key(),
-            content.as_json(),
-        )?;
+        let encrypted = self.encrypt_content(content.as_json())?;
 
         let p_tag = Tag::public_key(event.pubkey);
         let e_tag = Tag::event(event.id);
@@ -748,6 +1176,7 @@ impl NostrWalletConnect {
         node: &impl InvoiceHandler,
         nostr_manager: &NostrManager<S, P, C>,
         params: PayInvoiceRequestParams,
+        result_type: Method,
         needs_delete: &mut bool,
         needs_save: &mut bool,
     ) -> anyhow::Result<Option<Event>> {
@@ -755,17 +1184,54 @@ impl NostrWalletConnect {
             Ok(Some(invoice)) => invoice,
             Ok(None) => return Ok(None),
             Err(err_string) => {
+                let content = Response {
+                    result_type,
+                    error: Some(NIP47Error {
+                        code: ErrorCode::Other,
+                        message: err_string,
+                    }),
+                    result: None,
+                };
                 return self
-                    .get_skipped_error_event(
-                        &event,
-                        Method::PayInvoice,
-                        ErrorCode::Other,
-                        err_string,
-                    )
+                    .build_response_event(&event, content, params.id)
                     .map(Some);
             }
         };
 
+        let sats = invoice.amount_milli_satoshis().unwrap_or_default() / 1_000;
+        let now = Utc::now();
+        let payee = PaymentPayee::from_invoice(&invoice);
+        if let Err(rule) = self.profile.spending_rules.check_payment(&payee, now) {
+            log_warn!(
+                nostr_manager.logger,
+                "NWC payment blocked by spending rule {rule}"
+            );
+            let content = Response {
+                result_type,
+                error: Some(rule.error()),
+                result: None,
+            };
+            return self
+                .build_response_event(&event, content, params.id)
+                .map(Some);
+        }
+
+        // payments above the approval threshold always need approval
+        if self.profile.spending_rules.needs_approval(sats) {
+            self.save_pending_nwc_invoice(
+                nostr_manager,
+                event.id,
+                event.pubkey,
+                invoice,
+                params.id,
+            )
+            .await?;
+
+            return Ok(None);
+        }
+
+        let auto_pay = self.profile.spending_rules.auto_pays(sats);
+
         // if we need approval, just save in the db for later
         match self.profile.spending_conditions.clone() {
             SpendingConditions::SingleUse(mut single_use) => {
@@ -786,7 +1252,7 @@ impl NostrWalletConnect {
                     Some(HTLCStatus::Succeeded) => {
                         *needs_delete = true;
                         Response {
-                            result_type: Method::PayInvoice,
+                            result_type,
                             error: Some(NIP47Error {
                                 code: ErrorCode::QuotaExceeded,
                                 message: "Already Claimed".to_string(),
@@ -801,7 +1267,7 @@ impl NostrWalletConnect {
                                     // after it is spent, delete the profile
                                     // so that it cannot be used again
                                     *needs_delete = true;
-                                    resp
+                                    with_result_type(resp, result_type)
                                 }
                                 Err(e) => {
                                     let mut code = ErrorCode::InsufficientBalance;
@@ -833,7 +1299,7 @@ impl NostrWalletConnect {
                                         .await?
                                     }
                                     Response {
-                                        result_type: Method::PayInvoice,
+                                        result_type,
                                         error: Some(NIP47Error {
                                             code,
                                             message: format!("Failed to pay invoice: {e}"),
@@ -849,7 +1315,7 @@ impl NostrWalletConnect {
                             );
 
                             Response {
-                                result_type: Method::PayInvoice,
+                                result_type,
                                 error: Some(NIP47Error {
                                     code: ErrorCode::QuotaExceeded,
                                     message: format!("Invoice amount too high: {msats} msats"),
@@ -865,7 +1331,7 @@ impl NostrWalletConnect {
                         );
 
                         Response {
-                            result_type: Method::PayInvoice,
+                            result_type,
                             error: Some(NIP47Error {
                                 code: ErrorCode::RateLimited,
                                 message: "Previous payment still in flight, cannot pay".to_string(),
@@ -875,11 +1341,7 @@ impl NostrWalletConnect {
                     }
                 };
 
-                let encrypted = encrypt(
-                    self.server_key.secret_key()?,
-                    &self.client_key.public_key(),
-                    content.as_json(),
-                )?;
+                let encrypted = self.encrypt_content(content.as_json())?;
 
                 let p_tag = Tag::public_key(event.pubkey);
                 let e_tag = Tag::event(event.id);
@@ -899,6 +1361,35 @@ impl NostrWalletConnect {
 
                 Ok(Some(response))
             }
+            SpendingConditions::RequireApproval if auto_pay => {
+                // below the approval threshold, pay without asking
+                let content = match self.pay_nwc_invoice(node, &invoice).await {
+                    Ok(resp) => {
+                        *needs_save |= self.profile.spending_rules.record_payment(Utc::now());
+                        with_result_type(resp, result_type)
+                    }
+                    // don't send error message for timeout, it can still complete
+                    Err(MutinyError::PaymentTimeout) => return Ok(None),
+                    Err(e) => {
+                        let code = match e {
+                            MutinyError::InsufficientBalance => ErrorCode::InsufficientBalance,
+                            _ => ErrorCode::PaymentFailed,
+                        };
+
+                        Response {
+                            result_type,
+                            error: Some(NIP47Error {
+                                code,
+                                message: format!("Failed to pay invoice: {e}"),
+                            }),
+                            result: None,
+                        }
+                    }
+                };
+
+                self.build_response_event(&event, content, params.id)
+                    .map(Some)
+            }
             SpendingConditions::RequireApproval => {
                 self.save_pending_nwc_invoice(
                     nostr_manager,
@@ -923,28 +1414,7 @@ impl NostrWalletConnect {
                 } else if budget.sum_payments() + sats > budget.budget {
                     // budget might not actually be exceeded, we should verify that the payments
                     // all went through, and if not, remove them from the budget
-                    let mut indices_to_remove = Vec::new();
-                    for (index, p) in budget.payments.iter().enumerate() {
-                        let hash: [u8; 32] = FromHex::from_hex(&p.hash)?;
-                        indices_to_remove.push((index, hash));
-                    }
-
-                    let futures: Vec<_> = indices_to_remove
-                        .iter()
-                        .map(|(index, hash)| async move {
-                            match node.lookup_payment(hash).await.map(|i| i.status) {
-                                Some(HTLCStatus::Failed) => Some(*index),
-                                _ => None,
-                            }
-                        })
-                        .collect();
-
-                    let results = futures::future::join_all(futures).await;
-
-                    // Remove failed payments
-                    for index in results.into_iter().flatten().rev() {
-                        budget.payments.remove(index);
-                    }
+                    remove_failed_payments(&mut budget, node).await?;
 
                     // update budget with removed payments
                     self.profile.spending_conditions = SpendingConditions::Budget(budget.clone());
@@ -972,7 +1442,7 @@ impl NostrWalletConnect {
                         )
                         .await?;
                         Response {
-                            result_type: Method::PayInvoice,
+                            result_type,
                             error: Some(NIP47Error {
                                 code: ErrorCode::QuotaExceeded,
                                 message: err.to_string(),
@@ -990,7 +1460,11 @@ impl NostrWalletConnect {
 
                         // attempt to pay invoice
                         match self.pay_nwc_invoice(node, &invoice).await {
-                            Ok(resp) => resp,
+                            Ok(resp) => {
+                                *needs_save |=
+                                    self.profile.spending_rules.record_payment(Utc::now());
+                                with_result_type(resp, result_type)
+                            }
                             Err(e) => {
                                 // remove payment if it failed
                                 match e {
@@ -1047,7 +1521,7 @@ impl NostrWalletConnect {
                                 };
 
                                 Response {
-                                    result_type: Method::PayInvoice,
+                                    result_type,
                                     error: Some(NIP47Error {
                                         code,
                                         message: format!("Failed to pay invoice: {e}"),
@@ -1059,11 +1533,7 @@ impl NostrWalletConnect {
                     }
                 };
 
-                let encrypted = encrypt(
-                    self.server_key.secret_key()?,
-                    &self.client_key.public_key(),
-                    content.as_json(),
-                )?;
+                let encrypted = self.encrypt_content(content.as_json())?;
 
                 let p_tag = Tag::public_key(event.pubkey);
                 let e_tag = Tag::event(event.id);
@@ -1081,6 +1551,437 @@ impl NostrWalletConnect {
         }
     }
 
+    /// Encrypts a response to the given request, tagging it with the
+    /// request's `id` so multi requests can match responses to payments
+    fn build_response_event(
+        &self,
+        event: &Event,
+        content: Response,
+        id: Option<String>,
+    ) -> anyhow::Result<Event> {
+        let encrypted = self.encrypt_content(content.as_json())?;
+
+        let p_tag = Tag::public_key(event.pubkey);
+        let e_tag = Tag::event(event.id);
+        let tags = match id {
+            Some(id) => vec![p_tag, e_tag, Tag::Identifier(id)],
+            None => vec![p_tag, e_tag],
+        };
+
+        let response = EventBuilder::new(Kind::WalletConnectResponse, encrypted, tags)
+            .to_event(&self.server_key)?;
+
+        Ok(response)
+    }
+
+    async fn handle_list_transactions_request(
+        &self,
+        event: Event,
+        node: &impl InvoiceHandler,
+        params: ListTransactionsRequestParams,
+    ) -> anyhow::Result<Option<Event>> {
+        // only list payments made through this profile, don't leak the rest of our wallet
+        let label = self
+            .profile
+            .label
+            .clone()
+            .unwrap_or(self.profile.name.clone());
+
+        let content = match node.list_payments() {
+            Err(e) => Response {
+                result_type: Method::ListTransactions,
+                error: Some(NIP47Error {
+                    code: ErrorCode::Internal,
+                    message: format!("Failed to list transactions: {e}"),
+                }),
+                result: None,
+            },
+            Ok(payments) => {
+                let include_unpaid = params.unpaid.unwrap_or(false);
+                let from = params.from.unwrap_or(0);
+                let until = params.until.unwrap_or(u64::MAX);
+
+                let transactions = payments
+                    .into_iter()
+                    .filter(|p| p.labels.contains(&label))
+                    .map(nwc_transaction)
+                    .filter(|t| include_unpaid || t.settled_at.is_some())
+                    .filter(|t| {
+                        params.transaction_type.is_none()
+                            || t.transaction_type == params.transaction_type
+                    })
+                    .filter(|t| t.created_at >= from && t.created_at <= until)
+                    .sorted_by(|a, b| b.created_at.cmp(&a.created_at))
+                    .skip(params.offset.unwrap_or(0) as usize)
+                    .take(params.limit.map(|l| l as usize).unwrap_or(usize::MAX))
+                    .collect();
+
+                Response {
+                    result_type: Method::ListTransactions,
+                    error: None,
+                    result: Some(ResponseResult::ListTransactions(transactions)),
+                }
+            }
+        };
+
+        self.build_response_event(&event, content, None).map(Some)
+    }
+
+    /// Pays each invoice in a multi_pay_invoice request.
+    ///
+    /// Every payment gets its own response event, so they are sent here
+    /// and nothing is returned to the caller.
+    async fn handle_multi_pay_invoice_request<S: MutinyStorage, P: PrimalApi, C: NostrClient>(
+        &mut self,
+        event: Event,
+        node: &impl InvoiceHandler,
+        nostr_manager: &NostrManager<S, P, C>,
+        params: MultiPayInvoiceRequestParams,
+        needs_delete: &mut bool,
+        needs_save: &mut bool,
+    ) -> anyhow::Result<Option<Event>> {
+        for mut invoice_params in params.invoices {
+            // responses are identified by the given id, or the payment hash if there is none
+            if invoice_params.id.is_none() {
+                invoice_params.id = Bolt11Invoice::from_str(&invoice_params.invoice)
+                    .ok()
+                    .map(|i| i.payment_hash().to_string());
+            }
+
+            // a single use profile can only pay the first invoice
+            let response = if *needs_delete {
+                let content = Response {
+                    result_type: Method::MultiPayInvoice,
+                    error: Some(NIP47Error {
+                        code: ErrorCode::QuotaExceeded,
+                        message: "Already Claimed".to_string(),
+                    }),
+                    result: None,
+                };
+                Some(self.build_response_event(&event, content, invoice_params.id)?)
+            } else {
+                self.handle_pay_invoice_request(
+                    event.clone(),
+                    node,
+                    nostr_manager,
+                    invoice_params,
+                    Method::MultiPayInvoice,
+                    needs_delete,
+                    needs_save,
+                )
+                .await?
+            };
+
+            if let Some(response) = response {
+                if let Err(e) = nostr_manager.client.send_event(response).await {
+                    log_warn!(nostr_manager.logger, "Error sending NWC event: {e}");
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Pays each keysend in a multi_pay_keysend request.
+    ///
+    /// Every payment gets its own response event, so they are sent here
+    /// and nothing is returned to the caller.
+    async fn handle_multi_pay_keysend_request<S: MutinyStorage, P: PrimalApi, C: NostrClient>(
+        &mut self,
+        event: Event,
+        node: &impl InvoiceHandler,
+        nostr_manager: &NostrManager<S, P, C>,
+        params: MultiPayKeysendRequestParams,
+        needs_delete: &mut bool,
+        needs_save: &mut bool,
+    ) -> anyhow::Result<Option<Event>> {
+        for mut keysend_params in params.keysends {
+            // responses are identified by the given id, or the pubkey if there is none
+            if keysend_params.id.is_none() {
+                keysend_params.id = Some(keysend_params.pubkey.clone());
+            }
+
+            let response = self
+                .handle_pay_keysend_request(
+                    &event,
+                    node,
+                    nostr_manager,
+                    keysend_params,
+                    Method::MultiPayKeysend,
+                    needs_delete,
+                    needs_save,
+                )
+                .await?;
+
+            if let Some(response) = response {
+                if let Err(e) = nostr_manager.client.send_event(response).await {
+                    log_warn!(nostr_manager.logger, "Error sending NWC event: {e}");
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_pay_keysend_request<S: MutinyStorage, P: PrimalApi, C: NostrClient>(
+        &mut self,
+        event: &Event,
+        node: &impl InvoiceHandler,
+        nostr_manager: &NostrManager<S, P, C>,
+        params: PayKeysendRequestParams,
+        result_type: Method,
+        needs_delete: &mut bool,
+        needs_save: &mut bool,
+    ) -> anyhow::Result<Option<Event>> {
+        let content = match self
+            .pay_nwc_keysend(node, nostr_manager, &params, needs_delete, needs_save)
+            .await?
+        {
+            Ok(preimage) => {
+                let result = PayKeysendResponseResult { preimage };
+                let result = if result_type == Method::MultiPayKeysend {
+                    ResponseResult::MultiPayKeysend(result)
+                } else {
+                    ResponseResult::PayKeysend(result)
+                };
+                Response {
+                    result_type,
+                    error: None,
+                    result: Some(result),
+                }
+            }
+            Err(error) => Response {
+                result_type,
+                error: Some(error),
+                result: None,
+            },
+        };
+
+        self.build_response_event(event, content, params.id)
+            .map(Some)
+    }
+
+    /// Checks a keysend against the profile's spending conditions and pays it.
+    ///
+    /// Keysends cannot be queued for approval because there is no invoice to
+    /// approve, so profiles that require approval cannot keysend.
+    async fn pay_nwc_keysend<S: MutinyStorage, P: PrimalApi, C: NostrClient>(
+        &mut self,
+        node: &impl InvoiceHandler,
+        nostr_manager: &NostrManager<S, P, C>,
+        params: &PayKeysendRequestParams,
+        needs_delete: &mut bool,
+        needs_save: &mut bool,
+    ) -> anyhow::Result<Result<String, NIP47Error>> {
+        let Ok(to_node) = PublicKey::from_str(&params.pubkey) else {
+            return Ok(Err(nip47_error(ErrorCode::Other, "Invalid pubkey")));
+        };
+
+        if params.preimage.is_some() {
+            return Ok(Err(nip47_error(
+                ErrorCode::NotImplemented,
+                "Custom preimages are not supported",
+            )));
+        }
+
+        let message = match keysend_message(&params.tlv_records) {
+            Ok(message) => message,
+            Err(e) => return Ok(Err(nip47_error(ErrorCode::NotImplemented, e))),
+        };
+
+        // we cannot pay msat values
+        if params.amount == 0 || params.amount % 1_000 != 0 {
+            return Ok(Err(nip47_error(
+                ErrorCode::Other,
+                "Keysend amount must be a whole number of sats",
+            )));
+        }
+        let sats = params.amount / 1_000;
+
+        let now = Utc::now();
+        let payee = PaymentPayee {
+            node: Some(to_node),
+        };
+        if let Err(rule) = self.profile.spending_rules.check_payment(&payee, now) {
+            log_warn!(
+                nostr_manager.logger,
+                "NWC keysend blocked by spending rule {rule}"
+            );
+            return Ok(Err(rule.error()));
+        }
+
+        // keysends can't be saved for approval later
+        if self.profile.spending_rules.needs_approval(sats) {
+            return Ok(Err(nip47_error(
+                ErrorCode::Restricted,
+                "Keysend amount requires approval",
+            )));
+        }
+
+        let auto_pay = self.profile.spending_rules.auto_pays(sats);
+
+        let label = self
+            .profile
+            .label
+            .clone()
+            .unwrap_or(self.profile.name.clone());
+
+        match self.profile.spending_conditions.clone() {
+            SpendingConditions::RequireApproval if auto_pay => {
+                // below the approval threshold, pay without asking
+                match node
+                    .keysend(to_node, sats, message, None, vec![label])
+                    .await
+                {
+                    Ok(inv) => {
+                        *needs_save |= self.profile.spending_rules.record_payment(Utc::now());
+                        Ok(paid_preimage(&inv))
+                    }
+                    Err(MutinyError::PaymentTimeout) => Ok(Err(nip47_error(
+                        ErrorCode::Internal,
+                        "Failed to pay keysend: payment timed out",
+                    ))),
+                    Err(e) => Ok(Err(keysend_error(e))),
+                }
+            }
+            SpendingConditions::RequireApproval => Ok(Err(nip47_error(
+                ErrorCode::Restricted,
+                "Keysend requires a budget",
+            ))),
+            SpendingConditions::SingleUse(single_use) => {
+                if *needs_delete {
+                    return Ok(Err(nip47_error(
+                        ErrorCode::QuotaExceeded,
+                        "Already Claimed",
+                    )));
+                }
+
+                // check the status of a previous payment attempt, if one exists
+                if let Some(payment_hash) = single_use.payment_hash {
+                    let hash: [u8; 32] = FromHex::from_hex(&payment_hash)?;
+                    match node.lookup_payment(&hash).await.map(|i| i.status) {
+                        Some(HTLCStatus::Succeeded) => {
+                            *needs_delete = true;
+                            return Ok(Err(nip47_error(
+                                ErrorCode::QuotaExceeded,
+                                "Already Claimed",
+                            )));
+                        }
+                        Some(HTLCStatus::Pending) | Some(HTLCStatus::InFlight) => {
+                            return Ok(Err(nip47_error(
+                                ErrorCode::RateLimited,
+                                "Previous payment still in flight, cannot pay",
+                            )));
+                        }
+                        None | Some(HTLCStatus::Failed) => {}
+                    }
+                }
+
+                if sats > single_use.amount_sats {
+                    log_warn!(nostr_manager.logger, "Keysend amount too high: {sats} sats");
+                    return Ok(Err(nip47_error(
+                        ErrorCode::QuotaExceeded,
+                        format!("Keysend amount too high: {sats} sats"),
+                    )));
+                }
+
+                match node
+                    .keysend(to_node, sats, message, None, vec![label])
+                    .await
+                {
+                    Ok(inv) => {
+                        // after it is spent, delete the profile
+                        // so that it cannot be used again
+                        *needs_delete = true;
+                        Ok(paid_preimage(&inv))
+                    }
+                    Err(MutinyError::PaymentTimeout) => {
+                        // we don't learn the payment hash of a timed out keysend,
+                        // so we can't track it, delete the profile to be safe
+                        log_error!(
+                            nostr_manager.logger,
+                            "Keysend timeout, deleting single use profile"
+                        );
+                        *needs_delete = true;
+                        Ok(Err(nip47_error(
+                            ErrorCode::Internal,
+                            "Failed to pay keysend: payment timed out",
+                        )))
+                    }
+                    Err(e) => Ok(Err(keysend_error(e))),
+                }
+            }
+            SpendingConditions::Budget(mut budget) => {
+                if budget.single_max.is_some_and(|max| sats > max) {
+                    log_warn!(nostr_manager.logger, "Attempted to exceed budget");
+                    return Ok(Err(nip47_error(
+                        ErrorCode::QuotaExceeded,
+                        "Keysend amount too high.",
+                    )));
+                }
+
+                if budget.sum_payments() + sats > budget.budget {
+                    remove_failed_payments(&mut budget, node).await?;
+                    self.profile.spending_conditions = SpendingConditions::Budget(budget.clone());
+                    *needs_save = true;
+
+                    if budget.sum_payments() + sats > budget.budget {
+                        log_warn!(nostr_manager.logger, "Attempted to exceed budget");
+                        return Ok(Err(nip47_error(
+                            ErrorCode::QuotaExceeded,
+                            "Budget exceeded.",
+                        )));
+                    }
+                }
+
+                // pick the preimage ourselves so the payment hash is known up front,
+                // then reserve the amount under it and persist before paying
+                let preimage = bitcoin::secp256k1::rand::random::<[u8; 32]>();
+                let payment_hash = sha256::Hash::hash(&preimage).to_byte_array();
+                let payment_hash = payment_hash.to_lower_hex_string();
+                budget.add_tracked_payment(sats, payment_hash.clone());
+                self.profile.spending_conditions = SpendingConditions::Budget(budget.clone());
+                nostr_manager.save_nwc_profile(self.clone())?;
+
+                let result = match node
+                    .keysend(to_node, sats, message, Some(preimage), vec![label])
+                    .await
+                {
+                    Ok(inv) => {
+                        *needs_save |= self.profile.spending_rules.record_payment(Utc::now());
+                        paid_preimage(&inv)
+                    }
+                    Err(MutinyError::PaymentTimeout) => {
+                        // the payment can still complete, keep it reserved until
+                        // remove_failed_payments sees that it failed
+                        log_warn!(
+                            nostr_manager.logger,
+                            "Keysend timeout, keeping payment in budget until it resolves"
+                        );
+                        Err(nip47_error(
+                            ErrorCode::Internal,
+                            "Failed to pay keysend: payment timed out",
+                        ))
+                    }
+                    Err(e) => {
+                        log_warn!(
+                            nostr_manager.logger,
+                            "Failed to pay keysend: {e}, removing payment from budget"
+                        );
+                        budget.remove_tracked_payment(&payment_hash);
+                        Err(keysend_error(e))
+                    }
+                };
+
+                self.profile.spending_conditions = SpendingConditions::Budget(budget);
+                *needs_save = true;
+
+                Ok(result)
+            }
+        }
+    }
+
     pub fn nwc_profile(&self) -> NwcProfile {
         NwcProfile {
             name: self.profile.name.clone(),
@@ -1089,15 +1990,16 @@ impl NostrWalletConnect {
             relay: self.profile.relay.clone(),
             enabled: self.profile.enabled,
             archived: self.profile.archived,
-            nwc_uri: self
-                .get_nwc_uri()
-                .expect("failed to get nwc uri")
-                .map(|uri| uri.to_string()),
+            nwc_uri: self.get_nwc_uri_string().expect("failed to get nwc uri"),
             spending_conditions: self.profile.spending_conditions.clone(),
             commands: self.profile.commands.clone(),
             child_key_index: self.profile.child_key_index,
             tag: self.profile.tag,
             label: self.profile.label.clone(),
+            notifications: self.profile.notifications,
+            spending_rules: self.profile.spending_rules.clone(),
+            relays: self.profile.relays.clone(),
+            auth_relays: self.profile.auth_relays.clone(),
         }
     }
 }
@@ -1127,6 +2029,18 @@ pub struct NwcProfile {
     pub tag: NwcProfileTag,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub label: Option<String>,
+    /// Send NIP-47 notifications to this profile
+    #[serde(default)]
+    pub notifications: bool,
+    /// Rules checked before every payment, on top of the spending conditions
+    #[serde(default)]
+    pub spending_rules: SpendingRules,
+    /// Extra relays used alongside `relay`, so the profile keeps working if one is down
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<String>,
+    /// Relays that require NIP-42 authentication, we authenticate with the profile's key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_relays: Vec<String>,
 }
 
 impl NwcProfile {
@@ -1143,6 +2057,10 @@ impl NwcProfile {
             child_key_index: self.child_key_index,
             tag: self.tag,
             label: self.label.clone(),
+            notifications: self.notifications,
+            spending_rules: self.spending_rules.clone(),
+            relays: self.relays.clone(),
+            auth_relays: self.auth_relays.clone(),
         }
     }
 }
@@ -1163,6 +2081,9 @@ pub struct PendingNwcInvoice {
     /// `id` parameter given in the original request
     /// This is normally only given for MultiPayInvoice requests
     pub identifier: Option<String>,
+    /// Encryption used by the original request, the response must use the same
+    #[serde(default)]
+    pub encryption: EncryptionScheme,
 }
 
 impl PartialOrd for PendingNwcInvoice {
@@ -1183,21 +2104,179 @@ impl PendingNwcInvoice {
     }
 }
 
-/// Checks if it is a valid invoice
-/// Return an error string if invalid
-/// Otherwise returns an optional invoice that should be processed
-pub(crate) async fn check_valid_nwc_invoice(
-    params: &PayInvoiceRequestParams,
-    invoice_handler: &impl InvoiceHandler,
-) -> Result<Option<Bolt11Invoice>, String> {
-    let invoice = match Bolt11Invoice::from_str(&params.invoice) {
-        Ok(invoice) => invoice,
-        Err(_) => return Err("Invalid invoice".to_string()),
-    };
+/// Gets the encryption a request was sent with.
+///
+/// Requests without an encryption tag are detected from their payload,
+/// older clients only send NIP-04. Returns the tag value if it is not supported.
+fn request_encryption(event: &Event) -> Result<EncryptionScheme, String> {
+    let value = event.tags.iter().find_map(|tag| match tag {
+        Tag::Generic(TagKind::Custom(kind), values) if kind == NWC_ENCRYPTION_TAG => {
+            Some(values.first().cloned().unwrap_or_default())
+        }
+        _ => None,
+    });
 
-    // if the invoice has expired, skip it
-    if invoice.would_expire(utils::now()) {
-        return Err("Invoice expired".to_string());
+    match value {
+        None => Ok(EncryptionScheme::detect(&event.content)),
+        Some(value) => EncryptionScheme::from_str(&value).map_err(|_| value),
+    }
+}
+
+/// Most NWC responses are tagged with the method of the request,
+/// multi requests answer each payment with their own method
+fn with_result_type(mut resp: Response, result_type: Method) -> Response {
+    resp.result_type = result_type;
+    resp.result = resp.result.map(|result| match (result_type, result) {
+        (Method::MultiPayInvoice, ResponseResult::PayInvoice(r)) => {
+            ResponseResult::MultiPayInvoice(r)
+        }
+        (_, result) => result,
+    });
+    resp
+}
+
+fn nip47_error(code: ErrorCode, message: impl Into<String>) -> NIP47Error {
+    NIP47Error {
+        code,
+        message: message.into(),
+    }
+}
+
+fn keysend_error(e: MutinyError) -> NIP47Error {
+    let code = match e {
+        MutinyError::InsufficientBalance => ErrorCode::InsufficientBalance,
+        _ => ErrorCode::PaymentFailed,
+    };
+    nip47_error(code, format!("Failed to pay keysend: {e}"))
+}
+
+/// Gets the preimage of a successful payment, it should always be set
+fn paid_preimage(inv: &MutinyInvoice) -> Result<String, NIP47Error> {
+    inv.preimage
+        .clone()
+        .ok_or_else(|| nip47_error(ErrorCode::Internal, "Payment did not return a preimage"))
+}
+
+/// TLV type used to attach a text message to a keysend
+const KEYSEND_MESSAGE_TLV_TYPE: u64 = 34349334;
+
+/// Gets the message from a keysend's TLV records, other records are not supported
+fn keysend_message(records: &[KeysendTLVRecord]) -> Result<Option<String>, String> {
+    let mut message = None;
+    for record in records {
+        if record.tlv_type != KEYSEND_MESSAGE_TLV_TYPE {
+            return Err(format!("Unsupported TLV record type: {}", record.tlv_type));
+        }
+
+        let bytes: Vec<u8> = FromHex::from_hex(&record.value)
+            .map_err(|_| "Invalid keysend message record".to_string())?;
+        let msg =
+            String::from_utf8(bytes).map_err(|_| "Keysend message is not UTF-8".to_string())?;
+        message = Some(msg);
+    }
+
+    Ok(message)
+}
+
+/// Removes payments that failed from a budget, they should not count against it
+async fn remove_failed_payments(
+    budget: &mut BudgetedSpendingConditions,
+    node: &impl InvoiceHandler,
+) -> anyhow::Result<()> {
+    let mut indices_to_remove = Vec::new();
+    for (index, p) in budget.payments.iter().enumerate() {
+        let hash: [u8; 32] = FromHex::from_hex(&p.hash)?;
+        indices_to_remove.push((index, hash));
+    }
+
+    let futures: Vec<_> = indices_to_remove
+        .iter()
+        .map(|(index, hash)| async move {
+            match node.lookup_payment(hash).await.map(|i| i.status) {
+                Some(HTLCStatus::Failed) => Some(*index),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let results = futures::future::join_all(futures).await;
+
+    // Remove failed payments
+    for index in results.into_iter().flatten().rev() {
+        budget.payments.remove(index);
+    }
+
+    Ok(())
+}
+
+/// Converts a payment into the transaction format used by lookup_invoice and list_transactions
+fn nwc_transaction(invoice: MutinyInvoice) -> LookupInvoiceResponseResult {
+    let transaction_type = if invoice.inbound {
+        Some(TransactionType::Incoming)
+    } else {
+        Some(TransactionType::Outgoing)
+    };
+
+    let (description, description_hash) = match invoice.bolt11.as_ref() {
+        None => (None, None),
+        Some(invoice) => match invoice.description() {
+            Bolt11InvoiceDescription::Direct(desc) => (Some(desc.to_string()), None),
+            Bolt11InvoiceDescription::Hash(hash) => (None, Some(hash.0.to_string())),
+        },
+    };
+
+    // try to get created_at from invoice,
+    // if it is not set, use last_updated as that's our closest approximation
+    let created_at = invoice
+        .bolt11
+        .as_ref()
+        .map(|b| b.duration_since_epoch().as_secs())
+        .unwrap_or(invoice.last_updated);
+
+    let settled_at = if invoice.status == HTLCStatus::Succeeded {
+        Some(invoice.last_updated)
+    } else {
+        None
+    };
+
+    // only reveal preimage if it is settled
+    let preimage = if invoice.status == HTLCStatus::Succeeded {
+        invoice.preimage
+    } else {
+        None
+    };
+
+    LookupInvoiceResponseResult {
+        transaction_type,
+        invoice: invoice.bolt11.map(|i| i.to_string()),
+        description,
+        description_hash,
+        preimage,
+        payment_hash: invoice.payment_hash.into_32().to_lower_hex_string(),
+        amount: invoice.amount_sats.map(|a| a * 1_000).unwrap_or(0),
+        fees_paid: invoice.fees_paid.map(|a| a * 1_000).unwrap_or(0),
+        created_at,
+        expires_at: invoice.expire,
+        settled_at,
+        metadata: Default::default(),
+    }
+}
+
+/// Checks if it is a valid invoice
+/// Return an error string if invalid
+/// Otherwise returns an optional invoice that should be processed
+pub(crate) async fn check_valid_nwc_invoice(
+    params: &PayInvoiceRequestParams,
+    invoice_handler: &impl InvoiceHandler,
+) -> Result<Option<Bolt11Invoice>, String> {
+    let invoice = match Bolt11Invoice::from_str(&params.invoice) {
+        Ok(invoice) => invoice,
+        Err(_) => return Err("Invalid invoice".to_string()),
+    };
+
+    // if the invoice has expired, skip it
+    if invoice.would_expire(utils::now()) {
+        return Err("Invoice expired".to_string());
     }
 
     // if the invoice has no amount, we cannot pay it
@@ -1242,6 +2321,7 @@ pub(crate) async fn check_valid_nwc_invoice(
 #[cfg(test)]
 mod test {
     use super::*;
+    use bitcoin::hashes::{sha256, Hash};
     use chrono::Days;
 
     #[test]
@@ -1508,6 +2588,207 @@ mod test {
         budget.clean_old_payments(time);
         assert_eq!(budget.payments.len(), 0);
     }
+
+    #[test]
+    fn test_profile_relays() {
+        let profile = Profile {
+            name: "test".to_string(),
+            index: 0,
+            client_key: None,
+            relay: "wss://relay.mutinywallet.com".to_string(),
+            enabled: None,
+            archived: None,
+            spending_conditions: Default::default(),
+            commands: None,
+            child_key_index: None,
+            tag: Default::default(),
+            label: None,
+            notifications: false,
+            spending_rules: Default::default(),
+            relays: vec![
+                "wss://relay.damus.io".to_string(),
+                "wss://relay.mutinywallet.com".to_string(),
+            ],
+            auth_relays: vec![
+                "wss://auth.example.com".to_string(),
+                "wss://relay.damus.io".to_string(),
+            ],
+        };
+
+        assert_eq!(
+            profile.relays(),
+            vec![
+                "wss://relay.mutinywallet.com".to_string(),
+                "wss://relay.damus.io".to_string(),
+                "wss://auth.example.com".to_string(),
+            ]
+        );
+
+        // old profiles without the new fields still load
+        let json = r#"{"name":"test","index":0,"relay":"wss://relay.mutinywallet.com","enabled":null,"archived":null,"commands":null}"#;
+        let old: Profile = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            old.relays(),
+            vec!["wss://relay.mutinywallet.com".to_string()]
+        );
+        assert!(old.auth_relays.is_empty());
+    }
+
+    #[test]
+    fn test_spending_rules_expiry_and_hours() {
+        // 2024-4-20 12:00 UTC
+        let noon = NaiveDateTime::from_timestamp_opt(1713614400, 0)
+            .unwrap()
+            .and_utc();
+
+        let mut rules = SpendingRules {
+            expires_at: Some(noon.timestamp() as u64 + 60),
+            ..Default::default()
+        };
+        assert_eq!(rules.check_request(noon), Ok(()));
+        let later = noon.checked_add_signed(Duration::seconds(60)).unwrap();
+        assert_eq!(rules.check_request(later), Err(SpendingRule::Expiry));
+
+        rules.expires_at = None;
+        rules.allowed_hours = Some(AllowedHours { start: 9, end: 17 });
+        assert_eq!(rules.check_request(noon), Ok(()));
+        rules.allowed_hours = Some(AllowedHours { start: 13, end: 17 });
+        assert_eq!(rules.check_request(noon), Err(SpendingRule::AllowedHours));
+
+        // wraps past midnight
+        rules.allowed_hours = Some(AllowedHours { start: 22, end: 13 });
+        assert_eq!(rules.check_request(noon), Ok(()));
+        rules.allowed_hours = Some(AllowedHours { start: 22, end: 6 });
+        assert_eq!(rules.check_request(noon), Err(SpendingRule::AllowedHours));
+
+        // equal start and end allows every hour
+        rules.allowed_hours = Some(AllowedHours { start: 0, end: 0 });
+        assert_eq!(rules.check_request(noon), Ok(()));
+    }
+
+    #[test]
+    fn test_spending_rules_payees() {
+        let now = Utc::now();
+        let secp = Secp256k1::new();
+        let node = PublicKey::from_secret_key(
+            &secp,
+            &bitcoin::secp256k1::SecretKey::from_slice(&[1; 32]).unwrap(),
+        );
+        let other = PublicKey::from_secret_key(
+            &secp,
+            &bitcoin::secp256k1::SecretKey::from_slice(&[2; 32]).unwrap(),
+        );
+
+        let to_node = PaymentPayee { node: Some(node) };
+        let to_other = PaymentPayee { node: Some(other) };
+
+        let mut rules = SpendingRules {
+            payees: Some(PayeeFilter::Allow(vec![Payee::Node(node)])),
+            ..Default::default()
+        };
+        assert_eq!(rules.check_payment(&to_node, now), Ok(()));
+        assert_eq!(
+            rules.check_payment(&to_other, now),
+            Err(SpendingRule::PayeeAllowlist)
+        );
+        assert_eq!(
+            rules.check_payment(&PaymentPayee::default(), now),
+            Err(SpendingRule::PayeeAllowlist)
+        );
+
+        rules.payees = Some(PayeeFilter::Deny(vec![Payee::Node(node)]));
+        assert_eq!(
+            rules.check_payment(&to_node, now),
+            Err(SpendingRule::PayeeDenylist)
+        );
+        assert_eq!(rules.check_payment(&to_other, now), Ok(()));
+
+        // the payee is the node that signed the invoice
+        let invoice = lightning_invoice::InvoiceBuilder::new(lightning_invoice::Currency::Regtest)
+            .description("pay bob@example.com".to_string())
+            .duration_since_epoch(crate::utils::now())
+            .payment_hash(sha256::Hash::all_zeros())
+            .payment_secret(lightning::ln::PaymentSecret([0; 32]))
+            .min_final_cltv_expiry_delta(144)
+            .build_signed(|hash| {
+                secp.sign_ecdsa_recoverable(
+                    hash,
+                    &bitcoin::secp256k1::SecretKey::from_slice(&[2; 32]).unwrap(),
+                )
+            })
+            .unwrap();
+        let payee = PaymentPayee::from_invoice(&invoice);
+        assert_eq!(payee.node, Some(other));
+        assert_eq!(
+            rules.check_payment(&payee, now),
+            Err(SpendingRule::PayeeAllowlist)
+        );
+
+        // LNURL domains can't be matched against invoices, so rules with them are rejected
+        let json = r#"{"payees":{"Allow":[{"LnurlDomain":"example.com"}]}}"#;
+        assert!(serde_json::from_str::<SpendingRules>(json).is_err());
+    }
+
+    #[test]
+    fn test_spending_rules_rate_limit() {
+        let time = NaiveDateTime::from_timestamp_opt(100, 0).unwrap().and_utc();
+        let payee = PaymentPayee::default();
+
+        let mut rules = SpendingRules {
+            rate_limit: Some(RateLimit {
+                max_payments: 2,
+                period: BudgetPeriod::Seconds(10),
+                payments: vec![],
+            }),
+            ..Default::default()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(rules.check_payment(&payee, time), Ok(()));
+            assert!(rules.record_payment(time));
+        }
+        assert_eq!(
+            rules.check_payment(&payee, time),
+            Err(SpendingRule::RateLimit)
+        );
+
+        // old payments stop counting once they leave the period
+        let time = time.checked_add_signed(Duration::seconds(10)).unwrap();
+        assert_eq!(rules.check_payment(&payee, time), Ok(()));
+        assert!(rules.rate_limit.as_ref().unwrap().payments.is_empty());
+
+        // nothing to save without a rate limit
+        assert!(!SpendingRules::default().record_payment(time));
+    }
+
+    #[test]
+    fn test_spending_rules_approval_threshold() {
+        let rules = SpendingRules {
+            approval_threshold: Some(1_000),
+            ..Default::default()
+        };
+        assert!(!rules.needs_approval(1_000));
+        assert!(rules.auto_pays(1_000));
+        assert!(rules.needs_approval(1_001));
+        assert!(!rules.auto_pays(1_001));
+
+        let rules = SpendingRules::default();
+        assert!(!rules.needs_approval(u64::MAX));
+        assert!(!rules.auto_pays(0));
+    }
+
+    #[test]
+    fn test_spending_rule_error() {
+        let error = SpendingRule::PayeeDenylist.error();
+        assert!(matches!(error.code, ErrorCode::Restricted));
+        assert_eq!(
+            error.message,
+            "Blocked by spending rule payee_denylist: payee is on the denylist"
+        );
+
+        let error = SpendingRule::RateLimit.error();
+        assert!(matches!(error.code, ErrorCode::RateLimited));
+    }
 }
 
 #[cfg(test)]
@@ -1528,6 +2809,7 @@ mod wasm_test {
     use lightning::chain::BestBlock;
     use mockall::predicate::eq;
     use nostr::key::SecretKey;
+    use nostr::nips::nip04::{decrypt, encrypt};
     use serde_json::json;
     use std::sync::{atomic::AtomicBool, Arc};
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
@@ -1609,7 +2891,7 @@ mod wasm_test {
         let result = nwc
             .handle_nwc_request(event.clone(), &mw, &nostr_manager)
             .await;
-        assert_eq!(result.unwrap(), None);
+        assert!(result.unwrap().is_none());
 
         let pending: Vec<PendingNwcInvoice> = storage
             .get_data(PENDING_NWC_EVENTS_KEY)
@@ -1666,7 +2948,7 @@ mod wasm_test {
                 .unwrap()
         };
         let result = nwc.handle_nwc_request(event, &node, &nostr_manager).await;
-        assert_eq!(result.unwrap(), None);
+        assert!(result.unwrap().is_none());
         check_no_pending_invoices(&storage);
 
         // test unknown command
@@ -1796,7 +3078,7 @@ mod wasm_test {
             });
         let event = create_nwc_request(&uri, invoice.to_string());
         let result = nwc.handle_nwc_request(event, &node, &nostr_manager).await;
-        assert_eq!(result.unwrap(), None);
+        assert!(result.unwrap().is_none());
         check_no_pending_invoices(&storage);
 
         // test completed payment
@@ -1811,7 +3093,7 @@ mod wasm_test {
             });
         let event = create_nwc_request(&uri, invoice.to_string());
         let result = nwc.handle_nwc_request(event, &node, &nostr_manager).await;
-        assert_eq!(result.unwrap(), None);
+        assert!(result.unwrap().is_none());
         check_no_pending_invoices(&storage);
 
         // test it goes to pending
@@ -1823,7 +3105,7 @@ mod wasm_test {
         let result = nwc
             .handle_nwc_request(event.clone(), &node, &nostr_manager)
             .await;
-        assert_eq!(result.unwrap(), None);
+        assert!(result.unwrap().is_none());
 
         let pending: Vec<PendingNwcInvoice> = storage
             .get_data(PENDING_NWC_EVENTS_KEY)
@@ -1865,6 +3147,7 @@ mod wasm_test {
             event_id: EventId::all_zeros(),
             pubkey,
             identifier: None,
+            encryption: EncryptionScheme::Nip04,
         };
         // add an unexpired invoice
         let dummy_invoice = create_dummy_invoice(Some(1_000), Network::Regtest, None).0;
@@ -1874,6 +3157,7 @@ mod wasm_test {
             event_id: EventId::all_zeros(),
             pubkey,
             identifier: None,
+            encryption: EncryptionScheme::Nip04,
         };
         storage
             .set_data(
@@ -1986,6 +3270,99 @@ mod wasm_test {
         assert_eq!(pending[0].pubkey, event.pubkey);
     }
 
+    #[test]
+    async fn test_process_nwc_event_spending_rules() {
+        let storage = MemoryStorage::default();
+        let mw = create_mutiny_wallet(storage.clone()).await;
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            mw.logger.clone(),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::Budget(BudgetedSpendingConditions {
+                    budget: 10_000,
+                    single_max: None,
+                    payments: vec![],
+                    period: BudgetPeriod::Seconds(10),
+                }),
+                NwcProfileTag::General,
+                vec![Method::PayInvoice],
+            )
+            .unwrap();
+
+        let payee_key = bitcoin::secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let secp = Secp256k1::new();
+        let payee = PublicKey::from_secret_key(&secp, &payee_key);
+        let rules = SpendingRules {
+            payees: Some(PayeeFilter::Deny(vec![Payee::Node(payee)])),
+            approval_threshold: Some(100),
+            ..Default::default()
+        };
+        let profile = nostr_manager
+            .set_nwc_profile_spending_rules(profile.index, rules)
+            .unwrap();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        // denied payee gets an error naming the rule
+        let (invoice, _) = create_dummy_invoice(Some(10_000), Network::Regtest, Some(payee_key));
+        let event = create_nwc_request(&uri, invoice.to_string());
+        let result = nwc.handle_nwc_request(event, &mw, &nostr_manager).await;
+        check_nwc_error_response(
+            result.unwrap().unwrap(),
+            &uri.secret,
+            SpendingRule::PayeeDenylist.error(),
+        );
+        check_no_pending_invoices(&storage);
+
+        // payments above the threshold need approval, even within the budget
+        let (invoice, _) = create_dummy_invoice(Some(101_000), Network::Regtest, None);
+        let event = create_nwc_request(&uri, invoice.to_string());
+        let result = nwc
+            .handle_nwc_request(event.clone(), &mw, &nostr_manager)
+            .await;
+        assert!(result.unwrap().is_none());
+        let pending = nostr_manager.get_pending_nwc_invoices().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].invoice, invoice);
+        assert_eq!(pending[0].event_id, event.id);
+        nostr_manager.deny_all_pending_nwc().await.unwrap();
+
+        // expired profiles can't pay at all
+        let rules = SpendingRules {
+            expires_at: Some(utils::now().as_secs()),
+            ..Default::default()
+        };
+        let profile = nostr_manager
+            .set_nwc_profile_spending_rules(profile.index, rules)
+            .unwrap();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let (invoice, _) = create_dummy_invoice(Some(10_000), Network::Regtest, None);
+        let event = create_nwc_request(&uri, invoice.to_string());
+        let result = nwc.handle_nwc_request(event, &mw, &nostr_manager).await;
+        check_nwc_error_response(
+            result.unwrap().unwrap(),
+            &uri.secret,
+            SpendingRule::Expiry.error(),
+        );
+        check_no_pending_invoices(&storage);
+    }
+
     #[test]
     async fn test_process_nwc_event_budget() {
         let storage = MemoryStorage::default();
@@ -2473,4 +3850,563 @@ mod wasm_test {
             .await;
         assert!(result.is_err());
     }
+
+    #[test]
+    async fn test_list_transactions() {
+        let storage = MemoryStorage::default();
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            Arc::new(MutinyLogger::default()),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::RequireApproval,
+                NwcProfileTag::General,
+                vec![Method::ListTransactions],
+            )
+            .unwrap();
+
+        let paid = MutinyInvoice {
+            inbound: true,
+            status: HTLCStatus::Succeeded,
+            amount_sats: Some(1_000),
+            labels: vec!["test".to_string()],
+            last_updated: 100,
+            ..Default::default()
+        };
+        let unpaid = MutinyInvoice {
+            status: HTLCStatus::Pending,
+            labels: vec!["test".to_string()],
+            last_updated: 200,
+            ..Default::default()
+        };
+        let other_profile = MutinyInvoice {
+            status: HTLCStatus::Succeeded,
+            labels: vec!["other".to_string()],
+            last_updated: 300,
+            ..Default::default()
+        };
+        let payments = vec![paid, unpaid, other_profile];
+
+        let mut node = MockInvoiceHandler::new();
+        node.expect_list_payments()
+            .returning(move || Ok(payments.clone()));
+
+        let secp = Secp256k1::new();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        // only settled payments from this profile are listed by default
+        let event = sign_nwc_request(
+            &uri,
+            Request {
+                method: Method::ListTransactions,
+                params: RequestParams::ListTransactions(ListTransactionsRequestParams {
+                    from: None,
+                    until: None,
+                    limit: None,
+                    offset: None,
+                    unpaid: None,
+                    transaction_type: None,
+                }),
+            },
+        );
+        let event = nwc
+            .handle_nwc_request(event, &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert_eq!(response.result_type, Method::ListTransactions);
+        let transactions = match response.result {
+            Some(ResponseResult::ListTransactions(transactions)) => transactions,
+            _ => panic!("unexpected response"),
+        };
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].amount, 1_000_000);
+        assert_eq!(
+            transactions[0].transaction_type,
+            Some(TransactionType::Incoming)
+        );
+
+        // include unpaid, newest first
+        let event = sign_nwc_request(
+            &uri,
+            Request {
+                method: Method::ListTransactions,
+                params: RequestParams::ListTransactions(ListTransactionsRequestParams {
+                    from: None,
+                    until: None,
+                    limit: None,
+                    offset: None,
+                    unpaid: Some(true),
+                    transaction_type: None,
+                }),
+            },
+        );
+        let event = nwc
+            .handle_nwc_request(event, &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        let transactions = match response.result {
+            Some(ResponseResult::ListTransactions(transactions)) => transactions,
+            _ => panic!("unexpected response"),
+        };
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].created_at, 200);
+        assert_eq!(transactions[1].created_at, 100);
+    }
+
+    #[test]
+    async fn test_pay_keysend_budget() {
+        let storage = MemoryStorage::default();
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            Arc::new(MutinyLogger::default()),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let budget = 10_000;
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::Budget(BudgetedSpendingConditions {
+                    budget,
+                    single_max: None,
+                    payments: vec![],
+                    period: BudgetPeriod::Seconds(10),
+                }),
+                NwcProfileTag::General,
+                vec![Method::PayKeysend],
+            )
+            .unwrap();
+
+        // the node pays with the preimage it is given
+        let mut node = MockInvoiceHandler::new();
+        node.expect_keysend()
+            .once()
+            .returning(move |_, _, _, preimage, _| {
+                let preimage = preimage.expect("preimage should be picked up front");
+                Ok(MutinyInvoice {
+                    preimage: Some(preimage.to_lower_hex_string()),
+                    payment_hash: sha256::Hash::hash(&preimage),
+                    status: HTLCStatus::Succeeded,
+                    amount_sats: Some(budget),
+                    ..Default::default()
+                })
+            });
+        node.expect_lookup_payment().returning(|_| None);
+
+        let secp = Secp256k1::new();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        let keysend = |amount: u64| {
+            sign_nwc_request(
+                &uri,
+                Request {
+                    method: Method::PayKeysend,
+                    params: RequestParams::PayKeysend(PayKeysendRequestParams {
+                        id: None,
+                        amount,
+                        pubkey:
+                            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54"
+                                .to_string(),
+                        preimage: None,
+                        tlv_records: vec![],
+                    }),
+                },
+            )
+        };
+
+        // pay the whole budget
+        let event = nwc
+            .handle_nwc_request(keysend(budget * 1_000), &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert_eq!(response.result_type, Method::PayKeysend);
+        assert!(response.error.is_none());
+        let preimage: [u8; 32] = match response.result {
+            Some(ResponseResult::PayKeysend(result)) => {
+                FromHex::from_hex(&result.preimage).unwrap()
+            }
+            _ => panic!("unexpected response"),
+        };
+        let payment_hash = sha256::Hash::hash(&preimage).to_byte_array();
+
+        // the payment is tracked under its real payment hash
+        match &nwc.profile.spending_conditions {
+            SpendingConditions::Budget(budget) => {
+                assert_eq!(budget.payments.len(), 1);
+                assert_eq!(budget.payments[0].hash, payment_hash.to_lower_hex_string());
+            }
+            _ => panic!("unexpected spending conditions"),
+        }
+
+        // budget is used up
+        let event = nwc
+            .handle_nwc_request(keysend(1_000), &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        let error = response.error.unwrap();
+        assert!(matches!(error.code, ErrorCode::QuotaExceeded));
+    }
+
+    #[test]
+    async fn test_pay_keysend_timeout() {
+        let storage = MemoryStorage::default();
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            Arc::new(MutinyLogger::default()),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let budget = 10_000;
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::Budget(BudgetedSpendingConditions {
+                    budget,
+                    single_max: None,
+                    payments: vec![],
+                    period: BudgetPeriod::Seconds(10),
+                }),
+                NwcProfileTag::General,
+                vec![Method::PayKeysend],
+            )
+            .unwrap();
+
+        // first times out, then succeeds without giving us a preimage
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let timed_out = Arc::new(std::sync::Mutex::new(None));
+        let mut node = MockInvoiceHandler::new();
+        let timed_out_hash = timed_out.clone();
+        node.expect_keysend()
+            .times(2)
+            .returning(move |_, _, _, preimage, _| {
+                match calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                    0 => {
+                        let hash = sha256::Hash::hash(&preimage.unwrap()).to_byte_array();
+                        *timed_out_hash.lock().unwrap() = Some(hash);
+                        Err(MutinyError::PaymentTimeout)
+                    }
+                    _ => Ok(MutinyInvoice {
+                        status: HTLCStatus::Succeeded,
+                        amount_sats: Some(budget),
+                        ..Default::default()
+                    }),
+                }
+            });
+        // the timed out payment later fails
+        node.expect_lookup_payment().returning(|hash| {
+            Some(MutinyInvoice {
+                payment_hash: sha256::Hash::from_byte_array(*hash),
+                status: HTLCStatus::Failed,
+                ..Default::default()
+            })
+        });
+
+        let secp = Secp256k1::new();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        let keysend = || {
+            sign_nwc_request(
+                &uri,
+                Request {
+                    method: Method::PayKeysend,
+                    params: RequestParams::PayKeysend(PayKeysendRequestParams {
+                        id: None,
+                        amount: budget * 1_000,
+                        pubkey:
+                            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54"
+                                .to_string(),
+                        preimage: None,
+                        tlv_records: vec![],
+                    }),
+                },
+            )
+        };
+
+        let event = nwc
+            .handle_nwc_request(keysend(), &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert!(matches!(response.error.unwrap().code, ErrorCode::Internal));
+
+        // the timed out payment stays reserved under its payment hash
+        let hash = timed_out.lock().unwrap().unwrap().to_lower_hex_string();
+        match &nwc.profile.spending_conditions {
+            SpendingConditions::Budget(budget) => {
+                assert_eq!(budget.payments.len(), 1);
+                assert_eq!(budget.payments[0].hash, hash);
+            }
+            _ => panic!("unexpected spending conditions"),
+        }
+
+        // once it has failed the budget is freed again,
+        // and a payment without a preimage is an error instead of a panic
+        let event = nwc
+            .handle_nwc_request(keysend(), &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert!(response.result.is_none());
+        assert!(matches!(response.error.unwrap().code, ErrorCode::Internal));
+    }
+
+    #[test]
+    async fn test_nip44_request() {
+        let storage = MemoryStorage::default();
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            Arc::new(MutinyLogger::default()),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::RequireApproval,
+                NwcProfileTag::General,
+                vec![Method::GetBalance],
+            )
+            .unwrap();
+
+        let secp = Secp256k1::new();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        // we advertise both schemes
+        let info = nwc.create_nwc_info_event().unwrap();
+        assert!(info.tags.contains(&Tag::Generic(
+            TagKind::Custom(NWC_ENCRYPTION_TAG.to_string()),
+            vec!["nip44_v2 nip04".to_string()],
+        )));
+
+        let encrypted = encryption::encrypt(
+            EncryptionScheme::Nip44V2,
+            &uri.secret,
+            &uri.public_key,
+            Request::get_balance().as_json(),
+        )
+        .unwrap();
+        let tags = [
+            Tag::public_key(uri.public_key),
+            Tag::Generic(
+                TagKind::Custom(NWC_ENCRYPTION_TAG.to_string()),
+                vec!["nip44_v2".to_string()],
+            ),
+        ];
+        let event = EventBuilder::new(Kind::WalletConnectRequest, encrypted, tags)
+            .to_event(&Keys::new(uri.secret.clone()))
+            .unwrap();
+
+        let node = MockInvoiceHandler::new();
+        let event = nwc
+            .handle_nwc_request(event, &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the response uses the same scheme as the request
+        assert_eq!(
+            EncryptionScheme::detect(&event.content),
+            EncryptionScheme::Nip44V2
+        );
+        let content = encryption::decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert_eq!(response.to_get_balance().unwrap().balance, 0);
+
+        // unknown schemes are rejected over NIP-04
+        let encrypted = encrypt(
+            &uri.secret,
+            &uri.public_key,
+            Request::get_balance().as_json(),
+        )
+        .unwrap();
+        let tags = [
+            Tag::public_key(uri.public_key),
+            Tag::Generic(
+                TagKind::Custom(NWC_ENCRYPTION_TAG.to_string()),
+                vec!["nip44_v3".to_string()],
+            ),
+        ];
+        let event = EventBuilder::new(Kind::WalletConnectRequest, encrypted, tags)
+            .to_event(&Keys::new(uri.secret.clone()))
+            .unwrap();
+        let event = nwc
+            .handle_nwc_request(event, &node, &nostr_manager)
+            .await
+            .unwrap()
+            .unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let response: Response = Response::from_json(content).unwrap();
+        assert!(matches!(
+            response.error.unwrap().code,
+            ErrorCode::NotImplemented
+        ));
+    }
+
+    #[test]
+    async fn test_nwc_notifications() {
+        let storage = MemoryStorage::default();
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Regtest, &[0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let nostr_manager = NostrManager::from_mnemonic(
+            xprivkey,
+            NostrKeySource::Derived,
+            storage.clone(),
+            MockPrimalApi::new(),
+            get_mock_nostr_client(),
+            Arc::new(MutinyLogger::default()),
+            stop,
+        )
+        .await
+        .unwrap();
+
+        let profile = nostr_manager
+            .create_new_nwc_profile_internal(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                SpendingConditions::RequireApproval,
+                NwcProfileTag::General,
+                vec![Method::MakeInvoice],
+            )
+            .unwrap();
+        assert!(profile.notifications);
+
+        let secp = Secp256k1::new();
+        let mut nwc = NostrWalletConnect::new(&secp, xprivkey, profile.profile()).unwrap();
+        let uri = nwc.get_nwc_uri().unwrap().unwrap();
+
+        // notifications are advertised in the info event
+        let info = nwc.create_nwc_info_event().unwrap();
+        assert_eq!(info.content, "make_invoice notifications");
+        assert!(info.tags.iter().any(|t| t.as_vec()
+            == vec![
+                NWC_NOTIFICATIONS_TAG.to_string(),
+                "payment_received payment_sent".to_string()
+            ]));
+
+        let received = MutinyInvoice {
+            inbound: true,
+            status: HTLCStatus::Succeeded,
+            amount_sats: Some(1_000),
+            labels: vec!["test".to_string()],
+            last_updated: 100,
+            ..Default::default()
+        };
+        let event = nwc
+            .create_nwc_notification_event(&received)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.kind, NWC_NOTIFICATION_KIND);
+        assert_eq!(event.pubkey, nwc.server_pubkey());
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let notification: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(notification["notification_type"], "payment_received");
+        assert_eq!(notification["notification"]["amount"], 1_000_000);
+        assert_eq!(notification["notification"]["settled_at"], 100);
+
+        let sent = MutinyInvoice {
+            inbound: false,
+            ..received.clone()
+        };
+        let event = nwc.create_nwc_notification_event(&sent).unwrap().unwrap();
+        let content = decrypt(&uri.secret, &event.pubkey, &event.content).unwrap();
+        let notification: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(notification["notification_type"], "payment_sent");
+
+        // payments from other profiles or still pending are not sent
+        let other_profile = MutinyInvoice {
+            labels: vec!["other".to_string()],
+            ..received.clone()
+        };
+        assert!(nwc
+            .create_nwc_notification_event(&other_profile)
+            .unwrap()
+            .is_none());
+        let pending = MutinyInvoice {
+            status: HTLCStatus::Pending,
+            ..received.clone()
+        };
+        assert!(nwc
+            .create_nwc_notification_event(&pending)
+            .unwrap()
+            .is_none());
+
+        // nothing is sent once notifications are disabled
+        nwc.profile.notifications = false;
+        assert!(nwc
+            .create_nwc_notification_event(&received)
+            .unwrap()
+            .is_none());
+        let info = nwc.create_nwc_info_event().unwrap();
+        assert_eq!(info.content, "make_invoice");
+    }
 }
@@ -0,0 +1,252 @@
+use crate::error::MutinyError;
+use crate::nostr::client::NostrClient;
+use crate::nostr::encryption::{self, EncryptionScheme};
+use crate::utils;
+use anyhow::anyhow;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::{sha256, Hash};
+use nostr::nips::nip47::*;
+use nostr::{EventBuilder, Filter, JsonUtil, Keys, Kind, PublicKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long each relay query waits for a response to arrive
+const RESPONSE_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An external wallet we use over Nostr Wallet Connect,
+/// its balance is spent and received into alongside our federations and node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalWallet {
+    pub name: String,
+    /// The `nostr+walletconnect://` URI given by the wallet
+    pub uri: String,
+    /// Methods the wallet advertised in its info event
+    pub methods: Vec<Method>,
+    /// Whether to create invoices with this wallet before our node,
+    /// otherwise it is only used when our node can't make one
+    #[serde(default)]
+    pub prefer_for_invoices: bool,
+}
+
+impl ExternalWallet {
+    pub(crate) fn nwc_uri(&self) -> Result<NostrWalletConnectURI, MutinyError> {
+        NostrWalletConnectURI::from_str(&self.uri).map_err(|_| MutinyError::InvalidArgumentsError)
+    }
+
+    /// The public key of the wallet service, this identifies the wallet
+    pub fn wallet_pubkey(&self) -> Result<PublicKey, MutinyError> {
+        Ok(self.nwc_uri()?.public_key)
+    }
+
+    pub fn supports(&self, method: Method) -> bool {
+        self.methods.contains(&method)
+    }
+}
+
+/// The balance of an external wallet, in sats
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalWalletBalance {
+    pub name: String,
+    pub wallet_pubkey: PublicKey,
+    pub balance: u64,
+}
+
+/// The last balances we got from our external wallets
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CachedExternalWalletBalances {
+    pub balances: Vec<ExternalWalletBalance>,
+    /// Time the balances were fetched, in seconds since epoch
+    pub last_updated: u64,
+}
+
+/// An invoice we created or paid with an external wallet that has not settled yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ExternalWalletInvoice {
+    pub payment_hash: sha256::Hash,
+    pub wallet_pubkey: PublicKey,
+    /// Time the invoice expires, in seconds since epoch
+    pub expire: u64,
+}
+
+/// Fetches the methods a wallet supports from its info event.
+///
+/// If the wallet has not published an info event we only assume it can pay invoices.
+pub(crate) async fn get_wallet_methods<C: NostrClient>(
+    client: &C,
+    uri: &NostrWalletConnectURI,
+) -> Result<Vec<Method>, MutinyError> {
+    let relay = uri.relay_url.to_string();
+    client.add_relay(&relay).await?;
+    client.connect_relay(&relay).await?;
+
+    let filter = Filter::new()
+        .kind(Kind::WalletConnectInfo)
+        .author(uri.public_key)
+        .limit(1);
+    let events = client
+        .get_events_of(vec![filter], Some(RESPONSE_QUERY_TIMEOUT))
+        .await?;
+
+    let methods = match events.into_iter().max_by_key(|e| e.created_at) {
+        None => vec![Method::PayInvoice],
+        Some(info) => info
+            .content
+            .split_whitespace()
+            .filter_map(|m| Method::from_str(m).ok())
+            .collect(),
+    };
+
+    Ok(methods)
+}
+
+/// Sends a request to an external wallet and waits for its response.
+///
+/// Errors returned by the wallet are mapped to the closest [`MutinyError`].
+/// If the wallet doesn't respond in time this returns [`MutinyError::ConnectionFailed`],
+/// the request may still be carried out.
+pub(crate) async fn send_request<C: NostrClient>(
+    client: &C,
+    uri: &NostrWalletConnectURI,
+    request: Request,
+    timeout: Duration,
+) -> Result<ResponseResult, MutinyError> {
+    let keys = Keys::new(uri.secret.clone());
+    let encrypted = encryption::encrypt(
+        EncryptionScheme::Nip04,
+        &uri.secret,
+        &uri.public_key,
+        request.as_json(),
+    )?;
+    let event = EventBuilder::new(
+        Kind::WalletConnectRequest,
+        encrypted,
+        [Tag::public_key(uri.public_key)],
+    )
+    .to_event(&keys)?;
+
+    let relay = uri.relay_url.to_string();
+    client.add_relay(&relay).await?;
+    client.connect_relay(&relay).await?;
+    let request_id = client.send_event_to(vec![relay], event).await?;
+
+    let filter = Filter::new()
+        .kind(Kind::WalletConnectResponse)
+        .author(uri.public_key)
+        .pubkey(keys.public_key())
+        .event(request_id);
+
+    let start = utils::now();
+    loop {
+        let events = client
+            .get_events_of(vec![filter.clone()], Some(RESPONSE_QUERY_TIMEOUT))
+            .await?;
+
+        if let Some(event) = events.into_iter().next() {
+            let decrypted = encryption::decrypt(&uri.secret, &uri.public_key, &event.content)?;
+            let response = Response::from_json(decrypted).map_err(|_| MutinyError::NostrError)?;
+
+            if let Some(error) = response.error {
+                return Err(wallet_error(error));
+            }
+
+            return response.result.ok_or(MutinyError::NostrError);
+        }
+
+        if utils::now().saturating_sub(start) > timeout {
+            return Err(MutinyError::ConnectionFailed);
+        }
+
+        utils::sleep(1_000).await;
+    }
+}
+
+/// Whether a preimage returned by a wallet is actually the preimage of the payment
+pub(crate) fn preimage_matches(preimage: &str, payment_hash: &sha256::Hash) -> bool {
+    let preimage: Result<[u8; 32], _> = FromHex::from_hex(preimage);
+    preimage.is_ok_and(|preimage| sha256::Hash::hash(&preimage) == *payment_hash)
+}
+
+fn wallet_error(error: NIP47Error) -> MutinyError {
+    match error.code {
+        ErrorCode::InsufficientBalance | ErrorCode::QuotaExceeded => {
+            MutinyError::InsufficientBalance
+        }
+        ErrorCode::PaymentFailed => MutinyError::RoutingFailed,
+        code => MutinyError::Other(anyhow!(
+            "External wallet returned {code:?}: {}",
+            error.message
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_conservative::DisplayHex;
+
+    #[test]
+    fn test_wallet_error() {
+        let error = |code| NIP47Error {
+            code,
+            message: "failed".to_string(),
+        };
+
+        assert!(matches!(
+            wallet_error(error(ErrorCode::InsufficientBalance)),
+            MutinyError::InsufficientBalance
+        ));
+        assert!(matches!(
+            wallet_error(error(ErrorCode::PaymentFailed)),
+            MutinyError::RoutingFailed
+        ));
+        assert!(matches!(
+            wallet_error(error(ErrorCode::Internal)),
+            MutinyError::Other(_)
+        ));
+    }
+
+    #[test]
+    fn test_preimage_matches() {
+        let preimage = [7u8; 32];
+        let payment_hash = sha256::Hash::hash(&preimage);
+
+        assert!(preimage_matches(
+            &preimage.to_lower_hex_string(),
+            &payment_hash
+        ));
+        assert!(!preimage_matches(
+            &[8u8; 32].to_lower_hex_string(),
+            &payment_hash
+        ));
+        assert!(!preimage_matches("not hex", &payment_hash));
+        assert!(!preimage_matches("", &payment_hash));
+    }
+
+    #[test]
+    fn test_external_wallet_uri() {
+        let keys = Keys::generate();
+        let wallet = ExternalWallet {
+            name: "home node".to_string(),
+            uri: NostrWalletConnectURI::new(
+                keys.public_key(),
+                "wss://relay.example.com".parse().unwrap(),
+                Keys::generate().secret_key().unwrap().clone(),
+                None,
+            )
+            .to_string(),
+            methods: vec![Method::PayInvoice, Method::GetBalance],
+            prefer_for_invoices: false,
+        };
+
+        assert_eq!(wallet.wallet_pubkey().unwrap(), keys.public_key());
+        assert!(wallet.supports(Method::GetBalance));
+        assert!(!wallet.supports(Method::MakeInvoice));
+
+        let invalid = ExternalWallet {
+            uri: "nostr+walletconnect://invalid".to_string(),
+            ..wallet
+        };
+        assert!(invalid.wallet_pubkey().is_err());
+    }
+}
@@ -0,0 +1,634 @@
+use crate::error::MutinyError;
+use crate::logging::MutinyLogger;
+use crate::nostr::client::NostrClient;
+use crate::nostr::nip65::{get_relay_lists, select_outbox_relays, OutboxPool};
+use crate::nostr::primal::{PrimalApi, PrimalClient, TrustedUser};
+use crate::storage::{MutinyStorage, RELAY_CACHE_PREFIX_KEY};
+use crate::utils;
+use lightning::util::logger::Logger;
+use lightning::{log_debug, log_warn};
+use nostr::{Event, EventId, Filter, JsonUtil, Kind, Metadata, PublicKey, Tag, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+const RELAY_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How far before our last sync we look for new DMs again,
+/// messages can reach relays a while after they were created
+const DM_SYNC_OVERLAP_SECS: u64 = 60 * 60;
+
+fn metadata_key(npub: &PublicKey) -> String {
+    format!("{RELAY_CACHE_PREFIX_KEY}metadata/{}", npub.to_hex())
+}
+
+fn contact_list_key(npub: &PublicKey) -> String {
+    format!("{RELAY_CACHE_PREFIX_KEY}contacts/{}", npub.to_hex())
+}
+
+fn conversation_key(npub1: &PublicKey, npub2: &PublicKey) -> String {
+    // same key regardless of who is first
+    let (a, b) = (npub1.to_hex(), npub2.to_hex());
+    let (a, b) = if a < b { (a, b) } else { (b, a) };
+    format!("{RELAY_CACHE_PREFIX_KEY}dms/{a}/{b}")
+}
+
+/// The messages of a DM conversation we have fetched from relays
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedConversation {
+    events: Vec<Event>,
+    /// Time we last fetched new messages, in seconds since epoch
+    synced_at: Option<u64>,
+    /// We have every message from this time until `synced_at`
+    history_start: u64,
+}
+
+impl CachedConversation {
+    /// Adds events we don't have yet, returns the number of new events
+    fn merge(&mut self, events: Vec<Event>) -> usize {
+        let known: HashSet<EventId> = self.events.iter().map(|e| e.id).collect();
+        let before = self.events.len();
+        self.events
+            .extend(events.into_iter().filter(|e| !known.contains(&e.id)));
+        self.events.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        self.events.len() - before
+    }
+
+    /// Gets a page of messages, newest first
+    fn page(&self, limit: u64, until: Option<u64>, since: Option<u64>) -> Vec<Event> {
+        self.events
+            .iter()
+            .filter(|e| until.map_or(true, |until| e.created_at.as_u64() <= until))
+            .filter(|e| since.map_or(true, |since| e.created_at.as_u64() >= since))
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+/// How far back the DMs from a query with a `limit` per author cover both authors.
+///
+/// An author that hit the limit may have older messages we didn't get, so we only
+/// have every message back to the latest of those cutoffs. Authors below the limit
+/// gave us everything back to `floor`.
+fn dm_history_start(events: &[Event], authors: [PublicKey; 2], limit: u64, floor: u64) -> u64 {
+    authors
+        .iter()
+        .map(|author| {
+            let mut times: Vec<u64> = events
+                .iter()
+                .filter(|e| e.pubkey == *author)
+                .map(|e| e.created_at.as_u64())
+                .collect();
+            if (times.len() as u64) < limit {
+                return floor;
+            }
+            // each relay gave us its newest messages up to the limit,
+            // so we have all of them back to the limit-th newest
+            times.sort_unstable_by(|a, b| b.cmp(a));
+            times[limit as usize - 1]
+        })
+        .max()
+        .unwrap_or(floor)
+}
+
+/// Implements the [`PrimalApi`] by querying our relays directly.
+///
+/// Events are verified, deduplicated across relays and cached in storage,
+/// so we only ask relays for what changed since we last synced. If the relays
+/// can't be reached we answer from the cache.
+#[derive(Clone)]
+pub struct RelayApi<S: MutinyStorage, C: NostrClient> {
+    storage: S,
+    client: C,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<S: MutinyStorage, C: NostrClient> RelayApi<S, C> {
+    pub fn new(storage: S, client: C, logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            storage,
+            client,
+            logger,
+        }
+    }
+
+    /// Queries our relays, dropping any events with invalid signatures.
+    /// Relays that return the same event are deduplicated by the client.
+    async fn query(&self, filters: Vec<Filter>) -> Result<Vec<Event>, MutinyError> {
+        let events = self
+            .client
+            .get_events_of(filters, Some(RELAY_QUERY_TIMEOUT))
+            .await?;
+
+        Ok(events.into_iter().filter(|e| e.verify().is_ok()).collect())
+    }
+
+    /// Queries our relays and the `outbox` relays,
+    /// dropping any events with invalid signatures
+    async fn query_with_outbox(
+        &self,
+        filters: Vec<Filter>,
+        outbox: &OutboxPool<C>,
+    ) -> Result<Vec<Event>, MutinyError> {
+        let events = outbox
+            .get_events_of(&self.client, filters, Some(RELAY_QUERY_TIMEOUT))
+            .await?;
+
+        Ok(events.into_iter().filter(|e| e.verify().is_ok()).collect())
+    }
+
+    /// Fetches the newest replaceable event of `kind` for each author,
+    /// only asking for events newer than the ones we have cached.
+    async fn sync_replaceable(
+        &self,
+        kind: Kind,
+        authors: Vec<PublicKey>,
+        key: fn(&PublicKey) -> String,
+        outbox: &OutboxPool<C>,
+    ) -> Result<HashMap<PublicKey, Event>, MutinyError> {
+        let mut cached: HashMap<PublicKey, Event> = HashMap::with_capacity(authors.len());
+        for author in authors.iter() {
+            if let Some(event) = self.storage.get_data::<Event>(key(author))? {
+                cached.insert(*author, event);
+            }
+        }
+
+        // if we have all of them cached, only ask for newer ones
+        let mut filter = Filter::new().kind(kind).authors(authors.clone());
+        if cached.len() == authors.len() {
+            if let Some(oldest) = cached.values().map(|e| e.created_at).min() {
+                filter = filter.since(oldest);
+            }
+        }
+
+        let events = match self.query_with_outbox(vec![filter], outbox).await {
+            Ok(events) => events,
+            Err(e) => {
+                log_warn!(self.logger, "Failed to query relays, using cache: {e}");
+                return Ok(cached);
+            }
+        };
+
+        let mut updated = HashMap::new();
+        for event in events {
+            if event.kind != kind || !authors.contains(&event.pubkey) {
+                continue;
+            }
+            let newer = cached
+                .get(&event.pubkey)
+                .map_or(true, |current| event.created_at > current.created_at);
+            if newer {
+                updated.insert(event.pubkey, event);
+            }
+        }
+
+        if !updated.is_empty() {
+            let items = updated
+                .iter()
+                .map(|(pk, event)| (key(pk), event))
+                .collect::<Vec<_>>();
+            self.storage.set(items)?;
+            cached.extend(updated);
+        }
+
+        Ok(cached)
+    }
+
+    /// Connects to the NIP-65 write relays of the given users, where their
+    /// events should be fetched from, in a pool apart from our relays
+    async fn connect_outbox(&self, authors: &[PublicKey]) -> OutboxPool<C> {
+        let relays = match get_relay_lists(
+            &self.storage,
+            &self.client,
+            authors.to_vec(),
+            &self.logger,
+        )
+        .await
+        {
+            Ok(lists) => select_outbox_relays(lists.values(), false),
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get outbox relays: {e}");
+                Vec::new()
+            }
+        };
+
+        OutboxPool::connect(&self.client, relays, &self.logger).await
+    }
+}
+
+impl<S: MutinyStorage, C: NostrClient> PrimalApi for RelayApi<S, C> {
+    async fn get_user_profile(&self, npub: PublicKey) -> Result<Option<Metadata>, MutinyError> {
+        let mut profiles = self.get_user_profiles(vec![npub]).await?;
+        Ok(profiles.remove(&npub))
+    }
+
+    async fn get_user_profiles(
+        &self,
+        npubs: Vec<PublicKey>,
+    ) -> Result<HashMap<PublicKey, Metadata>, MutinyError> {
+        if npubs.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let outbox = self.connect_outbox(&npubs).await;
+        let events = self
+            .sync_replaceable(Kind::Metadata, npubs, metadata_key, &outbox)
+            .await;
+        outbox.disconnect(&self.logger).await;
+        let events = events?;
+
+        Ok(events
+            .into_iter()
+            .filter_map(|(pk, e)| Metadata::from_json(&e.content).ok().map(|m| (pk, m)))
+            .collect())
+    }
+
+    async fn get_nostr_contacts(
+        &self,
+        npub: PublicKey,
+    ) -> Result<(Option<Event>, HashMap<PublicKey, Metadata>), MutinyError> {
+        let outbox = self.connect_outbox(&[npub]).await;
+        let contact_list = self
+            .sync_replaceable(Kind::ContactList, vec![npub], contact_list_key, &outbox)
+            .await;
+        outbox.disconnect(&self.logger).await;
+        let contact_list = contact_list?.remove(&npub);
+
+        let Some(contact_list) = contact_list else {
+            return Ok((None, HashMap::new()));
+        };
+
+        let contacts = contact_list
+            .iter_tags()
+            .filter_map(|tag| match tag {
+                Tag::PublicKey {
+                    public_key,
+                    uppercase: false,
+                    ..
+                } => Some(*public_key),
+                _ => None,
+            })
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let metadata = self.get_user_profiles(contacts).await?;
+
+        Ok((Some(contact_list), metadata))
+    }
+
+    async fn get_dm_conversation(
+        &self,
+        npub1: PublicKey,
+        npub2: PublicKey,
+        limit: u64,
+        until: Option<u64>,
+        since: Option<u64>,
+    ) -> Result<Vec<Event>, MutinyError> {
+        let key = conversation_key(&npub1, &npub2);
+        let mut cached: CachedConversation = self.storage.get_data(&key)?.unwrap_or_default();
+
+        let filters = |since: Option<u64>, until: Option<u64>, limit: Option<u64>| {
+            [(npub1, npub2), (npub2, npub1)]
+                .into_iter()
+                .map(|(author, receiver)| {
+                    let mut filter = Filter::new()
+                        .kind(Kind::EncryptedDirectMessage)
+                        .author(author)
+                        .pubkey(receiver);
+                    if let Some(limit) = limit {
+                        filter = filter.limit(limit as usize);
+                    }
+                    if let Some(since) = since {
+                        filter = filter.since(Timestamp::from(since));
+                    }
+                    if let Some(until) = until {
+                        filter = filter.until(Timestamp::from(until));
+                    }
+                    filter
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let is_message = |e: &Event| {
+            e.kind == Kind::EncryptedDirectMessage && (e.pubkey == npub1 || e.pubkey == npub2)
+        };
+
+        // fetch any messages since we last synced, everything since then
+        // so there are no gaps, or the latest page if this is our first sync.
+        // Look a bit further back than our last sync for messages that reached
+        // relays late, the ones we already have are skipped when merging.
+        let now = utils::now().as_secs();
+        let new_since = cached
+            .synced_at
+            .map(|synced_at| synced_at.saturating_sub(DM_SYNC_OVERLAP_SECS));
+        let new_limit = cached.synced_at.is_none().then_some(limit);
+        match self.query(filters(new_since, None, new_limit)).await {
+            Ok(events) => {
+                let events: Vec<Event> = events.into_iter().filter(is_message).collect();
+                if cached.synced_at.is_none() {
+                    // first sync, we only have history back to where both authors' messages reach
+                    cached.history_start = dm_history_start(&events, [npub1, npub2], limit, 0);
+                }
+                let new = cached.merge(events);
+                log_debug!(self.logger, "Fetched {new} new dms from relays");
+                cached.synced_at = Some(now);
+            }
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "Failed to fetch dms from relays, using cache: {e}"
+                );
+                return Ok(cached.page(limit, until, since));
+            }
+        }
+
+        // fetch older messages if we don't have enough cached for this page
+        let page = cached.page(limit, until, since);
+        let needs_history = since.map_or(true, |since| since < cached.history_start);
+        if (page.len() as u64) < limit && cached.history_start > 0 && needs_history {
+            let older_until = until.map_or(cached.history_start, |u| u.min(cached.history_start));
+            match self
+                .query(filters(since, Some(older_until), Some(limit)))
+                .await
+            {
+                Ok(events) => {
+                    let events: Vec<Event> = events.into_iter().filter(is_message).collect();
+                    let floor = since.unwrap_or_default();
+                    cached.history_start = dm_history_start(&events, [npub1, npub2], limit, floor)
+                        .min(cached.history_start);
+                    cached.merge(events);
+                }
+                Err(e) => log_warn!(self.logger, "Failed to fetch older dms from relays: {e}"),
+            }
+        }
+
+        self.storage.set_data(key, &cached, None)?;
+
+        Ok(cached.page(limit, until, since))
+    }
+
+    async fn get_trusted_users(&self, _limit: u32) -> Result<Vec<TrustedUser>, MutinyError> {
+        // trust ranks are computed by primal, relays have no equivalent
+        Ok(Vec::new())
+    }
+}
+
+/// The [`PrimalApi`] used by the wallet
+pub type NostrApi<S> = FallbackPrimalClient<PrimalClient, S, nostr_sdk::Client>;
+
+/// Uses primal for profiles and contacts, falling back to our relays
+/// when it is unavailable. DMs are always synced from our relays.
+#[derive(Clone)]
+pub struct FallbackPrimalClient<P: PrimalApi, S: MutinyStorage, C: NostrClient> {
+    pub primal: P,
+    pub relays: RelayApi<S, C>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<P: PrimalApi, S: MutinyStorage, C: NostrClient> FallbackPrimalClient<P, S, C> {
+    pub fn new(primal: P, relays: RelayApi<S, C>, logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            primal,
+            relays,
+            logger,
+        }
+    }
+}
+
+impl<P: PrimalApi, S: MutinyStorage, C: NostrClient> PrimalApi for FallbackPrimalClient<P, S, C> {
+    async fn get_user_profile(&self, npub: PublicKey) -> Result<Option<Metadata>, MutinyError> {
+        match self.primal.get_user_profile(npub).await {
+            Ok(profile) => Ok(profile),
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "Failed to get profile from primal, using relays: {e}"
+                );
+                self.relays.get_user_profile(npub).await
+            }
+        }
+    }
+
+    async fn get_user_profiles(
+        &self,
+        npubs: Vec<PublicKey>,
+    ) -> Result<HashMap<PublicKey, Metadata>, MutinyError> {
+        match self.primal.get_user_profiles(npubs.clone()).await {
+            Ok(profiles) => Ok(profiles),
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "Failed to get profiles from primal, using relays: {e}"
+                );
+                self.relays.get_user_profiles(npubs).await
+            }
+        }
+    }
+
+    async fn get_nostr_contacts(
+        &self,
+        npub: PublicKey,
+    ) -> Result<(Option<Event>, HashMap<PublicKey, Metadata>), MutinyError> {
+        match self.primal.get_nostr_contacts(npub).await {
+            Ok(contacts) => Ok(contacts),
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "Failed to get contacts from primal, using relays: {e}"
+                );
+                self.relays.get_nostr_contacts(npub).await
+            }
+        }
+    }
+
+    async fn get_dm_conversation(
+        &self,
+        npub1: PublicKey,
+        npub2: PublicKey,
+        limit: u64,
+        until: Option<u64>,
+        since: Option<u64>,
+    ) -> Result<Vec<Event>, MutinyError> {
+        self.relays
+            .get_dm_conversation(npub1, npub2, limit, until, since)
+            .await
+    }
+
+    async fn get_trusted_users(&self, limit: u32) -> Result<Vec<TrustedUser>, MutinyError> {
+        match self.primal.get_trusted_users(limit).await {
+            Ok(users) => Ok(users),
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get trusted users from primal: {e}");
+                self.relays.get_trusted_users(limit).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nostr::client::MockNostrClient;
+    use crate::nostr::primal::MockPrimalApi;
+    use crate::storage::MemoryStorage;
+    use nostr::{EventBuilder, Keys};
+
+    fn create_dm(from: &Keys, to: PublicKey, msg: &str) -> Event {
+        EventBuilder::new(Kind::EncryptedDirectMessage, msg, vec![Tag::public_key(to)])
+            .to_event(from)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_relay_dm_conversation() {
+        let storage = MemoryStorage::new(None, None, None);
+        let logger = Arc::new(MutinyLogger::default());
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+
+        let first = create_dm(&alice, bob.public_key(), "hello");
+        let second = create_dm(&bob, alice.public_key(), "hi");
+        let unrelated = create_dm(&Keys::generate(), bob.public_key(), "spam");
+
+        let mut client = MockNostrClient::new();
+        let events = vec![first.clone(), second.clone(), unrelated];
+        client
+            .expect_get_events_of()
+            .once()
+            .returning(move |_, _| Ok(events.clone()));
+        let api = RelayApi::new(storage.clone(), client, logger.clone());
+
+        let messages = api
+            .get_dm_conversation(alice.public_key(), bob.public_key(), 10, None, None)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.contains(&first));
+        assert!(messages.contains(&second));
+
+        // next sync looks back a little past the last one, duplicates are ignored
+        let third = create_dm(&alice, bob.public_key(), "how are you?");
+        let cached: CachedConversation = storage
+            .get_data(conversation_key(&alice.public_key(), &bob.public_key()))
+            .unwrap()
+            .unwrap();
+        let since = cached.synced_at.unwrap() - DM_SYNC_OVERLAP_SECS;
+        let mut client = MockNostrClient::new();
+        let events = vec![second.clone(), third.clone()];
+        client
+            .expect_get_events_of()
+            .once()
+            .withf(move |filters, _| filters[0].since == Some(Timestamp::from(since)))
+            .returning(move |_, _| Ok(events.clone()));
+        let api = RelayApi::new(storage, client, logger);
+
+        // same conversation, no matter the order of the npubs
+        let messages = api
+            .get_dm_conversation(bob.public_key(), alice.public_key(), 10, None, None)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(messages.contains(&third));
+    }
+
+    #[test]
+    fn test_dm_history_start() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let dm = |from: &Keys, to: PublicKey, time: u64| {
+            EventBuilder::new(
+                Kind::EncryptedDirectMessage,
+                "hi",
+                vec![Tag::public_key(to)],
+            )
+            .custom_created_at(Timestamp::from(time))
+            .to_event(from)
+            .unwrap()
+        };
+        let authors = [alice.public_key(), bob.public_key()];
+
+        // both below the limit, we have everything
+        let events = vec![dm(&alice, bob.public_key(), 10)];
+        assert_eq!(dm_history_start(&events, authors, 2, 0), 0);
+
+        // alice hit the limit long ago, bob is chatty and hit it recently,
+        // so we only have both sides back to bob's cutoff
+        let events = vec![
+            dm(&alice, bob.public_key(), 10),
+            dm(&alice, bob.public_key(), 20),
+            dm(&bob, alice.public_key(), 100),
+            dm(&bob, alice.public_key(), 110),
+        ];
+        assert_eq!(dm_history_start(&events, authors, 2, 0), 100);
+
+        // one author below the limit covers back to the floor
+        let events = vec![
+            dm(&alice, bob.public_key(), 10),
+            dm(&bob, alice.public_key(), 100),
+            dm(&bob, alice.public_key(), 110),
+        ];
+        assert_eq!(dm_history_start(&events, authors, 2, 5), 100);
+
+        // more than the limit from several relays, the limit-th newest is covered everywhere
+        let events = vec![
+            dm(&bob, alice.public_key(), 50),
+            dm(&bob, alice.public_key(), 100),
+            dm(&bob, alice.public_key(), 110),
+        ];
+        assert_eq!(dm_history_start(&events, authors, 2, 0), 100);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_relays() {
+        let storage = MemoryStorage::new(None, None, None);
+        let logger = Arc::new(MutinyLogger::default());
+        let user = Keys::generate();
+        let alice = Keys::generate();
+
+        let contact_list = EventBuilder::new(
+            Kind::ContactList,
+            "",
+            vec![Tag::public_key(alice.public_key())],
+        )
+        .to_event(&user)
+        .unwrap();
+        let metadata = EventBuilder::metadata(&Metadata::new().name("alice"))
+            .to_event(&alice)
+            .unwrap();
+
+        let mut client = MockNostrClient::new();
+        let events = vec![contact_list.clone(), metadata];
+        client
+            .expect_get_events_of()
+            .times(4)
+            .returning(move |_, _| Ok(events.clone()));
+
+        let mut primal = MockPrimalApi::new();
+        primal
+            .expect_get_nostr_contacts()
+            .once()
+            .returning(|_| Err(MutinyError::NostrError));
+
+        let api = FallbackPrimalClient::new(
+            primal,
+            RelayApi::new(storage.clone(), client, logger.clone()),
+            logger,
+        );
+
+        let (list, contacts) = api.get_nostr_contacts(user.public_key()).await.unwrap();
+        assert_eq!(list, Some(contact_list.clone()));
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(
+            contacts.get(&alice.public_key()).unwrap().name,
+            Some("alice".to_string())
+        );
+
+        // events are cached for when the relays are unavailable
+        let cached: Option<Event> = storage
+            .get_data(contact_list_key(&user.public_key()))
+            .unwrap();
+        assert_eq!(cached, Some(contact_list));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::logging::MutinyLogger;
+use crate::PrivacyLevel;
+use anyhow::anyhow;
+use bitcoin::hashes::{sha256, Hash};
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+use nostr::prelude::decrypt_received_private_zap_message;
+use nostr::{Event, EventId, JsonUtil, Keys, Kind, PublicKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// A zap we sent or received
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Zap {
+    /// Payment hash of the zap's invoice, links it to its [`crate::MutinyInvoice`]
+    pub payment_hash: sha256::Hash,
+    /// If we received the zap
+    pub inbound: bool,
+    pub amount_msats: u64,
+    /// Who sent the zap, None if it is anonymous
+    pub sender: Option<PublicKey>,
+    pub recipient: PublicKey,
+    pub message: Option<String>,
+    pub privacy_level: PrivacyLevel,
+    /// The note that was zapped, if any
+    pub zapped_event: Option<EventId>,
+    pub zap_request_id: EventId,
+    /// Public key the recipient's LNURL provider signs zap receipts with
+    pub provider: Option<PublicKey>,
+    /// The verified zap receipt, None until the provider publishes it
+    pub receipt_id: Option<EventId>,
+    /// Contact the zap was sent to or received from
+    pub contact_id: Option<String>,
+    /// Time of the zap, in seconds since epoch
+    pub created_at: u64,
+}
+
+/// A zap receipt that matched its invoice and zap request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VerifiedZapReceipt {
+    pub id: EventId,
+    pub bolt11: Bolt11Invoice,
+    pub zap_request: Event,
+    /// The zapped user, from the zap request
+    pub recipient: PublicKey,
+    pub amount_msats: u64,
+    pub created_at: u64,
+}
+
+impl VerifiedZapReceipt {
+    pub fn payment_hash(&self) -> sha256::Hash {
+        *self.bolt11.payment_hash()
+    }
+
+    /// The note that was zapped, if any
+    pub fn zapped_event(&self) -> Option<EventId> {
+        tag_value(&self.zap_request, "e").and_then(|id| EventId::from_hex(&id).ok())
+    }
+}
+
+/// Gets the payment hash of a zap receipt's invoice without verifying it,
+/// used to find which zap the receipt is for
+pub(crate) fn receipt_payment_hash(receipt: &Event) -> Option<sha256::Hash> {
+    let bolt11 = tag_value(receipt, "bolt11")?;
+    Bolt11Invoice::from_str(&bolt11)
+        .ok()
+        .map(|invoice| *invoice.payment_hash())
+}
+
+/// Verifies a kind 9735 zap receipt as described in NIP-57.
+///
+/// The receipt must be signed by `provider` if it is known, its bolt11
+/// invoice must commit to the zap request it carries, and the amounts must match.
+pub(crate) fn verify_zap_receipt(
+    receipt: &Event,
+    provider: Option<PublicKey>,
+) -> anyhow::Result<VerifiedZapReceipt> {
+    if receipt.kind != Kind::ZapReceipt {
+        return Err(anyhow!("Not a zap receipt"));
+    }
+    receipt.verify()?;
+
+    if provider.is_some_and(|pk| pk != receipt.pubkey) {
+        return Err(anyhow!("Zap receipt not signed by the LNURL provider"));
+    }
+
+    let bolt11 = tag_value(receipt, "bolt11").ok_or(anyhow!("Zap receipt missing bolt11"))?;
+    let bolt11 = Bolt11Invoice::from_str(&bolt11)?;
+
+    let description =
+        tag_value(receipt, "description").ok_or(anyhow!("Zap receipt missing description"))?;
+    match bolt11.description() {
+        Bolt11InvoiceDescription::Hash(hash)
+            if hash.0 == sha256::Hash::hash(description.as_bytes()) => {}
+        _ => return Err(anyhow!("Invoice does not commit to the zap request")),
+    }
+
+    let zap_request = Event::from_json(&description)?;
+    if zap_request.kind != Kind::ZapRequest {
+        return Err(anyhow!("Zap receipt description is not a zap request"));
+    }
+    zap_request.verify()?;
+
+    let amount_msats = bolt11
+        .amount_milli_satoshis()
+        .ok_or(anyhow!("Zap invoice has no amount"))?;
+    if let Some(requested) = tag_value(&zap_request, "amount") {
+        if requested.parse::<u64>().ok() != Some(amount_msats) {
+            return Err(anyhow!("Zap invoice amount does not match the zap request"));
+        }
+    }
+
+    let recipient = tag_value(&zap_request, "p")
+        .and_then(|pk| PublicKey::from_hex(&pk).ok())
+        .ok_or(anyhow!("Zap request missing recipient"))?;
+
+    Ok(VerifiedZapReceipt {
+        id: receipt.id,
+        bolt11,
+        zap_request,
+        recipient,
+        amount_msats,
+        created_at: receipt.created_at.as_u64(),
+    })
+}
+
+/// Gets the privacy level, message and sender of a zap request.
+///
+/// Private zaps are decrypted with `profile_key` if we have it,
+/// otherwise they are treated as anonymous.
+pub(crate) fn zap_request_sender(
+    zap_req: &Event,
+    profile_key: Option<&Keys>,
+    logger: &MutinyLogger,
+) -> (PrivacyLevel, Option<String>, Option<PublicKey>) {
+    // handle private/anon zaps
+    let anon = zap_req.iter_tags().find_map(|tag| {
+        if let Tag::Anon { msg } = tag {
+            if msg.is_some() {
+                // an Anon tag with a message is a private zap
+                // try to decrypt the message and use that as the message
+                Some(handle_private_zap(zap_req, profile_key, logger))
+            } else {
+                // an Anon tag with no message is an anonymous zap
+                // the content of the zap is the message
+                Some((PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None))
+            }
+        } else {
+            None
+        }
+    });
+
+    // handled the anon tag, if there wasn't one, it is a public zap
+    anon.unwrap_or((
+        PrivacyLevel::Public,
+        Some(zap_req.content.clone()),
+        Some(zap_req.pubkey),
+    ))
+}
+
+fn handle_private_zap(
+    zap_req: &Event,
+    profile_key: Option<&Keys>,
+    logger: &MutinyLogger,
+) -> (PrivacyLevel, Option<String>, Option<PublicKey>) {
+    let key = match profile_key.and_then(|k| k.secret_key().ok()) {
+        Some(k) => k,
+        None => {
+            log_error!(logger, "No primary key to decrypt private zap");
+            // We can't decrypt the message, treat it as an anonymous zap
+            return (PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None);
+        }
+    };
+    // try to decrypt the message
+    match decrypt_received_private_zap_message(key, zap_req) {
+        Ok(event) => (
+            PrivacyLevel::Private,
+            Some(event.content.clone()),
+            Some(event.pubkey),
+        ),
+        Err(e) => {
+            // if we can't decrypt, treat it like it's an anonymous zap
+            log_error!(logger, "Error decrypting private zap: {e}");
+            (PrivacyLevel::Anonymous, Some(zap_req.content.clone()), None)
+        }
+    }
+}
+
+/// Gets the first value of the tag with the given name
+fn tag_value(event: &Event, name: &str) -> Option<String> {
+    event.iter_tags().find_map(|tag| {
+        let values = tag.as_vec();
+        if values.first().map(String::as_str) == Some(name) {
+            values.get(1).cloned()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::now;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning::ln::PaymentSecret;
+    use lightning_invoice::{Currency, InvoiceBuilder};
+    use nostr::nips::nip57::{anonymous_zap_request, ZapRequestData};
+    use nostr::EventBuilder;
+
+    fn create_zap(recipient: PublicKey, amount_msats: u64, description: &str) -> Bolt11Invoice {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[2; 32]).unwrap();
+        InvoiceBuilder::new(Currency::Regtest)
+            .description_hash(sha256::Hash::hash(description.as_bytes()))
+            .duration_since_epoch(now())
+            .payment_hash(sha256::Hash::hash(recipient.to_string().as_bytes()))
+            .payment_secret(PaymentSecret([0; 32]))
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(amount_msats)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &sk))
+            .unwrap()
+    }
+
+    fn zap_request(recipient: PublicKey, amount_msats: u64) -> Event {
+        anonymous_zap_request(ZapRequestData {
+            public_key: recipient,
+            relays: vec![],
+            message: "great post".to_string(),
+            amount: Some(amount_msats),
+            lnurl: None,
+            event_id: None,
+            event_coordinate: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_zap_receipt() {
+        let provider = Keys::generate();
+        let recipient = Keys::generate().public_key();
+
+        let request = zap_request(recipient, 21_000);
+        let bolt11 = create_zap(recipient, 21_000, &request.as_json());
+        let receipt = EventBuilder::zap_receipt(bolt11.to_string(), None, &request)
+            .to_event(&provider)
+            .unwrap();
+
+        assert_eq!(receipt_payment_hash(&receipt), Some(*bolt11.payment_hash()));
+
+        let verified = verify_zap_receipt(&receipt, Some(provider.public_key())).unwrap();
+        assert_eq!(verified.id, receipt.id);
+        assert_eq!(verified.payment_hash(), *bolt11.payment_hash());
+        assert_eq!(verified.zap_request, request);
+        assert_eq!(verified.recipient, recipient);
+        assert_eq!(verified.amount_msats, 21_000);
+        assert_eq!(verified.zapped_event(), None);
+
+        // signed by someone other than the provider
+        let other = Keys::generate().public_key();
+        assert!(verify_zap_receipt(&receipt, Some(other)).is_err());
+
+        // invoice that doesn't commit to the zap request
+        let bolt11 = create_zap(recipient, 21_000, "something else");
+        let receipt = EventBuilder::zap_receipt(bolt11.to_string(), None, &request)
+            .to_event(&provider)
+            .unwrap();
+        assert!(verify_zap_receipt(&receipt, None).is_err());
+
+        // invoice for a different amount than requested
+        let request = zap_request(recipient, 1_000);
+        let bolt11 = create_zap(recipient, 21_000, &request.as_json());
+        let receipt = EventBuilder::zap_receipt(bolt11.to_string(), None, &request)
+            .to_event(&provider)
+            .unwrap();
+        assert!(verify_zap_receipt(&receipt, None).is_err());
+    }
+
+    #[test]
+    fn test_zap_request_sender() {
+        let logger = MutinyLogger::default();
+        let recipient = Keys::generate();
+
+        let request = zap_request(recipient.public_key(), 21_000);
+        let (privacy_level, message, sender) =
+            zap_request_sender(&request, Some(&recipient), &logger);
+        assert_eq!(privacy_level, PrivacyLevel::Anonymous);
+        assert_eq!(message, Some("great post".to_string()));
+        assert_eq!(sender, None);
+
+        let sender_keys = Keys::generate();
+        let request = EventBuilder::public_zap_request(ZapRequestData {
+            public_key: recipient.public_key(),
+            relays: vec![],
+            message: "hello".to_string(),
+            amount: None,
+            lnurl: None,
+            event_id: None,
+            event_coordinate: None,
+        })
+        .to_event(&sender_keys)
+        .unwrap();
+        let (privacy_level, message, sender) =
+            zap_request_sender(&request, Some(&recipient), &logger);
+        assert_eq!(privacy_level, PrivacyLevel::Public);
+        assert_eq!(message, Some("hello".to_string()));
+        assert_eq!(sender, Some(sender_keys.public_key()));
+    }
+}
@@ -22,6 +22,7 @@ use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error, log_info, log_trace, log_warn};
 
 use crate::error::MutinyError;
+use crate::event::{EventBroadcaster, MutinyEvent};
 use crate::fees::MutinyFeeEstimator;
 use crate::labels::*;
 use crate::logging::MutinyLogger;
@@ -43,6 +44,7 @@ pub struct OnChainWallet<S: MutinyStorage> {
     pub blockchain: Arc<AsyncClient>,
     pub fees: Arc<MutinyFeeEstimator<S>>,
     pub(crate) stop: Arc<AtomicBool>,
+    pub(crate) event_broadcaster: EventBroadcaster,
     logger: Arc<MutinyLogger>,
 }
 
@@ -101,6 +103,7 @@ impl<S: MutinyStorage> OnChainWallet<S> {
             blockchain: esplora,
             fees,
             stop,
+            event_broadcaster: EventBroadcaster::default(),
             logger,
         })
     }
@@ -144,9 +147,9 @@ impl<S: MutinyStorage> OnChainWallet<S> {
                     // update the activity index, just get the list of transactions
                     // and insert them into the index, this is done in background so shouldn't
                     // block the wallet update
-                    let index_items = self
-                        .list_transactions(false)?
-                        .into_iter()
+                    let txs = self.list_transactions(false)?;
+                    let index_items = txs
+                        .iter()
                         .map(|t| IndexItem {
                             timestamp: match t.confirmation_time {
                                 ConfirmationTime::Confirmed { time, .. } => Some(time),
@@ -158,9 +161,32 @@ impl<S: MutinyStorage> OnChainWallet<S> {
 
                     let index = self.storage.activity_index();
                     let mut index = index.try_write()?;
+                    // keep track of which txs were unconfirmed before this update
+                    let previously_unconfirmed = index
+                        .iter()
+                        .filter(|i| i.key.starts_with(ONCHAIN_PREFIX) && i.timestamp.is_none())
+                        .map(|i| i.key.clone())
+                        .collect::<HashSet<_>>();
                     // remove old-onchain txs
                     index.retain(|i| !i.key.starts_with(ONCHAIN_PREFIX));
                     index.extend(index_items);
+                    drop(index);
+
+                    // notify subscribers of any newly confirmed txs
+                    for tx in txs {
+                        if let ConfirmationTime::Confirmed { height, .. } = tx.confirmation_time {
+                            let key = format!("{ONCHAIN_PREFIX}{}", tx.internal_id);
+                            if previously_unconfirmed.contains(&key) {
+                                self.event_broadcaster.broadcast(
+                                    MutinyEvent::TransactionConfirmed {
+                                        txid: tx.internal_id,
+                                        height,
+                                        federation_id: None,
+                                    },
+                                );
+                            }
+                        }
+                    }
 
                     Ok(true)
                 }
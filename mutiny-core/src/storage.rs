@@ -1,10 +1,13 @@
-use crate::nodemanager::{ChannelClosure, NodeStorage};
+use crate::cashu::{CashuMintQuote, CashuPendingSend, CashuProofs};
+use crate::nodemanager::{ChannelAcceptancePolicy, ChannelClosure, NodeStorage};
 use crate::utils::{now, spawn};
 use crate::vss::{MutinyVssClient, VssKeyValueItem};
 use crate::{blindauth::TokenStorage, logging::MutinyLogger};
 use crate::{
     encrypt::{decrypt_with_password, encrypt, encryption_key_from_pass, Cipher},
-    federation::FederationStorage,
+    federation::{
+        DepositStatus, EcashTransfer, FederationDeposit, FederationStorage, GatewayPreferences,
+    },
     DEVICE_LOCK_INTERVAL_SECS,
 };
 use crate::{
@@ -18,6 +21,7 @@ use async_trait::async_trait;
 use bdk::chain::{Append, PersistBackend};
 use bip39::Mnemonic;
 use bitcoin::{secp256k1::ThirtyTwoByteHash, Txid};
+use fedimint_core::config::FederationId;
 use fedimint_ln_common::bitcoin::hashes::hex::ToHex;
 use futures_util::lock::Mutex;
 use hex_conservative::*;
@@ -28,6 +32,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, RwLock};
+use url::Url;
 use uuid::Uuid;
 
 pub const SUBSCRIPTION_TIMESTAMP: &str = "subscription_timestamp";
@@ -48,10 +53,31 @@ pub const PAYMENT_INBOUND_PREFIX_KEY: &str = "payment_inbound/";
 pub const PAYMENT_OUTBOUND_PREFIX_KEY: &str = "payment_outbound/";
 pub const TRANSACTION_DETAILS_PREFIX_KEY: &str = "transaction_details/";
 pub(crate) const ONCHAIN_PREFIX: &str = "onchain_tx/";
+pub const ECASH_TRANSFER_PREFIX_KEY: &str = "ecash_transfer/";
+pub const FEDERATION_DEPOSIT_PREFIX_KEY: &str = "federation_deposit/";
+pub const CASHU_PROOFS_PREFIX_KEY: &str = "cashu_proofs/";
+pub const CASHU_MINT_QUOTE_PREFIX_KEY: &str = "cashu_mint_quote/";
+pub const CASHU_PENDING_SEND_PREFIX_KEY: &str = "cashu_pending_send/";
 pub const LAST_DM_SYNC_TIME_KEY: &str = "last_dm_sync_time";
 pub const LAST_HERMES_SYNC_TIME_KEY: &str = "last_hermes_sync_time";
+pub const NIP17_PEERS_KEY: &str = "nip17_peers";
+pub const NIP17_DMS_KEY: &str = "nip17_dms";
+pub const EXTERNAL_WALLETS_KEY: &str = "external_nwc_wallets";
+pub const EXTERNAL_WALLET_INVOICES_KEY: &str = "external_nwc_wallet_invoices";
+pub const EXTERNAL_WALLET_PAYMENTS_KEY: &str = "external_nwc_wallet_payments";
+pub const EXTERNAL_WALLET_BALANCES_KEY: &str = "external_nwc_wallet_balances";
 pub const NOSTR_PROFILE_METADATA: &str = "nostr_profile_metadata";
 pub const NOSTR_CONTACT_LIST: &str = "nostr_contact_list";
+pub const NOSTR_RELAY_LIST_KEY: &str = "nostr_relay_list";
+pub const NOSTR_BUNKER_USER_KEY: &str = "nostr_bunker_user";
+pub const ZAPS_KEY: &str = "zaps";
+pub const LAST_ZAP_RECEIPT_SYNC_TIME_KEY: &str = "last_zap_receipt_sync_time";
+pub const ZAP_PROVIDER_KEY: &str = "zap_provider";
+pub const RELAY_CACHE_PREFIX_KEY: &str = "relay_cache/";
+pub const CHANNEL_ACCEPTANCE_POLICY_KEY: &str = "channel_acceptance_policy";
+pub const FEDERATION_MAX_BALANCES_KEY: &str = "federation_max_balances";
+pub const DISCOVERED_FEDERATION_CHECKS_KEY: &str = "discovered_federation_checks";
+pub const GATEWAY_PREFERENCES_PREFIX_KEY: &str = "gateway_preferences/";
 const DELAYED_WRITE_MS: i32 = 50;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -545,6 +571,60 @@ pub trait MutinyStorage: Clone + Sized + Send + Sync + 'static {
         self.set_data(BITCOIN_PRICE_CACHE_KEY.to_string(), prices, None)
     }
 
+    /// Gets the channel acceptance policy, defaults to accepting all channels
+    fn get_channel_acceptance_policy(&self) -> Result<ChannelAcceptancePolicy, MutinyError> {
+        Ok(self
+            .get_data(CHANNEL_ACCEPTANCE_POLICY_KEY)?
+            .unwrap_or_default())
+    }
+
+    /// Inserts the channel acceptance policy into storage
+    fn set_channel_acceptance_policy(
+        &self,
+        policy: &ChannelAcceptancePolicy,
+    ) -> Result<(), MutinyError> {
+        self.set_data(CHANNEL_ACCEPTANCE_POLICY_KEY.to_string(), policy, None)
+    }
+
+    /// Gets the user configured maximum balance, in sats, for each federation
+    /// keyed by federation id.
+    fn get_federation_max_balances(&self) -> Result<HashMap<String, u64>, MutinyError> {
+        Ok(self
+            .get_data(FEDERATION_MAX_BALANCES_KEY)?
+            .unwrap_or_default())
+    }
+
+    /// Inserts the user configured maximum federation balances into storage
+    fn set_federation_max_balances(
+        &self,
+        max_balances: &HashMap<String, u64>,
+    ) -> Result<(), MutinyError> {
+        self.set_data(FEDERATION_MAX_BALANCES_KEY.to_string(), max_balances, None)
+    }
+
+    /// Gets the gateway preferences for a federation
+    fn get_gateway_preferences(
+        &self,
+        federation_id: &FederationId,
+    ) -> Result<GatewayPreferences, MutinyError> {
+        Ok(self
+            .get_data(format!("{GATEWAY_PREFERENCES_PREFIX_KEY}{federation_id}"))?
+            .unwrap_or_default())
+    }
+
+    /// Inserts the gateway preferences for a federation into storage
+    fn set_gateway_preferences(
+        &self,
+        federation_id: &FederationId,
+        preferences: &GatewayPreferences,
+    ) -> Result<(), MutinyError> {
+        self.set_data(
+            format!("{GATEWAY_PREFERENCES_PREFIX_KEY}{federation_id}"),
+            preferences,
+            None,
+        )
+    }
+
     fn has_done_first_sync(&self) -> Result<bool, MutinyError> {
         self.get_data::<bool>(FIRST_SYNC_KEY)
             .map(|v| v == Some(true))
@@ -606,7 +686,14 @@ pub trait MutinyStorage: Clone + Sized + Send + Sync + 'static {
             NOSTR_PROFILE_METADATA,
             LAST_DM_SYNC_TIME_KEY,
             NOSTR_CONTACT_LIST,
-        ])
+            NIP17_DMS_KEY,
+            ZAP_PROVIDER_KEY,
+        ])?;
+
+        // events we fetched from relays
+        let relay_cache = self.scan::<Value>(RELAY_CACHE_PREFIX_KEY, None)?;
+        let keys = relay_cache.into_keys().collect::<Vec<_>>();
+        self.delete(&keys)
     }
 
     fn get_device_id(&self) -> Result<String, MutinyError> {
@@ -958,6 +1045,180 @@ pub(crate) fn get_transaction_details<S: MutinyStorage>(
     }
 }
 
+pub(crate) fn ecash_transfer_key(operation_id: &str) -> String {
+    format!("{ECASH_TRANSFER_PREFIX_KEY}{operation_id}")
+}
+
+pub(crate) fn persist_ecash_transfer<S: MutinyStorage>(
+    storage: &S,
+    transfer: &EcashTransfer,
+) -> Result<(), MutinyError> {
+    let key = ecash_transfer_key(&transfer.operation_id);
+    storage.set_data(key.clone(), transfer, None)?;
+
+    // insert into activity index, replacing the old version
+    let index = storage.activity_index();
+    let mut index = index.try_write()?;
+    index.retain(|i| i.key != key);
+    index.insert(IndexItem {
+        timestamp: Some(transfer.last_updated),
+        key,
+    });
+
+    Ok(())
+}
+
+pub(crate) fn get_ecash_transfer<S: MutinyStorage>(
+    storage: &S,
+    operation_id: &str,
+    logger: &MutinyLogger,
+) -> Option<EcashTransfer> {
+    let key = ecash_transfer_key(operation_id);
+    log_trace!(logger, "Trace: checking ecash key: {key}");
+    match storage.get_data(&key).transpose() {
+        Some(Ok(v)) => Some(v),
+        _ => None,
+    }
+}
+
+pub(crate) fn federation_deposit_key(operation_id: &str) -> String {
+    format!("{FEDERATION_DEPOSIT_PREFIX_KEY}{operation_id}")
+}
+
+pub(crate) fn persist_federation_deposit<S: MutinyStorage>(
+    storage: &S,
+    deposit: &FederationDeposit,
+) -> Result<(), MutinyError> {
+    let key = federation_deposit_key(&deposit.operation_id);
+    storage.set_data(key.clone(), deposit, None)?;
+
+    // insert into activity index, replacing the old version.
+    // A deposit only shows up once a transaction to it is seen, and once
+    // the federation accepts the transaction it shows up as on-chain
+    // activity instead, so we drop it from the index.
+    let index = storage.activity_index();
+    let mut index = index.try_write()?;
+    index.retain(|i| i.key != key);
+    let timestamp = match deposit.status {
+        DepositStatus::WaitingForConfirmation => None,
+        DepositStatus::Failed => Some(deposit.last_updated),
+        DepositStatus::WaitingForTransaction
+        | DepositStatus::Confirmed
+        | DepositStatus::Claimed
+        | DepositStatus::Expired => {
+            return Ok(());
+        }
+    };
+    index.insert(IndexItem { timestamp, key });
+
+    Ok(())
+}
+
+pub(crate) fn get_federation_deposit<S: MutinyStorage>(
+    storage: &S,
+    operation_id: &str,
+) -> Result<Option<FederationDeposit>, MutinyError> {
+    storage.get_data(federation_deposit_key(operation_id))
+}
+
+pub(crate) fn list_federation_deposits<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<FederationDeposit>, MutinyError> {
+    let map: HashMap<String, FederationDeposit> =
+        storage.scan(FEDERATION_DEPOSIT_PREFIX_KEY, None)?;
+    Ok(map.into_values().collect())
+}
+
+pub(crate) fn cashu_proofs_key(mint_url: &Url) -> String {
+    format!("{CASHU_PROOFS_PREFIX_KEY}{mint_url}")
+}
+
+pub(crate) async fn persist_cashu_proofs<S: MutinyStorage>(
+    storage: &S,
+    proofs: &CashuProofs,
+) -> Result<(), MutinyError> {
+    // proofs are our money, so always back them up to VSS
+    storage
+        .set_data_async(
+            cashu_proofs_key(&proofs.mint_url),
+            proofs,
+            Some(proofs.version),
+        )
+        .await
+}
+
+pub(crate) fn get_cashu_proofs<S: MutinyStorage>(
+    storage: &S,
+    mint_url: &Url,
+) -> Result<Option<CashuProofs>, MutinyError> {
+    storage.get_data(cashu_proofs_key(mint_url))
+}
+
+pub(crate) fn list_cashu_proofs<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<CashuProofs>, MutinyError> {
+    let map: HashMap<String, CashuProofs> = storage.scan(CASHU_PROOFS_PREFIX_KEY, None)?;
+    Ok(map.into_values().collect())
+}
+
+pub(crate) fn cashu_mint_quote_key(quote: &str) -> String {
+    format!("{CASHU_MINT_QUOTE_PREFIX_KEY}{quote}")
+}
+
+pub(crate) fn persist_cashu_mint_quote<S: MutinyStorage>(
+    storage: &S,
+    quote: &CashuMintQuote,
+) -> Result<(), MutinyError> {
+    storage.set_data(cashu_mint_quote_key(&quote.quote), quote, None)
+}
+
+pub(crate) fn get_cashu_mint_quote<S: MutinyStorage>(
+    storage: &S,
+    quote: &str,
+) -> Result<Option<CashuMintQuote>, MutinyError> {
+    storage.get_data(cashu_mint_quote_key(quote))
+}
+
+pub(crate) fn list_cashu_mint_quotes<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<CashuMintQuote>, MutinyError> {
+    let map: HashMap<String, CashuMintQuote> = storage.scan(CASHU_MINT_QUOTE_PREFIX_KEY, None)?;
+    Ok(map.into_values().collect())
+}
+
+pub(crate) fn cashu_pending_send_key(id: &str) -> String {
+    format!("{CASHU_PENDING_SEND_PREFIX_KEY}{id}")
+}
+
+pub(crate) async fn persist_cashu_pending_send<S: MutinyStorage>(
+    storage: &S,
+    pending: &CashuPendingSend,
+) -> Result<(), MutinyError> {
+    // unclaimed sends are still our money, so back them up to VSS too
+    storage
+        .set_data_async(
+            cashu_pending_send_key(&pending.id),
+            pending,
+            Some(pending.version),
+        )
+        .await
+}
+
+pub(crate) fn get_cashu_pending_send<S: MutinyStorage>(
+    storage: &S,
+    id: &str,
+) -> Result<Option<CashuPendingSend>, MutinyError> {
+    storage.get_data(cashu_pending_send_key(id))
+}
+
+pub(crate) fn list_cashu_pending_sends<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<CashuPendingSend>, MutinyError> {
+    let map: HashMap<String, CashuPendingSend> =
+        storage.scan(CASHU_PENDING_SEND_PREFIX_KEY, None)?;
+    Ok(map.into_values().collect())
+}
+
 pub(crate) fn payment_key(inbound: bool, payment_hash: &[u8; 32]) -> String {
     if inbound {
         format!("{}{}", PAYMENT_INBOUND_PREFIX_KEY, payment_hash.as_hex())
@@ -1248,4 +1509,53 @@ mod tests {
             Err(crate::MutinyError::AlreadyRunning)
         );
     }
+
+    #[test]
+    fn federation_deposit_activity() {
+        use crate::federation::{DepositStatus, FederationDeposit};
+        use crate::storage::{federation_deposit_key, persist_federation_deposit};
+        use fedimint_core::config::FederationId;
+
+        let test_name = "federation_deposit_activity";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let mut deposit = FederationDeposit {
+            operation_id: "00".repeat(32),
+            federation_id: FederationId::dummy(),
+            address: "bcrt1qxyz".to_string(),
+            expected_amount: None,
+            txid: None,
+            amount: None,
+            confirmations: 0,
+            confirmations_required: 10,
+            status: DepositStatus::WaitingForTransaction,
+            labels: vec![],
+            created_at: 1_000,
+            expires_at: 2_000,
+            last_updated: 1_000,
+        };
+        let key = federation_deposit_key(&deposit.operation_id);
+        let in_index = || {
+            storage
+                .activity_index()
+                .try_read()
+                .unwrap()
+                .iter()
+                .any(|i| i.key == key)
+        };
+
+        // a new address isn't activity until something is sent to it
+        persist_federation_deposit(&storage, &deposit).unwrap();
+        assert!(!in_index());
+
+        deposit.status = DepositStatus::WaitingForConfirmation;
+        persist_federation_deposit(&storage, &deposit).unwrap();
+        assert!(in_index());
+
+        // once accepted it shows up as on-chain activity instead
+        deposit.status = DepositStatus::Confirmed;
+        persist_federation_deposit(&storage, &deposit).unwrap();
+        assert!(!in_index());
+    }
 }
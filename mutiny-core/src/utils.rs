@@ -15,6 +15,7 @@ use lightning::util::ser::Writeable;
 use lightning::util::ser::Writer;
 use lightning_invoice::Bolt11Invoice;
 use nostr::nips::nip05;
+use nostr::nips::nip46::NostrConnectURI;
 use nostr::{Event, Filter, FromBech32, JsonUtil, Keys, Kind, Metadata};
 use reqwest::Client;
 use serde_json::Value;
@@ -97,10 +98,19 @@ pub fn get_random_bip32_child_index() -> u32 {
 
 pub(crate) fn build_nostr_key_source(
     keys: Option<Keys>,
+    bunker: Option<NostrConnectURI>,
     #[cfg(target_arch = "wasm32")] extension_pk: Option<::nostr::PublicKey>,
 ) -> Result<NostrKeySource, MutinyError> {
     #[cfg(target_arch = "wasm32")]
-    if keys.is_some() && extension_pk.is_some() {
+    let extension_set = extension_pk.is_some();
+    #[cfg(not(target_arch = "wasm32"))]
+    let extension_set = false;
+
+    let sources_set = [keys.is_some(), bunker.is_some(), extension_set]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+    if sources_set > 1 {
         return Err(MutinyError::InvalidArgumentsError);
     }
 
@@ -109,6 +119,10 @@ pub(crate) fn build_nostr_key_source(
         return Ok(NostrKeySource::Extension(pk));
     }
 
+    if let Some(uri) = bunker {
+        return Ok(NostrKeySource::Bunker(uri));
+    }
+
     match keys {
         None => Ok(NostrKeySource::Derived),
         Some(keys) => Ok(NostrKeySource::Imported(keys)),
@@ -171,6 +171,12 @@ pub enum MutinyJsError {
     /// Token already spent.
     #[error("Token has been already spent.")]
     TokenAlreadySpent,
+    /// Cashu token could not be parsed.
+    #[error("Invalid cashu token.")]
+    InvalidCashuToken,
+    /// Cashu mint does not support what we need.
+    #[error("Cashu mint does not support this operation.")]
+    CashuMintUnsupported,
     /// Federation required.
     #[error("A federation is required")]
     FederationRequired,
@@ -235,6 +241,8 @@ impl From<MutinyError> for MutinyJsError {
             MutinyError::CashuMintError => MutinyJsError::CashuMintError,
             MutinyError::EmptyMintURLError => MutinyJsError::EmptyMintURLError,
             MutinyError::TokenAlreadySpent => MutinyJsError::TokenAlreadySpent,
+            MutinyError::InvalidCashuToken => MutinyJsError::InvalidCashuToken,
+            MutinyError::CashuMintUnsupported => MutinyJsError::CashuMintUnsupported,
             MutinyError::FederationRequired => MutinyJsError::FederationRequired,
             MutinyError::FederationConnectionFailed => MutinyJsError::FederationConnectionFailed,
             MutinyError::FederationTxTooLarge => MutinyJsError::FederationTxTooLarge,
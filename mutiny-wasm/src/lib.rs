@@ -23,18 +23,23 @@ use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::{Address, Network, OutPoint, Txid};
 use fedimint_core::{api::InviteCode, config::FederationId};
+use fedimint_mint_client::OOBNotes;
 use futures::lock::Mutex;
+use futures::StreamExt;
 use gloo_utils::format::JsValueSerdeExt;
 use hex_conservative::DisplayHex;
 use lightning::{log_error, log_info, log_warn, routing::gossip::NodeId, util::logger::Logger};
 use lightning_invoice::Bolt11Invoice;
 use lnurl::lightning_address::LightningAddress;
 use lnurl::lnurl::LnUrl;
-use moksha_core::token::TokenV3;
 use mutiny_core::auth::MutinyAuthClient;
+use mutiny_core::cashu::CashuToken;
 use mutiny_core::lnurlauth::AuthManager;
 use mutiny_core::nostr::nip49::NIP49URI;
-use mutiny_core::nostr::nwc::{BudgetedSpendingConditions, NwcProfileTag, SpendingConditions};
+use mutiny_core::nostr::nip65::RelayList;
+use mutiny_core::nostr::nwc::{
+    BudgetedSpendingConditions, NwcProfileTag, SpendingConditions, SpendingRules,
+};
 use mutiny_core::nostr::NostrKeySource;
 use mutiny_core::storage::{DeviceLock, MutinyStorage, DEVICE_LOCK_KEY};
 use mutiny_core::utils::{now, parse_npub, parse_npub_or_nip05, sleep, spawn};
@@ -45,19 +50,50 @@ use mutiny_core::{
 use mutiny_core::{labels::Contact, MutinyWalletBuilder};
 use mutiny_core::{
     labels::LabelStorage,
-    nodemanager::{create_lsp_config, NodeManager},
+    nodemanager::{create_lsp_config, ChannelAcceptancePolicy, NodeManager, PaymentOptions},
 };
 use mutiny_core::{logging::MutinyLogger, lsp::LspConfig, nostr::ProfileType};
+use nostr::nips::nip46::NostrConnectURI;
+use nostr::nips::nip47::NostrWalletConnectURI;
 use nostr::prelude::Method;
 use nostr::{Keys, ToBech32};
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use url::Url;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
 
 static INITIALIZED: once_cell::sync::Lazy<Mutex<bool>> =
     once_cell::sync::Lazy::new(|| Mutex::new(false));
 
+fn payment_options(
+    max_fee_sats: Option<u64>,
+    max_fee_ppm: Option<u64>,
+    retry_attempts: Option<u32>,
+    timeout_secs: Option<u64>,
+    max_path_count: Option<u8>,
+    excluded_first_hops: Vec<String>,
+    required_first_hops: Vec<String>,
+) -> Result<PaymentOptions, MutinyJsError> {
+    let parse_outpoints = |outpoints: Vec<String>| {
+        outpoints
+            .iter()
+            .map(|o| OutPoint::from_str(o).map_err(|_| MutinyJsError::InvalidArgumentsError))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    Ok(PaymentOptions {
+        max_fee_sats,
+        max_fee_ppm,
+        retry_attempts,
+        timeout_secs,
+        max_path_count,
+        excluded_first_hops: parse_outpoints(excluded_first_hops)?,
+        required_first_hops: parse_outpoints(required_first_hops)?,
+    })
+}
+
 #[cfg(test)]
 async fn uninit() {
     let mut init = INITIALIZED.lock().await;
@@ -107,11 +143,17 @@ impl MutinyWallet {
         primal_url: Option<String>,
         blind_auth_url: Option<String>,
         hermes_url: Option<String>,
+        nostr_connect_uri: Option<String>,
     ) -> Result<MutinyWallet, MutinyJsError> {
         let start = instant::Instant::now();
         // if both are set throw an error
         // todo default to nsec if both are for same key?
-        if nsec_override.is_some() && nip_07_key.is_some() {
+        let sources_set = [
+            nsec_override.is_some(),
+            nip_07_key.is_some(),
+            nostr_connect_uri.is_some(),
+        ];
+        if sources_set.into_iter().filter(|set| *set).count() > 1 {
             return Err(MutinyJsError::InvalidArgumentsError);
         }
 
@@ -146,6 +188,7 @@ impl MutinyWallet {
             primal_url,
             blind_auth_url,
             hermes_url,
+            nostr_connect_uri,
         )
         .await
         {
@@ -189,6 +232,7 @@ impl MutinyWallet {
         primal_url: Option<String>,
         blind_auth_url: Option<String>,
         hermes_url: Option<String>,
+        nostr_connect_uri: Option<String>,
     ) -> Result<MutinyWallet, MutinyJsError> {
         let safe_mode = safe_mode.unwrap_or(false);
         let logger = Arc::new(MutinyLogger::default());
@@ -328,6 +372,11 @@ impl MutinyWallet {
             let npub = parse_npub(&key)?;
             mw_builder.with_nostr_key_source(NostrKeySource::Extension(npub));
         }
+        if let Some(uri) = nostr_connect_uri {
+            let uri = NostrConnectURI::from_str(&uri)
+                .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            mw_builder.with_nostr_key_source(NostrKeySource::Bunker(uri));
+        }
         let inner = mw_builder.build().await?;
 
         Ok(MutinyWallet { mnemonic, inner })
@@ -422,6 +471,38 @@ impl MutinyWallet {
         Ok(self.inner.node_manager.stop().await?)
     }
 
+    /// Subscribes to wallet events such as received and sent payments,
+    /// channel changes, on-chain confirmations and NWC requests.
+    ///
+    /// The callback is called with each event as it happens.
+    /// Returns the subscription id to pass to `unsubscribe_events`.
+    #[wasm_bindgen]
+    pub fn subscribe_events(&self, callback: js_sys::Function) -> u32 {
+        let (id, mut events) = self.inner.subscribe_events();
+        let logger = self.inner.logger.clone();
+        spawn_local(async move {
+            while let Some(event) = events.next().await {
+                let value = match JsValue::from_serde(&event) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        log_error!(logger, "Failed to serialize event: {e}");
+                        continue;
+                    }
+                };
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    log_warn!(logger, "Event callback failed: {e:?}");
+                }
+            }
+        });
+        id
+    }
+
+    /// Stops calling the callback of an event subscription
+    #[wasm_bindgen]
+    pub fn unsubscribe_events(&self, id: u32) {
+        self.inner.unsubscribe_events(id)
+    }
+
     /// Returns the mnemonic seed phrase for the wallet.
     #[wasm_bindgen]
     pub fn show_seed(&self) -> String {
@@ -449,12 +530,13 @@ impl MutinyWallet {
             .map(|s| s.to_bech32().expect("bech32"))
     }
 
-    /// Change our active nostr keys to the given nsec
+    /// Change our active nostr keys to the given nsec, NIP-07 extension or NIP-46 bunker
     #[wasm_bindgen]
     pub async fn change_nostr_keys(
         &self,
         nsec: Option<String>,
         extension_pk: Option<String>,
+        nostr_connect_uri: Option<String>,
     ) -> Result<String, MutinyJsError> {
         let nsec = nsec
             .map(|n| Keys::parse(n).map_err(|_| MutinyJsError::InvalidArgumentsError))
@@ -462,13 +544,15 @@ impl MutinyWallet {
 
         let extension_pk = extension_pk.map(|p| parse_npub(&p)).transpose()?;
 
-        if nsec.is_some() && extension_pk.is_some() {
-            return Err(MutinyJsError::InvalidArgumentsError);
-        }
+        let bunker = nostr_connect_uri
+            .map(|uri| {
+                NostrConnectURI::from_str(&uri).map_err(|_| MutinyJsError::InvalidArgumentsError)
+            })
+            .transpose()?;
 
         Ok(self
             .inner
-            .change_nostr_keys(nsec, extension_pk)
+            .change_nostr_keys(nsec, bunker, extension_pk)
             .await
             .map(|pk| pk.to_bech32().expect("bech32"))?)
     }
@@ -772,6 +856,62 @@ impl MutinyWallet {
         }
     }
 
+    /// Returns the policy used to accept inbound channel open requests.
+    #[wasm_bindgen]
+    pub fn get_channel_acceptance_policy(
+        &self,
+    ) -> Result<JsValue /* ChannelAcceptancePolicy */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_channel_acceptance_policy()?,
+        )?)
+    }
+
+    /// Sets the policy used to accept inbound channel open requests.
+    ///
+    /// If allowed peers is set, only those peers can open channels to us.
+    /// Zero conf peers are trusted to open zero-conf channels.
+    /// Channel sizes are in satoshis.
+    /// Channels from the LSP are always accepted.
+    /// Private only takes effect once the node is restarted.
+    /// Rejected requests are sent as `ChannelRejected` events.
+    #[wasm_bindgen]
+    pub fn set_channel_acceptance_policy(
+        &self,
+        allowed_peers: Option<Vec<String>>,
+        zero_conf_peers: Vec<String>,
+        min_channel_size: Option<u64>,
+        max_channel_size: Option<u64>,
+        anchors_required: bool,
+        private_only: bool,
+    ) -> Result<(), MutinyJsError> {
+        let allowed_peers = allowed_peers
+            .map(|peers| {
+                peers
+                    .iter()
+                    .map(|p| PublicKey::from_str(p))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let zero_conf_peers = zero_conf_peers
+            .iter()
+            .map(|p| PublicKey::from_str(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let policy = ChannelAcceptancePolicy {
+            allowed_peers,
+            zero_conf_peers,
+            min_channel_size,
+            max_channel_size,
+            anchors_required,
+            private_only,
+        };
+
+        Ok(self
+            .inner
+            .node_manager
+            .set_channel_acceptance_policy(policy)?)
+    }
+
     /// Attempts to connect to a peer from the selected node.
     #[wasm_bindgen]
     pub async fn connect_to_peer(
@@ -844,6 +984,58 @@ impl MutinyWallet {
             .into())
     }
 
+    /// Pays a lightning invoice with limits on fees, retries, timeout and routing.
+    /// Fees should be in satoshis or parts per million, the timeout in seconds,
+    /// and first hop channels given as funding outpoints.
+    /// Only one of retry attempts and timeout can be set.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn pay_invoice_with_options(
+        &self,
+        invoice_str: String,
+        amt_sats: Option<u64>,
+        max_fee_sats: Option<u64>,
+        max_fee_ppm: Option<u64>,
+        retry_attempts: Option<u32>,
+        timeout_secs: Option<u64>,
+        max_path_count: Option<u8>,
+        excluded_first_hops: Vec<String>,
+        required_first_hops: Vec<String>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let invoice = Bolt11Invoice::from_str(&invoice_str)?;
+        let options = payment_options(
+            max_fee_sats,
+            max_fee_ppm,
+            retry_attempts,
+            timeout_secs,
+            max_path_count,
+            excluded_first_hops,
+            required_first_hops,
+        )?;
+        Ok(self
+            .inner
+            .pay_invoice_with_options(&invoice, amt_sats, &options, labels)
+            .await?
+            .into())
+    }
+
+    /// Waits for an outgoing payment to settle, even if paying it timed out.
+    /// Times out again if it is still in flight after the timeout, in seconds.
+    #[wasm_bindgen]
+    pub async fn await_payment_result(
+        &self,
+        hash: String,
+        timeout_secs: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&hash)?;
+        Ok(self
+            .inner
+            .await_payment_result(&hash, timeout_secs)
+            .await?
+            .into())
+    }
+
     /// Sends a spontaneous payment to a node from the selected node.
     /// The amount should be in satoshis.
     #[wasm_bindgen]
@@ -858,7 +1050,53 @@ impl MutinyWallet {
         Ok(self
             .inner
             .node_manager
-            .keysend(None, to_node, amt_sats, message, labels)
+            .keysend(
+                None,
+                to_node,
+                amt_sats,
+                message,
+                None,
+                &PaymentOptions::default(),
+                labels,
+            )
+            .await?
+            .into())
+    }
+
+    /// Sends a spontaneous payment with limits on fees, retries, timeout and routing.
+    /// Fees should be in satoshis or parts per million, the timeout in seconds,
+    /// and first hop channels given as funding outpoints.
+    /// Only one of retry attempts and timeout can be set.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn keysend_with_options(
+        &self,
+        to_node: String,
+        amt_sats: u64,
+        message: Option<String>,
+        max_fee_sats: Option<u64>,
+        max_fee_ppm: Option<u64>,
+        retry_attempts: Option<u32>,
+        timeout_secs: Option<u64>,
+        max_path_count: Option<u8>,
+        excluded_first_hops: Vec<String>,
+        required_first_hops: Vec<String>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let to_node = PublicKey::from_str(&to_node)?;
+        let options = payment_options(
+            max_fee_sats,
+            max_fee_ppm,
+            retry_attempts,
+            timeout_secs,
+            max_path_count,
+            excluded_first_hops,
+            required_first_hops,
+        )?;
+        Ok(self
+            .inner
+            .node_manager
+            .keysend(None, to_node, amt_sats, message, None, &options, labels)
             .await?
             .into())
     }
@@ -942,11 +1180,138 @@ impl MutinyWallet {
     pub async fn melt_cashu_token(
         &self,
         maybe_token: String,
-    ) -> Result<JsValue /* Vec<MutinyInvoice> */, MutinyJsError> {
-        let token = TokenV3::deserialize(maybe_token)?;
+    ) -> Result<JsValue /* Vec<CashuMeltResult> */, MutinyJsError> {
+        let token = CashuToken::from_str(&maybe_token)?;
         let result = self.inner.melt_cashu_token(token).await?;
-        let invoices: Vec<MutinyInvoice> = result.into_iter().map(|i| i.into()).collect();
-        Ok(JsValue::from_serde(&invoices)?)
+        let results: Vec<CashuMeltResult> = result.into_iter().map(|r| r.into()).collect();
+        Ok(JsValue::from_serde(&results)?)
+    }
+
+    /// Gets our cashu balance at each mint we hold ecash from.
+    #[wasm_bindgen]
+    pub fn list_cashu_balances(
+        &self,
+    ) -> Result<JsValue /* Vec<CashuMintBalance> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.list_cashu_balances()?)?)
+    }
+
+    /// Lists the requests we have made to mint cashu ecash.
+    #[wasm_bindgen]
+    pub fn list_cashu_mint_quotes(
+        &self,
+    ) -> Result<JsValue /* Vec<CashuMintQuote> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.list_cashu_mint_quotes()?)?)
+    }
+
+    /// Asks a cashu mint for an invoice that mints us ecash once paid.
+    #[wasm_bindgen]
+    pub async fn create_cashu_mint_quote(
+        &self,
+        mint_url: String,
+        amount: u64,
+    ) -> Result<JsValue /* CashuMintQuote */, MutinyJsError> {
+        let mint_url = Url::parse(&mint_url).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let quote = self.inner.create_cashu_mint_quote(mint_url, amount).await?;
+        Ok(JsValue::from_serde(&quote)?)
+    }
+
+    /// Claims the ecash for a mint quote if its invoice has been paid.
+    #[wasm_bindgen]
+    pub async fn mint_cashu(
+        &self,
+        quote: String,
+    ) -> Result<JsValue /* CashuMintQuote */, MutinyJsError> {
+        let quote = self.inner.mint_cashu(quote).await?;
+        Ok(JsValue::from_serde(&quote)?)
+    }
+
+    /// Pays a cashu mint from our lightning balance to mint ecash.
+    #[wasm_bindgen]
+    pub async fn fund_cashu_mint(
+        &self,
+        mint_url: String,
+        amount: u64,
+    ) -> Result<JsValue /* CashuMintQuote */, MutinyJsError> {
+        let mint_url = Url::parse(&mint_url).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let quote = self.inner.fund_cashu_mint(mint_url, amount).await?;
+        Ok(JsValue::from_serde(&quote)?)
+    }
+
+    /// Decodes a cashu token to show its mints, amounts and unit without claiming it.
+    #[wasm_bindgen]
+    pub fn decode_cashu_token(
+        &self,
+        token: String,
+    ) -> Result<JsValue /* CashuTokenPreview */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.decode_cashu_token(token)?)?)
+    }
+
+    /// Gets what a cashu mint supports from its info endpoint.
+    #[wasm_bindgen]
+    pub async fn get_cashu_mint_info(
+        &self,
+        mint_url: String,
+    ) -> Result<JsValue /* MintInfo */, MutinyJsError> {
+        let mint_url = Url::parse(&mint_url).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.get_cashu_mint_info(mint_url).await?,
+        )?)
+    }
+
+    /// Claims a cashu token into our wallet, returns the amount received.
+    #[wasm_bindgen]
+    pub async fn receive_cashu_token(&self, token: String) -> Result<u64, MutinyJsError> {
+        let token = CashuToken::from_str(&token)?;
+        Ok(self.inner.receive_cashu_token(token).await?)
+    }
+
+    /// Creates a cashu token worth `amount` sats from our ecash at the given mint.
+    #[wasm_bindgen]
+    pub async fn send_cashu_token(
+        &self,
+        mint_url: String,
+        amount: u64,
+    ) -> Result<String, MutinyJsError> {
+        let mint_url = Url::parse(&mint_url).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let token = self.inner.send_cashu_token(mint_url, amount).await?;
+        Ok(token.serialize()?)
+    }
+
+    /// Lists the cashu tokens we have sent and whether they were claimed.
+    #[wasm_bindgen]
+    pub fn list_cashu_pending_sends(
+        &self,
+    ) -> Result<JsValue /* Vec<CashuPendingSend> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.list_cashu_pending_sends()?,
+        )?)
+    }
+
+    /// Takes back the ecash of a cashu token we sent that hasn't been claimed,
+    /// returns the amount reclaimed.
+    #[wasm_bindgen]
+    pub async fn reclaim_cashu_token(&self, id: String) -> Result<u64, MutinyJsError> {
+        Ok(self.inner.reclaim_cashu_token(id).await?)
+    }
+
+    /// Moves ecash from one cashu mint to another.
+    #[wasm_bindgen]
+    pub async fn transfer_cashu(
+        &self,
+        from: String,
+        to: String,
+        amount: u64,
+    ) -> Result<JsValue /* CashuMintQuote */, MutinyJsError> {
+        let from = Url::parse(&from).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let to = Url::parse(&to).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let quote = self.inner.transfer_cashu(from, to, amount).await?;
+        Ok(JsValue::from_serde(&quote)?)
+    }
+
+    /// Drops any of our cashu ecash that has been spent, returns the amount removed.
+    #[wasm_bindgen]
+    pub async fn check_cashu_proofs(&self) -> Result<u64, MutinyJsError> {
+        Ok(self.inner.check_cashu_proofs().await?)
     }
 
     /// Authenticates with a LNURL-auth for the given profile.
@@ -1106,6 +1471,125 @@ impl MutinyWallet {
             .into())
     }
 
+    /// Moves an amount of sats from one federation to another through their gateways.
+    pub async fn transfer_between_federations(
+        &self,
+        from_federation_id: String,
+        to_federation_id: String,
+        amount: u64,
+    ) -> Result<FedimintSweepResult, MutinyJsError> {
+        let from_federation_id = FederationId::from_str(&from_federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let to_federation_id = FederationId::from_str(&to_federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self
+            .inner
+            .transfer_between_federations(from_federation_id, to_federation_id, amount)
+            .await?
+            .into())
+    }
+
+    /// Gets the most we want to hold in the given federation, in sats.
+    pub async fn get_federation_max_balance(
+        &self,
+        federation_id: String,
+    ) -> Result<Option<u64>, MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self.inner.get_federation_max_balance(federation_id).await?)
+    }
+
+    /// Sets the most we want to hold in the given federation, in sats.
+    /// Passing undefined falls back to the federation's own limit.
+    pub fn set_federation_max_balance(
+        &self,
+        federation_id: String,
+        max_balance: Option<u64>,
+    ) -> Result<(), MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self
+            .inner
+            .set_federation_max_balance(federation_id, max_balance)?)
+    }
+
+    /// Moves funds out of any federation that is over its maximum balance.
+    pub async fn rebalance_federations(
+        &self,
+    ) -> Result<JsValue /* Vec<FedimintSweepResult> */, MutinyJsError> {
+        let results: Vec<FedimintSweepResult> = self
+            .inner
+            .rebalance_federations()
+            .await?
+            .into_iter()
+            .map(|r| r.into())
+            .collect();
+
+        Ok(JsValue::from_serde(&results)?)
+    }
+
+    /// Lists the lightning gateways registered with a federation,
+    /// with their fees, vetted status and when we last saw them.
+    pub async fn list_gateways(
+        &self,
+        federation_id: String,
+    ) -> Result<JsValue /* Vec<FederationGateway> */, MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(JsValue::from_serde(
+            &self.inner.list_gateways(federation_id).await?,
+        )?)
+    }
+
+    /// Pins a gateway for a federation so it is always used when available.
+    /// Passing undefined goes back to picking the cheapest gateway per payment.
+    pub async fn pin_gateway(
+        &self,
+        federation_id: String,
+        gateway_id: Option<String>,
+    ) -> Result<(), MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self.inner.pin_gateway(federation_id, gateway_id).await?)
+    }
+
+    /// Excludes a gateway for a federation so it is never used, or allows it again.
+    pub async fn exclude_gateway(
+        &self,
+        federation_id: String,
+        gateway_id: String,
+        excluded: bool,
+    ) -> Result<(), MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self
+            .inner
+            .exclude_gateway(federation_id, gateway_id, excluded)
+            .await?)
+    }
+
+    /// Sets whether payments from a federation may fall back to unvetted
+    /// gateways that charge suspiciously low fees.
+    pub async fn allow_unvetted_gateways(
+        &self,
+        federation_id: String,
+        allowed: bool,
+    ) -> Result<(), MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self
+            .inner
+            .allow_unvetted_gateways(federation_id, allowed)
+            .await?)
+    }
+
     /// Closes a channel with the given outpoint.
     ///
     /// If force is true, the channel will be force closed.
@@ -1131,6 +1615,50 @@ impl MutinyWallet {
             .await?)
     }
 
+    /// Moves liquidity between two of our channels by paying ourselves out of
+    /// `from_channel` and back in through `to_channel`.
+    /// The channels are given by their funding outpoints.
+    /// The amount and max fee should be in satoshis.
+    #[wasm_bindgen]
+    pub async fn rebalance(
+        &self,
+        from_channel: String,
+        to_channel: String,
+        amount: u64,
+        max_fee: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let from_channel: OutPoint =
+            OutPoint::from_str(&from_channel).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let to_channel: OutPoint =
+            OutPoint::from_str(&to_channel).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .rebalance(&from_channel, &to_channel, amount, max_fee)
+            .await?
+            .into())
+    }
+
+    /// Sends funds from one of our nodes to another over lightning.
+    /// The amount and max fee should be in satoshis.
+    #[wasm_bindgen]
+    pub async fn transfer_between_nodes(
+        &self,
+        from_node: String,
+        to_node: String,
+        amount: u64,
+        max_fee: Option<u64>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let to_node = PublicKey::from_str(&to_node)?;
+        Ok(self
+            .inner
+            .node_manager
+            .transfer_between_nodes(&from_node, &to_node, amount, max_fee)
+            .await?
+            .into())
+    }
+
     /// Lists all the channels for all the nodes in the node manager.
     #[wasm_bindgen]
     pub async fn list_channels(&self) -> Result<JsValue /* Vec<MutinyChannel> */, MutinyJsError> {
@@ -1260,6 +1788,115 @@ impl MutinyWallet {
         Ok(self.inner.get_federation_balances().await?.into())
     }
 
+    /// Connects to an external wallet by its `nostr+walletconnect://` URI,
+    /// so it can be used for payments alongside federations and our node.
+    #[wasm_bindgen]
+    pub async fn add_external_wallet(
+        &self,
+        name: String,
+        nwc_uri: String,
+    ) -> Result<JsValue /* ExternalWallet */, MutinyJsError> {
+        let uri = NostrWalletConnectURI::from_str(&nwc_uri)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.add_external_wallet(name, uri).await?,
+        )?)
+    }
+
+    /// Lists the external wallets we have connected to.
+    #[wasm_bindgen]
+    pub fn list_external_wallets(
+        &self,
+    ) -> Result<JsValue /* Vec<ExternalWallet> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.list_external_wallets()?)?)
+    }
+
+    /// Sets whether invoices are created with an external wallet before our node,
+    /// otherwise it is only used when our node can't create the invoice.
+    #[wasm_bindgen]
+    pub fn set_external_wallet_prefer_for_invoices(
+        &self,
+        wallet_npub: String,
+        prefer: bool,
+    ) -> Result<JsValue /* ExternalWallet */, MutinyJsError> {
+        let wallet_pubkey = parse_npub(&wallet_npub)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .set_external_wallet_prefer_for_invoices(wallet_pubkey, prefer)?,
+        )?)
+    }
+
+    /// Disconnects an external wallet by the npub of its wallet service.
+    #[wasm_bindgen]
+    pub async fn remove_external_wallet(&self, wallet_npub: String) -> Result<(), MutinyJsError> {
+        let wallet_pubkey = parse_npub(&wallet_npub)?;
+        Ok(self.inner.remove_external_wallet(wallet_pubkey).await?)
+    }
+
+    /// Gets the current balance of each external wallet.
+    #[wasm_bindgen]
+    pub async fn get_external_wallet_balances(
+        &self,
+    ) -> Result<JsValue /* Vec<ExternalWalletBalance> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.get_external_wallet_balances().await?,
+        )?)
+    }
+
+    /// Lists on-chain deposits into our federations that a transaction was seen for,
+    /// newest first, with their transaction, confirmations and whether they
+    /// have been credited yet.
+    #[wasm_bindgen]
+    pub async fn list_federation_deposits(
+        &self,
+    ) -> Result<JsValue /* Vec<FederationDeposit> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.list_federation_deposits().await?,
+        )?)
+    }
+
+    /// Checks on the guardians of each federation and reports any warnings.
+    #[wasm_bindgen]
+    pub async fn get_federation_health(
+        &self,
+    ) -> Result<JsValue /* Vec<FederationHealth> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.get_federation_health().await?,
+        )?)
+    }
+
+    /// Takes ecash out of a federation so it can be handed to someone directly.
+    /// Returns the serialized notes, which can be shown as a string or QR code.
+    /// The amount should be in satoshis.
+    #[wasm_bindgen]
+    pub async fn spend_ecash(
+        &self,
+        federation_id: String,
+        amount: u64,
+        labels: Vec<String>,
+    ) -> Result<String, MutinyJsError> {
+        let federation_id = FederationId::from_str(&federation_id)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .spend_ecash(federation_id, amount, labels)
+            .await?
+            .notes)
+    }
+
+    /// Claims ecash notes that were handed to us directly.
+    /// Returns the amount received in satoshis.
+    #[wasm_bindgen]
+    pub async fn receive_ecash(
+        &self,
+        notes: String,
+        labels: Vec<String>,
+    ) -> Result<u64, MutinyJsError> {
+        let notes = OOBNotes::from_str(&notes).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.receive_ecash(notes, labels).await?.amount_sats)
+    }
+
     /// Creates a recommendation event for a federation
     pub async fn recommend_federation(
         &self,
@@ -1304,7 +1941,8 @@ impl MutinyWallet {
             .await?)
     }
 
-    /// Queries our relays for federation announcements
+    /// Queries our relays for federation announcements, highest scored first.
+    /// Each federation includes a breakdown of its score.
     pub async fn discover_federations(
         &self,
     ) -> Result<JsValue /* Vec<NostrDiscoveredFedimint> */, MutinyJsError> {
@@ -1591,9 +2229,11 @@ impl MutinyWallet {
         let commands = match commands {
             None => vec![
                 Method::PayInvoice,
+                Method::MultiPayInvoice,
                 Method::GetInfo,
                 Method::GetBalance,
                 Method::LookupInvoice,
+                Method::ListTransactions,
                 Method::MakeInvoice,
             ],
             Some(strs) => strs
@@ -1628,9 +2268,13 @@ impl MutinyWallet {
         let commands = match commands {
             None => vec![
                 Method::PayInvoice,
+                Method::MultiPayInvoice,
+                Method::PayKeysend,
+                Method::MultiPayKeysend,
                 Method::GetInfo,
                 Method::GetBalance,
                 Method::LookupInvoice,
+                Method::ListTransactions,
                 Method::MakeInvoice,
             ],
             Some(strs) => strs
@@ -1740,6 +2384,125 @@ impl MutinyWallet {
             .into())
     }
 
+    /// Set the spending rules for a NWC Profile: node allowlists or denylists,
+    /// a rate limit, an expiry, allowed hours and an approval threshold
+    #[wasm_bindgen]
+    pub async fn set_nwc_profile_spending_rules(
+        &self,
+        profile_index: u32,
+        rules: JsValue,
+    ) -> Result<models::NwcProfile, MutinyJsError> {
+        let rules: SpendingRules = rules
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .nostr
+            .set_nwc_profile_spending_rules(profile_index, rules)?
+            .into())
+    }
+
+    /// Set which NIP-47 commands a NWC Profile is allowed to use
+    #[wasm_bindgen]
+    pub async fn set_nwc_profile_commands(
+        &self,
+        profile_index: u32,
+        commands: Vec<String>,
+    ) -> Result<models::NwcProfile, MutinyJsError> {
+        let commands = commands
+            .into_iter()
+            .map(|s| Method::from_str(&s))
+            .collect::<Result<_, _>>()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .nostr
+            .set_nwc_profile_commands(profile_index, commands)
+            .await?
+            .into())
+    }
+
+    /// Enable or disable NIP-47 notifications for a NWC Profile
+    #[wasm_bindgen]
+    pub async fn set_nwc_profile_notifications(
+        &self,
+        profile_index: u32,
+        enabled: bool,
+    ) -> Result<models::NwcProfile, MutinyJsError> {
+        Ok(self
+            .inner
+            .nostr
+            .set_nwc_profile_notifications(profile_index, enabled)
+            .await?
+            .into())
+    }
+
+    /// Set the extra relays a NWC Profile uses alongside its primary relay,
+    /// and which of its relays require NIP-42 authentication
+    #[wasm_bindgen]
+    pub async fn set_nwc_profile_relays(
+        &self,
+        profile_index: u32,
+        relays: Vec<String>,
+        auth_relays: Vec<String>,
+    ) -> Result<models::NwcProfile, MutinyJsError> {
+        Ok(self
+            .inner
+            .nostr
+            .set_nwc_profile_relays(profile_index, relays, auth_relays)
+            .await?
+            .into())
+    }
+
+    /// Get the connection health of the nostr relays we use
+    #[wasm_bindgen]
+    pub fn get_relay_health(&self) -> Result<JsValue /* Vec<RelayHealth> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.nostr.get_relay_health())?)
+    }
+
+    /// Get our NIP-65 relay list, the default relays if we haven't published one
+    #[wasm_bindgen]
+    pub async fn get_relay_list(&self) -> Result<JsValue /* RelayList */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.nostr.get_relay_list().await?,
+        )?)
+    }
+
+    /// Publish a new NIP-65 relay list, each relay needs a url
+    /// and whether we read from it, write to it, or both
+    #[wasm_bindgen]
+    pub async fn set_relay_list(
+        &self,
+        relay_list: JsValue,
+    ) -> Result<JsValue /* RelayList */, MutinyJsError> {
+        let relay_list: RelayList = relay_list
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let relay_list = self.inner.nostr.set_relay_list(relay_list).await?;
+        Ok(JsValue::from_serde(&relay_list)?)
+    }
+
+    /// Fetch our latest NIP-65 relay list from relays,
+    /// in case it was changed by another client
+    #[wasm_bindgen]
+    pub async fn sync_relay_list(&self) -> Result<JsValue /* RelayList */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.nostr.sync_relay_list().await?,
+        )?)
+    }
+
+    /// Fetches and verifies receipts for the zaps we sent and received
+    #[wasm_bindgen]
+    pub async fn sync_zap_receipts(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.sync_zap_receipts().await?)
+    }
+
+    /// Gets our zap history, newest first, with the invoice of each zap
+    #[wasm_bindgen]
+    pub async fn get_zap_history(&self) -> Result<JsValue /* Vec<ZapActivity> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.get_zap_history().await?)?)
+    }
+
     /// Require approval for a NWC Profile
     #[wasm_bindgen]
     pub async fn set_nwc_profile_require_approval(
@@ -2120,6 +2883,28 @@ impl MutinyWallet {
         Ok(JsValue::from_serde(&res)?)
     }
 
+    /// Backs up the federations we are in to each federation.
+    pub async fn backup_federations(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.backup_federations().await?)
+    }
+
+    /// Restores the federations we were in from backup, must be in safe mode.
+    pub async fn restore_federations(&self) -> Result<JsValue /* Vec<String> */, MutinyJsError> {
+        let ids: Vec<String> = self
+            .inner
+            .restore_federations()
+            .await?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect();
+        Ok(JsValue::from_serde(&ids)?)
+    }
+
+    pub fn get_federation_restore_progress(&self) -> Result<JsValue, MutinyJsError> {
+        let res = self.inner.get_federation_restore_progress()?;
+        Ok(JsValue::from_serde(&res)?)
+    }
+
     /// Restore's the mnemonic after deleting the previous state.
     ///
     /// Backup the state beforehand. Does not restore lightning data.
@@ -2251,6 +3036,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -2288,6 +3074,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -2319,6 +3106,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await;
 
@@ -2363,6 +3151,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -2393,6 +3182,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await;
 
@@ -2444,6 +3234,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .unwrap();
@@ -2493,6 +3284,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .unwrap();
@@ -2529,6 +3321,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await;
 
@@ -2569,6 +3362,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -2639,6 +3433,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -2698,6 +3493,7 @@ mod tests {
             None,
             None,
             None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -8,7 +8,7 @@ use lnurl::lightning_address::LightningAddress;
 use lnurl::lnurl::LnUrl;
 use mutiny_core::event::HTLCStatus;
 use mutiny_core::labels::Contact as MutinyContact;
-use mutiny_core::nostr::nwc::SpendingConditions;
+use mutiny_core::nostr::nwc::{SpendingConditions, SpendingRules};
 use mutiny_core::*;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::json;
@@ -22,6 +22,8 @@ pub enum ActivityType {
     Lightning,
     ChannelOpen,
     ChannelClose,
+    Ecash,
+    FederationDeposit,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -72,6 +74,8 @@ impl From<mutiny_core::ActivityItem> for ActivityItem {
             }
             mutiny_core::ActivityItem::Lightning(_) => ActivityType::Lightning,
             mutiny_core::ActivityItem::ChannelClosed(_) => ActivityType::ChannelClose,
+            mutiny_core::ActivityItem::Ecash(_) => ActivityType::Ecash,
+            mutiny_core::ActivityItem::FederationDeposit(_) => ActivityType::FederationDeposit,
         };
 
         let id = match a {
@@ -83,6 +87,8 @@ impl From<mutiny_core::ActivityItem> for ActivityItem {
                 .user_channel_id
                 .map(|c| c.to_lower_hex_string())
                 .unwrap_or_default(),
+            mutiny_core::ActivityItem::Ecash(ref e) => e.operation_id.clone(),
+            mutiny_core::ActivityItem::FederationDeposit(ref d) => d.operation_id.clone(),
         };
 
         let (inbound, amount_sats) = match a {
@@ -97,6 +103,10 @@ impl From<mutiny_core::ActivityItem> for ActivityItem {
             }
             mutiny_core::ActivityItem::Lightning(ref ln) => (ln.inbound, ln.amount_sats),
             mutiny_core::ActivityItem::ChannelClosed(_) => (false, None),
+            mutiny_core::ActivityItem::Ecash(ref e) => (e.inbound, Some(e.amount_sats)),
+            mutiny_core::ActivityItem::FederationDeposit(ref d) => {
+                (true, d.amount.or(d.expected_amount))
+            }
         };
 
         let privacy_level = match kind {
@@ -110,6 +120,8 @@ impl From<mutiny_core::ActivityItem> for ActivityItem {
             }
             ActivityType::ChannelOpen => PrivacyLevel::NotAvailable,
             ActivityType::ChannelClose => PrivacyLevel::NotAvailable,
+            ActivityType::Ecash => PrivacyLevel::NotAvailable,
+            ActivityType::FederationDeposit => PrivacyLevel::NotAvailable,
         };
 
         ActivityItem {
@@ -433,6 +445,8 @@ pub struct MutinyBalance {
     pub unconfirmed: u64,
     pub lightning: u64,
     pub federation: u64,
+    pub cashu: u64,
+    pub external: u64,
     pub force_close: u64,
 }
 
@@ -451,6 +465,8 @@ impl From<mutiny_core::MutinyBalance> for MutinyBalance {
             unconfirmed: m.unconfirmed,
             lightning: m.lightning,
             federation: m.federation,
+            cashu: m.cashu,
+            external: m.external,
             force_close: m.force_close,
         }
     }
@@ -850,6 +866,10 @@ pub struct NwcProfile {
     tag: String,
     label: Option<String>,
     enabled: bool,
+    notifications: bool,
+    spending_rules: SpendingRules,
+    relays: Vec<String>,
+    auth_relays: Vec<String>,
 }
 
 impl Serialize for NwcProfile {
@@ -874,6 +894,10 @@ impl Serialize for NwcProfile {
             "spending_conditions_type": self.spending_conditions_type(),
             "url_suffix": self.url_suffix(),
             "enabled": self.enabled(),
+            "notifications": self.notifications(),
+            "spending_rules": json!(self.spending_rules),
+            "relays": self.relays,
+            "auth_relays": self.auth_relays,
         });
 
         json.serialize(serializer)
@@ -993,6 +1017,28 @@ impl NwcProfile {
     pub fn enabled(&self) -> bool {
         self.enabled
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn notifications(&self) -> bool {
+        self.notifications
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn spending_rules(&self) -> JsValue {
+        JsValue::from_serde(&self.spending_rules).unwrap()
+    }
+
+    /// Extra relays used alongside the primary relay
+    #[wasm_bindgen(getter)]
+    pub fn relays(&self) -> Vec<String> {
+        self.relays.clone()
+    }
+
+    /// Relays that require NIP-42 authentication
+    #[wasm_bindgen(getter)]
+    pub fn auth_relays(&self) -> Vec<String> {
+        self.auth_relays.clone()
+    }
 }
 
 impl From<nostr::nwc::NwcProfile> for NwcProfile {
@@ -1020,6 +1066,10 @@ impl From<nostr::nwc::NwcProfile> for NwcProfile {
             tag: value.tag.to_string(),
             label: value.label,
             enabled: value.enabled.unwrap_or(true),
+            notifications: value.notifications,
+            spending_rules: value.spending_rules,
+            relays: value.relays,
+            auth_relays: value.auth_relays,
         }
     }
 }
@@ -1129,6 +1179,28 @@ impl From<mutiny_core::Plan> for Plan {
     }
 }
 
+/// The result of melting the part of a cashu token from a single mint.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CashuMeltResult {
+    pub mint_url: String,
+    pub amount: u64,
+    pub invoice: Option<MutinyInvoice>,
+    pub change: u64,
+    pub error: Option<String>,
+}
+
+impl From<mutiny_core::CashuMeltResult> for CashuMeltResult {
+    fn from(m: mutiny_core::CashuMeltResult) -> Self {
+        CashuMeltResult {
+            mint_url: m.mint_url.to_string(),
+            amount: m.amount,
+            invoice: m.invoice.map(|i| i.into()),
+            change: m.change,
+            error: m.error,
+        }
+    }
+}
+
 /// FedimintSweepResult is the result of how much was swept and the fees paid.
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
 #[wasm_bindgen]
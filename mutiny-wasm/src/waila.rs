@@ -1,4 +1,6 @@
 use bitcoin::Network;
+use gloo_utils::format::JsValueSerdeExt;
+use mutiny_core::cashu::CashuToken;
 use nostr::prelude::ToBech32;
 use std::str::FromStr;
 use wasm_bindgen::prelude::*;
@@ -7,15 +9,29 @@ use wasm_bindgen::prelude::*;
 #[wasm_bindgen]
 pub struct PaymentParams {
     string: String,
-    params: bitcoin_waila::PaymentParams<'static>,
+    // `None` for cashu tokens, which we parse ourselves to support every version
+    params: Option<bitcoin_waila::PaymentParams<'static>>,
+    cashu_token: Option<CashuToken>,
 }
 
 #[wasm_bindgen]
 impl PaymentParams {
     #[wasm_bindgen(constructor)]
     pub fn from_string(string: String) -> Result<PaymentParams, JsValue> {
+        if let Ok(token) = CashuToken::from_str(&string) {
+            return Ok(PaymentParams {
+                string,
+                params: None,
+                cashu_token: Some(token),
+            });
+        }
+
         let params = bitcoin_waila::PaymentParams::from_str(&string).map_err(|_| JsValue::NULL)?;
-        Ok(PaymentParams { string, params })
+        Ok(PaymentParams {
+            string,
+            params: Some(params),
+            cashu_token: None,
+        })
     }
 
     #[wasm_bindgen(getter)]
@@ -25,73 +41,95 @@ impl PaymentParams {
 
     #[wasm_bindgen(getter)]
     pub fn memo(&self) -> Option<String> {
-        self.params.memo()
+        match self.cashu_token.as_ref() {
+            Some(token) => token.memo.clone(),
+            None => self.params.as_ref()?.memo(),
+        }
     }
 
     #[wasm_bindgen(getter)]
     pub fn network(&self) -> Option<String> {
-        self.params.network().map(|n| n.to_string())
+        self.params.as_ref()?.network().map(|n| n.to_string())
     }
 
     #[wasm_bindgen]
     pub fn valid_for_network(&self, network: String) -> Option<bool> {
         let network = Network::from_str(&network).ok()?;
-        self.params.valid_for_network(network)
+        self.params.as_ref()?.valid_for_network(network)
     }
 
     #[wasm_bindgen(getter)]
     pub fn amount_sats(&self) -> Option<u64> {
-        self.params.amount().map(|amount| amount.to_sat())
+        match self.cashu_token.as_ref() {
+            Some(token) => token.is_sat().then(|| token.amount()),
+            None => self.params.as_ref()?.amount().map(|amount| amount.to_sat()),
+        }
     }
 
     #[wasm_bindgen(getter)]
     pub fn amount_msats(&self) -> Option<u64> {
-        self.params.amount_msats()
+        match self.cashu_token.as_ref() {
+            Some(token) => token.is_sat().then(|| token.amount() * 1_000),
+            None => self.params.as_ref()?.amount_msats(),
+        }
     }
 
     #[wasm_bindgen(getter)]
     pub fn address(&self) -> Option<String> {
-        self.params.address().map(|addr| addr.to_string())
+        self.params.as_ref()?.address().map(|addr| addr.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn invoice(&self) -> Option<String> {
-        self.params.invoice().map(|invoice| invoice.to_string())
+        self.params
+            .as_ref()?
+            .invoice()
+            .map(|invoice| invoice.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn offer(&self) -> Option<String> {
-        self.params.offer().map(|offer| offer.to_string())
+        self.params.as_ref()?.offer().map(|offer| offer.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn refund(&self) -> Option<String> {
-        self.params.refund().map(|refund| refund.to_string())
+        self.params
+            .as_ref()?
+            .refund()
+            .map(|refund| refund.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn node_pubkey(&self) -> Option<String> {
-        self.params.node_pubkey().map(|pubkey| pubkey.to_string())
+        self.params
+            .as_ref()?
+            .node_pubkey()
+            .map(|pubkey| pubkey.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn lnurl(&self) -> Option<String> {
-        self.params.lnurl().map(|lnurl| lnurl.to_string())
+        self.params.as_ref()?.lnurl().map(|lnurl| lnurl.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn lightning_address(&self) -> Option<String> {
-        self.params.lightning_address().map(|addr| addr.to_string())
+        self.params
+            .as_ref()?
+            .lightning_address()
+            .map(|addr| addr.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn is_lnurl_auth(&self) -> bool {
-        self.params.is_lnurl_auth()
+        self.params.as_ref().is_some_and(|p| p.is_lnurl_auth())
     }
 
     #[wasm_bindgen(getter)]
     pub fn nostr_pubkey(&self) -> Option<String> {
         self.params
+            .as_ref()?
             .nostr_pubkey()
             .and_then(|key| key.to_bech32().ok())
     }
@@ -99,37 +137,62 @@ impl PaymentParams {
     #[wasm_bindgen(getter)]
     pub fn fedimint_invite_code(&self) -> Option<String> {
         self.params
+            .as_ref()?
             .fedimint_invite_code()
             .map(|code| code.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn nostr_wallet_auth(&self) -> Option<String> {
-        self.params.nostr_wallet_auth().map(|u| u.to_string())
+        self.params
+            .as_ref()?
+            .nostr_wallet_auth()
+            .map(|u| u.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn cashu_token(&self) -> Option<String> {
-        self.params.cashu_token().and_then(|t| t.serialize().ok())
+        // pass along the original string so no version specific data is lost
+        self.cashu_token
+            .as_ref()
+            .map(|_| self.string.trim().to_string())
+    }
+
+    /// What the scanned cashu token holds, for showing before claiming it
+    #[wasm_bindgen(getter)]
+    pub fn cashu_token_preview(&self) -> JsValue {
+        self.cashu_token
+            .as_ref()
+            .and_then(|t| JsValue::from_serde(&t.preview()).ok())
+            .unwrap_or(JsValue::NULL)
     }
 
     #[wasm_bindgen(getter)]
     pub fn fedimint_oob_notes(&self) -> Option<String> {
-        self.params.fedimint_oob_notes().map(|t| t.to_string())
+        self.params
+            .as_ref()?
+            .fedimint_oob_notes()
+            .map(|t| t.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn payjoin_endpoint(&self) -> Option<String> {
-        self.params.payjoin_endpoint().map(|n| n.to_string())
+        self.params
+            .as_ref()?
+            .payjoin_endpoint()
+            .map(|n| n.to_string())
     }
 
     #[wasm_bindgen(getter)]
     pub fn disable_output_substitution(&self) -> Option<bool> {
-        self.params.disable_output_substitution()
+        self.params.as_ref()?.disable_output_substitution()
     }
 
     #[wasm_bindgen(getter)]
     pub fn payjoin_supported(&self) -> bool {
-        self.params.payjoin_endpoint().is_some()
+        self.params
+            .as_ref()
+            .and_then(|p| p.payjoin_endpoint())
+            .is_some()
     }
 }